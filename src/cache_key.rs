@@ -1,3 +1,70 @@
+use serde_either::StringOrStruct;
+
+/// protected/non_protectedのブランチ・タグでキャッシュを分離するための名前空間
+///
+/// GitLab CIと同様に、キャッシュキーの末尾に`-protected`/`-non_protected`を付与する。
+/// これにより、non_protectedなブランチのジョブがprotectedなブランチのキャッシュを
+/// 汚染（キャッシュポイズニング）できなくなる。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheNamespace {
+    /// 名前空間を分けない（`unprotect = true`）
+    Shared,
+    Protected,
+    NonProtected,
+}
+
+impl CacheNamespace {
+    /// 全ての名前空間
+    pub const ALL: [CacheNamespace; 3] = [Self::Shared, Self::Protected, Self::NonProtected];
+
+    /// キャッシュキーに名前空間のサフィックスを付与する
+    pub fn apply(&self, key: &str) -> String {
+        match self {
+            Self::Shared => key.to_string(),
+            Self::Protected => format!("{key}-protected"),
+            Self::NonProtected => format!("{key}-non_protected"),
+        }
+    }
+}
+
+/// 1つのキャッシュについて、restore時に試行する順のキャッシュキー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKeys {
+    pub primary: String,
+    pub fallbacks: Vec<String>,
+}
+
+impl CacheKeys {
+    /// プライマリキー、fallback_keysの順に全てのキーを返す
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.primary.as_str()).chain(self.fallbacks.iter().map(String::as_str))
+    }
+
+    /// 全てのキーに世代を反映する
+    pub fn with_generation(self, generation: u64) -> Self {
+        Self {
+            primary: apply_generation(&self.primary, generation),
+            fallbacks: self
+                .fallbacks
+                .iter()
+                .map(|key| apply_generation(key, generation))
+                .collect(),
+        }
+    }
+}
+
+/// キャッシュキーに世代（`cafce delete --bump-generation`で増やす）のサフィックスを付与する
+///
+/// 世代を増やすと全てのキーが変わるため、既存のキャッシュを削除せずに一度に無効化できる。
+/// 世代0（一度も増やしていない）の場合は、従来のキーと互換にするため何も付与しない。
+pub fn apply_generation(key: &str, generation: u64) -> String {
+    if generation == 0 {
+        key.to_string()
+    } else {
+        format!("{key}-g{generation}")
+    }
+}
+
 pub struct CacheKeyGenerator {
     max_files: usize,
     base_path: std::path::PathBuf,
@@ -33,6 +100,35 @@ impl CacheKeyGenerator {
         
         Ok(final_key)
     }
+
+    /// 設定からプライマリキーとfallback_keysを求め、名前空間を適用する
+    ///
+    /// `key`が文字列の場合はそのまま、`key.files`の場合は`generate_key`で計算したキーを使う。
+    /// 名前空間はプライマリキー・fallback_keysの両方に適用する
+    /// （fallbackだけ名前空間を越えられると、分離の意味がなくなるため）。
+    pub fn generate_cache_keys(
+        &self,
+        setting: &crate::setting::Setting,
+        namespace: CacheNamespace,
+    ) -> anyhow::Result<CacheKeys> {
+        let span = tracing::info_span!("cafce.cache_key", key = tracing::field::Empty);
+        let _entered = span.enter();
+        let primary = match setting.key() {
+            StringOrStruct::String(key) => key.clone(),
+            StringOrStruct::Struct(key_config) => self.generate_key(key_config)?,
+        };
+
+        let primary = namespace.apply(&primary);
+        span.record("key", primary.as_str());
+        Ok(CacheKeys {
+            primary,
+            fallbacks: setting
+                .fallback_keys()
+                .iter()
+                .map(|key| namespace.apply(key))
+                .collect(),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -196,4 +292,93 @@ mod tests {
         assert!(result2.is_ok());
         assert_ne!(result1.unwrap(), result2.unwrap());
     }
+
+    #[test]
+    fn test_cache_namespace_apply() {
+        assert_eq!(super::CacheNamespace::Shared.apply("key"), "key");
+        assert_eq!(super::CacheNamespace::Protected.apply("key"), "key-protected");
+        assert_eq!(
+            super::CacheNamespace::NonProtected.apply("key"),
+            "key-non_protected"
+        );
+    }
+
+    #[test]
+    fn test_with_generation() {
+        let keys = super::CacheKeys {
+            primary: "deps-protected".to_string(),
+            fallbacks: vec!["main-protected".to_string()],
+        };
+        assert_eq!(keys.clone().with_generation(0), keys);
+        let keys = keys.with_generation(3);
+        assert_eq!(keys.primary, "deps-protected-g3");
+        assert_eq!(keys.fallbacks, vec!["main-protected-g3"]);
+    }
+
+    #[test]
+    fn test_generate_cache_keys_string_key() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let setting: crate::setting::Setting = toml::from_str(
+            r#"
+            paths = ["node_modules"]
+            key = "my-cache"
+            fallback_keys = ["main", "default"]
+            "#,
+        )
+        .unwrap();
+
+        let generator = super::CacheKeyGenerator::new(50, temp_dir.path().to_path_buf());
+        let keys = generator
+            .generate_cache_keys(&setting, super::CacheNamespace::Protected)
+            .unwrap();
+        assert_eq!(keys.primary, "my-cache-protected");
+        assert_eq!(keys.fallbacks, vec!["main-protected", "default-protected"]);
+    }
+
+    #[test]
+    fn test_generate_cache_keys_files_key() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("test.txt"), "test content").unwrap();
+        let setting: crate::setting::Setting = toml::from_str(
+            r#"
+            paths = ["node_modules"]
+            fallback_keys = ["main"]
+
+            [key]
+            files = ["test.txt"]
+            prefix = "v1"
+            "#,
+        )
+        .unwrap();
+
+        let generator = super::CacheKeyGenerator::new(50, temp_dir.path().to_path_buf());
+        let keys = generator
+            .generate_cache_keys(&setting, super::CacheNamespace::NonProtected)
+            .unwrap();
+        assert!(keys.primary.starts_with("v1-"));
+        assert!(keys.primary.ends_with("-non_protected"));
+        assert_eq!(keys.fallbacks, vec!["main-non_protected"]);
+    }
+
+    #[test]
+    fn test_generate_cache_keys_unprotect() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let setting: crate::setting::Setting = toml::from_str(
+            r#"
+            paths = ["node_modules"]
+            key = "my-cache"
+            fallback_keys = ["main"]
+            unprotect = true
+            "#,
+        )
+        .unwrap();
+        assert!(setting.unprotect());
+
+        let generator = super::CacheKeyGenerator::new(50, temp_dir.path().to_path_buf());
+        let keys = generator
+            .generate_cache_keys(&setting, super::CacheNamespace::Shared)
+            .unwrap();
+        assert_eq!(keys.primary, "my-cache");
+        assert_eq!(keys.fallbacks, vec!["main"]);
+    }
 }
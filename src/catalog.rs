@@ -0,0 +1,1080 @@
+//! バケット内のキャッシュエントリの一覧
+//!
+//! ListObjectsV2でプロジェクトのプレフィックス配下を列挙し、オブジェクトキーをテンプレートと
+//! 照合してキャッシュキー・キャッシュ名を取り出す。ListObjectsV2はユーザー定義メタデータを返さないため、
+//! 最終アクセス日時・作成したパイプライン等はエントリごとにHeadObjectで取得する。
+
+use crate::i18n::tr;
+use crate::object_key::{Codec, ObjectKeyContext, ObjectKeyError, ObjectKeyTemplate};
+use crate::retention::{PrunePlan, ProtectedKeys, Retention};
+use crate::s3_client::SseConfig;
+use crate::setting::Setting;
+use crate::storage::{read_generation_object, S3Storage, StorageError};
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata};
+use aws_sdk_s3::operation::get_object::GetObjectOutput;
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt::Write;
+use tracing::Instrument;
+
+/// キャッシュ名を記録するメタデータ（`x-amz-meta-cafce-cache-name`）
+pub const METADATA_CACHE_NAME: &str = "cafce-cache-name";
+/// 圧縮方式（"zstd" / "gzip" / "none"）を記録するメタデータ
+pub const METADATA_CODEC: &str = "cafce-codec";
+/// キャッシュを作成したパイプラインのID（CI_PIPELINE_ID）を記録するメタデータ
+pub const METADATA_PIPELINE_ID: &str = "cafce-pipeline-id";
+/// 最後にrestoreで使用された日時（RFC 3339）を記録するメタデータ
+pub const METADATA_LAST_ACCESSED: &str = "cafce-last-accessed";
+
+/// HeadObjectを同時に実行する数
+const HEAD_CONCURRENCY: usize = 16;
+
+/// 1回のDeleteObjectsで削除できるオブジェクトの最大数（S3の上限）
+const DELETE_BATCH_SIZE: usize = 1000;
+
+/// 世代の更新が他の実行と競合した場合に、読み出しからやり直す回数の上限
+const GENERATION_UPDATE_ATTEMPTS: usize = 5;
+
+/// キャッシュエントリの一覧取得時のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum CatalogError {
+    #[error("{}", tr!("設定ファイルでbucketを指定してください", "set bucket in the config file"))]
+    MissingBucket,
+    #[error(transparent)]
+    ObjectKey(#[from] ObjectKeyError),
+    #[error("{}", tr!("キャッシュの一覧の取得に失敗しました: s3://{bucket}/{prefix}: {message}", "failed to list caches: s3://{bucket}/{prefix}: {message}"))]
+    List {
+        bucket: String,
+        prefix: String,
+        message: String,
+    },
+    #[error("{}", tr!("キャッシュのメタデータの取得に失敗しました: {object_key}: {message}", "failed to get the cache metadata: {object_key}: {message}"))]
+    Head { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュが見つかりません: キャッシュ名 {cache_name}, キー {key}", "cache not found: cache name {cache_name}, key {key}"))]
+    NotFound { cache_name: String, key: String },
+    #[error("{}", tr!("キャッシュの取得に失敗しました: {object_key}: {message}", "failed to get the cache: {object_key}: {message}"))]
+    Get { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュの削除に失敗しました: {object_key}: {message}", "failed to delete the cache: {object_key}: {message}"))]
+    Delete { object_key: String, message: String },
+    #[error("{}", tr!("削除するキー・パターン・キャッシュ名のいずれか、または--bump-generationを指定してください", "specify keys, patterns or a cache name to delete, or --bump-generation"))]
+    NoDeleteTarget,
+    #[error("{}", tr!("キーのパターンが不正です: {pattern}", "invalid key pattern: {pattern}"))]
+    InvalidPattern {
+        pattern: String,
+        #[source]
+        source: glob::PatternError,
+    },
+    #[error("{}", tr!("キャッシュの世代の読み書きに失敗しました: {object_key}: {message}", "failed to read or write the cache generation: {object_key}: {message}"))]
+    Generation { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュの世代の値が不正です: {object_key}: {value}", "invalid cache generation: {object_key}: {value}"))]
+    InvalidGeneration { object_key: String, value: String },
+    #[error("{}", tr!("キャッシュの世代が同時に更新されたため、{attempts}回試行しても増やせませんでした: {object_key}", "the cache generation was updated concurrently and could not be incremented after {attempts} attempts: {object_key}"))]
+    GenerationConflict { object_key: String, attempts: usize },
+    #[error("{}", tr!(
+        "list・prune・delete・inspectはS3の保存先のみ対応しています: {url}",
+        "list, prune, delete and inspect only support S3 storage: {url}"
+    ))]
+    UnsupportedStorage { url: String },
+}
+
+impl CatalogError {
+    /// 設定の誤りか（S3との通信の失敗ではないか）
+    pub fn is_config_error(&self) -> bool {
+        matches!(
+            self,
+            Self::MissingBucket
+                | Self::ObjectKey(_)
+                | Self::NotFound { .. }
+                | Self::NoDeleteTarget
+                | Self::InvalidPattern { .. }
+                | Self::UnsupportedStorage { .. }
+        )
+    }
+}
+
+/// バケット内の1つのキャッシュ
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CacheEntry {
+    /// キャッシュキー（名前空間適用後）
+    pub key: String,
+    pub cache_name: Option<String>,
+    pub object_key: String,
+    /// オブジェクトのサイズ（圧縮・暗号化後）
+    pub size: u64,
+    pub codec: Option<Codec>,
+    #[serde(serialize_with = "serialize_date_time")]
+    pub last_modified: Option<DateTime>,
+    /// 最後にrestoreで使用された日時（記録がない場合はNone）
+    #[serde(serialize_with = "serialize_date_time")]
+    pub last_accessed: Option<DateTime>,
+    /// キャッシュを作成したパイプラインのID
+    pub producer_pipeline: Option<String>,
+}
+
+impl CacheEntry {
+    /// メタデータの値でエントリを補完する
+    ///
+    /// オブジェクトキーから求めた値より、store時に記録したメタデータを優先する。
+    fn apply_metadata(&mut self, metadata: &HashMap<String, String>) {
+        if let Some(cache_name) = metadata.get(METADATA_CACHE_NAME) {
+            self.cache_name = Some(cache_name.clone());
+        }
+        if let Some(codec) = metadata.get(METADATA_CODEC).and_then(|codec| Codec::from_name(codec)) {
+            self.codec = Some(codec);
+        }
+        self.producer_pipeline = metadata.get(METADATA_PIPELINE_ID).cloned();
+        self.last_accessed = metadata
+            .get(METADATA_LAST_ACCESSED)
+            .and_then(|value| DateTime::from_str(value, Format::DateTime).ok());
+    }
+}
+
+fn serialize_date_time<S: Serializer>(value: &Option<DateTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value.and_then(|value| value.fmt(Format::DateTime).ok()) {
+        Some(value) => serializer.serialize_str(&value),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// 一覧の絞り込み条件
+#[derive(Debug, Clone, Default)]
+pub struct ListFilter {
+    /// キャッシュ名（完全一致）
+    pub cache_name: Option<String>,
+    /// キャッシュキーの前方一致
+    pub key_prefix: Option<String>,
+}
+
+impl ListFilter {
+    fn matches(&self, entry: &CacheEntry) -> bool {
+        let cache_name_matches = match (&self.cache_name, &entry.cache_name) {
+            (Some(filter), Some(cache_name)) => filter == cache_name,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let key_matches = self
+            .key_prefix
+            .as_ref()
+            .is_none_or(|key_prefix| entry.key.starts_with(key_prefix.as_str()));
+        cache_name_matches && key_matches
+    }
+}
+
+/// 削除するキャッシュの指定（`cafce delete`）
+///
+/// キーはglobパターンとして照合する（globの特殊文字を含まないパターンは完全一致）。
+/// キャッシュ名のみを指定した場合は、そのキャッシュ名の全てのキーを対象とする。
+#[derive(Debug, Clone)]
+pub struct DeleteSelector {
+    cache_name: Option<String>,
+    patterns: Vec<glob::Pattern>,
+}
+
+impl DeleteSelector {
+    pub fn new(cache_name: Option<String>, patterns: &[String]) -> Result<Self, CatalogError> {
+        if cache_name.is_none() && patterns.is_empty() {
+            return Err(CatalogError::NoDeleteTarget);
+        }
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern).map_err(|source| CatalogError::InvalidPattern {
+                    pattern: pattern.clone(),
+                    source,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { cache_name, patterns })
+    }
+
+    /// 一覧取得時の絞り込み条件（キャッシュ名のみ）
+    pub fn list_filter(&self) -> ListFilter {
+        ListFilter {
+            cache_name: self.cache_name.clone(),
+            key_prefix: None,
+        }
+    }
+
+    pub fn matches(&self, entry: &CacheEntry) -> bool {
+        self.list_filter().matches(entry)
+            && (self.patterns.is_empty() || self.patterns.iter().any(|pattern| pattern.matches(&entry.key)))
+    }
+}
+
+/// プロジェクトのキャッシュを保存しているバケット
+pub struct Catalog {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    template: ObjectKeyTemplate,
+    project: Option<String>,
+    codec: Codec,
+    sse: SseConfig,
+}
+
+impl Catalog {
+    /// `setting`のバケット・オブジェクトキーのテンプレートから一覧の対象を決める
+    ///
+    /// # Arguments
+    /// * `project` - GitLabのプロジェクトパス（CI_PROJECT_PATH）
+    /// * `sse` - SSE-Cで暗号化されたオブジェクトのHeadObjectに必要な設定
+    pub fn new(
+        client: aws_sdk_s3::Client,
+        setting: &Setting,
+        project: Option<&str>,
+        sse: SseConfig,
+    ) -> Result<Self, CatalogError> {
+        Ok(Self {
+            client,
+            bucket: setting.bucket().ok_or(CatalogError::MissingBucket)?.to_string(),
+            template: setting.object_key_template()?,
+            project: project.map(String::from),
+            codec: setting.codec(),
+            sse,
+        })
+    }
+
+    /// ListObjectsV2に指定するプレフィックス
+    ///
+    /// キャッシュ名で絞り込む場合はキャッシュのプレフィックス（`{key}`の直前まで）にキーの前方一致を加え、
+    /// それ以外はプロジェクトのプレフィックスとする。
+    fn list_prefix(&self, filter: &ListFilter) -> Result<String, CatalogError> {
+        let Some(cache_name) = &filter.cache_name else {
+            return Ok(self.template.project_prefix(&self.context(""))?);
+        };
+        let mut prefix = self.template.prefix(&self.context(cache_name))?;
+        if let Some(key_prefix) = &filter.key_prefix {
+            prefix.push_str(key_prefix);
+        }
+        Ok(prefix)
+    }
+
+    fn context<'a>(&'a self, cache_name: &'a str) -> ObjectKeyContext<'a> {
+        ObjectKeyContext {
+            project: self.project.as_deref(),
+            cache_name,
+            codec: self.codec,
+        }
+    }
+
+    /// キャッシュエントリを一覧する
+    ///
+    /// テンプレートに一致しないオブジェクト（cafce以外が作成したもの・古い方式のキー）は含めない。
+    /// キャッシュ名ごとに、更新日時の新しい順に並べる。
+    pub async fn list(&self, filter: &ListFilter) -> Result<Vec<CacheEntry>, CatalogError> {
+        let prefix = self.list_prefix(filter)?;
+        let span = tracing::info_span!("cafce.list", prefix = prefix.as_str());
+        self.list_under(&prefix, filter).instrument(span).await
+    }
+
+    async fn list_under(&self, prefix: &str, filter: &ListFilter) -> Result<Vec<CacheEntry>, CatalogError> {
+        let mut entries = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| CatalogError::List {
+                bucket: self.bucket.clone(),
+                prefix: prefix.to_string(),
+                message: DisplayErrorContext(&e).to_string(),
+            })?;
+            for object in page.contents() {
+                let Some(object_key) = object.key() else {
+                    continue;
+                };
+                let Some(parsed) = self.template.match_object_key(self.project.as_deref(), object_key) else {
+                    continue;
+                };
+                let entry = CacheEntry {
+                    key: parsed.key,
+                    cache_name: parsed.cache_name,
+                    object_key: object_key.to_string(),
+                    size: object.size().unwrap_or_default().max(0) as u64,
+                    codec: parsed.codec,
+                    last_modified: object.last_modified().copied(),
+                    last_accessed: None,
+                    producer_pipeline: None,
+                };
+                // テンプレートに`{cache_name}`がない場合は、メタデータを取得してから絞り込む
+                if entry.cache_name.is_none() || filter.matches(&entry) {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        let mut entries = self.fetch_metadata(entries).await?;
+        entries.retain(|entry| filter.matches(entry));
+        entries.sort_by(|a, b| {
+            a.cache_name
+                .cmp(&b.cache_name)
+                .then_with(|| b.last_modified.cmp(&a.last_modified))
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        Ok(entries)
+    }
+
+    /// キャッシュ名とキー（完全一致）でエントリを探す
+    ///
+    /// 圧縮方式はオブジェクトキーの拡張子・メタデータから求めるため、設定ファイルと異なる方式で
+    /// 保存されたキャッシュも見つかる。
+    pub async fn find(&self, cache_name: &str, key: &str) -> Result<CacheEntry, CatalogError> {
+        let filter = ListFilter {
+            cache_name: Some(cache_name.to_string()),
+            key_prefix: Some(key.to_string()),
+        };
+        self.list(&filter)
+            .await?
+            .into_iter()
+            .find(|entry| entry.key == key)
+            .ok_or_else(|| CatalogError::NotFound {
+                cache_name: cache_name.to_string(),
+                key: key.to_string(),
+            })
+    }
+
+    /// オブジェクトをGetObjectで取得する（本文はストリームのまま返す）
+    pub async fn get(&self, object_key: &str) -> Result<GetObjectOutput, CatalogError> {
+        self.sse
+            .apply_to_get_object(self.client.get_object())
+            .bucket(&self.bucket)
+            .key(object_key)
+            .send()
+            .instrument(tracing::info_span!("cafce.get", object_key))
+            .await
+            .map_err(|e| CatalogError::Get {
+                object_key: object_key.to_string(),
+                message: DisplayErrorContext(&e).to_string(),
+            })
+    }
+
+    /// 各エントリのメタデータをHeadObjectで取得する
+    ///
+    /// 一覧の取得後に削除されたオブジェクトは結果から除く。
+    async fn fetch_metadata(&self, entries: Vec<CacheEntry>) -> Result<Vec<CacheEntry>, CatalogError> {
+        let mut fetched = Vec::with_capacity(entries.len());
+        let mut entries = entries.into_iter().peekable();
+        while entries.peek().is_some() {
+            let mut tasks = tokio::task::JoinSet::new();
+            for (index, entry) in entries.by_ref().take(HEAD_CONCURRENCY).enumerate() {
+                let request = self
+                    .sse
+                    .apply_to_head_object(self.client.head_object())
+                    .bucket(&self.bucket)
+                    .key(&entry.object_key);
+                tasks.spawn(async move { (index, entry, request.send().await) });
+            }
+            let mut results = tasks.join_all().await;
+            // 一覧の順序を保つ
+            results.sort_by_key(|(index, _, _)| *index);
+            for (_, mut entry, result) in results {
+                match result {
+                    Ok(output) => {
+                        if let Some(metadata) = output.metadata() {
+                            entry.apply_metadata(metadata);
+                        }
+                        fetched.push(entry);
+                    }
+                    Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => {}
+                    Err(e) => {
+                        return Err(CatalogError::Head {
+                            object_key: entry.object_key,
+                            message: DisplayErrorContext(&e).to_string(),
+                        })
+                    }
+                }
+            }
+        }
+        Ok(fetched)
+    }
+}
+
+impl Catalog {
+    fn generation_object_key(&self) -> Result<String, CatalogError> {
+        Ok(self.template.generation_object_key(&self.context(""))?)
+    }
+
+    /// プロジェクトのキャッシュの世代を取得する（一度も増やしていない場合は0）
+    ///
+    /// store / restoreと同じ値を読むよう、`storage::read_generation`と同じ方法で取得する。
+    pub async fn generation(&self) -> Result<u64, CatalogError> {
+        Ok(self.generation_object().await?.0)
+    }
+
+    /// 世代と、世代を保存するオブジェクトのETag（存在しない場合はNone）を取得する
+    async fn generation_object(&self) -> Result<(u64, Option<String>), CatalogError> {
+        let object_key = self.generation_object_key()?;
+        let storage = S3Storage::new(self.client.clone(), &self.bucket, self.sse.clone());
+        read_generation_object(&storage, &object_key).await.map_err(|error| match error {
+            StorageError::InvalidGeneration { object_key, value } => {
+                CatalogError::InvalidGeneration { object_key, value }
+            }
+            error => CatalogError::Generation {
+                object_key: object_key.clone(),
+                message: error.to_string(),
+            },
+        })
+    }
+
+    /// プロジェクトのキャッシュの世代を1つ増やし、新しい世代を返す
+    ///
+    /// 以降のstore/restoreは全て新しいキーを使うため、既存のキャッシュは全て使われなくなる
+    /// （古いキャッシュは`cafce prune`で削除する）。
+    ///
+    /// 読み出した時点から変わっていない場合のみ書き込む（`If-Match`、未作成の場合は`If-None-Match: *`）。
+    /// 同時に実行されて条件が満たされなかった場合は、読み出しからやり直す。
+    pub async fn bump_generation(&self) -> Result<u64, CatalogError> {
+        let object_key = self.generation_object_key()?;
+        for _ in 0..GENERATION_UPDATE_ATTEMPTS {
+            let (current, etag) = self.generation_object().await?;
+            let generation = current + 1;
+            let request = self
+                .sse
+                .apply_to_put_object(self.client.put_object())
+                .bucket(&self.bucket)
+                .key(&object_key)
+                .content_type("text/plain")
+                .body(generation.to_string().into_bytes().into());
+            let request = match etag {
+                Some(etag) => request.if_match(etag),
+                None => request.if_none_match("*"),
+            };
+            match request.send().await {
+                Ok(_) => return Ok(generation),
+                // PreconditionFailed: 条件を満たさない（他で更新された）
+                // ConditionalRequestConflict: 同じキーへの条件付き書き込みが競合した
+                Err(e)
+                    if matches!(
+                        e.as_service_error().and_then(|e| e.code()),
+                        Some("PreconditionFailed" | "ConditionalRequestConflict")
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    return Err(CatalogError::Generation {
+                        object_key,
+                        message: DisplayErrorContext(&e).to_string(),
+                    })
+                }
+            }
+        }
+        Err(CatalogError::GenerationConflict {
+            object_key,
+            attempts: GENERATION_UPDATE_ATTEMPTS,
+        })
+    }
+
+    /// `cache_name`のキャッシュに保持ルールを適用し、削除対象を削除する（`dry_run`の場合は削除しない）
+    ///
+    /// `protected`は設定ファイルの`fallback_keys`から作るため、その設定ファイルのキャッシュ名のみを対象とする。
+    /// 他のキャッシュ名のキャッシュは、それぞれの設定ファイルでpruneする。
+    pub async fn prune(
+        &self,
+        cache_name: &str,
+        retention: &Retention,
+        protected: &ProtectedKeys,
+        now: i64,
+        dry_run: bool,
+    ) -> Result<PrunePlan, CatalogError> {
+        let filter = ListFilter {
+            cache_name: Some(cache_name.to_string()),
+            key_prefix: None,
+        };
+        let entries = self.list(&filter).await?;
+        let plan = retention.plan(entries, protected, now);
+        if !dry_run {
+            let object_keys: Vec<String> = plan
+                .delete
+                .iter()
+                .map(|candidate| candidate.entry.object_key.clone())
+                .collect();
+            self.delete(&object_keys).await?;
+        }
+        Ok(plan)
+    }
+
+    /// オブジェクトをDeleteObjectsでまとめて削除する
+    ///
+    /// 1000件ごとに分けて送信する。存在しないオブジェクトの削除は成功として扱われる。
+    /// 一部のオブジェクトの削除に失敗した場合は、最初の失敗をエラーとして返す。
+    pub async fn delete(&self, object_keys: &[String]) -> Result<(), CatalogError> {
+        use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+
+        for batch in object_keys.chunks(DELETE_BATCH_SIZE) {
+            let delete_error = |object_key: &str, message: String| CatalogError::Delete {
+                object_key: object_key.to_string(),
+                message,
+            };
+            let objects = batch
+                .iter()
+                .map(|object_key| {
+                    ObjectIdentifier::builder()
+                        .key(object_key)
+                        .build()
+                        .map_err(|e| delete_error(object_key, e.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let delete = Delete::builder()
+                .set_objects(Some(objects))
+                .quiet(true)
+                .build()
+                .map_err(|e| delete_error(&batch[0], e.to_string()))?;
+            let output = self
+                .client
+                .delete_objects()
+                .bucket(&self.bucket)
+                .delete(delete)
+                .send()
+                .await
+                .map_err(|e| delete_error(&batch[0], DisplayErrorContext(&e).to_string()))?;
+            if let Some(error) = output.errors().first() {
+                return Err(delete_error(
+                    error.key().unwrap_or_default(),
+                    format!(
+                        "{}: {}",
+                        error.code().unwrap_or_default(),
+                        error.message().unwrap_or_default()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// バイト数を人が読みやすい単位（KiB・MiB等）で表す
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// キャッシュエントリの一覧を表形式にする
+pub fn entries_to_table(entries: &[CacheEntry]) -> String {
+    let none = || "-".to_string();
+    let format_time = |time: Option<DateTime>| {
+        time.and_then(|time| time.fmt(Format::DateTime).ok())
+            .unwrap_or_else(none)
+    };
+    let header = [
+        "KEY",
+        "CACHE",
+        "SIZE",
+        "CODEC",
+        "LAST MODIFIED",
+        "LAST ACCESSED",
+        "PIPELINE",
+    ]
+    .map(String::from);
+    let rows: Vec<[String; 7]> = entries
+        .iter()
+        .map(|entry| {
+            [
+                entry.key.clone(),
+                entry.cache_name.clone().unwrap_or_else(none),
+                format_size(entry.size),
+                entry.codec.map(|codec| codec.name().to_string()).unwrap_or_else(none),
+                format_time(entry.last_modified),
+                format_time(entry.last_accessed),
+                entry.producer_pipeline.clone().unwrap_or_else(none),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.clone().map(|column| column.chars().count());
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let mut line = String::new();
+        for (index, (column, width)) in row.iter().zip(widths).enumerate() {
+            if index > 0 {
+                line.push_str("  ");
+            }
+            let _ = write!(line, "{column:width$}");
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{CiEnv, Env};
+    use crate::s3_client::build_s3_client;
+    use crate::test_util::{MockResponse, MockServer, RecordedRequest};
+
+    fn setting(contents: &str) -> Setting {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cafce.toml");
+        std::fs::write(&path, contents).unwrap();
+        Setting::new_from_file(&path).unwrap()
+    }
+
+    fn default_setting() -> Setting {
+        setting(
+            r#"
+bucket = "b"
+paths = ["node_modules"]
+key = "static"
+fallback_keys = []
+"#,
+        )
+    }
+
+    fn s3_env(address: &str) -> Env {
+        Env::new_for_test(
+            Some(address.to_string()),
+            Some("access".to_string()),
+            Some("secret".to_string()),
+            None,
+            None,
+            None,
+            None,
+            true,
+            None,
+            None,
+        )
+    }
+
+    fn list_xml(keys: &[(&str, u64)], next_token: Option<&str>) -> String {
+        let contents: String = keys
+            .iter()
+            .map(|(key, size)| {
+                format!(
+                    "<Contents><Key>{key}</Key><LastModified>2026-10-0{size}T00:00:00.000Z</LastModified>\
+                     <ETag>\"etag\"</ETag><Size>{size}</Size><StorageClass>STANDARD</StorageClass></Contents>"
+                )
+            })
+            .collect();
+        let truncated = match next_token {
+            Some(token) => format!("<IsTruncated>true</IsTruncated><NextContinuationToken>{token}</NextContinuationToken>"),
+            None => "<IsTruncated>false</IsTruncated>".to_string(),
+        };
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+             <Name>b</Name><KeyCount>{}</KeyCount><MaxKeys>1000</MaxKeys>{truncated}{contents}</ListBucketResult>",
+            keys.len()
+        )
+    }
+
+    /// 2ページに分かれた一覧と、オブジェクトごとのメタデータを返すS3
+    fn mock_s3(request: &RecordedRequest) -> MockResponse {
+        if request.method == "HEAD" {
+            if request.path.ends_with("/gone.tar.zst") {
+                return MockResponse::new(404, "");
+            }
+            let mut response = MockResponse::new(200, "");
+            if request.path.contains("/node_modules/") {
+                response = response
+                    .header("x-amz-meta-cafce-pipeline-id", "42")
+                    .header("x-amz-meta-cafce-last-accessed", "2026-10-10T12:00:00Z");
+            }
+            return response;
+        }
+        if request.path.contains("continuation-token=page2") {
+            return MockResponse::new(
+                200,
+                list_xml(
+                    &[
+                        ("group/app/cargo/v1/target-protected.tar.gz", 3),
+                        ("group/app/cargo/v1/gone.tar.zst", 1),
+                    ],
+                    None,
+                ),
+            );
+        }
+        MockResponse::new(
+            200,
+            list_xml(
+                &[
+                    ("group/app/node_modules/v1/deps-old-protected.tar.zst", 1),
+                    ("group/app/node_modules/v1/deps-new-protected.tar.zst", 2),
+                    // テンプレートに一致しないオブジェクト
+                    ("group/app/node_modules/v0/deps.tar.zst", 1),
+                    ("group/app/README", 1),
+                ],
+                Some("page2"),
+            ),
+        )
+    }
+
+    async fn catalog(s3: &MockServer, setting: &Setting) -> Catalog {
+        let env = s3_env(s3.address());
+        let client = build_s3_client(&env, &CiEnv::default()).await.unwrap();
+        Catalog::new(client, setting, Some("group/app"), SseConfig::None).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_list_pages_and_metadata() {
+        let s3 = MockServer::start(mock_s3);
+        let catalog = catalog(&s3, &default_setting()).await;
+
+        let entries = catalog.list(&ListFilter::default()).await.unwrap();
+        let keys: Vec<_> = entries.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, ["target-protected", "deps-new-protected", "deps-old-protected"]);
+
+        let target = &entries[0];
+        assert_eq!(target.cache_name.as_deref(), Some("cargo"));
+        assert_eq!(target.codec, Some(Codec::Gzip));
+        assert_eq!(target.size, 3);
+        assert_eq!(target.producer_pipeline, None);
+
+        let deps = &entries[1];
+        assert_eq!(deps.object_key, "group/app/node_modules/v1/deps-new-protected.tar.zst");
+        assert_eq!(deps.producer_pipeline.as_deref(), Some("42"));
+        assert_eq!(
+            deps.last_accessed,
+            Some(DateTime::from_str("2026-10-10T12:00:00Z", Format::DateTime).unwrap())
+        );
+
+        let list_requests: Vec<_> = s3
+            .requests()
+            .into_iter()
+            .filter(|request| request.method == "GET")
+            .collect();
+        assert_eq!(list_requests.len(), 2);
+        assert!(list_requests[0].path.contains("prefix=group%2Fapp%2F"), "{}", list_requests[0].path);
+    }
+
+    #[tokio::test]
+    async fn test_list_filter() {
+        let s3 = MockServer::start(mock_s3);
+        let catalog = catalog(&s3, &default_setting()).await;
+
+        let filter = ListFilter {
+            cache_name: Some("node_modules".to_string()),
+            key_prefix: Some("deps-old".to_string()),
+        };
+        let entries = catalog.list(&filter).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "deps-old-protected");
+
+        // キャッシュ名で絞り込む場合は、キャッシュのプレフィックスとキーの前方一致で一覧する
+        let list_request = s3.requests().into_iter().find(|request| request.method == "GET").unwrap();
+        assert!(
+            list_request
+                .path
+                .contains("prefix=group%2Fapp%2Fnode_modules%2Fv1%2Fdeps-old"),
+            "{}",
+            list_request.path
+        );
+        // 絞り込みで除外したエントリのメタデータは取得しない
+        let head_requests = s3.requests().into_iter().filter(|request| request.method == "HEAD").count();
+        assert_eq!(head_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_find() {
+        let s3 = MockServer::start(mock_s3);
+        let catalog = catalog(&s3, &default_setting()).await;
+
+        let entry = catalog.find("node_modules", "deps-new-protected").await.unwrap();
+        assert_eq!(entry.object_key, "group/app/node_modules/v1/deps-new-protected.tar.zst");
+        assert_eq!(entry.codec, Some(Codec::Zstd));
+
+        // 前方一致するだけのキーは対象としない
+        let result = catalog.find("node_modules", "deps").await;
+        assert!(matches!(result, Err(CatalogError::NotFound { .. })), "{result:?}");
+        assert!(result.unwrap_err().is_config_error());
+    }
+
+    #[tokio::test]
+    async fn test_list_error() {
+        let s3 = MockServer::start(|_| MockResponse::new(403, "<Error><Code>AccessDenied</Code></Error>"));
+        let catalog = catalog(&s3, &default_setting()).await;
+        let result = catalog.list(&ListFilter::default()).await;
+        assert!(matches!(result, Err(CatalogError::List { .. })), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn test_delete_batches() {
+        let s3 = MockServer::start(|_| {
+            MockResponse::new(200, "<?xml version=\"1.0\" encoding=\"UTF-8\"?><DeleteResult></DeleteResult>")
+        });
+        let catalog = catalog(&s3, &default_setting()).await;
+        let object_keys: Vec<String> = (0..1001).map(|index| format!("group/app/deps/v1/{index}.tar.zst")).collect();
+        catalog.delete(&object_keys).await.unwrap();
+
+        let requests = s3.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request.method == "POST" && request.path.contains("delete")));
+        assert_eq!(requests[0].body_str().matches("<Key>").count(), 1000);
+        assert!(requests[1].body_str().contains("<Key>group/app/deps/v1/1000.tar.zst</Key>"));
+    }
+
+    #[tokio::test]
+    async fn test_delete_partial_failure() {
+        let s3 = MockServer::start(|_| {
+            MockResponse::new(
+                200,
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?><DeleteResult>\
+                 <Error><Key>group/app/deps/v1/a.tar.zst</Key><Code>AccessDenied</Code><Message>Access Denied</Message></Error>\
+                 </DeleteResult>",
+            )
+        });
+        let catalog = catalog(&s3, &default_setting()).await;
+        let result = catalog.delete(&["group/app/deps/v1/a.tar.zst".to_string()]).await;
+        assert!(
+            matches!(&result, Err(CatalogError::Delete { object_key, message })
+                if object_key == "group/app/deps/v1/a.tar.zst" && message.contains("AccessDenied")),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prune_only_the_configured_cache() {
+        let s3 = MockServer::start(|request| {
+            if request.method == "POST" {
+                return MockResponse::new(200, "<?xml version=\"1.0\" encoding=\"UTF-8\"?><DeleteResult></DeleteResult>");
+            }
+            mock_s3(request)
+        });
+        let catalog = catalog(&s3, &default_setting()).await;
+        // 全てのキャッシュが期限切れになるルール
+        let retention = Retention {
+            max_age_days: Some(0),
+            ..Retention::default()
+        };
+        let protected = ProtectedKeys::new(&["deps-old".to_string()], &[], 0).unwrap();
+        let now = DateTime::from_str("2026-10-19T00:00:00Z", Format::DateTime).unwrap().secs();
+
+        let plan = catalog.prune("node_modules", &retention, &protected, now, false).await.unwrap();
+        // cargoのfallback_keysはこの設定ファイルからは分からないため、cargoのキャッシュは対象外
+        let deleted: Vec<_> = plan.delete.iter().map(|candidate| candidate.entry.key.as_str()).collect();
+        assert_eq!(deleted, ["deps-new-protected"]);
+        assert_eq!((plan.kept, plan.protected), (1, 1));
+        let delete = s3.requests().into_iter().find(|request| request.method == "POST").unwrap();
+        assert_eq!(delete.body_str().matches("<Key>").count(), 1);
+        assert!(delete.body_str().contains("<Key>group/app/node_modules/v1/deps-new-protected.tar.zst</Key>"));
+    }
+
+    #[tokio::test]
+    async fn test_generation() {
+        let s3 = MockServer::start(|request| match request.method.as_str() {
+            "GET" if request.path.contains("/b/group/app/.cafce-generation") => {
+                MockResponse::new(200, "2\n").header("ETag", "\"gen2\"")
+            }
+            "PUT" => MockResponse::new(200, ""),
+            _ => MockResponse::new(404, "<Error><Code>NoSuchKey</Code></Error>"),
+        });
+        let catalog = catalog(&s3, &default_setting()).await;
+        assert_eq!(catalog.generation().await.unwrap(), 2);
+        assert_eq!(catalog.bump_generation().await.unwrap(), 3);
+
+        let put = s3.requests().into_iter().find(|request| request.method == "PUT").unwrap();
+        assert!(put.path.starts_with("/b/group/app/.cafce-generation"), "{}", put.path);
+        assert_eq!(put.body_str(), "3");
+        // 読み出した世代から変わっていない場合のみ書き込む
+        assert_eq!(put.header("If-Match"), Some("\"gen2\""));
+        assert_eq!(put.header("If-None-Match"), None);
+    }
+
+    #[tokio::test]
+    async fn test_generation_defaults_to_zero() {
+        let s3 = MockServer::start(|request| match request.method.as_str() {
+            "PUT" => MockResponse::new(200, ""),
+            _ => MockResponse::new(404, "<Error><Code>NoSuchKey</Code></Error>"),
+        });
+        let catalog = catalog(&s3, &default_setting()).await;
+        assert_eq!(catalog.generation().await.unwrap(), 0);
+        assert_eq!(catalog.bump_generation().await.unwrap(), 1);
+
+        // 未作成の場合は、他で作成されていないときのみ書き込む
+        let put = s3.requests().into_iter().find(|request| request.method == "PUT").unwrap();
+        assert_eq!(put.header("If-None-Match"), Some("*"));
+        assert_eq!(put.header("If-Match"), None);
+    }
+
+    #[tokio::test]
+    async fn test_bump_generation_retries_on_conflict() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // 1回目の書き込みの前に他の実行が世代を3にした
+        let puts = std::sync::Arc::new(AtomicUsize::new(0));
+        let s3 = MockServer::start({
+            let puts = std::sync::Arc::clone(&puts);
+            move |request| match request.method.as_str() {
+                "GET" if puts.load(Ordering::SeqCst) == 0 => MockResponse::new(200, "2").header("ETag", "\"gen2\""),
+                "GET" => MockResponse::new(200, "3").header("ETag", "\"gen3\""),
+                "PUT" if puts.fetch_add(1, Ordering::SeqCst) == 0 => {
+                    MockResponse::new(412, "<Error><Code>PreconditionFailed</Code></Error>")
+                }
+                _ => MockResponse::new(200, ""),
+            }
+        });
+        let catalog = catalog(&s3, &default_setting()).await;
+        assert_eq!(catalog.bump_generation().await.unwrap(), 4);
+
+        let puts: Vec<_> = s3.requests().into_iter().filter(|request| request.method == "PUT").collect();
+        assert_eq!(puts.len(), 2);
+        assert_eq!(puts[1].header("If-Match"), Some("\"gen3\""));
+        assert_eq!(puts[1].body_str(), "4");
+    }
+
+    #[tokio::test]
+    async fn test_bump_generation_retries_on_conditional_request_conflict() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let puts = AtomicUsize::new(0);
+        let s3 = MockServer::start(move |request| match request.method.as_str() {
+            "GET" => MockResponse::new(200, "2").header("ETag", "\"gen2\""),
+            "PUT" if puts.fetch_add(1, Ordering::SeqCst) == 0 => {
+                MockResponse::new(409, "<Error><Code>ConditionalRequestConflict</Code></Error>")
+            }
+            _ => MockResponse::new(200, ""),
+        });
+        let catalog = catalog(&s3, &default_setting()).await;
+        assert_eq!(catalog.bump_generation().await.unwrap(), 3);
+        assert_eq!(s3.requests().into_iter().filter(|request| request.method == "PUT").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_bump_generation_does_not_retry_other_errors() {
+        // 同じステータスでも、条件付き書き込みの競合以外のエラーはやり直さない
+        let s3 = MockServer::start(|request| match request.method.as_str() {
+            "GET" => MockResponse::new(200, "2").header("ETag", "\"gen2\""),
+            _ => MockResponse::new(409, "<Error><Code>OperationAborted</Code></Error>"),
+        });
+        let catalog = catalog(&s3, &default_setting()).await;
+        let result = catalog.bump_generation().await;
+        assert!(matches!(result, Err(CatalogError::Generation { .. })), "{result:?}");
+        assert_eq!(s3.requests().into_iter().filter(|request| request.method == "PUT").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_bump_generation_gives_up_after_conflicts() {
+        let s3 = MockServer::start(|request| match request.method.as_str() {
+            "GET" => MockResponse::new(200, "2").header("ETag", "\"gen2\""),
+            _ => MockResponse::new(412, "<Error><Code>PreconditionFailed</Code></Error>"),
+        });
+        let catalog = catalog(&s3, &default_setting()).await;
+        let result = catalog.bump_generation().await;
+        assert!(
+            matches!(result, Err(CatalogError::GenerationConflict { attempts: GENERATION_UPDATE_ATTEMPTS, .. })),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_generation() {
+        let s3 = MockServer::start(|_| MockResponse::new(200, "two"));
+        let catalog = catalog(&s3, &default_setting()).await;
+        let result = catalog.generation().await;
+        assert!(matches!(result, Err(CatalogError::InvalidGeneration { value, .. }) if value == "two"));
+    }
+
+    #[tokio::test]
+    async fn test_generation_requires_project_prefix() {
+        let s3 = MockServer::start(|_| MockResponse::new(404, "<Error><Code>NoSuchKey</Code></Error>"));
+        let setting = setting(
+            r#"
+bucket = "b"
+object_key = "{cache_name}/{key}.{ext}"
+paths = ["node_modules"]
+key = "static"
+fallback_keys = []
+"#,
+        );
+        let catalog = catalog(&s3, &setting).await;
+        // バケット直下の世代・一覧は全てのプロジェクトで共有されてしまうため、送信せずにエラーとする
+        for result in [catalog.generation().await, catalog.bump_generation().await] {
+            assert!(
+                matches!(&result, Err(error @ CatalogError::ObjectKey(ObjectKeyError::MissingProjectPrefix)) if error.is_config_error()),
+                "{result:?}"
+            );
+        }
+        let result = catalog.list(&ListFilter::default()).await;
+        assert!(matches!(result, Err(CatalogError::ObjectKey(ObjectKeyError::MissingProjectPrefix))), "{result:?}");
+        assert!(s3.requests().is_empty());
+    }
+
+    #[test]
+    fn test_delete_selector() {
+        let entry = |key: &str, cache_name: &str| CacheEntry {
+            key: key.to_string(),
+            cache_name: Some(cache_name.to_string()),
+            object_key: format!("group/app/{cache_name}/v1/{key}.tar.zst"),
+            size: 0,
+            codec: None,
+            last_modified: None,
+            last_accessed: None,
+            producer_pipeline: None,
+        };
+
+        let selector = DeleteSelector::new(None, &["deps-abc-protected".to_string(), "feature-*".to_string()]).unwrap();
+        assert!(selector.matches(&entry("deps-abc-protected", "deps")));
+        assert!(selector.matches(&entry("feature-x-non_protected", "target")));
+        assert!(!selector.matches(&entry("deps-abc-protected-g1", "deps")));
+
+        let selector = DeleteSelector::new(Some("deps".to_string()), &[]).unwrap();
+        assert!(selector.matches(&entry("anything", "deps")));
+        assert!(!selector.matches(&entry("anything", "target")));
+
+        assert!(matches!(DeleteSelector::new(None, &[]), Err(CatalogError::NoDeleteTarget)));
+        assert!(matches!(
+            DeleteSelector::new(None, &["[".to_string()]),
+            Err(CatalogError::InvalidPattern { .. })
+        ));
+    }
+
+    #[test]
+    fn test_missing_bucket() {
+        let setting = setting("paths = []\nkey = \"k\"\nfallback_keys = []\n");
+        let client = aws_sdk_s3::Client::from_conf(aws_sdk_s3::Config::builder().behavior_version_latest().build());
+        let result = Catalog::new(client, &setting, None, SseConfig::None);
+        assert!(matches!(result, Err(CatalogError::MissingBucket)));
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(2 * 1024 * 1024 * 1024), "2.0 GiB");
+    }
+
+    #[test]
+    fn test_entries_to_table() {
+        let entries = [CacheEntry {
+            key: "deps-protected".to_string(),
+            cache_name: Some("node_modules".to_string()),
+            object_key: "group/app/node_modules/v1/deps-protected.tar.zst".to_string(),
+            size: 2048,
+            codec: Some(Codec::Zstd),
+            last_modified: Some(DateTime::from_secs(1_790_000_000)),
+            last_accessed: None,
+            producer_pipeline: Some("42".to_string()),
+        }];
+        assert_eq!(
+            entries_to_table(&entries),
+            "KEY             CACHE         SIZE     CODEC  LAST MODIFIED         LAST ACCESSED  PIPELINE\n\
+             deps-protected  node_modules  2.0 KiB  zstd   2026-09-21T14:13:20Z  -              42\n"
+        );
+
+        let value = serde_json::to_value(&entries[0]).unwrap();
+        assert_eq!(value["last_modified"], "2026-09-21T14:13:20Z");
+        assert_eq!(value["last_accessed"], serde_json::Value::Null);
+        assert_eq!(value["codec"], "zstd");
+    }
+}
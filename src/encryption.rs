@@ -0,0 +1,642 @@
+// src/encryption.rs
+use crate::env::Env;
+use crate::i18n::tr;
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm, Nonce};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// 暗号化に使用した鍵IDを記録するオブジェクトメタデータのキー
+///
+/// S3ではユーザー定義メタデータ（`x-amz-meta-cafce-encryption-key-id`）として保存される。
+/// `cafce inspect`での表示用であり、restore時はストリームヘッダーの鍵IDで鍵を選択する
+/// （メタデータを保持しない保存先でも復号できるようにするため）。
+pub const KEY_ID_METADATA: &str = "cafce-encryption-key-id";
+
+/// 暗号化ストリームの先頭に付与するマジックバイト
+const MAGIC: &[u8; 8] = b"CAFCEENC";
+
+/// 暗号化ストリームのフォーマットバージョン
+///
+/// ヘッダー = マジックバイト || バージョン(1バイト) || 鍵IDの長さ(1バイト) || 鍵ID || nonceプレフィックス
+/// ヘッダー全体を各チャンクの追加認証データ（AAD）とするため、鍵IDの書き換えも復号時に検出される。
+const FORMAT_VERSION: u8 = 2;
+
+/// 平文を分割するチャンクサイズ（最終チャンク以外は必ずこのサイズになる）
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// AES-GCMの認証タグ長
+const TAG_SIZE: usize = 16;
+
+/// チャンクごとのnonceのうち、ストリーム単位でランダムに決める部分の長さ
+///
+/// nonce(12バイト) = プレフィックス(7バイト) || チャンク番号(4バイト, BE) || 最終チャンクフラグ(1バイト)
+const NONCE_PREFIX_SIZE: usize = 7;
+
+/// 暗号化設定・処理のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum EncryptionError {
+    #[error("{}", tr!("CAFCE_ENCRYPTION_KEYが指定されている場合、CAFCE_ENCRYPTION_KEY_IDの指定が必須です", "CAFCE_ENCRYPTION_KEY_ID is required when CAFCE_ENCRYPTION_KEY is set"))]
+    MissingKeyId,
+    #[error("{}", tr!("CAFCE_ENCRYPTION_KEY_IDが指定されていますが、CAFCE_ENCRYPTION_KEYが指定されていません", "CAFCE_ENCRYPTION_KEY_ID is set but CAFCE_ENCRYPTION_KEY is not"))]
+    MissingKey,
+    #[error("{}", tr!("暗号化鍵が不正です（64文字の16進数で指定してください）: 鍵ID {key_id}", "invalid encryption key (expected 64 hex characters): key ID {key_id}"))]
+    InvalidKey { key_id: String },
+    #[error("{}", tr!("CAFCE_ENCRYPTION_OLD_KEYSの書式が不正です（\"鍵ID:16進数の鍵\"のカンマ区切り）: {index}番目の要素", "invalid CAFCE_ENCRYPTION_OLD_KEYS (expected comma-separated \"key-id:hex-key\"): entry {index}"))]
+    InvalidOldKeyEntry { index: usize },
+    #[error("{}", tr!("鍵IDが重複しています: {key_id}", "duplicate key ID: {key_id}"))]
+    DuplicateKeyId { key_id: String },
+    #[error("{}", tr!("鍵IDが長すぎます（255バイト以内で指定してください）: {key_id}", "key ID is too long (must be at most 255 bytes): {key_id}"))]
+    KeyIdTooLong { key_id: String },
+    #[error("{}", tr!("鍵ID {key_id} に対応する鍵が設定されていません", "no key configured for key ID {key_id}"))]
+    UnknownKeyId { key_id: String },
+    #[error("{}", tr!("暗号化ストリームのヘッダーが不正です", "invalid encrypted stream header"))]
+    InvalidHeader,
+    #[error("{}", tr!("暗号化鍵が設定されていますが、キャッシュが暗号化されていません", "an encryption key is configured but the cache is not encrypted"))]
+    NotEncrypted,
+    #[error("{}", tr!("暗号化ストリームの復号に失敗しました（鍵の誤り、またはデータの改ざん・欠損）", "failed to decrypt the encrypted stream (wrong key, or tampered or truncated data)"))]
+    Decrypt,
+    #[error("{}", tr!("暗号化ストリームのチャンク数が上限を超えました", "too many chunks in the encrypted stream"))]
+    TooManyChunks,
+}
+
+impl From<EncryptionError> for std::io::Error {
+    fn from(e: EncryptionError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// AES-256-GCMの鍵
+///
+/// Debug出力で鍵の値が漏れないよう、Debugは手動実装している。
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// 64文字の16進数文字列から鍵を生成する
+    fn from_hex(key_id: &str, hex_key: &str) -> Result<Self, EncryptionError> {
+        let invalid = || EncryptionError::InvalidKey {
+            key_id: key_id.to_string(),
+        };
+        let bytes = hex::decode(hex_key.trim()).map_err(|_| invalid())?;
+        let key: [u8; 32] = bytes.try_into().map_err(|_| invalid())?;
+        Ok(Self(key))
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.0.into())
+    }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// 鍵IDと鍵の対応表
+///
+/// - store時は`active_key()`（CAFCE_ENCRYPTION_KEY / CAFCE_ENCRYPTION_KEY_ID）で暗号化する
+/// - restore時はストリームヘッダーの鍵IDで`key()`を引き、旧鍵も含めて復号に使う
+#[derive(Debug, Default)]
+pub struct Keyring {
+    active_key_id: Option<String>,
+    keys: HashMap<String, EncryptionKey>,
+}
+
+impl Keyring {
+    /// 環境変数設定から鍵束を構築する
+    pub fn from_env(env: &Env) -> Result<Self, EncryptionError> {
+        Self::parse(
+            env.encryption_key(),
+            env.encryption_key_id(),
+            env.encryption_old_keys(),
+        )
+    }
+
+    /// 鍵・鍵ID・旧鍵リストの文字列から鍵束を構築する
+    ///
+    /// この関数は純粋なロジックであり、環境変数を直接読まない。
+    ///
+    /// # Arguments
+    /// * `key` - 現行の鍵（64文字の16進数）
+    /// * `key_id` - 現行の鍵ID（`key`指定時は必須）
+    /// * `old_keys` - 復号専用の旧鍵（"鍵ID:16進数の鍵"のカンマ区切り）
+    pub fn parse(
+        key: Option<&str>,
+        key_id: Option<&str>,
+        old_keys: Option<&str>,
+    ) -> Result<Self, EncryptionError> {
+        let mut keyring = Self::default();
+
+        match (key, key_id) {
+            (Some(key), Some(key_id)) => {
+                let key_id = key_id.trim();
+                if key_id.is_empty() {
+                    return Err(EncryptionError::MissingKeyId);
+                }
+                keyring.insert(key_id, EncryptionKey::from_hex(key_id, key)?)?;
+                keyring.active_key_id = Some(key_id.to_string());
+            }
+            (Some(_), None) => return Err(EncryptionError::MissingKeyId),
+            (None, Some(_)) => return Err(EncryptionError::MissingKey),
+            (None, None) => {}
+        }
+
+        for (index, entry) in old_keys
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .enumerate()
+        {
+            let (key_id, hex_key) = entry
+                .split_once(':')
+                .map(|(id, key)| (id.trim(), key))
+                .filter(|(id, _)| !id.is_empty())
+                .ok_or(EncryptionError::InvalidOldKeyEntry { index: index + 1 })?;
+            keyring.insert(key_id, EncryptionKey::from_hex(key_id, hex_key)?)?;
+        }
+
+        Ok(keyring)
+    }
+
+    fn insert(&mut self, key_id: &str, key: EncryptionKey) -> Result<(), EncryptionError> {
+        if key_id.len() > usize::from(u8::MAX) {
+            return Err(EncryptionError::KeyIdTooLong {
+                key_id: key_id.to_string(),
+            });
+        }
+        if self.keys.contains_key(key_id) {
+            return Err(EncryptionError::DuplicateKeyId {
+                key_id: key_id.to_string(),
+            });
+        }
+        self.keys.insert(key_id.to_string(), key);
+        Ok(())
+    }
+
+    /// store時に使用する鍵IDと鍵を取得する
+    ///
+    /// 未設定の場合はNone（暗号化しない）
+    pub fn active_key(&self) -> Option<(&str, &EncryptionKey)> {
+        let key_id = self.active_key_id.as_deref()?;
+        self.keys.get(key_id).map(|key| (key_id, key))
+    }
+
+    /// 鍵IDに対応する鍵を取得する（restore時の復号用）
+    pub fn key(&self, key_id: &str) -> Result<&EncryptionKey, EncryptionError> {
+        self.keys
+            .get(key_id)
+            .ok_or_else(|| EncryptionError::UnknownKeyId {
+                key_id: key_id.to_string(),
+            })
+    }
+}
+
+/// アーカイブを読み出すReaderを、暗号化されていれば復号するReaderにする
+///
+/// ストリームの先頭のマジックバイトで暗号化の有無を判定し、鍵はヘッダーの鍵IDで`keyring`から選ぶ。
+/// 現行の鍵が設定されているのに暗号化されていない場合は、保存先で平文のキャッシュに
+/// 差し替えられた可能性があるため`NotEncrypted`とする。
+pub fn decrypt_stream<'a, R: Read + 'a>(mut reader: R, keyring: &Keyring) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut magic = Vec::with_capacity(MAGIC.len());
+    (&mut reader).take(MAGIC.len() as u64).read_to_end(&mut magic)?;
+    let is_encrypted = magic == MAGIC;
+    let reader = std::io::Cursor::new(magic).chain(reader);
+    if is_encrypted {
+        Ok(Box::new(DecryptReader::new(reader, keyring)?))
+    } else if keyring.active_key().is_some() {
+        Err(EncryptionError::NotEncrypted.into())
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_SIZE], counter: u32, last: bool) -> Nonce<U12> {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = u8::from(last);
+    nonce.into()
+}
+
+/// アーカイブストリームをAES-256-GCMで暗号化して書き出すWriter
+///
+/// 平文を`CHUNK_SIZE`ごとに分割し、チャンク単位で認証付き暗号化する。
+/// nonceにチャンク番号と最終チャンクフラグを含めるため、チャンクの並べ替え・
+/// 途中での切り詰めは復号時に検出される。ヘッダー（鍵IDを含む）はAADとして認証する。
+///
+/// 書き込み完了後は必ず`finish()`を呼ぶこと（最終チャンクが書き出される）。
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    cipher: Aes256Gcm,
+    header: Vec<u8>,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// ストリームヘッダーを書き出し、暗号化Writerを生成する
+    pub fn new(mut inner: W, key_id: &str, key: &EncryptionKey) -> std::io::Result<Self> {
+        let key_id_len = u8::try_from(key_id.len()).map_err(|_| EncryptionError::KeyIdTooLong {
+            key_id: key_id.to_string(),
+        })?;
+        let random_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        nonce_prefix.copy_from_slice(&random_nonce[..NONCE_PREFIX_SIZE]);
+
+        let mut header = Vec::with_capacity(MAGIC.len() + 2 + key_id.len() + NONCE_PREFIX_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.push(FORMAT_VERSION);
+        header.push(key_id_len);
+        header.extend_from_slice(key_id.as_bytes());
+        header.extend_from_slice(&nonce_prefix);
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            cipher: key.cipher(),
+            header,
+            nonce_prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        })
+    }
+
+    fn write_chunk(&mut self, len: usize, last: bool) -> std::io::Result<()> {
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter, last);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &self.buffer[..len],
+                    aad: &self.header,
+                },
+            )
+            .map_err(|_| std::io::Error::other(tr!("AES-GCMによる暗号化に失敗しました", "AES-GCM encryption failed")))?;
+        self.inner.write_all(&ciphertext)?;
+        self.buffer.drain(..len);
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(EncryptionError::TooManyChunks)?;
+        Ok(())
+    }
+
+    /// 残りの平文を最終チャンクとして書き出し、内側のWriterを返す
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let len = self.buffer.len();
+        self.write_chunk(len, true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        // 後続データがあると確定したチャンクのみ書き出す
+        // （ちょうどCHUNK_SIZEで終わる場合も最終チャンクはfinish()で書き出す）
+        while self.buffer.len() > CHUNK_SIZE {
+            self.write_chunk(CHUNK_SIZE, false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `EncryptWriter`で暗号化されたストリームを復号して読み出すReader
+///
+/// 認証に失敗したチャンクは1バイトも返さず、`InvalidData`のI/Oエラーとする。
+pub struct DecryptReader<R: Read> {
+    inner: BufReader<R>,
+    cipher: Aes256Gcm,
+    header: Vec<u8>,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u32,
+    plaintext: Vec<u8>,
+    pos: usize,
+    finished: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// ストリームヘッダーを読み取り、ヘッダーの鍵IDに対応する`keyring`の鍵で復号Readerを生成する
+    pub fn new(inner: R, keyring: &Keyring) -> std::io::Result<Self> {
+        let mut inner = BufReader::new(inner);
+        let mut read_header = |len: usize, header: &mut Vec<u8>| -> Result<(), EncryptionError> {
+            let start = header.len();
+            header.resize(start + len, 0);
+            inner
+                .read_exact(&mut header[start..])
+                .map_err(|_| EncryptionError::InvalidHeader)
+        };
+
+        let mut header = Vec::new();
+        read_header(MAGIC.len() + 2, &mut header)?;
+        if &header[..MAGIC.len()] != MAGIC || header[MAGIC.len()] != FORMAT_VERSION {
+            return Err(EncryptionError::InvalidHeader.into());
+        }
+        let key_id_start = header.len();
+        read_header(usize::from(header[MAGIC.len() + 1]), &mut header)?;
+        let key_id = std::str::from_utf8(&header[key_id_start..]).map_err(|_| EncryptionError::InvalidHeader)?;
+        let cipher = keyring.key(key_id)?.cipher();
+        read_header(NONCE_PREFIX_SIZE, &mut header)?;
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        nonce_prefix.copy_from_slice(&header[header.len() - NONCE_PREFIX_SIZE..]);
+
+        Ok(Self {
+            inner,
+            cipher,
+            header,
+            nonce_prefix,
+            counter: 0,
+            plaintext: Vec::new(),
+            pos: 0,
+            finished: false,
+        })
+    }
+
+    fn read_chunk(&mut self) -> std::io::Result<()> {
+        let mut ciphertext = Vec::with_capacity(CHUNK_SIZE + TAG_SIZE);
+        (&mut self.inner)
+            .take((CHUNK_SIZE + TAG_SIZE) as u64)
+            .read_to_end(&mut ciphertext)?;
+
+        // 満杯でないチャンク、または後続データが無いチャンクが最終チャンク
+        let last = ciphertext.len() < CHUNK_SIZE + TAG_SIZE || self.inner.fill_buf()?.is_empty();
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter, last);
+        self.plaintext = self
+            .cipher
+            .decrypt(
+                &nonce,
+                Payload {
+                    msg: &ciphertext,
+                    aad: &self.header,
+                },
+            )
+            .map_err(|_| EncryptionError::Decrypt)?;
+        self.pos = 0;
+        self.finished = last;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(EncryptionError::TooManyChunks)?;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.plaintext.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.read_chunk()?;
+        }
+        let n = buf.len().min(self.plaintext.len() - self.pos);
+        buf[..n].copy_from_slice(&self.plaintext[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY_B: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    fn encrypt(keyring: &Keyring, plaintext: &[u8]) -> Vec<u8> {
+        let (key_id, key) = keyring.active_key().unwrap();
+        let mut writer = EncryptWriter::new(Vec::new(), key_id, key).unwrap();
+        writer.write_all(plaintext).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(keyring: &Keyring, ciphertext: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut reader = decrypt_stream(ciphertext, keyring)?;
+        let mut plaintext = Vec::new();
+        reader.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    fn encryption_error(error: &std::io::Error) -> Option<&EncryptionError> {
+        error.get_ref().and_then(|error| error.downcast_ref())
+    }
+
+    mod keyring_tests {
+        use super::*;
+
+        #[test]
+        fn test_parse_disabled() {
+            let keyring = Keyring::parse(None, None, None).unwrap();
+            assert!(keyring.active_key().is_none());
+        }
+
+        #[test]
+        fn test_parse_active_key() {
+            let keyring = Keyring::parse(Some(KEY_A), Some("k1"), None).unwrap();
+            let (key_id, _) = keyring.active_key().unwrap();
+            assert_eq!(key_id, "k1");
+            assert!(keyring.key("k1").is_ok());
+        }
+
+        #[test]
+        fn test_parse_old_keys_for_rotation() {
+            let old_keys = format!("k0:{KEY_B}, k-1:{KEY_A}");
+            let keyring = Keyring::parse(Some(KEY_A), Some("k1"), Some(&old_keys)).unwrap();
+            assert_eq!(keyring.active_key().unwrap().0, "k1");
+            assert!(keyring.key("k0").is_ok());
+            assert!(keyring.key("k-1").is_ok());
+        }
+
+        #[test]
+        fn test_parse_old_keys_only() {
+            // 暗号化を止めた後も、既存キャッシュの復号はできる
+            let keyring = Keyring::parse(None, None, Some(&format!("k0:{KEY_B}"))).unwrap();
+            assert!(keyring.active_key().is_none());
+            assert!(keyring.key("k0").is_ok());
+        }
+
+        #[test]
+        fn test_parse_missing_key_id() {
+            let result = Keyring::parse(Some(KEY_A), None, None);
+            assert!(matches!(result, Err(EncryptionError::MissingKeyId)));
+        }
+
+        #[test]
+        fn test_parse_missing_key() {
+            let result = Keyring::parse(None, Some("k1"), None);
+            assert!(matches!(result, Err(EncryptionError::MissingKey)));
+        }
+
+        #[test]
+        fn test_parse_invalid_key_length() {
+            let result = Keyring::parse(Some("0011"), Some("k1"), None);
+            assert!(matches!(result, Err(EncryptionError::InvalidKey { .. })));
+        }
+
+        #[test]
+        fn test_parse_invalid_old_key_entry() {
+            let result = Keyring::parse(None, None, Some(KEY_A));
+            assert!(matches!(
+                result,
+                Err(EncryptionError::InvalidOldKeyEntry { index: 1 })
+            ));
+        }
+
+        #[test]
+        fn test_parse_duplicate_key_id() {
+            let old_keys = format!("k1:{KEY_B}");
+            let result = Keyring::parse(Some(KEY_A), Some("k1"), Some(&old_keys));
+            assert!(matches!(result, Err(EncryptionError::DuplicateKeyId { .. })));
+        }
+
+        #[test]
+        fn test_unknown_key_id() {
+            let keyring = Keyring::parse(Some(KEY_A), Some("k1"), None).unwrap();
+            assert!(matches!(
+                keyring.key("k2"),
+                Err(EncryptionError::UnknownKeyId { .. })
+            ));
+        }
+
+        #[test]
+        fn test_parse_key_id_too_long() {
+            let key_id = "k".repeat(256);
+            let result = Keyring::parse(Some(KEY_A), Some(&key_id), None);
+            assert!(matches!(result, Err(EncryptionError::KeyIdTooLong { .. })));
+        }
+
+        #[test]
+        fn test_debug_does_not_leak_key() {
+            let keyring = Keyring::parse(Some(KEY_A), Some("k1"), None).unwrap();
+            let debug = format!("{keyring:?}");
+            assert!(!debug.contains(KEY_A));
+            assert!(!debug.contains("0001020304"));
+        }
+    }
+
+    mod stream_tests {
+        use super::*;
+
+        fn keyring_a() -> Keyring {
+            Keyring::parse(Some(KEY_A), Some("a"), None).unwrap()
+        }
+
+        fn header_len(key_id: &str) -> usize {
+            MAGIC.len() + 2 + key_id.len() + NONCE_PREFIX_SIZE
+        }
+
+        #[test]
+        fn test_round_trip_small() {
+            let keyring = keyring_a();
+            let ciphertext = encrypt(&keyring, b"hello cafce");
+            assert_ne!(&ciphertext[header_len("a")..], b"hello cafce");
+            assert_eq!(decrypt(&keyring, &ciphertext).unwrap(), b"hello cafce");
+        }
+
+        #[test]
+        fn test_round_trip_empty() {
+            let keyring = keyring_a();
+            let ciphertext = encrypt(&keyring, b"");
+            assert_eq!(decrypt(&keyring, &ciphertext).unwrap(), b"");
+        }
+
+        #[test]
+        fn test_round_trip_chunk_boundaries() {
+            let keyring = keyring_a();
+            for len in [CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1, CHUNK_SIZE * 3] {
+                let plaintext: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+                let ciphertext = encrypt(&keyring, &plaintext);
+                assert_eq!(decrypt(&keyring, &ciphertext).unwrap(), plaintext, "len={len}");
+            }
+        }
+
+        #[test]
+        fn test_key_selected_by_header() {
+            // ローテーション後も、ヘッダーの鍵IDで旧鍵を選んで復号できる
+            let ciphertext = encrypt(&keyring_a(), b"secret");
+            let rotated = Keyring::parse(Some(KEY_B), Some("b"), Some(&format!("a:{KEY_A}"))).unwrap();
+            assert_eq!(decrypt(&rotated, &ciphertext).unwrap(), b"secret");
+        }
+
+        #[test]
+        fn test_unknown_key_id_fails() {
+            let ciphertext = encrypt(&keyring_a(), b"secret");
+            let keyring_b = Keyring::parse(Some(KEY_B), Some("b"), None).unwrap();
+            let error = decrypt(&keyring_b, &ciphertext).unwrap_err();
+            assert!(matches!(encryption_error(&error), Some(EncryptionError::UnknownKeyId { .. })), "{error:?}");
+        }
+
+        #[test]
+        fn test_wrong_key_fails() {
+            let ciphertext = encrypt(&keyring_a(), b"secret");
+            // 同じ鍵IDで別の鍵が設定されている
+            let keyring_b = Keyring::parse(Some(KEY_B), Some("a"), None).unwrap();
+            let error = decrypt(&keyring_b, &ciphertext).unwrap_err();
+            assert!(matches!(encryption_error(&error), Some(EncryptionError::Decrypt)), "{error:?}");
+        }
+
+        #[test]
+        fn test_tampered_key_id_fails() {
+            // ヘッダーの鍵IDはAADとして認証されるため、別の鍵IDへの書き換えも検出される
+            let keyring = Keyring::parse(Some(KEY_A), Some("a"), Some(&format!("b:{KEY_A}"))).unwrap();
+            let mut ciphertext = encrypt(&keyring, b"secret");
+            ciphertext[MAGIC.len() + 2] = b'b';
+            let error = decrypt(&keyring, &ciphertext).unwrap_err();
+            assert!(matches!(encryption_error(&error), Some(EncryptionError::Decrypt)), "{error:?}");
+        }
+
+        #[test]
+        fn test_tampered_ciphertext_fails() {
+            let keyring = keyring_a();
+            let mut ciphertext = encrypt(&keyring, b"secret");
+            let last = ciphertext.len() - 1;
+            ciphertext[last] ^= 0x01;
+            assert!(decrypt(&keyring, &ciphertext).is_err());
+        }
+
+        #[test]
+        fn test_truncated_at_chunk_boundary_fails() {
+            // 最終チャンクフラグにより、チャンク境界での切り詰めも検出される
+            let keyring = keyring_a();
+            let plaintext = vec![0u8; CHUNK_SIZE * 2 + 10];
+            let ciphertext = encrypt(&keyring, &plaintext);
+            let truncated = &ciphertext[..header_len("a") + CHUNK_SIZE + TAG_SIZE];
+            assert!(decrypt(&keyring, truncated).is_err());
+        }
+
+        #[test]
+        fn test_invalid_header_fails() {
+            let keyring = keyring_a();
+            let error = decrypt(&keyring, b"CAFCEENC\x01").unwrap_err();
+            assert!(matches!(encryption_error(&error), Some(EncryptionError::InvalidHeader)), "{error:?}");
+        }
+
+        #[test]
+        fn test_plaintext_rejected_when_key_configured() {
+            // 保存先で平文のキャッシュに差し替えられても、そのまま展開しない
+            let keyring = keyring_a();
+            let error = decrypt(&keyring, b"not encrypted at all").unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+            assert!(matches!(encryption_error(&error), Some(EncryptionError::NotEncrypted)), "{error:?}");
+        }
+
+        #[test]
+        fn test_plaintext_passes_through_without_active_key() {
+            // 暗号化を止めた後（旧鍵のみ）に保存された平文のキャッシュは読める
+            let old_keys_only = Keyring::parse(None, None, Some(&format!("a:{KEY_A}"))).unwrap();
+            assert_eq!(decrypt(&old_keys_only, b"plain").unwrap(), b"plain");
+            assert_eq!(decrypt(&Keyring::default(), b"").unwrap(), b"");
+        }
+    }
+}
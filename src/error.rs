@@ -1,11 +1,335 @@
+use crate::i18n::tr;
+
 #[derive(Debug, thiserror::Error)]
 pub enum CacheKeyError {
-    #[error("ファイル数が制限を超えています: {count} > {limit}")]
+    #[error("{}", tr!("ファイル数が制限を超えています: {count} > {limit}", "too many files: {count} > {limit}"))]
     TooManyFiles { count: usize, limit: usize },
 
-    #[error("絶対パスのパターンは指定できません: {pattern}")]
+    #[error("{}", tr!("絶対パスのパターンは指定できません: {pattern}", "absolute path patterns are not allowed: {pattern}"))]
     AbsolutePathNotAllowed { pattern: String },
 
-    #[error("指定されたパターンにマッチするファイルがありません")]
+    #[error("{}", tr!("指定されたパターンにマッチするファイルがありません", "no files matched the given patterns"))]
     NoFilesMatched,
 }
+
+/// エラーの分類
+///
+/// 分類ごとに終了コードを固定し、CIのスクリプトがキャッシュミス（終了コード0）と
+/// 実際の失敗、および失敗の種類を区別できるようにする。終了コードは互換性のため変更しない。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// 設定ファイル・環境変数の誤り
+    Config,
+    /// キャッシュキーの計算（キーファイルの探索・ハッシュ計算）の失敗
+    KeyComputation,
+    /// 認証・クレデンシャル取得の失敗
+    Auth,
+    /// S3・STSとの通信の失敗
+    Network,
+    /// キャッシュの復号・整合性検証の失敗
+    Integrity,
+    /// キャッシュアーカイブの作成・展開の失敗
+    Extraction,
+}
+
+impl ErrorCategory {
+    /// 分類に対応する終了コード
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Config => 2,
+            Self::KeyComputation => 3,
+            Self::Auth => 4,
+            Self::Network => 5,
+            Self::Integrity => 6,
+            Self::Extraction => 7,
+        }
+    }
+
+    /// キャッシュ操作の失敗か（`on_error = "warn"`で警告のみとする対象か）
+    ///
+    /// 設定の誤り・キャッシュキーの計算の失敗は、キャッシュの有無に関わらず
+    /// 結果が変わらないため対象外とする。
+    pub fn is_cache_failure(self) -> bool {
+        !matches!(self, Self::Config | Self::KeyComputation)
+    }
+}
+
+/// cafceのコマンド全体で扱うエラー
+#[derive(Debug, thiserror::Error)]
+pub enum CafceError {
+    #[error("{}", tr!("環境変数の設定が不正です: {}", "invalid environment variable: {}", .0))]
+    Env(#[from] envy::Error),
+    #[error(transparent)]
+    Setting(#[from] crate::setting::SettingError),
+    #[error(transparent)]
+    ObjectKey(#[from] crate::object_key::ObjectKeyError),
+    #[error(transparent)]
+    SessionPolicy(#[from] crate::session_policy::SessionPolicyError),
+    #[error("{}", tr!("カレントディレクトリを取得できません", "cannot determine the current directory"))]
+    CurrentDir(#[source] std::io::Error),
+    #[error("{}", tr!("キャッシュキーの計算に失敗しました: {:#}", "failed to compute the cache key: {:#}", .0))]
+    CacheKey(#[source] anyhow::Error),
+    #[error(transparent)]
+    BuildClient(#[from] crate::s3_client::BuildClientError),
+    #[error(transparent)]
+    Encryption(#[from] crate::encryption::EncryptionError),
+    #[error(transparent)]
+    Storage(#[from] crate::storage::StorageError),
+    #[error(transparent)]
+    Transfer(#[from] crate::transfer::TransferError),
+    #[error(transparent)]
+    Catalog(#[from] crate::catalog::CatalogError),
+    #[error(transparent)]
+    Retention(#[from] crate::retention::RetentionError),
+    #[error(transparent)]
+    Inspect(#[from] crate::inspect::InspectError),
+    #[error("{}", tr!("非同期ランタイムの起動に失敗しました", "failed to start the async runtime"))]
+    Runtime(#[source] std::io::Error),
+    #[error("{}", tr!("レポートの書き込みに失敗しました: {}", "failed to write the report: {}", .path.display()))]
+    WriteReport {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Dotenv(#[from] crate::report::DotenvError),
+}
+
+impl CafceError {
+    /// エラーの分類を取得する
+    pub fn category(&self) -> ErrorCategory {
+        use crate::encryption::EncryptionError;
+        use crate::storage::StorageError;
+        use crate::transfer::TransferError;
+        match self {
+            Self::Env(_)
+            | Self::Setting(_)
+            | Self::ObjectKey(_)
+            | Self::SessionPolicy(_)
+            | Self::Retention(_)
+            | Self::WriteReport { .. }
+            | Self::Dotenv(_) => ErrorCategory::Config,
+            // ランタイムの起動失敗はキャッシュの有無と無関係な実行環境の問題のため、警告扱いにしない
+            Self::Runtime(_) => ErrorCategory::Config,
+            Self::CurrentDir(_) | Self::CacheKey(_) => ErrorCategory::KeyComputation,
+            Self::BuildClient(error) if error.is_runtime_error() => ErrorCategory::Auth,
+            Self::BuildClient(_) => ErrorCategory::Config,
+            Self::Encryption(
+                EncryptionError::InvalidHeader
+                | EncryptionError::NotEncrypted
+                | EncryptionError::Decrypt
+                | EncryptionError::TooManyChunks
+                | EncryptionError::UnknownKeyId { .. },
+            ) => ErrorCategory::Integrity,
+            Self::Encryption(_) => ErrorCategory::Config,
+            Self::Storage(error) if error.is_config_error() => ErrorCategory::Config,
+            // クレデンシャルは最初のS3リクエストの際に取得するため、STSのエラーは保存先のエラーとして返る
+            Self::Storage(StorageError::Credentials { .. })
+            | Self::Transfer(TransferError::Storage(StorageError::Credentials { .. })) => ErrorCategory::Auth,
+            Self::Storage(_) => ErrorCategory::Network,
+            Self::Transfer(error) if error.is_config_error() => ErrorCategory::Config,
+            Self::Transfer(error) if error.is_integrity_error() => ErrorCategory::Integrity,
+            Self::Transfer(TransferError::Storage(_)) => ErrorCategory::Network,
+            Self::Transfer(_) => ErrorCategory::Extraction,
+            Self::Catalog(error) if error.is_config_error() => ErrorCategory::Config,
+            Self::Catalog(_) => ErrorCategory::Network,
+            Self::Inspect(error) if error.is_integrity_error() => ErrorCategory::Integrity,
+            Self::Inspect(crate::inspect::InspectError::Download { .. }) => ErrorCategory::Network,
+            Self::Inspect(crate::inspect::InspectError::Archive { .. }) => ErrorCategory::Extraction,
+        }
+    }
+
+    /// 終了コードを取得する
+    pub fn exit_code(&self) -> i32 {
+        self.category().exit_code()
+    }
+
+    /// 原因（source）を含めたメッセージを組み立てる
+    ///
+    /// メッセージに既に含まれている原因は重複して出力しない。
+    pub fn message_with_causes(&self) -> String {
+        use std::error::Error;
+        let mut message = self.to_string();
+        let mut source = self.source();
+        while let Some(cause) = source {
+            let cause_message = cause.to_string();
+            if !message.contains(&cause_message) {
+                message.push_str(": ");
+                message.push_str(&cause_message);
+            }
+            source = cause.source();
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::EncryptionError;
+    use crate::env::CredentialSource;
+    use crate::s3_client::BuildClientError;
+
+    #[test]
+    fn test_exit_codes_are_stable() {
+        assert_eq!(ErrorCategory::Config.exit_code(), 2);
+        assert_eq!(ErrorCategory::KeyComputation.exit_code(), 3);
+        assert_eq!(ErrorCategory::Auth.exit_code(), 4);
+        assert_eq!(ErrorCategory::Network.exit_code(), 5);
+        assert_eq!(ErrorCategory::Integrity.exit_code(), 6);
+        assert_eq!(ErrorCategory::Extraction.exit_code(), 7);
+    }
+
+    #[test]
+    fn test_build_client_error_category() {
+        let error = CafceError::from(BuildClientError::AssumeRole("dispatch failure".to_string()));
+        assert_eq!(error.category(), ErrorCategory::Auth);
+        let error = CafceError::from(BuildClientError::Credentials {
+            credential_source: CredentialSource::Static,
+            message: "expired".to_string(),
+        });
+        assert_eq!(error.category(), ErrorCategory::Auth);
+        let error = CafceError::from(BuildClientError::InvalidMaxAttempts);
+        assert_eq!(error.category(), ErrorCategory::Config);
+        // 最初のS3リクエストで返ったクレデンシャルの取得の失敗
+        let error = CafceError::from(crate::storage::StorageError::Credentials {
+            object_key: "app/deps".to_string(),
+            message: "AssumeRole failed".to_string(),
+        });
+        assert_eq!(error.category(), ErrorCategory::Auth);
+    }
+
+    #[test]
+    fn test_encryption_error_category() {
+        assert_eq!(
+            CafceError::from(EncryptionError::Decrypt).category(),
+            ErrorCategory::Integrity
+        );
+        assert_eq!(
+            CafceError::from(EncryptionError::MissingKeyId).category(),
+            ErrorCategory::Config
+        );
+    }
+
+    #[test]
+    fn test_catalog_error_category() {
+        use crate::catalog::CatalogError;
+        assert_eq!(
+            CafceError::from(CatalogError::MissingBucket).category(),
+            ErrorCategory::Config
+        );
+        let error = CatalogError::UnsupportedStorage {
+            url: "file:///mnt/cache".to_string(),
+        };
+        assert_eq!(CafceError::from(error).category(), ErrorCategory::Config);
+        let error = CatalogError::List {
+            bucket: "b".to_string(),
+            prefix: "group/app/".to_string(),
+            message: "dispatch failure".to_string(),
+        };
+        assert_eq!(CafceError::from(error).category(), ErrorCategory::Network);
+    }
+
+    #[test]
+    fn test_inspect_error_category() {
+        use crate::inspect::InspectError;
+        let error = InspectError::Download {
+            object_key: "o".to_string(),
+            message: "connection reset".to_string(),
+        };
+        assert_eq!(CafceError::from(error).category(), ErrorCategory::Network);
+        let error = InspectError::Archive {
+            object_key: "o".to_string(),
+            source: std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "eof"),
+        };
+        assert_eq!(CafceError::from(error).category(), ErrorCategory::Extraction);
+        let error = InspectError::Archive {
+            object_key: "o".to_string(),
+            source: crate::encryption::EncryptionError::Decrypt.into(),
+        };
+        assert_eq!(CafceError::from(error).category(), ErrorCategory::Integrity);
+    }
+
+    #[test]
+    fn test_transfer_error_category() {
+        use crate::transfer::TransferError;
+        let error = TransferError::InvalidPath {
+            pattern: "../outside".to_string(),
+        };
+        assert_eq!(CafceError::from(error).category(), ErrorCategory::Config);
+        let error = TransferError::Storage(crate::storage::StorageError::Put {
+            object_key: "o".to_string(),
+            message: "connection reset".to_string(),
+        });
+        assert_eq!(CafceError::from(error).category(), ErrorCategory::Network);
+        let error = TransferError::Extract {
+            object_key: "o".to_string(),
+            source: std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "eof"),
+        };
+        assert_eq!(CafceError::from(error).category(), ErrorCategory::Extraction);
+        let error = TransferError::Extract {
+            object_key: "o".to_string(),
+            source: crate::encryption::EncryptionError::Decrypt.into(),
+        };
+        assert_eq!(CafceError::from(error).category(), ErrorCategory::Integrity);
+    }
+
+    #[test]
+    fn test_runtime_error_category() {
+        let error = CafceError::Runtime(std::io::Error::other("too many open files"));
+        assert_eq!(error.category(), ErrorCategory::Config);
+        assert!(!error.category().is_cache_failure());
+    }
+
+    #[test]
+    fn test_cache_key_error_category() {
+        let error = CafceError::CacheKey(CacheKeyError::NoFilesMatched.into());
+        assert_eq!(error.category(), ErrorCategory::KeyComputation);
+        assert!(!error.category().is_cache_failure());
+        assert!(error.to_string().contains("指定されたパターンにマッチするファイルがありません"));
+    }
+
+    #[test]
+    fn test_message_with_causes() {
+        let error = CafceError::CurrentDir(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "gone",
+        ));
+        assert_eq!(error.message_with_causes(), "カレントディレクトリを取得できません: gone");
+
+        // メッセージに含まれている原因は繰り返さない
+        let error = CafceError::from(EncryptionError::Decrypt);
+        assert_eq!(error.message_with_causes(), EncryptionError::Decrypt.to_string());
+    }
+
+    #[test]
+    fn test_english_messages() {
+        use crate::i18n::{with_lang_for_test, Lang};
+        let error = CafceError::CacheKey(CacheKeyError::TooManyFiles { count: 60, limit: 50 }.into());
+        let (message, exit_code) =
+            with_lang_for_test(Lang::En, || (error.message_with_causes(), error.exit_code()));
+        assert_eq!(message, "failed to compute the cache key: too many files: 60 > 50");
+        // 言語によって分類・終了コードは変わらない
+        assert_eq!(exit_code, error.exit_code());
+        assert!(error
+            .message_with_causes()
+            .starts_with("キャッシュキーの計算に失敗しました: ファイル数が制限を超えています"));
+
+        let error = CafceError::from(BuildClientError::InvalidSse("kms".to_string()));
+        let message = with_lang_for_test(Lang::En, || error.to_string());
+        assert_eq!(
+            message,
+            "invalid CAFCE_AWS_SSE (expected \"AES256\" or \"aws:kms\"): kms"
+        );
+    }
+
+    #[test]
+    fn test_is_cache_failure() {
+        assert!(!ErrorCategory::Config.is_cache_failure());
+        assert!(ErrorCategory::Auth.is_cache_failure());
+        assert!(ErrorCategory::Network.is_cache_failure());
+        assert!(ErrorCategory::Integrity.is_cache_failure());
+        assert!(ErrorCategory::Extraction.is_cache_failure());
+    }
+}
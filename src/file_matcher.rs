@@ -1,4 +1,4 @@
-const MAX_FILES: usize = 50;
+pub const MAX_FILES: usize = 50;
 
 pub struct FileMatcher {
     max_files: usize,
@@ -21,7 +21,13 @@ impl FileMatcher {
         base_path: &std::path::Path,
     ) -> anyhow::Result<std::vec::Vec<std::path::PathBuf>> {
         use anyhow::Context;
-        
+
+        let span = tracing::info_span!(
+            "cafce.match_files",
+            patterns = patterns.len(),
+            files = tracing::field::Empty
+        );
+        let _entered = span.enter();
         let mut all_files = std::collections::HashSet::new();
         
         for pattern in patterns {
@@ -42,7 +48,9 @@ impl FileMatcher {
 
             // globパターンでファイルを検索
             let glob_result = glob::glob(&full_pattern)
-                .with_context(|| format!("パターンマッチングに失敗しました: {pattern}"))?;
+                .with_context(|| {
+                    crate::i18n::tr!("パターンマッチングに失敗しました: {pattern}", "failed to match pattern: {pattern}")
+                })?;
             
             // OKの結果のみを取得し、ファイルのみをフィルタリング
             for path in glob_result.filter_map(Result::ok) {
@@ -57,6 +65,8 @@ impl FileMatcher {
             }
         }
         
+        span.record("files", all_files.len());
+
         // ファイル数制限チェック
         if all_files.len() > self.max_files {
             return Err(crate::error::CacheKeyError::TooManyFiles {
@@ -0,0 +1,821 @@
+//! GitLabの汎用パッケージレジストリの保存先（`gitlab://`）
+//!
+//! バケットを用意していないプロジェクトでも、プロジェクトのパッケージレジストリにキャッシュを保存できる。
+//! キャッシュは`packages/generic/cafce/<キャッシュ名>/<キャッシュキー>.<拡張子>`として、
+//! パッケージ名`cafce`・バージョンにキャッシュ名を使用した汎用パッケージのファイルとして保存する。
+//! APIのベースURL（CI_API_V4_URL）・プロジェクト（CI_PROJECT_ID）・認証（CI_JOB_TOKEN）は
+//! GitLab CIの定義済み変数から取得する。
+//!
+//! 同じ名前のファイルをアップロードすると同じパッケージにファイルが追加されるため、
+//! アップロード後に古いファイルを削除する。ETagにはパッケージファイルのSHA-256を使用する。
+//! パッケージファイルにはメタデータを保存できないため、メタデータは同じ名前に`SIDECAR_EXTENSION`を
+//! 付けたJSONファイル（サイドカー）として同じパッケージに保存する。
+//! 世代はバージョン`cafce-generation`に保存するため、このキャッシュ名は使用できない。
+
+use crate::env::{CiEnv, Env};
+use crate::http_client::PlainHttpClient;
+use crate::http_storage::{self, HttpStorage, REQUEST_TIMEOUT};
+use crate::i18n::tr;
+use crate::object_key::{ObjectKeyContext, ObjectKeyTemplate};
+use crate::storage::{ObjectMetadata, Storage, StorageError, StorageFuture, SIDECAR_EXTENSION};
+use aws_smithy_types::body::SdkBody;
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH};
+use http::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use url::Url;
+
+/// キャッシュを保存する汎用パッケージの名前
+pub const PACKAGE_NAME: &str = "cafce";
+
+/// キャッシュの世代を保存するパッケージのバージョン・ファイル名
+///
+/// キャッシュのパッケージのバージョンと重ならないよう、このキャッシュ名は使用できない。
+const GENERATION_PACKAGE_VERSION: &str = "cafce-generation";
+const GENERATION_FILE_NAME: &str = "generation";
+
+/// パッケージ・パッケージファイルの一覧を1ページあたりに取得する件数（APIの上限）
+const PER_PAGE: usize = 100;
+
+/// パッケージ（`GET /projects/:id/packages`）
+#[derive(Debug, Deserialize)]
+struct Package {
+    id: u64,
+    name: String,
+    version: String,
+}
+
+/// パッケージファイル（`GET /projects/:id/packages/:package_id/package_files`）
+#[derive(Debug, Clone, Deserialize)]
+struct PackageFile {
+    id: u64,
+    /// 一覧の応答には含まれないため、一覧の取得時に設定する
+    #[serde(default)]
+    package_id: u64,
+    file_name: String,
+    #[serde(default)]
+    size: u64,
+    created_at: Option<String>,
+    file_sha256: Option<String>,
+    file_md5: Option<String>,
+}
+
+impl PackageFile {
+    fn etag(&self) -> Option<String> {
+        self.file_sha256
+            .as_deref()
+            .or(self.file_md5.as_deref())
+            .map(|checksum| format!("\"{checksum}\""))
+    }
+
+    fn object_metadata(&self, metadata: HashMap<String, String>) -> ObjectMetadata {
+        ObjectMetadata {
+            size: self.size,
+            etag: self.etag(),
+            last_modified: self
+                .created_at
+                .as_deref()
+                .and_then(|created_at| DateTime::from_str(created_at, Format::DateTime).ok()),
+            metadata,
+        }
+    }
+}
+
+/// サイドカーファイルの内容
+///
+/// キャッシュのファイルとサイドカーは別々にアップロードするため、置き換えの途中では新旧が食い違うことがある。
+/// メタデータを記録したファイルのSHA-256も保存し、一致しない場合はメタデータなしとして扱う。
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Sidecar {
+    file_sha256: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+/// パッケージファイルに対応するサイドカーのファイル名
+fn sidecar_file_name(file_name: &str) -> String {
+    format!("{file_name}{SIDECAR_EXTENSION}")
+}
+
+/// GitLabの汎用パッケージレジストリの保存先
+pub struct GitLabStorage {
+    /// APIのベースURL（CI_API_V4_URL）に対してリクエストを送信する
+    http: HttpStorage,
+    project_id: String,
+    template: ObjectKeyTemplate,
+    project: Option<String>,
+    /// テンプレートに`{cache_name}`がない場合のキャッシュ名
+    cache_name: String,
+    /// 世代を保存するオブジェクトのキー（テンプレートに`{project}`のプレフィックスがない場合はNone）
+    generation_object_key: Option<String>,
+}
+
+impl GitLabStorage {
+    pub fn new(
+        env: &Env,
+        ci: &CiEnv,
+        template: ObjectKeyTemplate,
+        context: &ObjectKeyContext,
+    ) -> Result<Self, StorageError> {
+        let missing = |name| StorageError::MissingCiVariable { name };
+        let api_url = ci.api_v4_url().ok_or_else(|| missing("CI_API_V4_URL"))?;
+        let project_id = ci.project_id().ok_or_else(|| missing("CI_PROJECT_ID"))?;
+        let job_token = ci.job_token().ok_or_else(|| missing("CI_JOB_TOKEN"))?;
+        reject_reserved_cache_name(context.cache_name)?;
+        let base_url = Url::parse(api_url).map_err(|e| StorageError::InvalidUrl {
+            url: api_url.to_string(),
+            message: e.to_string(),
+        })?;
+        let mut job_token = HeaderValue::try_from(job_token).map_err(|_| missing("CI_JOB_TOKEN"))?;
+        job_token.set_sensitive(true);
+        let client = PlainHttpClient::new(env, REQUEST_TIMEOUT).map_err(StorageError::HttpClient)?;
+        let generation_object_key = template
+            .has_project_prefix()
+            .then(|| template.generation_object_key(context))
+            .transpose()?;
+        Ok(Self {
+            http: HttpStorage::with_client(
+                client,
+                base_url,
+                Some((HeaderName::from_static("job-token"), job_token)),
+            ),
+            project_id: project_id.to_string(),
+            template,
+            project: context.project.map(String::from),
+            cache_name: context.cache_name.to_string(),
+            generation_object_key,
+        })
+    }
+
+    /// オブジェクトキーに対応するパッケージのバージョン（キャッシュ名）とファイル名
+    ///
+    /// オブジェクトキーをテンプレートと照合してキャッシュ名・キャッシュキーを取り出す。
+    fn package_file(&self, object_key: &str) -> Result<(String, String), StorageError> {
+        if self.generation_object_key.as_deref() == Some(object_key) {
+            return Ok((GENERATION_PACKAGE_VERSION.to_string(), GENERATION_FILE_NAME.to_string()));
+        }
+        let parsed = self
+            .template
+            .match_object_key(self.project.as_deref(), object_key)
+            .ok_or_else(|| StorageError::InvalidObjectKey {
+                object_key: object_key.to_string(),
+            })?;
+        let file_name = match parsed.codec {
+            Some(codec) => format!("{}.{}", parsed.key, codec.extension()),
+            None => parsed.key,
+        };
+        let cache_name = parsed.cache_name.unwrap_or_else(|| self.cache_name.clone());
+        reject_reserved_cache_name(&cache_name)?;
+        Ok((cache_name, file_name))
+    }
+
+    /// パッケージファイルのダウンロード・アップロードのURL
+    fn file_url(&self, version: &str, file_name: &str) -> Result<Url, StorageError> {
+        self.http.url_for([
+            "projects",
+            &self.project_id,
+            "packages",
+            "generic",
+            PACKAGE_NAME,
+            version,
+            file_name,
+        ])
+    }
+
+    /// 一覧のAPIの全てのページを取得する（404の場合は空とする）
+    async fn get_pages<T: DeserializeOwned>(
+        &self,
+        url: Url,
+        error: impl Fn(String) -> StorageError + Copy,
+    ) -> Result<Vec<T>, StorageError> {
+        let mut items = Vec::new();
+        for page in 1.. {
+            let mut page_url = url.clone();
+            page_url
+                .query_pairs_mut()
+                .append_pair("per_page", &PER_PAGE.to_string())
+                .append_pair("page", &page.to_string());
+            let response = self
+                .http
+                .send(Method::GET, &page_url, HeaderMap::new(), SdkBody::empty(), error)
+                .await?;
+            let mut body = Vec::new();
+            if http_storage::read_object(response, &mut body, error).await?.is_none() {
+                break;
+            }
+            let page_items: Vec<T> = serde_json::from_slice(&body).map_err(|e| error(e.to_string()))?;
+            let is_last = page_items.len() < PER_PAGE;
+            items.extend(page_items);
+            if is_last {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// パッケージ内の`file_name`のファイルを、アップロードの古い順に取得する
+    async fn package_files(
+        &self,
+        version: &str,
+        file_name: &str,
+        error: impl Fn(String) -> StorageError + Copy,
+    ) -> Result<Vec<PackageFile>, StorageError> {
+        let mut url = self.http.url_for(["projects", &self.project_id, "packages"])?;
+        url.query_pairs_mut()
+            .append_pair("package_type", "generic")
+            .append_pair("package_name", PACKAGE_NAME)
+            .append_pair("package_version", version);
+        // package_nameは部分一致のため、名前・バージョンが一致するものに絞り込む
+        let packages: Vec<Package> = self.get_pages(url, error).await?;
+        let mut files = Vec::new();
+        for package in packages
+            .iter()
+            .filter(|package| package.name == PACKAGE_NAME && package.version == version)
+        {
+            let package_id = package.id.to_string();
+            let url = self.http.url_for([
+                "projects",
+                &self.project_id,
+                "packages",
+                &package_id,
+                "package_files",
+            ])?;
+            let package_files: Vec<PackageFile> = self.get_pages(url, error).await?;
+            files.extend(
+                package_files
+                    .into_iter()
+                    .filter(|file| file.file_name == file_name)
+                    .map(|file| PackageFile {
+                        package_id: package.id,
+                        ..file
+                    }),
+            );
+        }
+        files.sort_by_key(|file| file.id);
+        Ok(files)
+    }
+
+    async fn delete_package_file(
+        &self,
+        file: &PackageFile,
+        error: impl Fn(String) -> StorageError + Copy,
+    ) -> Result<(), StorageError> {
+        let url = self.http.url_for([
+            "projects",
+            &self.project_id,
+            "packages",
+            &file.package_id.to_string(),
+            "package_files",
+            &file.id.to_string(),
+        ])?;
+        let response = self
+            .http
+            .send(Method::DELETE, &url, HeaderMap::new(), SdkBody::empty(), error)
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(()),
+            status => http_storage::check_status(status, error),
+        }
+    }
+
+    /// サイドカーからパッケージファイルのメタデータを読み込む
+    ///
+    /// サイドカーがない場合、または別の内容のファイルのサイドカーの場合は空とする。
+    async fn read_metadata(
+        &self,
+        version: &str,
+        file: &PackageFile,
+        error: impl Fn(String) -> StorageError + Copy,
+    ) -> Result<HashMap<String, String>, StorageError> {
+        let url = self.file_url(version, &sidecar_file_name(&file.file_name))?;
+        let response = self
+            .http
+            .send(Method::GET, &url, HeaderMap::new(), SdkBody::empty(), error)
+            .await?;
+        let mut body = Vec::new();
+        if http_storage::read_object(response, &mut body, error).await?.is_none() {
+            return Ok(HashMap::new());
+        }
+        let sidecar: Sidecar = serde_json::from_slice(&body).map_err(|e| error(e.to_string()))?;
+        let matches = sidecar.file_sha256.is_some() && sidecar.file_sha256 == file.file_sha256;
+        Ok(if matches { sidecar.metadata } else { HashMap::new() })
+    }
+
+    /// 最後にアップロードされたファイルの情報を取得する（ダウンロードではこのファイルが返される）
+    async fn head_file(&self, object_key: &str) -> Result<Option<ObjectMetadata>, StorageError> {
+        let error = |message| StorageError::Head {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let (version, file_name) = self.package_file(object_key)?;
+        let files = self.package_files(&version, &file_name, error).await?;
+        let Some(file) = files.last() else {
+            return Ok(None);
+        };
+        let metadata = self.read_metadata(&version, file, error).await?;
+        Ok(Some(file.object_metadata(metadata)))
+    }
+
+    /// 最後にアップロードされたファイルをダウンロードする
+    ///
+    /// ダウンロードのAPIはファイル名で指定するため、一覧の取得後に新しいファイルがアップロードされると
+    /// 一覧とは別のファイルが返される。ダウンロードした本文のSHA-256と一致するファイルの情報を返し、
+    /// 一覧に見つからない場合は一覧を取り直す。
+    async fn get_file(
+        &self,
+        object_key: &str,
+        destination: &mut (dyn Write + Send),
+    ) -> Result<Option<ObjectMetadata>, StorageError> {
+        let error = |message| StorageError::Get {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let (version, file_name) = self.package_file(object_key)?;
+        let files = self.package_files(&version, &file_name, error).await?;
+        if files.is_empty() {
+            return Ok(None);
+        }
+        let url = self.file_url(&version, &file_name)?;
+        let response = self
+            .http
+            .send(Method::GET, &url, HeaderMap::new(), SdkBody::empty(), error)
+            .await?;
+        let mut writer = Sha256Writer {
+            destination,
+            hasher: Sha256::new(),
+        };
+        if http_storage::read_object(response, &mut writer, error).await?.is_none() {
+            return Ok(None);
+        }
+        let checksum = hex::encode(writer.hasher.finalize());
+        let find = |files: Vec<PackageFile>| {
+            files
+                .into_iter()
+                .rfind(|file| file.file_sha256.as_deref() == Some(checksum.as_str()))
+        };
+        let file = match find(files) {
+            Some(file) => file,
+            None => find(self.package_files(&version, &file_name, error).await?).ok_or_else(|| {
+                error(tr!(
+                    "ダウンロードしたファイルがパッケージファイルの一覧にありません（SHA-256: {checksum}）",
+                    "the downloaded file is not in the package file list (SHA-256: {checksum})"
+                ))
+            })?,
+        };
+        let metadata = self.read_metadata(&version, &file, error).await?;
+        Ok(Some(file.object_metadata(metadata)))
+    }
+
+    /// メタデータがあれば、キャッシュのファイルのアップロード後にサイドカーをアップロードする
+    async fn put_file(
+        &self,
+        object_key: &str,
+        source: &Path,
+        metadata: &HashMap<String, String>,
+    ) -> Result<Option<String>, StorageError> {
+        let error = |message| StorageError::Put {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let (version, file_name) = self.package_file(object_key)?;
+        let (headers, body) = http_storage::file_body(source).await?;
+        let uploaded = self.upload_package_file(&version, &file_name, headers, body, error).await?;
+        if !metadata.is_empty() {
+            let sidecar = Sidecar {
+                file_sha256: uploaded.file_sha256.clone(),
+                metadata: metadata.clone(),
+            };
+            let contents = serde_json::to_vec(&sidecar).expect("sidecar must be serializable");
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(contents.len()));
+            self.upload_package_file(&version, &sidecar_file_name(&file_name), headers, SdkBody::from(contents), error)
+                .await?;
+        }
+        Ok(uploaded.etag())
+    }
+
+    /// パッケージファイルをアップロードし、同じ名前の古いファイルを削除する
+    async fn upload_package_file(
+        &self,
+        version: &str,
+        file_name: &str,
+        headers: HeaderMap,
+        body: SdkBody,
+        error: impl Fn(String) -> StorageError + Copy,
+    ) -> Result<PackageFile, StorageError> {
+        let mut url = self.file_url(version, file_name)?;
+        url.query_pairs_mut().append_pair("select", "package_file");
+        let response = self.http.send(Method::PUT, &url, headers, body, error).await?;
+        let mut response_body = Vec::new();
+        if http_storage::read_object(response, &mut response_body, error)
+            .await?
+            .is_none()
+        {
+            return Err(error(http_storage::status_message(StatusCode::NOT_FOUND)));
+        }
+        let uploaded: PackageFile = serde_json::from_slice(&response_body).map_err(|e| error(e.to_string()))?;
+
+        // 同じ名前の古いファイルを削除し、アップロードしたファイルだけを残す
+        let files = self.package_files(version, file_name, error).await?;
+        for file in files.iter().filter(|file| file.id < uploaded.id) {
+            self.delete_package_file(file, error).await?;
+        }
+        Ok(uploaded)
+    }
+
+    async fn delete_file(&self, object_key: &str) -> Result<(), StorageError> {
+        let error = |message| StorageError::Delete {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let (version, file_name) = self.package_file(object_key)?;
+        for file_name in [sidecar_file_name(&file_name), file_name] {
+            for file in self.package_files(&version, &file_name, error).await? {
+                self.delete_package_file(&file, error).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 世代を保存するバージョンと同じキャッシュ名を拒否する
+fn reject_reserved_cache_name(cache_name: &str) -> Result<(), StorageError> {
+    if cache_name == GENERATION_PACKAGE_VERSION {
+        return Err(StorageError::ReservedCacheName {
+            cache_name: cache_name.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// ダウンロードした本文を書き込みながら、SHA-256を計算する
+struct Sha256Writer<'a> {
+    destination: &'a mut (dyn Write + Send),
+    hasher: Sha256,
+}
+
+impl Write for Sha256Writer<'_> {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        let len = self.destination.write(buffer)?;
+        self.hasher.update(&buffer[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.destination.flush()
+    }
+}
+
+impl Storage for GitLabStorage {
+    fn head<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        Box::pin(self.head_file(object_key))
+    }
+
+    fn get<'a>(
+        &'a self,
+        object_key: &'a str,
+        destination: &'a mut (dyn Write + Send),
+    ) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        Box::pin(self.get_file(object_key, destination))
+    }
+
+    fn put<'a>(
+        &'a self,
+        object_key: &'a str,
+        source: &'a Path,
+        metadata: &'a HashMap<String, String>,
+    ) -> StorageFuture<'a, Option<String>> {
+        Box::pin(self.put_file(object_key, source, metadata))
+    }
+
+    fn delete<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(self.delete_file(object_key))
+    }
+
+    /// 記録のたびにサイドカーをアップロードし直すことになるため、記録しない
+    fn record_access<'a>(&'a self, _object_key: &'a str, _now: i64) -> StorageFuture<'a, bool> {
+        Box::pin(async { Ok(false) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_key::Codec;
+    use crate::test_util::{MockResponse, MockServer, RecordedRequest};
+
+    const OBJECT_KEY: &str = "group/app/node_modules/v1/deps-protected.tar.zst";
+    const FILE_PATH: &str = "/api/v4/projects/42/packages/generic/cafce/node_modules/deps-protected.tar.zst";
+    /// ダウンロードで返す本文（"hello"）のSHA-256
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn gitlab_storage(server: &MockServer) -> GitLabStorage {
+        let ci = CiEnv::default()
+            .with_pipeline_for_test("group/app", "1")
+            .with_gitlab_api_for_test(&format!("{}/api/v4", server.url()), "42", "job-token-value");
+        let context = ObjectKeyContext {
+            project: Some("group/app"),
+            cache_name: "node_modules",
+            codec: Codec::Zstd,
+        };
+        GitLabStorage::new(&Env::default(), &ci, ObjectKeyTemplate::default(), &context).unwrap()
+    }
+
+    /// パッケージ7に、ファイル1（古い）・ファイル2（新しい、本文は"hello"）・別名のファイル3がある状態を返す
+    fn registry(request: &RecordedRequest) -> MockResponse {
+        let path = request.path.split('?').next().unwrap();
+        match (request.method.as_str(), path) {
+            ("GET", "/api/v4/projects/42/packages") => MockResponse::new(
+                200,
+                r#"[{"id":7,"name":"cafce","version":"node_modules"},{"id":8,"name":"cafce-other","version":"node_modules"}]"#,
+            ),
+            ("GET", "/api/v4/projects/42/packages/7/package_files") => MockResponse::new(
+                200,
+                format!(
+                    r#"[
+                    {{"id":1,"file_name":"deps-protected.tar.zst","size":3,"created_at":"2026-10-01T00:00:00.000Z","file_sha256":"old"}},
+                    {{"id":2,"file_name":"deps-protected.tar.zst","size":5,"created_at":"2026-10-02T00:00:00.000Z","file_sha256":"{HELLO_SHA256}"}},
+                    {{"id":3,"file_name":"other.tar.zst","size":1,"created_at":"2026-10-03T00:00:00.000Z","file_sha256":"other"}}
+                ]"#
+                ),
+            ),
+            ("GET", FILE_PATH) => MockResponse::new(200, "hello"),
+            ("PUT", FILE_PATH) => MockResponse::new(
+                201,
+                r#"{"id":4,"package_id":7,"file_name":"deps-protected.tar.zst","size":5,"file_sha256":"uploaded"}"#,
+            ),
+            ("DELETE", _) => MockResponse::new(204, ""),
+            _ => MockResponse::new(404, r#"{"message":"404 Not Found"}"#),
+        }
+    }
+
+    fn deleted_files(server: &MockServer) -> Vec<String> {
+        server
+            .requests()
+            .into_iter()
+            .filter(|request| request.method == "DELETE")
+            .map(|request| request.path)
+            .collect()
+    }
+
+    #[test]
+    fn test_package_file() {
+        let server = MockServer::start(registry);
+        let storage = gitlab_storage(&server);
+        assert_eq!(
+            storage.package_file(OBJECT_KEY).unwrap(),
+            ("node_modules".to_string(), "deps-protected.tar.zst".to_string())
+        );
+        assert_eq!(
+            storage.package_file("group/app/.cafce-generation").unwrap(),
+            ("cafce-generation".to_string(), "generation".to_string())
+        );
+        let result = storage.package_file("other/app/node_modules/v1/deps.tar.zst");
+        assert!(matches!(result, Err(StorageError::InvalidObjectKey { .. })), "{result:?}");
+        // 世代のバージョンと同じキャッシュ名のキーは扱わない
+        let result = storage.package_file("group/app/cafce-generation/v1/deps.tar.zst");
+        assert!(matches!(result, Err(StorageError::ReservedCacheName { .. })), "{result:?}");
+    }
+
+    #[test]
+    fn test_reserved_cache_name() {
+        let ci = CiEnv::default().with_gitlab_api_for_test("https://gitlab.example.com/api/v4", "42", "job-token-value");
+        let context = ObjectKeyContext {
+            project: Some("group/app"),
+            cache_name: "cafce-generation",
+            codec: Codec::Zstd,
+        };
+        let result = GitLabStorage::new(&Env::default(), &ci, ObjectKeyTemplate::default(), &context);
+        let error = result.err().unwrap();
+        assert!(matches!(error, StorageError::ReservedCacheName { .. }), "{error:?}");
+        assert!(error.is_config_error());
+    }
+
+    #[test]
+    fn test_missing_ci_variables() {
+        let context = ObjectKeyContext {
+            project: Some("group/app"),
+            cache_name: "node_modules",
+            codec: Codec::Zstd,
+        };
+        let result = GitLabStorage::new(&Env::default(), &CiEnv::default(), ObjectKeyTemplate::default(), &context);
+        assert!(
+            matches!(result, Err(StorageError::MissingCiVariable { name: "CI_API_V4_URL" })),
+            "{:?}",
+            result.err()
+        );
+        assert!(result.err().unwrap().is_config_error());
+    }
+
+    #[tokio::test]
+    async fn test_head_and_get() {
+        let server = MockServer::start(registry);
+        let storage = gitlab_storage(&server);
+
+        let object = storage.head(OBJECT_KEY).await.unwrap().unwrap();
+        assert_eq!(object.size, 5);
+        assert_eq!(object.etag, Some(format!("\"{HELLO_SHA256}\"")));
+        assert_eq!(
+            object.last_modified,
+            Some(DateTime::from_str("2026-10-02T00:00:00Z", Format::DateTime).unwrap())
+        );
+
+        let mut body = Vec::new();
+        assert_eq!(storage.get(OBJECT_KEY, &mut body).await.unwrap(), Some(object));
+        assert_eq!(body, b"hello");
+        let requests = server.requests();
+        let list = &requests[0];
+        assert_eq!(
+            list.path,
+            "/api/v4/projects/42/packages?package_type=generic&package_name=cafce&package_version=node_modules&per_page=100&page=1"
+        );
+        assert_eq!(list.header("job-token"), Some("job-token-value"));
+        assert!(requests.iter().any(|request| request.path == FILE_PATH));
+
+        assert_eq!(storage.head("group/app/node_modules/v1/missing.tar.zst").await.unwrap(), None);
+        assert_eq!(
+            storage
+                .get("group/app/node_modules/v1/missing.tar.zst", &mut Vec::new())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_matches_the_downloaded_file() {
+        // 最初の一覧の後にファイル2がアップロードされ、ダウンロードではファイル2が返される
+        let lists = std::sync::atomic::AtomicUsize::new(0);
+        let server = MockServer::start(move |request| {
+            let path = request.path.split('?').next().unwrap();
+            if path == "/api/v4/projects/42/packages/7/package_files"
+                && lists.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0
+            {
+                return MockResponse::new(
+                    200,
+                    r#"[{"id":1,"file_name":"deps-protected.tar.zst","size":3,"file_sha256":"old"}]"#,
+                );
+            }
+            registry(request)
+        });
+        let storage = gitlab_storage(&server);
+
+        let mut body = Vec::new();
+        let object = storage.get(OBJECT_KEY, &mut body).await.unwrap().unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(object.size, 5);
+        assert_eq!(object.etag, Some(format!("\"{HELLO_SHA256}\"")));
+    }
+
+    #[tokio::test]
+    async fn test_put_replaces_older_files() {
+        let server = MockServer::start(registry);
+        let storage = gitlab_storage(&server);
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("archive");
+        std::fs::write(&source, b"hello").unwrap();
+
+        let etag = storage.put(OBJECT_KEY, &source, &HashMap::new()).await.unwrap();
+        assert_eq!(etag.as_deref(), Some("\"uploaded\""));
+        let put = server.requests().into_iter().find(|request| request.method == "PUT").unwrap();
+        assert_eq!(put.path, format!("{FILE_PATH}?select=package_file"));
+        assert_eq!(put.body, b"hello");
+        assert_eq!(put.header("job-token"), Some("job-token-value"));
+        assert_eq!(
+            deleted_files(&server),
+            vec![
+                "/api/v4/projects/42/packages/7/package_files/1",
+                "/api/v4/projects/42/packages/7/package_files/2"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete() {
+        let server = MockServer::start(registry);
+        let storage = gitlab_storage(&server);
+
+        storage.delete(OBJECT_KEY).await.unwrap();
+        assert_eq!(
+            deleted_files(&server),
+            vec![
+                "/api/v4/projects/42/packages/7/package_files/1",
+                "/api/v4/projects/42/packages/7/package_files/2"
+            ]
+        );
+        // 存在しないキャッシュの削除は成功とする
+        storage.delete("group/app/node_modules/v1/missing.tar.zst").await.unwrap();
+        assert!(!storage.record_access(OBJECT_KEY, 0).await.unwrap());
+    }
+
+    /// アップロードされたファイルをメモリに保持するパッケージレジストリ（パッケージ7のみ）
+    fn in_memory_registry() -> impl Fn(&RecordedRequest) -> MockResponse + Send + Sync + 'static {
+        let files = std::sync::Mutex::new(Vec::<(u64, String, Vec<u8>)>::new());
+        let generic_prefix = "/api/v4/projects/42/packages/generic/cafce/node_modules/";
+        move |request| {
+            let mut files = files.lock().unwrap();
+            let path = request.path.split('?').next().unwrap();
+            let file_json = |(id, file_name, body): &(u64, String, Vec<u8>)| {
+                serde_json::json!({
+                    "id": id,
+                    "package_id": 7,
+                    "file_name": file_name,
+                    "size": body.len(),
+                    "file_sha256": hex::encode(Sha256::digest(body)),
+                })
+            };
+            match (request.method.as_str(), path) {
+                ("GET", "/api/v4/projects/42/packages") => {
+                    MockResponse::new(200, r#"[{"id":7,"name":"cafce","version":"node_modules"}]"#)
+                }
+                ("GET", "/api/v4/projects/42/packages/7/package_files") => {
+                    let list: Vec<_> = files.iter().map(file_json).collect();
+                    MockResponse::new(200, serde_json::to_vec(&list).unwrap())
+                }
+                ("GET", path) if path.starts_with(generic_prefix) => {
+                    let file_name = &path[generic_prefix.len()..];
+                    match files.iter().rfind(|(_, name, _)| name == file_name) {
+                        Some((_, _, body)) => MockResponse::new(200, body.clone()),
+                        None => MockResponse::new(404, r#"{"message":"404 Not Found"}"#),
+                    }
+                }
+                ("PUT", path) if path.starts_with(generic_prefix) => {
+                    let id = files.iter().map(|(id, _, _)| id + 1).max().unwrap_or(1);
+                    files.push((id, path[generic_prefix.len()..].to_string(), request.body.clone()));
+                    MockResponse::new(201, file_json(files.last().unwrap()).to_string())
+                }
+                ("DELETE", path) => {
+                    let id: u64 = path.rsplit('/').next().unwrap().parse().unwrap();
+                    files.retain(|(file_id, _, _)| *file_id != id);
+                    MockResponse::new(204, "")
+                }
+                _ => MockResponse::new(404, r#"{"message":"404 Not Found"}"#),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metadata_sidecar() {
+        let server = MockServer::start(in_memory_registry());
+        let storage = gitlab_storage(&server);
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("archive");
+        std::fs::write(&source, b"hello").unwrap();
+        let metadata = HashMap::from([("cafce-cache-name".to_string(), "node_modules".to_string())]);
+
+        storage.put(OBJECT_KEY, &source, &metadata).await.unwrap();
+        let object = storage.head(OBJECT_KEY).await.unwrap().unwrap();
+        assert_eq!(object.metadata, metadata);
+        assert_eq!(object.etag, Some(format!("\"{HELLO_SHA256}\"")));
+
+        // サイドカーを残したままファイルだけが置き換えられた場合は、メタデータなしとして扱う
+        std::fs::write(&source, b"other").unwrap();
+        storage.put(OBJECT_KEY, &source, &HashMap::new()).await.unwrap();
+        let mut body = Vec::new();
+        let object = storage.get(OBJECT_KEY, &mut body).await.unwrap().unwrap();
+        assert_eq!(body, b"other");
+        assert!(object.metadata.is_empty(), "{:?}", object.metadata);
+
+        // 削除ではサイドカーも削除する
+        storage.delete(OBJECT_KEY).await.unwrap();
+        let deletes = server.requests().into_iter().filter(|request| request.method == "DELETE").count();
+        assert_eq!(deletes, 3);
+        assert_eq!(storage.head(OBJECT_KEY).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_store_and_restore_encrypted() {
+        use crate::encryption::{Keyring, KEY_ID_METADATA};
+        use crate::transfer;
+
+        let server = MockServer::start(in_memory_registry());
+        let storage = gitlab_storage(&server);
+        let source = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("node_modules/pkg")).unwrap();
+        std::fs::write(source.path().join("node_modules/pkg/index.js"), b"hello").unwrap();
+        let keyring = Keyring::parse(Some(&"11".repeat(32)), Some("k1"), None).unwrap();
+        let paths = ["node_modules".to_string()];
+
+        transfer::store(&storage, OBJECT_KEY, &paths, source.path(), Codec::Zstd, &HashMap::new(), &keyring)
+            .await
+            .unwrap();
+        let uploaded = server.requests().into_iter().find(|request| request.method == "PUT").unwrap();
+        assert!(uploaded.body.starts_with(b"CAFCEENC"));
+
+        let destination = tempfile::tempdir().unwrap();
+        let candidates = [("deps-protected".to_string(), OBJECT_KEY.to_string())];
+        let restored = transfer::restore(&storage, &candidates, Codec::Zstd, destination.path(), &keyring, 0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.object.metadata.get(KEY_ID_METADATA).map(String::as_str), Some("k1"));
+        let restored_file = destination.path().join("node_modules/pkg/index.js");
+        assert_eq!(std::fs::read(restored_file).unwrap(), b"hello");
+    }
+}
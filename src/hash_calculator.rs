@@ -7,6 +7,7 @@ impl HashCalculator {
     pub fn calculate_files_hash(files: &[std::path::PathBuf]) -> anyhow::Result<String> {
         use sha2::Digest;
 
+        let _span = tracing::info_span!("cafce.hash", files = files.len()).entered();
         if files.is_empty() {
             // 空のファイルリストの場合は空文字列のハッシュを返す
             let mut hasher = sha2::Sha256::new();
@@ -40,7 +41,13 @@ impl HashCalculator {
         use sha2::Digest;
         
         let content = std::fs::read(file)
-            .with_context(|| format!("ファイルの読み込みに失敗しました: {}", file.display()))?;
+            .with_context(|| {
+                crate::i18n::tr!(
+                    "ファイルの読み込みに失敗しました: {}",
+                    "failed to read file: {}",
+                    file.display()
+                )
+            })?;
         
         let mut hasher = sha2::Sha256::new();
         hasher.update(&content);
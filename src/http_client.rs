@@ -0,0 +1,753 @@
+//! S3・STSの通信に使用するHTTPクライアント
+//!
+//! 社内CAで署名されたエンドポイント・mTLS（クライアント証明書）・HTTPプロキシに対応する。
+//! AWS SDK標準のHTTPクライアントはクライアント証明書に対応していないため、
+//! これらが設定されている場合はhyper + rustlsで組み立てた独自のクライアントを使用する。
+
+use crate::env::Env;
+use crate::i18n::tr;
+use aws_config::timeout::TimeoutConfig;
+use aws_smithy_runtime_api::client::http::{
+    HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings, SharedHttpClient,
+    SharedHttpConnector,
+};
+use aws_smithy_runtime_api::client::orchestrator::{HttpRequest, HttpResponse};
+use aws_smithy_runtime_api::client::result::ConnectorError;
+use aws_smithy_runtime_api::client::runtime_components::RuntimeComponents;
+use aws_smithy_types::body::SdkBody;
+use hyper_util::client::legacy::connect::proxy::Tunnel;
+use hyper_util::client::legacy::connect::HttpConnector as TcpConnector;
+use hyper_util::client::proxy::matcher::Matcher;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// HTTPクライアント構築時のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum HttpClientError {
+    #[error("{}", tr!("ファイルの読み込みに失敗しました: {path}", "failed to read file: {path}"))]
+    ReadFile {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}", tr!("PEM形式の証明書が含まれていません: {path}", "no PEM certificate found: {path}"))]
+    NoCertificate { path: String },
+    #[error("{}", tr!("PEM形式の証明書の読み込みに失敗しました: {path}: {message}", "failed to load PEM certificate: {path}: {message}"))]
+    InvalidCertificate { path: String, message: String },
+    #[error("{}", tr!("PEM形式の秘密鍵の読み込みに失敗しました: {path}: {message}", "failed to load PEM private key: {path}: {message}"))]
+    InvalidPrivateKey { path: String, message: String },
+    #[error("{}", tr!("CAFCE_AWS_CLIENT_CERTとCAFCE_AWS_CLIENT_KEYは両方とも指定してください", "CAFCE_AWS_CLIENT_CERT and CAFCE_AWS_CLIENT_KEY must be set together"))]
+    IncompleteClientCertificate,
+    #[error("{}", tr!("TLSの設定に失敗しました: {}", "failed to configure TLS: {}", .0))]
+    Tls(#[from] rustls::Error),
+    #[error("{}", tr!("プロキシのURLが不正です（http://host:port形式で指定してください）: {}", "invalid proxy URL (expected http://host:port): {}", .0))]
+    InvalidProxy(String),
+}
+
+/// タイムアウトの設定を組み立てる
+///
+/// - 接続タイムアウト・読み取りタイムアウト（HTTPクライアント）
+/// - 1回の試行あたり・リトライを含めた操作全体のタイムアウト（SDKのオーケストレーター）
+///
+/// 未指定の項目はSDKデフォルトのまま（config loaderがデフォルト値で補完する）。
+pub fn timeout_config(env: &Env) -> Option<TimeoutConfig> {
+    if env.connect_timeout().is_none()
+        && env.read_timeout().is_none()
+        && env.operation_attempt_timeout().is_none()
+        && env.operation_timeout().is_none()
+    {
+        return None;
+    }
+    let mut builder = TimeoutConfig::builder();
+    builder
+        .set_connect_timeout(env.connect_timeout())
+        .set_read_timeout(env.read_timeout())
+        .set_operation_attempt_timeout(env.operation_attempt_timeout())
+        .set_operation_timeout(env.operation_timeout());
+    Some(builder.build())
+}
+
+/// 環境変数設定に基づいてHTTPクライアントを構築する
+///
+/// CA証明書・クライアント証明書・プロキシのいずれも指定されていない場合はNone
+/// （SDK標準のHTTPクライアントを使用する）。
+pub fn build_http_client(env: &Env) -> Result<Option<SharedHttpClient>, HttpClientError> {
+    if env.ca_bundle().is_none()
+        && env.client_cert().is_none()
+        && env.client_key().is_none()
+        && env.http_proxy().is_none()
+        && env.https_proxy().is_none()
+    {
+        return Ok(None);
+    }
+
+    let tls_config = build_tls_config(env)?;
+    let proxy = build_proxy_matcher(env)?;
+    Ok(Some(SharedHttpClient::new(CafceHttpClient {
+        tls_config: Arc::new(tls_config),
+        proxy: proxy.map(Arc::new),
+        connectors: Mutex::new(HashMap::new()),
+    })))
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, HttpClientError> {
+    std::fs::read(path).map_err(|source| HttpClientError::ReadFile {
+        path: path.to_string(),
+        source,
+    })
+}
+
+/// PEMファイルから証明書を全て読み込む
+fn read_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, HttpClientError> {
+    let pem = read_file(path)?;
+    let certificates = CertificateDer::pem_slice_iter(&pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| HttpClientError::InvalidCertificate {
+            path: path.to_string(),
+            message: e.to_string(),
+        })?;
+    if certificates.is_empty() {
+        return Err(HttpClientError::NoCertificate {
+            path: path.to_string(),
+        });
+    }
+    Ok(certificates)
+}
+
+/// TLSの設定を組み立てる
+///
+/// - OSの証明書ストアに加えて、`env.ca_bundle()`の証明書を信頼する
+///   （社内CAのMinIOとAWSのSTSを同じ設定で使えるよう、置き換えではなく追加とする）
+/// - `env.client_cert()` / `env.client_key()`が指定されている場合はmTLSのクライアント証明書として提示する
+fn build_tls_config(env: &Env) -> Result<rustls::ClientConfig, HttpClientError> {
+    let mut roots = rustls::RootCertStore::empty();
+    // 読み込めないOSの証明書があってもエラーにはしない（社内CAのみで足りる場合があるため）
+    let native = rustls_native_certs::load_native_certs();
+    roots.add_parsable_certificates(native.certs);
+
+    if let Some(path) = env.ca_bundle() {
+        for certificate in read_certificates(path)? {
+            roots
+                .add(certificate)
+                .map_err(|e| HttpClientError::InvalidCertificate {
+                    path: path.to_string(),
+                    message: e.to_string(),
+                })?;
+        }
+    }
+
+    let builder = rustls::ClientConfig::builder().with_root_certificates(roots);
+    let config = match (env.client_cert(), env.client_key()) {
+        (Some(cert_path), Some(key_path)) => {
+            let certificates = read_certificates(cert_path)?;
+            let key = PrivateKeyDer::from_pem_slice(&read_file(key_path)?).map_err(|e| {
+                HttpClientError::InvalidPrivateKey {
+                    path: key_path.to_string(),
+                    message: e.to_string(),
+                }
+            })?;
+            builder.with_client_auth_cert(certificates, key)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err(HttpClientError::IncompleteClientCertificate),
+    };
+    Ok(config)
+}
+
+/// プロキシの振り分け設定を組み立てる（プロキシ未指定の場合はNone）
+///
+/// プロキシへはCONNECTでトンネルを張るため、プロキシ自体のURLはhttp://のみ対応する。
+fn build_proxy_matcher(env: &Env) -> Result<Option<Matcher>, HttpClientError> {
+    if env.http_proxy().is_none() && env.https_proxy().is_none() {
+        return Ok(None);
+    }
+
+    let mut builder = Matcher::builder();
+    for proxy in [env.http_proxy(), env.https_proxy()].into_iter().flatten() {
+        let is_valid = proxy
+            .parse::<http::Uri>()
+            .is_ok_and(|uri| uri.scheme_str() == Some("http") && uri.host().is_some());
+        if !is_valid {
+            return Err(HttpClientError::InvalidProxy(proxy.to_string()));
+        }
+    }
+    if let Some(proxy) = env.http_proxy() {
+        builder = builder.http(proxy);
+    }
+    if let Some(proxy) = env.https_proxy() {
+        builder = builder.https(proxy);
+    }
+    if let Some(no_proxy) = env.no_proxy() {
+        builder = builder.no(no_proxy);
+    }
+    Ok(Some(builder.build()))
+}
+
+/// コネクターのキャッシュキー（接続タイムアウト, 読み取りタイムアウト）
+type ConnectorKey = (Option<Duration>, Option<Duration>);
+
+/// CA証明書・クライアント証明書・プロキシに対応したHTTPクライアント
+///
+/// コネクションプールを使い回すため、タイムアウト設定ごとにコネクターをキャッシュする。
+#[derive(Debug)]
+struct CafceHttpClient {
+    tls_config: Arc<rustls::ClientConfig>,
+    proxy: Option<Arc<Matcher>>,
+    connectors: Mutex<HashMap<ConnectorKey, SharedHttpConnector>>,
+}
+
+impl HttpClient for CafceHttpClient {
+    fn http_connector(
+        &self,
+        settings: &HttpConnectorSettings,
+        _components: &RuntimeComponents,
+    ) -> SharedHttpConnector {
+        let key = (settings.connect_timeout(), settings.read_timeout());
+        self.connectors
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| {
+                let client = build_hyper_client(
+                    &self.tls_config,
+                    self.proxy.clone(),
+                    settings.connect_timeout(),
+                );
+                SharedHttpConnector::new(CafceHttpConnector {
+                    client,
+                    read_timeout: settings.read_timeout(),
+                })
+            })
+            .clone()
+    }
+}
+
+type HyperClient = hyper_util::client::legacy::Client<
+    hyper_rustls::HttpsConnector<ProxyConnector>,
+    SdkBody,
+>;
+
+fn build_hyper_client(
+    tls_config: &rustls::ClientConfig,
+    proxy: Option<Arc<Matcher>>,
+    connect_timeout: Option<Duration>,
+) -> HyperClient {
+    let mut tcp = TcpConnector::new();
+    // https://のURIもhyper-rustlsから渡されるため、スキームの制限を外す
+    tcp.enforce_http(false);
+    tcp.set_connect_timeout(connect_timeout);
+
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config.clone())
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(ProxyConnector { tcp, proxy });
+    hyper_util::client::legacy::Client::builder(TokioExecutor::new()).build(https)
+}
+
+/// S3・STS以外（テレメトリーの送信先等）へHTTPリクエストを送信するクライアント
+///
+/// CA証明書・クライアント証明書・プロキシ・接続タイムアウトの設定はS3・STSと共通。
+#[derive(Clone)]
+pub struct PlainHttpClient {
+    client: HyperClient,
+    /// レスポンスヘッダーを受信するまでのタイムアウト
+    timeout: Duration,
+}
+
+impl PlainHttpClient {
+    pub fn new(env: &Env, timeout: Duration) -> Result<Self, HttpClientError> {
+        let tls_config = build_tls_config(env)?;
+        let proxy = build_proxy_matcher(env)?.map(Arc::new);
+        Ok(Self {
+            client: build_hyper_client(&tls_config, proxy, env.connect_timeout()),
+            timeout,
+        })
+    }
+
+    /// リクエストを送信し、レスポンスのステータスコードを返す（レスポンスのボディは読み捨てる）
+    pub async fn send(
+        &self,
+        method: http::Method,
+        url: &str,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<http::StatusCode, BoxError> {
+        let request = http::Request::builder()
+            .method(method)
+            .uri(url)
+            .header(http::header::CONTENT_TYPE, content_type)
+            .body(SdkBody::from(body))?;
+        Ok(self.request(request).await?.status())
+    }
+
+    /// リクエストを送信し、レスポンスヘッダーを受信した時点でレスポンスを返す
+    ///
+    /// レスポンスのボディは呼び出し元で読み込む（読み込みにはタイムアウトを適用しない）。
+    pub async fn request(
+        &self,
+        request: http::Request<SdkBody>,
+    ) -> Result<http::Response<hyper::body::Incoming>, BoxError> {
+        Ok(tokio::time::timeout(self.timeout, self.client.request(request)).await??)
+    }
+}
+
+struct CafceHttpConnector {
+    client: HyperClient,
+    /// レスポンスヘッダーを受信するまでのタイムアウト
+    read_timeout: Option<Duration>,
+}
+
+impl std::fmt::Debug for CafceHttpConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CafceHttpConnector")
+            .field("read_timeout", &self.read_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HttpConnector for CafceHttpConnector {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let request = match request.try_into_http1x() {
+            Ok(request) => request,
+            Err(e) => return HttpConnectorFuture::ready(Err(ConnectorError::user(e.into()))),
+        };
+        let response = self.client.request(request);
+        let read_timeout = self.read_timeout;
+
+        HttpConnectorFuture::new(async move {
+            let response = match read_timeout {
+                Some(duration) => tokio::time::timeout(duration, response)
+                    .await
+                    .map_err(|e| ConnectorError::timeout(e.into()))?,
+                None => response.await,
+            }
+            .map_err(|e| {
+                if e.is_connect() {
+                    ConnectorError::io(e.into())
+                } else {
+                    ConnectorError::other(e.into(), None)
+                }
+            })?;
+            HttpResponse::try_from(response.map(SdkBody::from_body_1_x))
+                .map_err(|e| ConnectorError::other(e.into(), None))
+        })
+    }
+}
+
+/// 接続先に応じて、直接またはプロキシ経由（CONNECTトンネル）でTCP接続するコネクター
+#[derive(Clone)]
+struct ProxyConnector {
+    tcp: TcpConnector,
+    proxy: Option<Arc<Matcher>>,
+}
+
+type ConnectFuture =
+    Pin<Box<dyn Future<Output = Result<TokioIo<tokio::net::TcpStream>, BoxError>> + Send>>;
+
+impl tower_service::Service<http::Uri> for ProxyConnector {
+    type Response = TokioIo<tokio::net::TcpStream>;
+    type Error = BoxError;
+    type Future = ConnectFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, destination: http::Uri) -> Self::Future {
+        let intercept = self
+            .proxy
+            .as_ref()
+            .and_then(|matcher| matcher.intercept(&destination));
+        match intercept {
+            Some(intercept) => {
+                let mut tunnel = Tunnel::new(intercept.uri().clone(), self.tcp.clone());
+                if let Some(auth) = intercept.basic_auth() {
+                    tunnel = tunnel.with_auth(auth.clone());
+                }
+                Box::pin(async move {
+                    tower_service::Service::call(&mut tunnel, destination)
+                        .await
+                        .map_err(Into::into)
+                })
+            }
+            None => {
+                let mut tcp = self.tcp.clone();
+                Box::pin(async move {
+                    tower_service::Service::call(&mut tcp, destination)
+                        .await
+                        .map_err(Into::into)
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// テスト用の社内CA・サーバー証明書・クライアント証明書
+    struct TestPki {
+        dir: tempfile::TempDir,
+        server_cert: CertificateDer<'static>,
+        server_key: PrivateKeyDer<'static>,
+        ca_cert: CertificateDer<'static>,
+    }
+
+    impl TestPki {
+        fn generate() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+
+            let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+            std::fs::write(dir.path().join("ca.pem"), ca.pem()).unwrap();
+
+            let server_key = KeyPair::generate().unwrap();
+            let server_cert = CertificateParams::new(vec!["localhost".to_string()])
+                .unwrap()
+                .signed_by(&server_key, &ca)
+                .unwrap();
+
+            let client_key = KeyPair::generate().unwrap();
+            let client_cert = CertificateParams::new(vec!["cafce-client".to_string()])
+                .unwrap()
+                .signed_by(&client_key, &ca)
+                .unwrap();
+            std::fs::write(dir.path().join("client.pem"), client_cert.pem()).unwrap();
+            std::fs::write(dir.path().join("client.key"), client_key.serialize_pem()).unwrap();
+
+            Self {
+                dir,
+                server_cert: server_cert.der().clone(),
+                server_key: PrivateKeyDer::try_from(server_key.serialize_der()).unwrap(),
+                ca_cert: ca.der().clone(),
+            }
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.path().join(name).to_str().unwrap().to_string()
+        }
+
+        /// クライアント証明書を要求するHTTPSサーバーを起動し、`host:port`を返す
+        async fn start_mtls_server(&self) -> String {
+            let mut client_roots = rustls::RootCertStore::empty();
+            client_roots.add(self.ca_cert.clone()).unwrap();
+            let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(client_roots))
+                .build()
+                .unwrap();
+            let config = rustls::ServerConfig::builder()
+                .with_client_cert_verifier(verifier)
+                .with_single_cert(vec![self.server_cert.clone()], self.server_key.clone_key())
+                .unwrap();
+            let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                loop {
+                    let Ok((stream, _)) = listener.accept().await else { return };
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        let Ok(mut stream) = acceptor.accept(stream).await else { return };
+                        let mut request = Vec::new();
+                        let mut buffer = [0u8; 4096];
+                        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                            match stream.read(&mut buffer).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => request.extend_from_slice(&buffer[..n]),
+                            }
+                        }
+                        let body = "<ListAllMyBucketsResult><Buckets></Buckets></ListAllMyBucketsResult>";
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/xml\r\nContent-Length: {}\r\n\r\n{body}",
+                            body.len()
+                        );
+                        let _ = stream.write_all(response.as_bytes()).await;
+                        let _ = stream.shutdown().await;
+                    });
+                }
+            });
+            format!("localhost:{port}")
+        }
+    }
+
+    fn env_for(server_address: &str) -> Env {
+        Env::new_for_test(
+            Some(server_address.to_string()),
+            Some("ak".to_string()),
+            Some("sk".to_string()),
+            None,
+            None,
+            None,
+            None,
+            false,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_timeout_config() {
+        assert!(timeout_config(&Env::default()).is_none());
+
+        let config = timeout_config(&Env::default().with_timeouts_for_test(Some(5), None)).unwrap();
+        assert_eq!(config.connect_timeout(), Some(Duration::from_secs(5)));
+        assert_eq!(config.read_timeout(), None);
+
+        let config = timeout_config(&Env::default().with_timeouts_for_test(None, Some(30))).unwrap();
+        assert_eq!(config.read_timeout(), Some(Duration::from_secs(30)));
+
+        let env = Env::default().with_retry_for_test(None, None, Some(10), Some(60), false);
+        let config = timeout_config(&env).unwrap();
+        assert_eq!(config.operation_attempt_timeout(), Some(Duration::from_secs(10)));
+        assert_eq!(config.operation_timeout(), Some(Duration::from_secs(60)));
+        assert_eq!(config.connect_timeout(), None);
+    }
+
+    #[test]
+    fn test_sdk_default_client_without_settings() {
+        assert!(build_http_client(&Env::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_incomplete_client_certificate() {
+        let pki = TestPki::generate();
+        let env = Env::default().with_tls_for_test(None, Some(&pki.path("client.pem")), None);
+        assert!(matches!(
+            build_http_client(&env),
+            Err(HttpClientError::IncompleteClientCertificate)
+        ));
+    }
+
+    #[test]
+    fn test_ca_bundle_errors() {
+        let pki = TestPki::generate();
+        let env = Env::default().with_tls_for_test(Some(&pki.path("missing.pem")), None, None);
+        assert!(matches!(build_http_client(&env), Err(HttpClientError::ReadFile { .. })));
+
+        // 証明書を含まないファイル（秘密鍵のみ）
+        let env = Env::default().with_tls_for_test(Some(&pki.path("client.key")), None, None);
+        assert!(matches!(
+            build_http_client(&env),
+            Err(HttpClientError::NoCertificate { .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_private_key() {
+        let pki = TestPki::generate();
+        let env = Env::default().with_tls_for_test(
+            None,
+            Some(&pki.path("client.pem")),
+            Some(&pki.path("client.pem")),
+        );
+        assert!(matches!(
+            build_http_client(&env),
+            Err(HttpClientError::InvalidPrivateKey { .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_proxy() {
+        for proxy in ["https://proxy:3128", "proxy:3128", "not a url"] {
+            let env = Env::default().with_proxy_for_test(None, Some(proxy), None);
+            assert!(
+                matches!(build_http_client(&env), Err(HttpClientError::InvalidProxy(_))),
+                "proxy={proxy}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mtls_with_ca_bundle() {
+        let pki = TestPki::generate();
+        let address = pki.start_mtls_server().await;
+        let env = env_for(&address).with_tls_for_test(
+            Some(&pki.path("ca.pem")),
+            Some(&pki.path("client.pem")),
+            Some(&pki.path("client.key")),
+        );
+
+        let client = crate::s3_client::build_s3_client(&env, &crate::env::CiEnv::default())
+            .await
+            .unwrap();
+        let result = client.list_buckets().send().await;
+        assert!(result.is_ok(), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn test_mtls_without_client_certificate_is_rejected() {
+        let pki = TestPki::generate();
+        let address = pki.start_mtls_server().await;
+        let env = env_for(&address).with_tls_for_test(Some(&pki.path("ca.pem")), None, None);
+
+        let client = crate::s3_client::build_s3_client(&env, &crate::env::CiEnv::default())
+            .await
+            .unwrap();
+        assert!(client.list_buckets().send().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_ca_is_rejected() {
+        let pki = TestPki::generate();
+        let address = pki.start_mtls_server().await;
+        // 別のCAを信頼していても、サーバー証明書は検証できない
+        let other = TestPki::generate();
+        let env = env_for(&address).with_tls_for_test(
+            Some(&other.path("ca.pem")),
+            Some(&pki.path("client.pem")),
+            Some(&pki.path("client.key")),
+        );
+
+        let client = crate::s3_client::build_s3_client(&env, &crate::env::CiEnv::default())
+            .await
+            .unwrap();
+        assert!(client.list_buckets().send().await.is_err());
+    }
+
+    /// CONNECTを受け付けて接続先へ中継する簡易プロキシを起動し、(URL, 受信したCONNECT先)を返す
+    async fn start_connect_proxy() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let targets = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&targets);
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut client, _)) = listener.accept().await else { return };
+                let recorded = Arc::clone(&recorded);
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        if client.read_exact(&mut byte).await.is_err() {
+                            return;
+                        }
+                        request.push(byte[0]);
+                    }
+                    let request = String::from_utf8_lossy(&request).into_owned();
+                    let Some(target) = request
+                        .strip_prefix("CONNECT ")
+                        .and_then(|rest| rest.split_whitespace().next())
+                        .map(String::from)
+                    else {
+                        return;
+                    };
+                    recorded.lock().unwrap().push(target.clone());
+                    let Ok(mut upstream) = tokio::net::TcpStream::connect(&target).await else {
+                        return;
+                    };
+                    if client
+                        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                });
+            }
+        });
+        (url, targets)
+    }
+
+    #[tokio::test]
+    async fn test_https_proxy() {
+        let pki = TestPki::generate();
+        let address = pki.start_mtls_server().await;
+        let (proxy, targets) = start_connect_proxy().await;
+        let env = env_for(&address)
+            .with_tls_for_test(
+                Some(&pki.path("ca.pem")),
+                Some(&pki.path("client.pem")),
+                Some(&pki.path("client.key")),
+            )
+            .with_proxy_for_test(None, Some(&proxy), None);
+
+        let client = crate::s3_client::build_s3_client(&env, &crate::env::CiEnv::default())
+            .await
+            .unwrap();
+        let result = client.list_buckets().send().await;
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(targets.lock().unwrap().as_slice(), [address]);
+    }
+
+    #[tokio::test]
+    async fn test_no_proxy() {
+        let pki = TestPki::generate();
+        let address = pki.start_mtls_server().await;
+        let (proxy, targets) = start_connect_proxy().await;
+        let env = env_for(&address)
+            .with_tls_for_test(
+                Some(&pki.path("ca.pem")),
+                Some(&pki.path("client.pem")),
+                Some(&pki.path("client.key")),
+            )
+            .with_proxy_for_test(None, Some(&proxy), Some("example.com,localhost"));
+
+        let client = crate::s3_client::build_s3_client(&env, &crate::env::CiEnv::default())
+            .await
+            .unwrap();
+        let result = client.list_buckets().send().await;
+        assert!(result.is_ok(), "{result:?}");
+        assert!(targets.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sts_uses_http_proxy() {
+        use crate::test_util::{MockResponse, MockServer};
+
+        let sts = MockServer::start(|_| {
+            MockResponse::new(
+                200,
+                r#"<AssumeRoleWithWebIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleWithWebIdentityResult>
+    <Credentials>
+      <AccessKeyId>ASIAWEBIDENTITY</AccessKeyId>
+      <SecretAccessKey>web-identity-secret</SecretAccessKey>
+      <SessionToken>web-identity-session-token</SessionToken>
+      <Expiration>2099-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleWithWebIdentityResult>
+</AssumeRoleWithWebIdentityResponse>"#,
+            )
+            .header("Content-Type", "text/xml")
+        });
+        let s3 = MockServer::start(|_| MockResponse::new(200, "<ListAllMyBucketsResult></ListAllMyBucketsResult>"));
+        let (proxy, targets) = start_connect_proxy().await;
+        let env = Env::new_for_test(
+            Some(s3.address().to_string()),
+            None,
+            None,
+            None,
+            Some("arn:aws:iam::123456789012:role/cafce-ci".to_string()),
+            None,
+            None,
+            true,
+            None,
+            None,
+        )
+        .with_web_identity_for_test(Some("oidc-jwt"), None, Some(&sts.url()))
+        .with_proxy_for_test(Some(&proxy), None, None);
+
+        let client = crate::s3_client::build_s3_client(&env, &crate::env::CiEnv::default())
+            .await
+            .unwrap();
+        // クレデンシャルは最初のS3リクエストの前に取得する
+        let _ = client.list_buckets().send().await;
+        assert_eq!(sts.requests().len(), 1);
+        assert_eq!(targets.lock().unwrap().as_slice(), [sts.address(), s3.address()]);
+    }
+}
@@ -0,0 +1,485 @@
+//! HTTPキャッシュサーバーの保存先（`http://` / `https://`）
+//!
+//! Bazel・sccacheのリモートキャッシュと同様に、`{URL}/{オブジェクトキー}`に対して
+//! GET（取得）・PUT（保存）・HEAD（メタデータ）・DELETE（削除）を送信する。
+//! 認証はCAFCE_STORAGE_TOKEN（Bearer）またはCAFCE_STORAGE_USERNAME/PASSWORD（Basic）で指定する。
+//!
+//! cafceのメタデータ（`cafce-cache-name`等）は`x-cafce-meta-`で始まるヘッダーとして送信する。
+//! ヘッダーを保存しないサーバーではメタデータなしとして扱う。
+
+use crate::env::Env;
+use crate::http_client::PlainHttpClient;
+use crate::storage::{self, ObjectMetadata, Storage, StorageError, StorageFuture};
+use aws_smithy_types::body::SdkBody;
+use aws_smithy_types::byte_stream::ByteStream;
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use base64::Engine;
+use http::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, ETAG, LAST_MODIFIED, LOCATION};
+use http::{Method, StatusCode};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use url::Url;
+
+/// メタデータを送信するヘッダーの接頭辞
+pub const METADATA_HEADER_PREFIX: &str = "x-cafce-meta-";
+
+/// レスポンスヘッダーを受信するまでのタイムアウト（PUTの場合はアップロードの完了までを含む）
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// GET・HEADでリダイレクトをたどる最大回数
+const MAX_REDIRECTS: usize = 5;
+
+/// HTTPキャッシュサーバーの保存先
+pub struct HttpStorage {
+    client: PlainHttpClient,
+    base_url: Url,
+    /// 認証に使用するヘッダー（Authorization等）
+    auth_header: Option<(HeaderName, HeaderValue)>,
+}
+
+impl HttpStorage {
+    /// CA証明書・クライアント証明書・プロキシの設定はS3と共通
+    pub fn new(env: &Env, base_url: Url) -> Result<Self, StorageError> {
+        let client = PlainHttpClient::new(env, REQUEST_TIMEOUT).map_err(StorageError::HttpClient)?;
+        let authorization = match (env.storage_token(), env.storage_basic_auth()) {
+            (Some(_), Some(_)) => return Err(StorageError::ConflictingAuth),
+            (Some(token), None) => Some(format!("Bearer {token}")),
+            (None, Some((username, password))) => Some(format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"))
+            )),
+            (None, None) => None,
+        };
+        let authorization = authorization
+            .map(|value| {
+                let mut value = HeaderValue::try_from(value).map_err(|e| StorageError::InvalidUrl {
+                    url: base_url.to_string(),
+                    message: e.to_string(),
+                })?;
+                value.set_sensitive(true);
+                Ok::<_, StorageError>(value)
+            })
+            .transpose()?;
+        Ok(Self::with_client(
+            client,
+            base_url,
+            authorization.map(|value| (AUTHORIZATION, value)),
+        ))
+    }
+
+    /// `auth_header`はベースURLと同じオリジンへのリクエストにのみ付与する
+    pub(crate) fn with_client(
+        client: PlainHttpClient,
+        base_url: Url,
+        auth_header: Option<(HeaderName, HeaderValue)>,
+    ) -> Self {
+        Self {
+            client,
+            base_url,
+            auth_header,
+        }
+    }
+
+    /// ベースURLのパスの後に、各セグメントをエンコードして追加したURL
+    pub(crate) fn url_for<'s>(&self, segments: impl IntoIterator<Item = &'s str>) -> Result<Url, StorageError> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|()| StorageError::InvalidUrl {
+                url: self.base_url.to_string(),
+                message: "cannot be a base URL".to_string(),
+            })?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    /// オブジェクトのURL（ベースURLのパスの後にオブジェクトキーの各セグメントをエンコードして追加する）
+    pub(crate) fn object_url(&self, object_key: &str) -> Result<Url, StorageError> {
+        storage::validate_object_key(object_key)?;
+        self.url_for(object_key.split('/'))
+    }
+
+    /// リクエストを送信し、レスポンスを返す
+    ///
+    /// GET・HEADのリダイレクト（オブジェクトストレージの署名付きURL等）はたどる。
+    /// 認証ヘッダーは、ベースURLと異なるオリジンへのリダイレクト先には送信しない。
+    /// 通信に失敗した場合は、`error`でオブジェクトキーに応じたエラーを組み立てる。
+    pub(crate) async fn send(
+        &self,
+        method: Method,
+        url: &Url,
+        headers: HeaderMap,
+        body: SdkBody,
+        error: impl Fn(String) -> StorageError,
+    ) -> Result<http::Response<hyper::body::Incoming>, StorageError> {
+        let follows_redirects = method == Method::GET || method == Method::HEAD;
+        let mut url = url.clone();
+        let mut body = Some(body);
+        for _ in 0..=MAX_REDIRECTS {
+            let mut request = http::Request::builder().method(method.clone()).uri(url.as_str());
+            if let Some((name, value)) = &self.auth_header {
+                if url.origin() == self.base_url.origin() {
+                    request = request.header(name, value);
+                }
+            }
+            if let Some(request_headers) = request.headers_mut() {
+                request_headers.extend(headers.clone());
+            }
+            let body = body.take().unwrap_or_else(SdkBody::empty);
+            let request = request.body(body).map_err(|e| error(e.to_string()))?;
+            let response = self.client.request(request).await.map_err(|e| error(e.to_string()))?;
+            let location = header_str(response.headers(), LOCATION);
+            match location {
+                Some(location) if follows_redirects && response.status().is_redirection() => {
+                    url = url.join(location).map_err(|e| error(e.to_string()))?;
+                }
+                _ => return Ok(response),
+            }
+        }
+        Err(error(format!("too many redirects: {url}")))
+    }
+
+    async fn head_object(&self, object_key: &str) -> Result<Option<ObjectMetadata>, StorageError> {
+        let error = |message| StorageError::Head {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let url = self.object_url(object_key)?;
+        let response = self
+            .send(Method::HEAD, &url, HeaderMap::new(), SdkBody::empty(), error)
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(object_metadata(response.headers()))),
+            status => Err(error(status_message(status))),
+        }
+    }
+
+    async fn get_object(
+        &self,
+        object_key: &str,
+        destination: &mut (dyn Write + Send),
+    ) -> Result<Option<ObjectMetadata>, StorageError> {
+        let error = |message| StorageError::Get {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let url = self.object_url(object_key)?;
+        let response = self
+            .send(Method::GET, &url, HeaderMap::new(), SdkBody::empty(), error)
+            .await?;
+        read_object(response, destination, error).await
+    }
+
+    async fn put_object(
+        &self,
+        object_key: &str,
+        source: &Path,
+        metadata: &HashMap<String, String>,
+    ) -> Result<Option<String>, StorageError> {
+        let error = |message| StorageError::Put {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let url = self.object_url(object_key)?;
+        let (headers, body) = file_body(source).await?;
+        let mut headers = headers;
+        for (name, value) in metadata {
+            let name = http::HeaderName::try_from(format!("{METADATA_HEADER_PREFIX}{name}"))
+                .map_err(|e| error(e.to_string()))?;
+            let value = HeaderValue::try_from(value.as_str()).map_err(|e| error(e.to_string()))?;
+            headers.insert(name, value);
+        }
+        let response = self.send(Method::PUT, &url, headers, body, error).await?;
+        check_status(response.status(), error)?;
+        Ok(header_str(response.headers(), ETAG).map(String::from))
+    }
+
+    async fn delete_object(&self, object_key: &str) -> Result<(), StorageError> {
+        let error = |message| StorageError::Delete {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let url = self.object_url(object_key)?;
+        let response = self
+            .send(Method::DELETE, &url, HeaderMap::new(), SdkBody::empty(), error)
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(()),
+            status => check_status(status, error),
+        }
+    }
+}
+
+impl Storage for HttpStorage {
+    fn head<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        Box::pin(self.head_object(object_key))
+    }
+
+    fn get<'a>(
+        &'a self,
+        object_key: &'a str,
+        destination: &'a mut (dyn Write + Send),
+    ) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        Box::pin(self.get_object(object_key, destination))
+    }
+
+    fn put<'a>(
+        &'a self,
+        object_key: &'a str,
+        source: &'a Path,
+        metadata: &'a HashMap<String, String>,
+    ) -> StorageFuture<'a, Option<String>> {
+        Box::pin(self.put_object(object_key, source, metadata))
+    }
+
+    fn delete<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(self.delete_object(object_key))
+    }
+
+    /// HTTPキャッシュサーバーにはメタデータを書き換える方法がないため、記録しない
+    fn record_access<'a>(&'a self, _object_key: &'a str, _now: i64) -> StorageFuture<'a, bool> {
+        Box::pin(async { Ok(false) })
+    }
+}
+
+/// アップロードするファイルのボディ（メモリに読み込まずに送信する）とContent-Length
+pub(crate) async fn file_body(source: &Path) -> Result<(HeaderMap, SdkBody), StorageError> {
+    let io_error = |source_error| StorageError::Io {
+        path: source.to_path_buf(),
+        source: source_error,
+    };
+    let size = std::fs::metadata(source).map_err(io_error)?.len();
+    let body = ByteStream::from_path(source)
+        .await
+        .map_err(|e| io_error(std::io::Error::other(e)))?;
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(size));
+    Ok((headers, body.into_inner()))
+}
+
+/// GETのレスポンスの本文を`destination`に書き込み、メタデータを返す（404の場合はNone）
+pub(crate) async fn read_object(
+    response: http::Response<hyper::body::Incoming>,
+    destination: &mut (dyn Write + Send),
+    error: impl Fn(String) -> StorageError,
+) -> Result<Option<ObjectMetadata>, StorageError> {
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    check_status(response.status(), &error)?;
+    let metadata = object_metadata(response.headers());
+    let mut body = ByteStream::new(SdkBody::from_body_1_x(response.into_body()));
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| error(e.to_string()))?;
+        destination.write_all(&chunk).map_err(|e| error(e.to_string()))?;
+    }
+    Ok(Some(metadata))
+}
+
+pub(crate) fn check_status(status: StatusCode, error: impl Fn(String) -> StorageError) -> Result<(), StorageError> {
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(error(status_message(status)))
+    }
+}
+
+pub(crate) fn status_message(status: StatusCode) -> String {
+    format!("HTTP {status}")
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// レスポンスヘッダーからオブジェクトの情報を組み立てる
+pub(crate) fn object_metadata(headers: &HeaderMap) -> ObjectMetadata {
+    let metadata = headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let name = name.as_str().strip_prefix(METADATA_HEADER_PREFIX)?;
+            Some((name.to_string(), value.to_str().ok()?.to_string()))
+        })
+        .collect();
+    ObjectMetadata {
+        size: header_str(headers, CONTENT_LENGTH)
+            .and_then(|length| length.parse().ok())
+            .unwrap_or_default(),
+        etag: header_str(headers, ETAG).map(String::from),
+        last_modified: header_str(headers, LAST_MODIFIED)
+            .and_then(|value| DateTime::from_str(value, Format::HttpDate).ok()),
+        metadata,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer};
+
+    const OBJECT_KEY: &str = "group/app/node_modules/v1/deps-protected.tar.zst";
+
+    fn http_storage(server: &MockServer, env: &Env) -> HttpStorage {
+        HttpStorage::new(env, Url::parse(&format!("{}/cache/", server.url())).unwrap()).unwrap()
+    }
+
+    fn parse_env(vars: &[(&str, &str)]) -> Env {
+        envy::prefixed("CAFCE_")
+            .from_iter(vars.iter().map(|(key, value)| (key.to_string(), value.to_string())))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_object_url() {
+        let env = Env::default();
+        let storage = HttpStorage::new(&env, Url::parse("https://cache.example.com/cafce").unwrap()).unwrap();
+        assert_eq!(
+            storage.object_url("group/app/deps 1.tar.zst").unwrap().as_str(),
+            "https://cache.example.com/cafce/group/app/deps%201.tar.zst"
+        );
+        let storage = HttpStorage::new(&env, Url::parse("https://cache.example.com/").unwrap()).unwrap();
+        assert_eq!(
+            storage.object_url("group/app/deps.tar.zst").unwrap().as_str(),
+            "https://cache.example.com/group/app/deps.tar.zst"
+        );
+        let result = storage.object_url("group/../other/deps.tar.zst");
+        assert!(matches!(result, Err(StorageError::InvalidObjectKey { .. })), "{result:?}");
+    }
+
+    #[test]
+    fn test_conflicting_auth() {
+        let env = parse_env(&[("CAFCE_STORAGE_TOKEN", "t"), ("CAFCE_STORAGE_USERNAME", "u")]);
+        let result = HttpStorage::new(&env, Url::parse("https://cache.example.com/").unwrap());
+        assert!(matches!(result, Err(StorageError::ConflictingAuth)));
+    }
+
+    #[tokio::test]
+    async fn test_put_get_head_delete() {
+        let server = MockServer::start(|request| match request.method.as_str() {
+            "PUT" => MockResponse::new(201, "").header("ETag", "\"etag1\""),
+            "GET" if request.path.ends_with("/missing.tar.zst") => MockResponse::new(404, ""),
+            "GET" => MockResponse::new(200, "hello")
+                .header("ETag", "\"etag1\"")
+                .header("Last-Modified", "Wed, 21 Oct 2026 07:28:00 GMT")
+                .header("x-cafce-meta-cafce-cache-name", "node_modules"),
+            "HEAD" => MockResponse::new(200, "").header("ETag", "\"etag1\""),
+            "DELETE" => MockResponse::new(204, ""),
+            _ => MockResponse::new(405, ""),
+        });
+        let storage = http_storage(&server, &parse_env(&[("CAFCE_STORAGE_TOKEN", "secret-token")]));
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("archive");
+        std::fs::write(&source, b"hello").unwrap();
+        let metadata = HashMap::from([("cafce-cache-name".to_string(), "node_modules".to_string())]);
+
+        let etag = storage.put(OBJECT_KEY, &source, &metadata).await.unwrap();
+        assert_eq!(etag.as_deref(), Some("\"etag1\""));
+        let put = server.requests().into_iter().find(|request| request.method == "PUT").unwrap();
+        assert_eq!(put.path, format!("/cache/{OBJECT_KEY}"));
+        assert_eq!(put.body, b"hello");
+        assert_eq!(put.header("authorization"), Some("Bearer secret-token"));
+        assert_eq!(put.header("x-cafce-meta-cafce-cache-name"), Some("node_modules"));
+
+        let mut body = Vec::new();
+        let object = storage.get(OBJECT_KEY, &mut body).await.unwrap().unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(object.size, 5);
+        assert_eq!(object.etag.as_deref(), Some("\"etag1\""));
+        assert_eq!(object.last_modified.unwrap().secs(), 1_792_567_680);
+        assert_eq!(object.metadata, metadata);
+        assert_eq!(storage.get("group/app/missing.tar.zst", &mut Vec::new()).await.unwrap(), None);
+
+        let object = storage.head(OBJECT_KEY).await.unwrap().unwrap();
+        assert_eq!(object.etag.as_deref(), Some("\"etag1\""));
+        storage.delete(OBJECT_KEY).await.unwrap();
+        let delete = server.requests().into_iter().find(|request| request.method == "DELETE").unwrap();
+        assert_eq!(delete.path, format!("/cache/{OBJECT_KEY}"));
+        assert!(!storage.record_access(OBJECT_KEY, 0).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_get_follows_redirect() {
+        let object_storage = MockServer::start(|_| MockResponse::new(200, "hello"));
+        let signed_url = format!("{}/bucket/object?signature=abc", object_storage.url());
+        let server = MockServer::start(move |_| MockResponse::new(302, "").header("Location", &signed_url));
+        let storage = http_storage(&server, &parse_env(&[("CAFCE_STORAGE_TOKEN", "secret-token")]));
+
+        let mut body = Vec::new();
+        storage.get(OBJECT_KEY, &mut body).await.unwrap().unwrap();
+        assert_eq!(body, b"hello");
+        let redirected = &object_storage.requests()[0];
+        assert_eq!(redirected.path, "/bucket/object?signature=abc");
+        // 別のオリジンのリダイレクト先には認証ヘッダーを送信しない
+        assert_eq!(redirected.header("authorization"), None);
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_and_errors() {
+        let server = MockServer::start(|request| match request.method.as_str() {
+            "HEAD" => MockResponse::new(404, ""),
+            "DELETE" => MockResponse::new(404, ""),
+            _ => MockResponse::new(403, "forbidden"),
+        });
+        let env = parse_env(&[("CAFCE_STORAGE_USERNAME", "user"), ("CAFCE_STORAGE_PASSWORD", "pass")]);
+        let storage = http_storage(&server, &env);
+
+        assert_eq!(storage.head(OBJECT_KEY).await.unwrap(), None);
+        // 存在しないオブジェクトの削除は成功とする
+        storage.delete(OBJECT_KEY).await.unwrap();
+        let error = storage.get(OBJECT_KEY, &mut Vec::new()).await.unwrap_err();
+        assert!(matches!(error, StorageError::Get { .. }), "{error:?}");
+        assert!(error.to_string().contains("403"), "{error}");
+        assert_eq!(
+            server.requests()[0].header("authorization"),
+            Some("Basic dXNlcjpwYXNz")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_store_and_restore_encrypted_without_metadata_headers() {
+        use crate::encryption::Keyring;
+        use crate::object_key::Codec;
+        use crate::transfer;
+
+        // x-cafce-meta-*ヘッダーを保存せず、本文だけを返すサーバー
+        let stored = std::sync::Mutex::new(None::<Vec<u8>>);
+        let server = MockServer::start(move |request| match request.method.as_str() {
+            "PUT" => {
+                *stored.lock().unwrap() = Some(request.body.clone());
+                MockResponse::new(201, "")
+            }
+            "GET" => match stored.lock().unwrap().clone() {
+                Some(body) => MockResponse::new(200, body),
+                None => MockResponse::new(404, ""),
+            },
+            _ => MockResponse::new(405, ""),
+        });
+        let storage = http_storage(&server, &Env::default());
+        let source = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("node_modules/pkg")).unwrap();
+        std::fs::write(source.path().join("node_modules/pkg/index.js"), b"hello").unwrap();
+        let keyring = Keyring::parse(Some(&"11".repeat(32)), Some("k1"), None).unwrap();
+        let paths = ["node_modules".to_string()];
+
+        transfer::store(&storage, OBJECT_KEY, &paths, source.path(), Codec::Zstd, &HashMap::new(), &keyring)
+            .await
+            .unwrap();
+        let put = server.requests().into_iter().find(|request| request.method == "PUT").unwrap();
+        assert_eq!(put.header("x-cafce-meta-cafce-encryption-key-id"), Some("k1"));
+
+        // 鍵IDはストリームヘッダーから読むため、メタデータが返されなくても復号できる
+        let destination = tempfile::tempdir().unwrap();
+        let candidates = [("deps-protected".to_string(), OBJECT_KEY.to_string())];
+        let restored = transfer::restore(&storage, &candidates, Codec::Zstd, destination.path(), &keyring, 0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(restored.object.metadata.is_empty());
+        let restored_file = destination.path().join("node_modules/pkg/index.js");
+        assert_eq!(std::fs::read(restored_file).unwrap(), b"hello");
+    }
+}
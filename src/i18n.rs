@@ -0,0 +1,162 @@
+//! メッセージの言語の選択
+//!
+//! エラー・ログのメッセージは日本語と英語の両方を持ち、表示時に言語を選択する。
+//! エラーのバリアントや終了コードは言語に関わらず同一。
+
+#[cfg(not(test))]
+use std::sync::OnceLock;
+
+/// メッセージの言語
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Ja,
+    En,
+}
+
+impl Lang {
+    /// "ja" / "en"、またはロケール名（"ja_JP.UTF-8"等）から言語を決定する
+    ///
+    /// 空文字と"C" / "POSIX"（言語の指定なし）はNone。日本語以外のロケールは英語とする。
+    pub fn parse(value: &str) -> Option<Self> {
+        let language = value.trim().split(['_', '-', '.', '@']).next().unwrap_or_default();
+        if language.is_empty() || language == "C" || language == "POSIX" {
+            None
+        } else if language.eq_ignore_ascii_case("ja") {
+            Some(Self::Ja)
+        } else {
+            Some(Self::En)
+        }
+    }
+
+    /// 環境変数から言語を決定する
+    ///
+    /// CAFCE_LANG、LC_ALL、LC_MESSAGES、LANGの順に、最初に言語が指定されているものを使う。
+    /// いずれも未設定の場合は、従来どおり日本語とする。
+    ///
+    /// CAFCE_LANGは、CAFCE_*の環境変数の読み込みエラーも選択した言語で表示するため、
+    /// Envとは別に直接読み込む。
+    pub fn detect(var: impl Fn(&str) -> Option<String>) -> Self {
+        ["CAFCE_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .find_map(|name| var(name).as_deref().and_then(Self::parse))
+            .unwrap_or(Self::Ja)
+    }
+}
+
+/// メッセージの言語を取得する
+///
+/// 初回の呼び出し時にプロセスの環境変数から決定し、以降は同じ値を返す。
+#[cfg(not(test))]
+pub fn lang() -> Lang {
+    static LANG: OnceLock<Lang> = OnceLock::new();
+    *LANG.get_or_init(|| Lang::detect(|name| std::env::var(name).ok()))
+}
+
+/// メッセージの言語を取得する
+///
+/// テストでは環境変数に依存しないよう、`with_lang_for_test`で指定しない限り日本語とする。
+#[cfg(test)]
+pub fn lang() -> Lang {
+    TEST_LANG.with(|lang| lang.get())
+}
+
+#[cfg(test)]
+thread_local! {
+    static TEST_LANG: std::cell::Cell<Lang> = const { std::cell::Cell::new(Lang::Ja) };
+}
+
+/// `f`の実行中のみメッセージの言語を`lang`に切り替える
+#[cfg(test)]
+pub(crate) fn with_lang_for_test<T>(lang: Lang, f: impl FnOnce() -> T) -> T {
+    let previous = TEST_LANG.with(|current| current.replace(lang));
+    let result = f();
+    TEST_LANG.with(|current| current.set(previous));
+    result
+}
+
+/// 日本語・英語のメッセージから、現在の言語のものを整形する
+///
+/// 書式文字列の後に`format!`と同じ引数を指定できる。
+/// `#[error("{}", tr!(...))]`の形でエラーのメッセージにも使用する。
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __tr {
+    ($ja:literal, $en:literal $(, $arg:expr)* $(,)?) => {
+        match $crate::i18n::lang() {
+            $crate::i18n::Lang::Ja => format!($ja $(, $arg)*),
+            $crate::i18n::Lang::En => format!($en $(, $arg)*),
+        }
+    };
+}
+pub use __tr as tr;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |name| {
+            pairs
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Lang::parse("ja"), Some(Lang::Ja));
+        assert_eq!(Lang::parse("ja_JP.UTF-8"), Some(Lang::Ja));
+        assert_eq!(Lang::parse("JA"), Some(Lang::Ja));
+        assert_eq!(Lang::parse("en"), Some(Lang::En));
+        assert_eq!(Lang::parse("en_US.UTF-8"), Some(Lang::En));
+        assert_eq!(Lang::parse("de_DE.UTF-8"), Some(Lang::En));
+        assert_eq!(Lang::parse("C.UTF-8"), None);
+        assert_eq!(Lang::parse("POSIX"), None);
+        assert_eq!(Lang::parse(""), None);
+    }
+
+    #[test]
+    fn test_detect_default() {
+        assert_eq!(Lang::detect(vars(&[])), Lang::Ja);
+        assert_eq!(Lang::detect(vars(&[("LANG", "C.UTF-8")])), Lang::Ja);
+    }
+
+    #[test]
+    fn test_detect_locale() {
+        assert_eq!(Lang::detect(vars(&[("LANG", "ja_JP.UTF-8")])), Lang::Ja);
+        assert_eq!(Lang::detect(vars(&[("LANG", "en_US.UTF-8")])), Lang::En);
+        // LC_ALLはLANGより優先
+        assert_eq!(
+            Lang::detect(vars(&[("LC_ALL", "en_US.UTF-8"), ("LANG", "ja_JP.UTF-8")])),
+            Lang::En
+        );
+        // 空文字・"C"は未設定と同じ扱い
+        assert_eq!(
+            Lang::detect(vars(&[("LC_ALL", "C"), ("LANG", "en_US.UTF-8")])),
+            Lang::En
+        );
+    }
+
+    #[test]
+    fn test_detect_cafce_lang_overrides_locale() {
+        assert_eq!(
+            Lang::detect(vars(&[("CAFCE_LANG", "en"), ("LC_ALL", "ja_JP.UTF-8")])),
+            Lang::En
+        );
+        assert_eq!(
+            Lang::detect(vars(&[("CAFCE_LANG", "ja"), ("LANG", "en_US.UTF-8")])),
+            Lang::Ja
+        );
+    }
+
+    #[test]
+    fn test_tr() {
+        let count = 3;
+        assert_eq!(tr!("{count}件", "{count} items"), "3件");
+        let message = with_lang_for_test(Lang::En, || tr!("{}件", "{} items", count));
+        assert_eq!(message, "3 items");
+        // 切り替えは`with_lang_for_test`の中のみ
+        assert_eq!(lang(), Lang::Ja);
+    }
+}
@@ -0,0 +1,457 @@
+//! キャッシュオブジェクトの中身の確認（`cafce inspect`）
+//!
+//! GetObjectの本文を受信しながら復号・展開し、tarのヘッダーのみを読んで含まれるファイルを列挙する。
+//! ファイルの内容は読み捨てるため、ディスクには何も書き込まない。
+
+use crate::catalog::{self, CacheEntry};
+use crate::encryption::{EncryptionError, Keyring, KEY_ID_METADATA};
+use crate::i18n::tr;
+use crate::object_key::Codec;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::operation::get_object::GetObjectOutput;
+use aws_smithy_types::byte_stream::ByteStream;
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::mpsc;
+
+/// 受信した本文のチャンクを読み込み側に渡すまでにバッファする最大数
+const CHANNEL_CAPACITY: usize = 16;
+
+/// キャッシュオブジェクトの読み込み時のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum InspectError {
+    #[error("{}", tr!("キャッシュのダウンロードに失敗しました: {object_key}: {message}", "failed to download the cache: {object_key}: {message}"))]
+    Download { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュアーカイブの読み込みに失敗しました: {object_key}", "failed to read the cache archive: {object_key}"))]
+    Archive {
+        object_key: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl InspectError {
+    /// 復号の失敗（鍵の誤り、またはデータの改ざん・欠損）か
+    pub fn is_integrity_error(&self) -> bool {
+        match self {
+            Self::Archive { source, .. } => source.get_ref().is_some_and(|error| error.is::<EncryptionError>()),
+            Self::Download { .. } => false,
+        }
+    }
+}
+
+/// `cafce inspect`で表示するオブジェクトの情報
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ObjectInfo {
+    #[serde(flatten)]
+    pub entry: CacheEntry,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    /// 暗号化に使用した鍵ID（暗号化していない場合はNone）
+    pub encryption_key_id: Option<String>,
+    /// ユーザー定義メタデータ（`x-amz-meta-`を除いた名前で、名前順）
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl ObjectInfo {
+    pub fn new(entry: CacheEntry, output: &GetObjectOutput) -> Self {
+        let metadata: BTreeMap<String, String> = output
+            .metadata()
+            .map(|metadata| metadata.clone().into_iter().collect())
+            .unwrap_or_default();
+        Self {
+            entry,
+            content_type: output.content_type().map(String::from),
+            etag: output.e_tag().map(String::from),
+            encryption_key_id: metadata.get(KEY_ID_METADATA).cloned(),
+            metadata,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let none = || "-".to_string();
+        let format_time = |time: Option<DateTime>| {
+            time.and_then(|time| time.fmt(Format::DateTime).ok())
+                .unwrap_or_else(none)
+        };
+        let mut lines = vec![
+            format!("key: {}", self.entry.key),
+            format!("cache: {}", self.entry.cache_name.clone().unwrap_or_else(none)),
+            format!("object key: {}", self.entry.object_key),
+            format!(
+                "codec: {}",
+                self.entry.codec.map(|codec| codec.name().to_string()).unwrap_or_else(none)
+            ),
+            format!("size: {} ({} bytes)", catalog::format_size(self.entry.size), self.entry.size),
+            format!("last modified: {}", format_time(self.entry.last_modified)),
+            format!("last accessed: {}", format_time(self.entry.last_accessed)),
+            format!("pipeline: {}", self.entry.producer_pipeline.clone().unwrap_or_else(none)),
+            format!("encryption key ID: {}", self.encryption_key_id.clone().unwrap_or_else(none)),
+            format!("content type: {}", self.content_type.clone().unwrap_or_else(none)),
+            format!("etag: {}", self.etag.clone().unwrap_or_else(none)),
+            "metadata:".to_string(),
+        ];
+        for (name, value) in &self.metadata {
+            lines.push(format!("  {name}: {value}"));
+        }
+        lines.join("\n")
+    }
+}
+
+/// アーカイブ内の1つのエントリ（tarのヘッダーの内容）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArchiveEntry {
+    pub path: String,
+    /// 種類（"file" / "dir" / "symlink" / "hardlink" / "other"）
+    pub kind: &'static str,
+    pub size: u64,
+    /// パーミッション（JSONでは"0644"のような8進数の文字列）
+    #[serde(serialize_with = "serialize_mode")]
+    pub mode: u32,
+    /// 更新日時（UNIX時刻の秒、JSONではRFC 3339）
+    #[serde(serialize_with = "serialize_mtime")]
+    pub mtime: u64,
+    /// リンク先（シンボリックリンク・ハードリンクの場合）
+    pub link: Option<String>,
+}
+
+fn serialize_mode<S: Serializer>(mode: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{mode:04o}"))
+}
+
+fn serialize_mtime<S: Serializer>(mtime: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_mtime(*mtime))
+}
+
+fn format_mtime(mtime: u64) -> String {
+    DateTime::from_secs(mtime as i64)
+        .fmt(Format::DateTime)
+        .unwrap_or_else(|_| mtime.to_string())
+}
+
+impl ArchiveEntry {
+    fn from_tar<R: Read>(entry: &tar::Entry<R>) -> std::io::Result<Self> {
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        let kind = if entry_type.is_file() {
+            "file"
+        } else if entry_type.is_dir() {
+            "dir"
+        } else if entry_type.is_symlink() {
+            "symlink"
+        } else if entry_type.is_hard_link() {
+            "hardlink"
+        } else {
+            "other"
+        };
+        Ok(Self {
+            path: entry.path()?.to_string_lossy().into_owned(),
+            kind,
+            size: entry.size(),
+            mode: header.mode()? & 0o7777,
+            mtime: header.mtime()?,
+            link: entry.link_name()?.map(|link| link.to_string_lossy().into_owned()),
+        })
+    }
+
+    /// `ls -l`に似た1行の表示（種類とパーミッション・サイズ・更新日時・パス）
+    pub fn to_line(&self) -> String {
+        let kind = match self.kind {
+            "dir" => 'd',
+            "symlink" => 'l',
+            "file" | "hardlink" => '-',
+            _ => '?',
+        };
+        let permissions: String = (0..9)
+            .map(|bit| {
+                if self.mode & (0o400 >> bit) == 0 {
+                    '-'
+                } else {
+                    ['r', 'w', 'x'][bit % 3]
+                }
+            })
+            .collect();
+        let mut line = format!(
+            "{kind}{permissions} {:>12} {} {}",
+            self.size,
+            format_mtime(self.mtime),
+            self.path
+        );
+        if let Some(link) = &self.link {
+            line.push_str(" -> ");
+            line.push_str(link);
+        }
+        line
+    }
+}
+
+/// アーカイブの集計
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ArchiveSummary {
+    /// エントリ数（ディレクトリ・リンクを含む）
+    pub entries: usize,
+    /// 通常ファイルの数
+    pub files: usize,
+    /// 通常ファイルのサイズの合計
+    pub uncompressed_size: u64,
+}
+
+impl ArchiveSummary {
+    fn add(&mut self, entry: &ArchiveEntry) {
+        self.entries += 1;
+        if entry.kind == "file" {
+            self.files += 1;
+            self.uncompressed_size += entry.size;
+        }
+    }
+}
+
+/// 圧縮・暗号化されたtarを読み、エントリごとに`on_entry`を呼ぶ
+///
+/// # Arguments
+/// * `keyring` - 暗号化されている場合の復号鍵（ストリームヘッダーの鍵IDで選ぶ）
+pub fn read_archive<'a, R: Read + 'a>(
+    reader: R,
+    codec: Codec,
+    keyring: &Keyring,
+    mut on_entry: impl FnMut(&ArchiveEntry),
+) -> std::io::Result<ArchiveSummary> {
+    let reader = crate::transfer::decode_archive(reader, codec, keyring)?;
+    let mut archive = tar::Archive::new(reader);
+    let mut summary = ArchiveSummary::default();
+    for entry in archive.entries()? {
+        let entry = ArchiveEntry::from_tar(&entry?)?;
+        summary.add(&entry);
+        on_entry(&entry);
+    }
+    Ok(summary)
+}
+
+/// GetObjectの本文を受信しながらアーカイブのエントリを列挙する
+///
+/// 本文は非同期ストリームのため、`runtime`で受信したチャンクをチャネル経由で
+/// 別スレッドの`read_archive`に渡す。メモリに保持するのは最大`CHANNEL_CAPACITY`チャンクのみ。
+pub fn stream_archive(
+    runtime: &tokio::runtime::Runtime,
+    object_key: &str,
+    body: ByteStream,
+    codec: Codec,
+    keyring: &Keyring,
+    on_entry: impl FnMut(&ArchiveEntry) + Send,
+) -> Result<ArchiveSummary, InspectError> {
+    let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    let (downloaded, listed) = std::thread::scope(|scope| {
+        let reader = scope.spawn(move || read_archive(ChannelReader::new(receiver), codec, keyring, on_entry));
+        let downloaded = runtime.block_on(forward_body(body, sender));
+        (downloaded, reader.join().expect("archive reader panicked"))
+    });
+    // 受信の失敗で本文が途切れた場合は、tarの読み込みのエラーより受信のエラーを優先する
+    downloaded.map_err(|message| InspectError::Download {
+        object_key: object_key.to_string(),
+        message,
+    })?;
+    listed.map_err(|source| InspectError::Archive {
+        object_key: object_key.to_string(),
+        source,
+    })
+}
+
+/// 本文のチャンクを読み込み側に送る（読み込み側が終了した場合はそこで受信をやめる）
+async fn forward_body(mut body: ByteStream, sender: mpsc::SyncSender<Vec<u8>>) -> Result<(), String> {
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| DisplayErrorContext(&e).to_string())?;
+        if sender.send(chunk.to_vec()).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// チャネルで受け取ったチャンクを連続したストリームとして読むReader
+struct ChannelReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    fn new(receiver: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            receiver,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                // 送信側が終了した（本文の終端）
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{EncryptWriter, Keyring};
+    use std::io::Write;
+
+    const MTIME: u64 = 1_760_000_000;
+
+    fn tar_archive() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_mtime(MTIME);
+        header.set_size(0);
+        builder
+            .append_data(&mut header, "node_modules/", std::io::empty())
+            .unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_mtime(MTIME);
+        header.set_size(5);
+        builder
+            .append_data(&mut header, "node_modules/a.js", &b"hello"[..])
+            .unwrap();
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_mode(0o777);
+        header.set_mtime(MTIME);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "node_modules/b.js", "a.js")
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn compress(codec: Codec, data: &[u8]) -> Vec<u8> {
+        match codec {
+            Codec::Zstd => zstd::encode_all(data, 0).unwrap(),
+            Codec::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Codec::None => data.to_vec(),
+        }
+    }
+
+    fn keyring() -> Keyring {
+        Keyring::parse(Some(&"11".repeat(32)), Some("k1"), Some(&format!("k0:{}", "00".repeat(32)))).unwrap()
+    }
+
+    #[test]
+    fn test_read_archive() {
+        for codec in Codec::ALL {
+            let data = compress(codec, &tar_archive());
+            let mut entries = Vec::new();
+            let summary = read_archive(&data[..], codec, &Keyring::default(), |entry| entries.push(entry.clone())).unwrap();
+
+            assert_eq!(
+                summary,
+                ArchiveSummary {
+                    entries: 3,
+                    files: 1,
+                    uncompressed_size: 5
+                },
+                "{codec:?}"
+            );
+            assert_eq!(
+                entries[1],
+                ArchiveEntry {
+                    path: "node_modules/a.js".to_string(),
+                    kind: "file",
+                    size: 5,
+                    mode: 0o644,
+                    mtime: MTIME,
+                    link: None,
+                }
+            );
+            assert_eq!(entries[0].kind, "dir");
+            assert_eq!(entries[2].kind, "symlink");
+            assert_eq!(entries[2].link.as_deref(), Some("a.js"));
+        }
+    }
+
+    #[test]
+    fn test_read_encrypted_archive() {
+        let keyring = keyring();
+        let (key_id, key) = keyring.active_key().unwrap();
+        let mut writer = EncryptWriter::new(Vec::new(), key_id, key).unwrap();
+        writer.write_all(&compress(Codec::Zstd, &tar_archive())).unwrap();
+        let data = writer.finish().unwrap();
+
+        let summary = read_archive(&data[..], Codec::Zstd, &keyring, |_| {}).unwrap();
+        assert_eq!(summary.entries, 3);
+
+        // 鍵が異なる場合は復号の失敗として扱う
+        let wrong_key = Keyring::parse(Some(&"22".repeat(32)), Some("k1"), None).unwrap();
+        let source = read_archive(&data[..], Codec::Zstd, &wrong_key, |_| {}).unwrap_err();
+        let error = InspectError::Archive {
+            object_key: "o".to_string(),
+            source,
+        };
+        assert!(error.is_integrity_error(), "{error:?}");
+    }
+
+    #[test]
+    fn test_stream_archive() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let body = ByteStream::from(compress(Codec::Gzip, &tar_archive()));
+        let mut paths = Vec::new();
+        let summary = stream_archive(&runtime, "o", body, Codec::Gzip, &Keyring::default(), |entry| {
+            paths.push(entry.path.clone())
+        })
+        .unwrap();
+        assert_eq!(summary.files, 1);
+        assert_eq!(paths, ["node_modules/", "node_modules/a.js", "node_modules/b.js"]);
+
+        // 圧縮方式の誤り等で読めない場合は展開の失敗として扱う
+        let body = ByteStream::from(b"not an archive".to_vec());
+        let error = stream_archive(&runtime, "o", body, Codec::Zstd, &Keyring::default(), |_| {}).unwrap_err();
+        assert!(matches!(error, InspectError::Archive { .. }), "{error:?}");
+        assert!(!error.is_integrity_error());
+    }
+
+    #[test]
+    fn test_entry_line_and_json() {
+        let entry = ArchiveEntry {
+            path: "node_modules/b.js".to_string(),
+            kind: "symlink",
+            size: 0,
+            mode: 0o755,
+            mtime: MTIME,
+            link: Some("a.js".to_string()),
+        };
+        assert_eq!(
+            entry.to_line(),
+            "lrwxr-xr-x            0 2025-10-09T08:53:20Z node_modules/b.js -> a.js"
+        );
+        let value = serde_json::to_value(&entry).unwrap();
+        assert_eq!(value["mode"], "0755");
+        assert_eq!(value["mtime"], "2025-10-09T08:53:20Z");
+    }
+}
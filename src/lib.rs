@@ -1,5 +1,24 @@
+pub mod i18n;
 pub mod error;
 pub mod file_matcher;
 pub mod hash_calculator;
 pub mod cache_key;
 pub mod setting;
+pub mod env;
+pub mod http_client;
+pub mod s3_client;
+pub mod encryption;
+pub mod object_key;
+pub mod session_policy;
+pub mod report;
+pub mod storage;
+pub mod http_storage;
+pub mod gitlab_storage;
+pub mod local_cache;
+pub mod catalog;
+pub mod retention;
+pub mod inspect;
+pub mod transfer;
+pub mod telemetry;
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,646 @@
+//! ランナー上のローカルキャッシュ（保存先の手前のL1キャッシュ）
+//!
+//! shell executor等で同じランナーが同じキャッシュを繰り返しダウンロードしないよう、
+//! ダウンロード・アップロードしたオブジェクトをランナーのディスクに保持する。
+//! restoreは保存先のメタデータ（HeadObject）のETagがローカルのものと一致する場合のみ
+//! ローカルのファイルを使うため、storeで置き換えられた古いキャッシュを使うことはない。
+//!
+//! 合計サイズが上限を超えた場合は、最後に使用した日時の古い順に削除する（LRU）。
+//! 同じランナーで並行して動くジョブが索引を壊さないよう、索引の読み書きと
+//! ファイルの配置・削除はロックファイルの排他ロックを取得して行う。
+
+use crate::i18n::tr;
+use crate::storage::{self, ObjectMetadata, Storage, StorageError, StorageFuture};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// ローカルキャッシュの合計サイズの上限の既定値（MiB）
+pub const DEFAULT_MAX_SIZE_MIB: u64 = 10 * 1024;
+
+/// 索引（オブジェクトキーごとのETag・サイズ・最終使用日時）のファイル名
+const INDEX_FILE_NAME: &str = "index.json";
+
+/// 排他ロックに使用するファイル名
+const LOCK_FILE_NAME: &str = ".lock";
+
+/// オブジェクトを保存するサブディレクトリ名
+const OBJECTS_DIR_NAME: &str = "objects";
+
+/// ダウンロード中の一時ファイルの接頭辞
+const TEMPORARY_PREFIX: &str = ".tmp-";
+
+/// 中断したジョブが残した一時ファイルを削除するまでの経過時間（秒）
+const STALE_TEMPORARY_SECS: u64 = 24 * 60 * 60;
+
+/// ローカルキャッシュの操作時のエラー
+///
+/// ローカルキャッシュが使えなくても保存先から直接読み書きできるため、警告のみとする。
+#[derive(Debug, thiserror::Error)]
+pub enum LocalCacheError {
+    #[error("{}", tr!("ローカルキャッシュの読み書きに失敗しました: {}: {source}", "failed to read or write the local cache: {}: {source}", .path.display()))]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// 索引ファイルの内容
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default)]
+    entries: BTreeMap<String, IndexEntry>,
+}
+
+impl Index {
+    fn total_size(&self) -> u64 {
+        self.entries.values().map(|entry| entry.size).sum()
+    }
+}
+
+/// 索引のエントリ（キーはオブジェクトキー）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct IndexEntry {
+    /// `objects`ディレクトリ内のファイル名（オブジェクトキーのSHA-256）
+    file: String,
+    /// 保存先でのETag
+    etag: String,
+    size: u64,
+    /// 最後にrestore・storeで使用した日時（UNIX時間）
+    last_used: i64,
+}
+
+/// ランナー上のローカルキャッシュのディレクトリ
+#[derive(Debug, Clone)]
+pub struct LocalCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl LocalCache {
+    /// `max_size`はバイト数
+    pub fn new(dir: PathBuf, max_size: u64) -> Self {
+        Self { dir, max_size }
+    }
+
+    fn objects_dir(&self) -> PathBuf {
+        self.dir.join(OBJECTS_DIR_NAME)
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join(INDEX_FILE_NAME)
+    }
+
+    /// ロックファイルの排他ロックを取得する（ファイルを閉じると解放される）
+    fn lock(&self) -> Result<File, LocalCacheError> {
+        let path = self.dir.join(LOCK_FILE_NAME);
+        let io_error = |source| LocalCacheError::Io {
+            path: path.clone(),
+            source,
+        };
+        std::fs::create_dir_all(self.objects_dir()).map_err(io_error)?;
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(io_error)?;
+        file.lock().map_err(io_error)?;
+        Ok(file)
+    }
+
+    /// 排他ロックを取得した状態で索引を読み込み、`update`の後に書き戻す
+    fn with_index<T>(
+        &self,
+        update: impl FnOnce(&mut Index) -> Result<T, LocalCacheError>,
+    ) -> Result<T, LocalCacheError> {
+        let _lock = self.lock()?;
+        let path = self.index_path();
+        let mut index = match std::fs::read(&path) {
+            // 壊れた索引は空として扱う（索引にないファイルは次の追加時に削除される）
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_default(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Index::default(),
+            Err(source) => return Err(LocalCacheError::Io { path, source }),
+        };
+        let result = update(&mut index)?;
+        let contents = serde_json::to_vec_pretty(&index).expect("index must be serializable");
+        storage::write_atomically(&path, |file| file.write_all(&contents)).map_err(|e| match e {
+            StorageError::Io { path, source } => LocalCacheError::Io { path, source },
+            e => LocalCacheError::Io {
+                path: path.clone(),
+                source: std::io::Error::other(e),
+            },
+        })?;
+        Ok(result)
+    }
+
+    /// ETagが一致するオブジェクトを開く（ない場合・ETagが異なる場合はNone）
+    ///
+    /// ETagが異なるオブジェクトは保存先で置き換えられているため、その場で削除する。
+    pub fn open(&self, object_key: &str, etag: &str, now: i64) -> Result<Option<File>, LocalCacheError> {
+        let objects_dir = self.objects_dir();
+        self.with_index(|index| {
+            let Some(entry) = index.entries.get_mut(object_key) else {
+                return Ok(None);
+            };
+            let path = objects_dir.join(&entry.file);
+            if entry.etag == etag {
+                // 他のジョブが削除・置き換えても、開いたファイルはそのまま読める
+                if let Ok(file) = File::open(&path) {
+                    if file.metadata().is_ok_and(|metadata| metadata.len() == entry.size) {
+                        entry.last_used = now;
+                        return Ok(Some(file));
+                    }
+                }
+            }
+            index.entries.remove(object_key);
+            remove_file(&path)?;
+            Ok(None)
+        })
+    }
+
+    /// ダウンロード・コピー先の一時ファイルを作成する
+    pub fn create_temporary(&self) -> Result<(PathBuf, File), LocalCacheError> {
+        let objects_dir = self.objects_dir();
+        let path = objects_dir.join(format!("{TEMPORARY_PREFIX}{}", storage::temporary_suffix()));
+        std::fs::create_dir_all(&objects_dir)
+            .and_then(|()| File::create(&path))
+            .map(|file| (path.clone(), file))
+            .map_err(|source| LocalCacheError::Io { path, source })
+    }
+
+    /// `create_temporary`で作成した一時ファイルをオブジェクトとして追加する
+    ///
+    /// 上限を超えた分は最後に使用した日時の古いオブジェクトから削除する。
+    /// オブジェクト単体で上限を超える場合は追加せずにfalseを返す。
+    pub fn commit(
+        &self,
+        object_key: &str,
+        etag: &str,
+        temporary: &Path,
+        now: i64,
+    ) -> Result<bool, LocalCacheError> {
+        let size = match std::fs::metadata(temporary) {
+            Ok(metadata) => metadata.len(),
+            Err(source) => {
+                let _ = std::fs::remove_file(temporary);
+                return Err(LocalCacheError::Io {
+                    path: temporary.to_path_buf(),
+                    source,
+                });
+            }
+        };
+        if size > self.max_size {
+            remove_file(temporary)?;
+            return Ok(false);
+        }
+        let objects_dir = self.objects_dir();
+        let file = object_file_name(object_key);
+        let result = self.with_index(|index| {
+            let path = objects_dir.join(&file);
+            std::fs::rename(temporary, &path).map_err(|source| LocalCacheError::Io { path, source })?;
+            index.entries.insert(
+                object_key.to_string(),
+                IndexEntry {
+                    file,
+                    etag: etag.to_string(),
+                    size,
+                    last_used: now,
+                },
+            );
+            self.evict(index, object_key)?;
+            Ok(true)
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(temporary);
+        }
+        result
+    }
+
+    /// `source`のファイルをコピーして追加する（storeでアップロードしたアーカイブ）
+    pub fn insert(&self, object_key: &str, etag: &str, source: &Path, now: i64) -> Result<bool, LocalCacheError> {
+        let (temporary, mut file) = self.create_temporary()?;
+        let copied = File::open(source).and_then(|mut input| std::io::copy(&mut input, &mut file));
+        drop(file);
+        if let Err(source_error) = copied {
+            let _ = std::fs::remove_file(&temporary);
+            return Err(LocalCacheError::Io {
+                path: source.to_path_buf(),
+                source: source_error,
+            });
+        }
+        self.commit(object_key, etag, &temporary, now)
+    }
+
+    /// オブジェクトを削除する（ない場合も成功とする）
+    pub fn remove(&self, object_key: &str) -> Result<(), LocalCacheError> {
+        let objects_dir = self.objects_dir();
+        self.with_index(|index| match index.entries.remove(object_key) {
+            Some(entry) => remove_file(&objects_dir.join(entry.file)),
+            None => Ok(()),
+        })
+    }
+
+    /// 合計サイズが上限に収まるまで、最後に使用した日時の古い順に削除する（`keep`は削除しない）
+    ///
+    /// 索引にないファイル（索引が壊れた場合等）と、中断したジョブが残した古い一時ファイルも削除する。
+    fn evict(&self, index: &mut Index, keep: &str) -> Result<(), LocalCacheError> {
+        let mut candidates: Vec<_> = index
+            .entries
+            .iter()
+            .filter(|(object_key, _)| object_key.as_str() != keep)
+            .map(|(object_key, entry)| (entry.last_used, object_key.clone()))
+            .collect();
+        candidates.sort();
+        let mut total_size = index.total_size();
+        for (_, object_key) in candidates {
+            if total_size <= self.max_size {
+                break;
+            }
+            if let Some(entry) = index.entries.remove(&object_key) {
+                remove_file(&self.objects_dir().join(entry.file))?;
+                total_size -= entry.size;
+            }
+        }
+
+        let objects_dir = self.objects_dir();
+        let read_dir = std::fs::read_dir(&objects_dir).map_err(|source| LocalCacheError::Io {
+            path: objects_dir.clone(),
+            source,
+        })?;
+        for dir_entry in read_dir.flatten() {
+            let name = dir_entry.file_name().to_string_lossy().into_owned();
+            let is_orphan = if name.starts_with(TEMPORARY_PREFIX) {
+                // 他のジョブがダウンロード中の一時ファイルは削除しない
+                dir_entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| modified.elapsed().ok())
+                    .is_some_and(|elapsed| elapsed.as_secs() >= STALE_TEMPORARY_SECS)
+            } else {
+                !index.entries.values().any(|entry| entry.file == name)
+            };
+            if is_orphan {
+                remove_file(&dir_entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// オブジェクトを保存するファイル名（オブジェクトキーのSHA-256）
+fn object_file_name(object_key: &str) -> String {
+    hex::encode(Sha256::digest(object_key.as_bytes()))
+}
+
+fn remove_file(path: &Path) -> Result<(), LocalCacheError> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(source) => Err(LocalCacheError::Io {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
+
+fn warn(error: &LocalCacheError) {
+    eprintln!(
+        "{}",
+        tr!(
+            "警告: ローカルキャッシュを使用せずに続行します: {error}",
+            "warning: continuing without the local cache: {error}"
+        )
+    );
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// 保存先の本文を、restore先とローカルキャッシュの一時ファイルの両方に書き込む
+///
+/// 一時ファイルへの書き込みに失敗した場合はローカルキャッシュへの追加のみを取りやめる。
+struct TeeWriter<'a> {
+    destination: &'a mut (dyn Write + Send),
+    file: File,
+    error: Option<std::io::Error>,
+}
+
+impl Write for TeeWriter<'_> {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        let len = self.destination.write(buffer)?;
+        if self.error.is_none() {
+            if let Err(e) = self.file.write_all(&buffer[..len]) {
+                self.error = Some(e);
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.destination.flush()
+    }
+}
+
+/// ローカルキャッシュを手前に置いた保存先
+pub struct LocalCachedStorage {
+    inner: Box<dyn Storage>,
+    cache: LocalCache,
+}
+
+impl LocalCachedStorage {
+    pub fn new(inner: Box<dyn Storage>, cache: LocalCache) -> Self {
+        Self { inner, cache }
+    }
+
+    /// 保存先のETagと一致すればローカルのファイルから、一致しなければ保存先から取得する
+    ///
+    /// 保存先から取得した場合は、取得と同時にローカルキャッシュの一時ファイルに書き込み、
+    /// 取得できたら追加する。
+    async fn get_cached(
+        &self,
+        object_key: &str,
+        destination: &mut (dyn Write + Send),
+    ) -> Result<Option<ObjectMetadata>, StorageError> {
+        let Some(remote) = self.inner.head(object_key).await? else {
+            if let Err(error) = self.cache.remove(object_key) {
+                warn(&error);
+            }
+            return Ok(None);
+        };
+        let Some(etag) = remote.etag.as_deref() else {
+            return self.inner.get(object_key, destination).await;
+        };
+        match self.cache.open(object_key, etag, now()) {
+            Ok(Some(mut file)) => {
+                let path = self.cache.objects_dir().join(object_file_name(object_key));
+                std::io::copy(&mut file, destination).map_err(|source| StorageError::Io { path, source })?;
+                return Ok(Some(remote));
+            }
+            Ok(None) => {}
+            Err(error) => warn(&error),
+        }
+
+        let (temporary, file) = match self.cache.create_temporary() {
+            Ok(temporary) => temporary,
+            Err(error) => {
+                warn(&error);
+                return self.inner.get(object_key, destination).await;
+            }
+        };
+        let mut tee = TeeWriter {
+            destination,
+            file,
+            error: None,
+        };
+        let result = self.inner.get(object_key, &mut tee).await;
+        let TeeWriter { file, error, .. } = tee;
+        drop(file);
+        // HEADの後に置き換えられた場合もあるため、取得した本文のETagで追加する
+        let committed = match (&result, error) {
+            (Ok(Some(ObjectMetadata { etag: Some(etag), .. })), None) => {
+                self.cache.commit(object_key, etag, &temporary, now()).map(|_| ())
+            }
+            (_, Some(source)) => Err(LocalCacheError::Io {
+                path: temporary.clone(),
+                source,
+            }),
+            _ => Ok(()),
+        };
+        if let Err(error) = committed {
+            warn(&error);
+        }
+        // 追加しなかった一時ファイルを削除する（追加した場合は既に移動している）
+        let _ = remove_file(&temporary);
+        result
+    }
+
+    async fn put_cached(
+        &self,
+        object_key: &str,
+        source: &Path,
+        metadata: &HashMap<String, String>,
+    ) -> Result<Option<String>, StorageError> {
+        let etag = self.inner.put(object_key, source, metadata).await?;
+        let result = match etag.as_deref() {
+            Some(etag) => self.cache.insert(object_key, etag, source, now()).map(|_| ()),
+            // ETagがなければ次回のrestoreで照合できないため、古いものを残さない
+            None => self.cache.remove(object_key),
+        };
+        if let Err(error) = result {
+            warn(&error);
+        }
+        Ok(etag)
+    }
+
+    async fn delete_cached(&self, object_key: &str) -> Result<(), StorageError> {
+        self.inner.delete(object_key).await?;
+        if let Err(error) = self.cache.remove(object_key) {
+            warn(&error);
+        }
+        Ok(())
+    }
+}
+
+impl Storage for LocalCachedStorage {
+    fn head<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        self.inner.head(object_key)
+    }
+
+    fn get<'a>(
+        &'a self,
+        object_key: &'a str,
+        destination: &'a mut (dyn Write + Send),
+    ) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        Box::pin(self.get_cached(object_key, destination))
+    }
+
+    fn put<'a>(
+        &'a self,
+        object_key: &'a str,
+        source: &'a Path,
+        metadata: &'a HashMap<String, String>,
+    ) -> StorageFuture<'a, Option<String>> {
+        Box::pin(self.put_cached(object_key, source, metadata))
+    }
+
+    fn delete<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(self.delete_cached(object_key))
+    }
+
+    fn record_access<'a>(&'a self, object_key: &'a str, now: i64) -> StorageFuture<'a, bool> {
+        self.inner.record_access(object_key, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FileStorage;
+
+    const OBJECT_KEY: &str = "group/app/node_modules/v1/deps-protected.tar.zst";
+
+    fn insert(cache: &LocalCache, object_key: &str, etag: &str, body: &[u8], now: i64) -> bool {
+        let source = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(source.path(), body).unwrap();
+        cache.insert(object_key, etag, source.path(), now).unwrap()
+    }
+
+    fn read(cache: &LocalCache, object_key: &str, etag: &str) -> Option<Vec<u8>> {
+        let mut file = cache.open(object_key, etag, 100).unwrap()?;
+        let mut body = Vec::new();
+        std::io::Read::read_to_end(&mut file, &mut body).unwrap();
+        Some(body)
+    }
+
+    fn object_files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(dir.join(OBJECTS_DIR_NAME))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_open_validates_etag() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path().to_path_buf(), 1024);
+        assert!(insert(&cache, OBJECT_KEY, "\"etag1\"", b"hello", 1));
+
+        assert_eq!(read(&cache, OBJECT_KEY, "\"etag1\""), Some(b"hello".to_vec()));
+        assert_eq!(read(&cache, "group/app/other.tar.zst", "\"etag1\""), None);
+        // 保存先で置き換えられたものは削除する
+        assert_eq!(read(&cache, OBJECT_KEY, "\"etag2\""), None);
+        assert_eq!(read(&cache, OBJECT_KEY, "\"etag1\""), None);
+        assert!(object_files(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_open_discards_truncated_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path().to_path_buf(), 1024);
+        insert(&cache, OBJECT_KEY, "\"etag1\"", b"hello", 1);
+        let path = dir.path().join(OBJECTS_DIR_NAME).join(object_file_name(OBJECT_KEY));
+        std::fs::write(&path, b"hel").unwrap();
+
+        assert_eq!(read(&cache, OBJECT_KEY, "\"etag1\""), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path().to_path_buf(), 10);
+        insert(&cache, "a", "\"a\"", b"aaaa", 1);
+        insert(&cache, "b", "\"b\"", b"bbbb", 2);
+        // aを使用したため、次に古いbが削除される
+        cache.open("a", "\"a\"", 3).unwrap().unwrap();
+        insert(&cache, "c", "\"c\"", b"cccc", 4);
+
+        assert!(cache.open("a", "\"a\"", 5).unwrap().is_some());
+        assert!(cache.open("b", "\"b\"", 5).unwrap().is_none());
+        assert!(cache.open("c", "\"c\"", 5).unwrap().is_some());
+        assert_eq!(object_files(dir.path()).len(), 2);
+
+        // 上限を超えるオブジェクトは追加しない
+        assert!(!insert(&cache, "d", "\"d\"", &[0; 11], 6));
+        assert!(cache.open("a", "\"a\"", 7).unwrap().is_some());
+        assert_eq!(object_files(dir.path()).len(), 2);
+    }
+
+    #[test]
+    fn test_removes_orphan_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path().to_path_buf(), 1024);
+        insert(&cache, "a", "\"a\"", b"aaaa", 1);
+        // 索引が壊れた場合、既存のファイルは次の追加時に削除される
+        std::fs::write(dir.path().join(INDEX_FILE_NAME), b"{").unwrap();
+        let (in_progress, _file) = cache.create_temporary().unwrap();
+        insert(&cache, "b", "\"b\"", b"bbbb", 2);
+
+        assert_eq!(
+            object_files(dir.path()),
+            vec![
+                in_progress.file_name().unwrap().to_string_lossy().into_owned(),
+                object_file_name("b")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = LocalCache::new(dir.path().to_path_buf(), 8 * 4);
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let cache = cache.clone();
+                scope.spawn(move || {
+                    for i in 0..10 {
+                        let key = format!("{thread}-{i}");
+                        insert(&cache, &key, &key, b"data", i);
+                    }
+                });
+            }
+        });
+
+        // 索引が壊れず、上限と索引・ファイルが一致している
+        let index: Index = serde_json::from_slice(&std::fs::read(dir.path().join(INDEX_FILE_NAME)).unwrap()).unwrap();
+        assert!(index.total_size() <= 8 * 4);
+        let mut files: Vec<_> = index.entries.values().map(|entry| entry.file.clone()).collect();
+        files.sort();
+        assert_eq!(object_files(dir.path()), files);
+    }
+
+    #[test]
+    fn test_local_cached_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote_dir = dir.path().join("remote");
+        let cache = LocalCache::new(dir.path().join("local"), 1024);
+        let storage = LocalCachedStorage::new(Box::new(FileStorage::new(remote_dir.clone())), cache.clone());
+        let source = dir.path().join("archive");
+        std::fs::write(&source, b"hello").unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        runtime.block_on(async {
+            // storeでローカルにも保存する
+            let etag = storage.put(OBJECT_KEY, &source, &HashMap::new()).await.unwrap().unwrap();
+            assert!(cache.open(OBJECT_KEY, &etag, 1).unwrap().is_some());
+
+            // 保存先のETagが一致すればローカルのファイルを使う
+            // （保存先の内容をサイズ・更新日時を変えずに書き換え、ダウンロードしていないことを確かめる）
+            let remote = std::fs::OpenOptions::new().write(true).open(remote_dir.join(OBJECT_KEY)).unwrap();
+            let modified = remote.metadata().unwrap().modified().unwrap();
+            (&remote).write_all(b"HELLO").unwrap();
+            remote.set_modified(modified).unwrap();
+            let mut body = Vec::new();
+            storage.get(OBJECT_KEY, &mut body).await.unwrap().unwrap();
+            assert_eq!(body, b"hello");
+
+            // 保存先で置き換えられた場合はダウンロードし、ローカルも置き換える
+            std::fs::write(&source, b"world").unwrap();
+            FileStorage::new(remote_dir.clone())
+                .put(OBJECT_KEY, &source, &HashMap::new())
+                .await
+                .unwrap();
+            let mut body = Vec::new();
+            let object = storage.get(OBJECT_KEY, &mut body).await.unwrap().unwrap();
+            assert_eq!(body, b"world");
+            assert_eq!(read(&cache, OBJECT_KEY, object.etag.as_deref().unwrap()), Some(b"world".to_vec()));
+
+            // 削除はローカルにも反映する
+            storage.delete(OBJECT_KEY).await.unwrap();
+            assert_eq!(storage.get(OBJECT_KEY, &mut Vec::new()).await.unwrap(), None);
+            assert!(object_files(&dir.path().join("local")).is_empty());
+        });
+    }
+}
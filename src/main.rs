@@ -1,12 +1,19 @@
-mod env;
-mod setting;
-mod s3_client;
-mod error;
-mod file_matcher;
-mod hash_calculator;
-mod cache_key;
 use bpaf::*;
-use std::path::PathBuf;
+use cafce::catalog::{self, Catalog, DeleteSelector, ListFilter};
+use cafce::error::CafceError;
+use cafce::gitlab_storage::GitLabStorage;
+use cafce::http_storage::HttpStorage;
+use cafce::i18n::tr;
+use cafce::local_cache::{LocalCache, LocalCachedStorage};
+use cafce::report::{CacheReport, OutputFormat, ReportError};
+use cafce::retention::ProtectedKeys;
+use cafce::storage::{self, FileStorage, S3Storage, Storage, StorageUrl};
+use cafce::{
+    cache_key, encryption, env, file_matcher, inspect, object_key, s3_client, session_policy, setting, telemetry, transfer,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 #[derive(Debug, Clone, Bpaf)]
 #[allow(dead_code)]
@@ -19,34 +26,875 @@ struct Opts {
 #[derive(Debug, Clone, Bpaf)]
 enum Action {
     #[bpaf(command)]
-    Store { config: PathBuf },
+    Store {
+        config: PathBuf,
+        #[bpaf(external(output_format))]
+        output: OutputFormat,
+    },
 
     #[bpaf(command)]
-    Restore { config: PathBuf },
+    Restore {
+        config: PathBuf,
+        #[bpaf(external(output_format))]
+        output: OutputFormat,
+        #[bpaf(argument("PATH"), help(tr!("復元結果（CAFCE_CACHE_HIT等）をdotenv形式で書き込むファイル", "file to write the restore result (CAFCE_CACHE_HIT etc.) to in dotenv format").as_str()))]
+        dotenv: Option<PathBuf>,
+    },
+
+    Key(#[bpaf(external(key_args))] KeyArgs),
+
+    List(#[bpaf(external(list_args))] ListArgs),
+
+    Prune(#[bpaf(external(prune_args))] PruneArgs),
+
+    Delete(#[bpaf(external(delete_args))] DeleteArgs),
+
+    Inspect(#[bpaf(external(inspect_args))] InspectArgs),
 
     #[bpaf(command)]
     Init { config: PathBuf },
+
+    #[bpaf(command)]
+    Policy {
+        #[bpaf(external(policy_action))]
+        action: PolicyAction,
+    },
+}
+
+// サブコマンドの説明をメッセージの言語に合わせるため、説明を持つサブコマンドは
+// rustdocではなく`help`で説明を指定する（enumのバリアントには`help`を指定できない）
+
+#[derive(Debug, Clone, Bpaf)]
+#[bpaf(command("key"), help(tr!("キャッシュキーを計算して出力する", "compute and print the cache key").as_str()))]
+struct KeyArgs {
+    config: PathBuf,
+    #[bpaf(external(output_format))]
+    output: OutputFormat,
+}
+
+#[derive(Debug, Clone, Bpaf)]
+#[bpaf(command("list"), help(tr!("バケット内のキャッシュを一覧する", "list the caches in the bucket").as_str()))]
+struct ListArgs {
+    config: PathBuf,
+    #[bpaf(argument("NAME"), help(tr!("キャッシュ名で絞り込む", "only list caches with this cache name").as_str()))]
+    cache: Option<String>,
+    #[bpaf(argument("PREFIX"), help(tr!("キャッシュキーの前方一致で絞り込む", "only list cache keys starting with this prefix").as_str()))]
+    key_prefix: Option<String>,
+    #[bpaf(external(output_format))]
+    output: OutputFormat,
+}
+
+#[derive(Debug, Clone, Bpaf)]
+#[bpaf(command("prune"), help(tr!("設定ファイルの保持ルール（[retention]）に従って古いキャッシュを削除する", "delete old caches according to the retention rules ([retention]) in the config file").as_str()))]
+struct PruneArgs {
+    config: PathBuf,
+    #[bpaf(external(dry_run))]
+    dry_run: bool,
+    #[bpaf(external(output_format))]
+    output: OutputFormat,
+}
+
+#[derive(Debug, Clone, Bpaf)]
+#[bpaf(command("delete"), help(tr!("キャッシュを削除する、またはキャッシュの世代を増やして全て無効化する", "delete caches, or bump the cache generation to invalidate all of them").as_str()))]
+struct DeleteArgs {
+    config: PathBuf,
+    #[bpaf(argument("NAME"), help(tr!("キャッシュ名で削除対象を指定する", "only delete caches with this cache name").as_str()))]
+    cache: Option<String>,
+    #[bpaf(help(tr!("プロジェクトのキャッシュの世代を増やし、全てのキャッシュを無効化する", "bump the project's cache generation, invalidating all caches").as_str()))]
+    bump_generation: bool,
+    #[bpaf(external(dry_run))]
+    dry_run: bool,
+    #[bpaf(external(output_format))]
+    output: OutputFormat,
+    #[bpaf(positional("KEY"), help(tr!("削除するキャッシュキー（globパターン可）", "cache keys to delete (glob patterns allowed)").as_str()))]
+    keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Bpaf)]
+#[bpaf(command("inspect"), help(tr!("キャッシュのメタデータと、アーカイブに含まれるファイルの一覧を表示する（展開はしない）", "show a cache's metadata and the files in its archive (without extracting)").as_str()))]
+struct InspectArgs {
+    config: PathBuf,
+    #[bpaf(argument("NAME"), help(tr!("キャッシュ名（省略時は設定ファイルのキャッシュ名）", "cache name (defaults to the cache name in the config file)").as_str()))]
+    cache: Option<String>,
+    #[bpaf(external(output_format))]
+    output: OutputFormat,
+    #[bpaf(positional("KEY"), help(tr!("表示するキャッシュキー（`cafce list`のKEY）", "cache key to show (KEY in `cafce list`)").as_str()))]
+    key: String,
+}
+
+#[derive(Debug, Clone, Bpaf)]
+enum PolicyAction {
+    Print(#[bpaf(external(print_policy_args))] PrintPolicyArgs),
+}
+
+#[derive(Debug, Clone, Bpaf)]
+#[bpaf(command("print"), help(tr!("AssumeRole時に渡すセッションポリシーを出力する", "print the session policy to pass to AssumeRole").as_str()))]
+struct PrintPolicyArgs {
+    config: PathBuf,
+}
+
+/// `--output`（出力形式）
+fn output_format() -> impl Parser<OutputFormat> {
+    long("output")
+        .help(tr!("出力形式（text / json）", "output format (text / json)").as_str())
+        .argument::<OutputFormat>("FORMAT")
+        .fallback(OutputFormat::Text)
+}
+
+/// `--dry-run`
+fn dry_run() -> impl Parser<bool> {
+    long("dry-run")
+        .help(tr!("削除せず、削除対象の表示のみ行う", "only show what would be deleted").as_str())
+        .switch()
+}
+
+/// 実行結果をレポートするサブコマンド
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CacheCommand {
+    Store,
+    Restore,
+    Key,
+}
+
+impl CacheCommand {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Store => "store",
+            Self::Restore => "restore",
+            Self::Key => "key",
+        }
+    }
 }
 
 fn main() {
     let ops = opts().run();
-    match ops.action {
-        Action::Init { config } => {
-            setting::Setting::init_to_file(&config).unwrap();
+    let result = match ops.action {
+        Action::Init { config } => setting::Setting::init_to_file(&config).map_err(CafceError::from),
+        Action::Store { config, output } => {
+            run_cache_command(CacheCommand::Store, &config, output, None)
+        }
+        Action::Restore {
+            config,
+            output,
+            dotenv,
+        } => run_cache_command(CacheCommand::Restore, &config, output, dotenv.as_deref()),
+        Action::Key(KeyArgs { config, output }) => {
+            run_cache_command(CacheCommand::Key, &config, output, None)
         }
-        Action::Store { config } => {
-            let environment = env::Env::new().unwrap();
-            let setting = setting::Setting::new_from_file(&config).unwrap();
-            println!("{config:#?}");
-            println!("{environment:#?}");
-            println!("{setting:#?}");
+        Action::List(ListArgs {
+            config,
+            cache,
+            key_prefix,
+            output,
+        }) => list_caches(
+            &config,
+            ListFilter {
+                cache_name: cache,
+                key_prefix,
+            },
+            output,
+        ),
+        Action::Prune(PruneArgs {
+            config,
+            dry_run,
+            output,
+        }) => prune_caches(&config, dry_run, output),
+        Action::Delete(DeleteArgs {
+            config,
+            cache,
+            bump_generation,
+            dry_run,
+            output,
+            keys,
+        }) => delete_caches(&config, cache, &keys, bump_generation, dry_run, output),
+        Action::Inspect(InspectArgs {
+            config,
+            cache,
+            output,
+            key,
+        }) => inspect_cache(&config, cache, &key, output),
+        Action::Policy {
+            action: PolicyAction::Print(PrintPolicyArgs { config }),
+        } => print_session_policy(&config),
+    };
+    if let Err(error) = result {
+        if error.category().is_cache_failure() {
+            eprintln!(
+                "{}",
+                tr!(
+                    "エラー: キャッシュ操作に失敗しました: {}",
+                    "error: cache operation failed: {}",
+                    error.message_with_causes()
+                )
+            );
+        } else {
+            eprintln!(
+                "{}",
+                tr!("エラー: {}", "error: {}", error.message_with_causes())
+            );
         }
-        Action::Restore { config } => {
-            let environment = env::Env::new().unwrap();
-            let setting = setting::Setting::new_from_file(&config).unwrap();
-            println!("{config:#?}");
-            println!("{environment:#?}");
-            println!("{setting:#?}");
+        std::process::exit(error.exit_code());
+    }
+}
+
+/// store / restore / keyを実行し、結果を`output`の形式で出力する
+///
+/// `on_error = "warn"`の場合、キャッシュ操作の失敗は警告を出力して成功扱いとする。
+/// JSON形式の場合は、失敗した場合もエラーを含めたレポートを出力する。
+/// `dotenv`を指定した場合、成功（警告のみの場合を含む）時に結果をdotenv形式で書き込む
+/// （書き込めない場合は警告のみとする）。
+/// 終了時に、設定されていればトレース・メトリクスを送信する。
+fn run_cache_command(
+    command: CacheCommand,
+    config: &Path,
+    output: OutputFormat,
+    dotenv: Option<&Path>,
+) -> Result<(), CafceError> {
+    let telemetry_env = env::Env::new().ok();
+    let traces = telemetry_env.as_ref().and_then(install_tracing);
+    let span = tracing::info_span!(
+        "cafce.run",
+        command = command.name(),
+        cache = tracing::field::Empty,
+        hit = tracing::field::Empty,
+        errors = tracing::field::Empty
+    );
+    let entered = span.enter();
+
+    let mut report = CacheReport::new(command.name());
+    let result = match execute_cache_command(command, config, &mut report) {
+        (Ok(()), _) => Ok(()),
+        (Err(error), setting::OnError::Warn) if error.category().is_cache_failure() => {
+            eprintln!(
+                "{}",
+                tr!(
+                    "警告: キャッシュ操作に失敗しましたが、on_error = \"warn\"のため続行します: {}",
+                    "warning: cache operation failed, continuing because on_error = \"warn\": {}",
+                    error.message_with_causes()
+                )
+            );
+            report.errors.push(ReportError::new(&error, true));
+            Ok(())
+        }
+        (Err(error), _) => {
+            report.errors.push(ReportError::new(&error, false));
+            Err(error)
+        }
+    };
+    // dotenvはジョブの補助的な出力のため、書き込めなくても警告のみとし、コマンドの結果は変えない
+    if let (Ok(()), Some(path)) = (&result, dotenv) {
+        let written = report.to_dotenv().map_err(CafceError::from).and_then(|dotenv| {
+            std::fs::write(path, dotenv).map_err(|source| CafceError::WriteReport {
+                path: path.to_path_buf(),
+                source,
+            })
+        });
+        if let Err(error) = written {
+            eprintln!(
+                "{}",
+                tr!("警告: {}", "warning: {}", error.message_with_causes())
+            );
+            report.errors.push(ReportError::new(&error, true));
+        }
+    }
+    if let Some(cache_name) = &report.cache_name {
+        span.record("cache", cache_name.as_str());
+    }
+    if let Some(hit) = report.hit {
+        span.record("hit", hit);
+    }
+    span.record("errors", report.errors.len());
+    drop(entered);
+    drop(span);
+    if let Some(telemetry_env) = &telemetry_env {
+        send_telemetry(command, telemetry_env, traces, &report);
+    }
+
+    match output {
+        OutputFormat::Json => println!("{}", report.to_json()),
+        OutputFormat::Text if result.is_err() => {}
+        OutputFormat::Text if command == CacheCommand::Key => {
+            println!("{}", report.key.as_deref().unwrap_or_default());
         }
+        OutputFormat::Text => println!("{}", report.to_text()),
+    }
+    result
+}
+
+/// トレースの記録を開始する（失敗しても警告のみとし、トレースなしで続行する）
+fn install_tracing(environment: &env::Env) -> Option<telemetry::Tracing> {
+    let ci_environment = env::CiEnv::new().unwrap_or_default();
+    telemetry::install_tracing(environment, &ci_environment).unwrap_or_else(|error| {
+        warn_telemetry(error);
+        None
+    })
+}
+
+fn warn_telemetry(error: telemetry::TelemetryError) {
+    eprintln!(
+        "{}",
+        tr!(
+            "警告: トレース・メトリクスの送信に失敗しました: {}",
+            "warning: failed to send traces or metrics: {}",
+            error
+        )
+    );
+}
+
+/// トレース・メトリクスを送信する（失敗しても警告のみとし、コマンドの結果には影響させない）
+fn send_telemetry(
+    command: CacheCommand,
+    environment: &env::Env,
+    traces: Option<telemetry::Tracing>,
+    report: &CacheReport,
+) {
+    if let Some(traces) = traces {
+        telemetry::export_traces(traces).unwrap_or_else(warn_telemetry);
+    }
+    if command != CacheCommand::Key {
+        let ci_environment = env::CiEnv::new().unwrap_or_default();
+        telemetry::push_metrics(environment, &ci_environment, report).unwrap_or_else(warn_telemetry);
     }
 }
+
+/// 設定を読み込んでコマンドを実行する
+///
+/// 実行結果とともに、エラーに適用する`on_error`を返す
+/// （設定の読み込みに失敗した場合は`on_error`が決まらないため"fail"とする）。
+fn execute_cache_command(
+    command: CacheCommand,
+    config: &Path,
+    report: &mut CacheReport,
+) -> (Result<(), CafceError>, setting::OnError) {
+    let started = Instant::now();
+    let loaded = env::Env::new().map_err(CafceError::from).and_then(|environment| {
+        let setting = setting::Setting::new_from_file(config)?;
+        Ok((environment, setting))
+    });
+    let (environment, setting) = match loaded {
+        Ok(loaded) => loaded,
+        Err(error) => return (Err(error), setting::OnError::Fail),
+    };
+    report.durations_ms.record("config", started.elapsed());
+
+    let on_error = setting.on_error(environment.on_error());
+    (execute(command, &environment, &setting, report), on_error)
+}
+
+fn execute(
+    command: CacheCommand,
+    environment: &env::Env,
+    setting: &setting::Setting,
+    report: &mut CacheReport,
+) -> Result<(), CafceError> {
+    report.cache_name = Some(setting.cache_name().to_string());
+    report.bucket = setting.bucket().map(String::from);
+
+    let ci_environment = env::CiEnv::new()?;
+    let base_path = std::env::current_dir().map_err(CafceError::CurrentDir)?;
+    let generator = cache_key::CacheKeyGenerator::new(file_matcher::MAX_FILES, base_path.clone());
+    let keys = report
+        .durations_ms
+        .measure("key", || {
+            generator.generate_cache_keys(setting, ci_environment.cache_namespace(setting.unprotect()))
+        })
+        .map_err(CafceError::CacheKey)?;
+    set_report_keys(report, setting, &ci_environment, &keys)?;
+    // keyはクレデンシャルなしでも使えるよう保存先に接続しないため、世代を反映する前のキーを出力する
+    if command == CacheCommand::Key {
+        return Ok(());
+    }
+
+    let keyring = encryption::Keyring::from_env(environment)?;
+
+    // 保存先に接続する（S3のクレデンシャル（AssumeRole等）は最初のリクエストの際に取得する）
+    let storage_url = StorageUrl::parse(setting.storage(environment.storage()))?;
+    let runtime = new_runtime()?;
+    let storage = report.durations_ms.measure("connect", || {
+        let _span = tracing::info_span!("cafce.connect").entered();
+        runtime.block_on(connect_storage(environment, &ci_environment, setting, storage_url))
+    })?;
+    // S3でバケットが未指定の場合は保存先がないため、設定の確認のみとする
+    let Some(storage) = storage else {
+        return Ok(());
+    };
+
+    let template = setting.object_key_template()?;
+    let context = setting.object_key_context(ci_environment.project_path());
+
+    // プロジェクトのキャッシュの世代（`cafce delete --bump-generation`）を全てのキーに反映する
+    // （`{project}`のプレフィックスがないテンプレートでは世代を持てないため、常に0とする）
+    let generation = if template.has_project_prefix() {
+        let generation_object_key = template.generation_object_key(&context)?;
+        let generation = report.durations_ms.measure("generation", || {
+            runtime.block_on(storage::read_generation(storage.as_ref(), &generation_object_key))
+        });
+        match generation {
+            // 世代が分からなければ復元するキーも決まらないため、キャッシュミスとする
+            Err(error) if command == CacheCommand::Restore && storage::is_restore_miss(environment, &error) => {
+                warn_restore_miss(error);
+                report.set_matched_key(None);
+                return Ok(());
+            }
+            generation => generation?,
+        }
+    } else {
+        0
+    };
+    report.generation = Some(generation);
+    let keys = keys.with_generation(generation);
+    set_report_keys(report, setting, &ci_environment, &keys)?;
+
+    // ランナー上のローカルキャッシュを保存先の手前に置く
+    // （世代は小さく、毎回保存先の値を使う必要があるため、ローカルキャッシュを通さずに取得する）
+    let storage: Box<dyn Storage> = match environment.local_cache_dir() {
+        Some(dir) => {
+            let max_size = environment.local_cache_max_size_mib().saturating_mul(1024 * 1024);
+            Box::new(LocalCachedStorage::new(storage, LocalCache::new(dir.to_path_buf(), max_size)))
+        }
+        None => storage,
+    };
+
+    match command {
+        CacheCommand::Store => {
+            let object_key = template.render(&context, &keys.primary)?;
+            let mut metadata = HashMap::from([
+                (catalog::METADATA_CACHE_NAME.to_string(), setting.cache_name().to_string()),
+                (catalog::METADATA_CODEC.to_string(), setting.codec().name().to_string()),
+            ]);
+            if let Some(pipeline_id) = ci_environment.pipeline_id() {
+                metadata.insert(catalog::METADATA_PIPELINE_ID.to_string(), pipeline_id.to_string());
+            }
+            let stored = report.durations_ms.measure("transfer", || {
+                runtime.block_on(transfer::store(
+                    storage.as_ref(),
+                    &object_key,
+                    setting.paths(),
+                    &base_path,
+                    setting.codec(),
+                    &metadata,
+                    &keyring,
+                ))
+            })?;
+            report.bytes_transferred = Some(stored.compressed_size);
+            report.compressed_size = Some(stored.compressed_size);
+            report.uncompressed_size = Some(stored.uncompressed_size);
+        }
+        CacheCommand::Restore => {
+            let candidates = keys
+                .iter()
+                .map(|key| Ok((key.to_string(), template.render(&context, key)?)))
+                .collect::<Result<Vec<_>, object_key::ObjectKeyError>>()?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64;
+            let restored = report.durations_ms.measure("transfer", || {
+                runtime.block_on(transfer::restore(
+                    storage.as_ref(),
+                    &candidates,
+                    setting.codec(),
+                    &base_path,
+                    &keyring,
+                    now,
+                ))
+            });
+            let restored = match restored {
+                Err(transfer::TransferError::Storage(error)) if storage::is_restore_miss(environment, &error) => {
+                    warn_restore_miss(error);
+                    None
+                }
+                restored => restored?,
+            };
+            report.set_matched_key(restored.as_ref().map(|restored| restored.key.as_str()));
+            if let Some(restored) = restored {
+                report.bytes_transferred = Some(restored.object.size);
+                report.compressed_size = Some(restored.object.size);
+                report.uncompressed_size = Some(restored.uncompressed_size);
+                if let Some(error) = restored.access_error {
+                    eprintln!(
+                        "{}",
+                        tr!(
+                            "警告: {}",
+                            "warning: {}",
+                            CafceError::from(error).message_with_causes()
+                        )
+                    );
+                }
+            }
+        }
+        CacheCommand::Key => unreachable!("key returns before connecting to the storage"),
+    }
+    Ok(())
+}
+
+/// restoreのタイムアウトを、CAFCE_RESTORE_TIMEOUT_AS_MISSによりキャッシュミスとして扱うことを警告する
+fn warn_restore_miss(error: storage::StorageError) {
+    eprintln!(
+        "{}",
+        tr!(
+            "警告: タイムアウトしたため、キャッシュミスとして続行します: {}",
+            "warning: treating the restore as a cache miss because it timed out: {}",
+            CafceError::from(error).message_with_causes()
+        )
+    );
+}
+
+/// 保存先に接続する（S3でバケットが未指定の場合はNone）
+async fn connect_storage(
+    environment: &env::Env,
+    ci_environment: &env::CiEnv,
+    setting: &setting::Setting,
+    url: StorageUrl,
+) -> Result<Option<Box<dyn Storage>>, CafceError> {
+    match url {
+        StorageUrl::S3 => {
+            let client = s3_client::build_s3_client_for_cache(environment, ci_environment, setting).await?;
+            let Some(bucket) = setting.bucket() else {
+                return Ok(None);
+            };
+            let sse = s3_client::SseConfig::from_env(environment)?;
+            Ok(Some(Box::new(S3Storage::new(client, bucket, sse))))
+        }
+        StorageUrl::File(root) => Ok(Some(Box::new(FileStorage::new(root)))),
+        StorageUrl::Http(url) => Ok(Some(Box::new(HttpStorage::new(environment, url)?))),
+        StorageUrl::GitLab => Ok(Some(Box::new(GitLabStorage::new(
+            environment,
+            ci_environment,
+            setting.object_key_template()?,
+            &setting.object_key_context(ci_environment.project_path()),
+        )?))),
+    }
+}
+
+/// キャッシュキーと、プライマリキーに対応するオブジェクトキーをレポートに記録する
+fn set_report_keys(
+    report: &mut CacheReport,
+    setting: &setting::Setting,
+    ci_environment: &env::CiEnv,
+    keys: &cache_key::CacheKeys,
+) -> Result<(), CafceError> {
+    let template = setting.object_key_template()?;
+    let context = setting.object_key_context(ci_environment.project_path());
+    let object_keys = keys
+        .iter()
+        .map(|key| template.render(&context, key))
+        .collect::<Result<Vec<_>, _>>()?;
+    report.key = Some(keys.primary.clone());
+    report.fallback_keys = keys.fallbacks.clone();
+    report.object_key = object_keys.into_iter().next();
+    Ok(())
+}
+
+/// サブコマンドの非同期処理を実行するシングルスレッドのランタイムを作成する
+fn new_runtime() -> Result<tokio::runtime::Runtime, CafceError> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(CafceError::Runtime)
+}
+
+/// list・prune・delete・inspectの保存先がS3か確認する
+///
+/// これらのサブコマンドはS3のAPIでバケットを直接操作するため、S3以外の保存先を設定している場合は
+/// store / restoreと異なる場所を操作しないよう、設定の誤りとする。
+fn require_s3_storage(environment: &env::Env, setting: &setting::Setting) -> Result<(), CafceError> {
+    let url = setting.storage(environment.storage());
+    match StorageUrl::parse(url)? {
+        StorageUrl::S3 => Ok(()),
+        _ => Err(catalog::CatalogError::UnsupportedStorage {
+            url: url.unwrap_or_default().to_string(),
+        }
+        .into()),
+    }
+}
+
+/// バケット内のキャッシュを一覧し、`output`の形式（表 / JSON）で出力する
+///
+/// キャッシュ名を問わず一覧するため、キャッシュのプレフィックスに限定したセッションポリシーは付与しない。
+fn list_caches(config: &Path, filter: ListFilter, output: OutputFormat) -> Result<(), CafceError> {
+    let environment = env::Env::new()?;
+    let setting = setting::Setting::new_from_file(config)?;
+    require_s3_storage(&environment, &setting)?;
+    let ci_environment = env::CiEnv::new()?;
+    let sse = s3_client::SseConfig::from_env(&environment)?;
+
+    let runtime = new_runtime()?;
+    let entries = runtime.block_on(async {
+        let client = s3_client::build_s3_client(&environment, &ci_environment).await?;
+        let catalog = Catalog::new(client, &setting, ci_environment.project_path(), sse)?;
+        Ok::<_, CafceError>(catalog.list(&filter).await?)
+    })?;
+
+    match output {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&entries).expect("entries must be serializable")
+        ),
+        OutputFormat::Text => print!("{}", catalog::entries_to_table(&entries)),
+    }
+    Ok(())
+}
+
+/// 保持ルールに従ってキャッシュを削除し、結果を`output`の形式で出力する
+///
+/// 保護するfallback_keysは設定ファイルのキャッシュのものしか分からないため、
+/// 設定ファイルのキャッシュ名のキャッシュのみを対象とする。
+fn prune_caches(config: &Path, dry_run: bool, output: OutputFormat) -> Result<(), CafceError> {
+    let environment = env::Env::new()?;
+    let setting = setting::Setting::new_from_file(config)?;
+    require_s3_storage(&environment, &setting)?;
+    let ci_environment = env::CiEnv::new()?;
+    let sse = s3_client::SseConfig::from_env(&environment)?;
+    let retention = setting.retention();
+
+    let runtime = new_runtime()?;
+    let plan = runtime.block_on(async {
+        let client = s3_client::build_s3_client(&environment, &ci_environment).await?;
+        let catalog = Catalog::new(client, &setting, ci_environment.project_path(), sse)?;
+        // store / restoreと同様、`{project}`のプレフィックスがないテンプレートの世代は常に0
+        let generation = if setting.object_key_template()?.has_project_prefix() {
+            catalog.generation().await?
+        } else {
+            0
+        };
+        let protected = ProtectedKeys::new(setting.fallback_keys(), &retention.protect, generation)?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let plan = catalog
+            .prune(setting.cache_name(), &retention, &protected, now, dry_run)
+            .await?;
+        Ok::<_, CafceError>(plan)
+    })?;
+
+    match output {
+        OutputFormat::Json => {
+            let mut value = serde_json::to_value(&plan).expect("plan must be serializable");
+            value["dry_run"] = dry_run.into();
+            value["freed_bytes"] = plan.freed_bytes().into();
+            println!("{}", serde_json::to_string_pretty(&value).expect("plan must be serializable"));
+        }
+        OutputFormat::Text => {
+            if retention.is_empty() {
+                eprintln!(
+                    "{}",
+                    tr!(
+                        "保持ルール（[retention]）が設定されていないため、削除するキャッシュはありません",
+                        "no retention rules ([retention]) are configured, so nothing is pruned"
+                    )
+                );
+            }
+            let action = deleted_label(dry_run);
+            for candidate in &plan.delete {
+                println!(
+                    "{action} {} ({}, {})",
+                    candidate.entry.object_key,
+                    candidate.reason.name(),
+                    catalog::format_size(candidate.entry.size)
+                );
+            }
+            println!(
+                "{}",
+                tr!(
+                    "削除: {}件（{}）、保持: {}件（{}、うち保護: {}件）",
+                    "deleted: {} ({}), kept: {} ({}, protected: {})",
+                    plan.delete.len(),
+                    catalog::format_size(plan.freed_bytes()),
+                    plan.kept,
+                    catalog::format_size(plan.kept_bytes),
+                    plan.protected
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+/// prune・deleteのテキスト出力で、削除した（`dry_run`の場合は削除する）キャッシュの行に付ける表記
+fn deleted_label(dry_run: bool) -> String {
+    if dry_run {
+        tr!("削除予定", "would delete")
+    } else {
+        tr!("削除済み", "deleted")
+    }
+}
+
+/// 指定したキャッシュを削除し、結果を`output`の形式で出力する
+///
+/// `bump_generation`の場合は、削除に加えて（またはキー等の指定がなければ削除せずに）
+/// プロジェクトのキャッシュの世代を増やす。世代を増やすと全てのキーが変わるため、
+/// オブジェクトの数に関わらず即座に全てのキャッシュが使われなくなる。
+fn delete_caches(
+    config: &Path,
+    cache: Option<String>,
+    keys: &[String],
+    bump_generation: bool,
+    dry_run: bool,
+    output: OutputFormat,
+) -> Result<(), CafceError> {
+    let environment = env::Env::new()?;
+    let setting = setting::Setting::new_from_file(config)?;
+    require_s3_storage(&environment, &setting)?;
+    let ci_environment = env::CiEnv::new()?;
+    let sse = s3_client::SseConfig::from_env(&environment)?;
+    let selector = if bump_generation && cache.is_none() && keys.is_empty() {
+        None
+    } else {
+        Some(DeleteSelector::new(cache, keys)?)
+    };
+
+    let runtime = new_runtime()?;
+    let (deleted, generation) = runtime.block_on(async {
+        let client = s3_client::build_s3_client(&environment, &ci_environment).await?;
+        let catalog = Catalog::new(client, &setting, ci_environment.project_path(), sse)?;
+
+        let mut deleted = Vec::new();
+        if let Some(selector) = &selector {
+            deleted = catalog.list(&selector.list_filter()).await?;
+            deleted.retain(|entry| selector.matches(entry));
+            if !dry_run {
+                let object_keys: Vec<String> = deleted.iter().map(|entry| entry.object_key.clone()).collect();
+                catalog.delete(&object_keys).await?;
+            }
+        }
+
+        let mut generation = None;
+        if bump_generation {
+            let previous = catalog.generation().await?;
+            let current = if dry_run { previous + 1 } else { catalog.bump_generation().await? };
+            generation = Some((previous, current));
+        }
+        Ok::<_, CafceError>((deleted, generation))
+    })?;
+
+    let freed_bytes: u64 = deleted.iter().map(|entry| entry.size).sum();
+    match output {
+        OutputFormat::Json => {
+            let value = serde_json::json!({
+                "dry_run": dry_run,
+                "deleted": deleted,
+                "freed_bytes": freed_bytes,
+                "generation": generation.map(|(previous, current)| {
+                    serde_json::json!({ "previous": previous, "current": current })
+                }),
+            });
+            println!("{}", serde_json::to_string_pretty(&value).expect("result must be serializable"));
+        }
+        OutputFormat::Text => {
+            let action = deleted_label(dry_run);
+            for entry in &deleted {
+                println!(
+                    "{action} {} ({})",
+                    entry.object_key,
+                    catalog::format_size(entry.size)
+                );
+            }
+            if selector.is_some() {
+                println!(
+                    "{}",
+                    tr!(
+                        "削除: {}件（{}）",
+                        "deleted: {} ({})",
+                        deleted.len(),
+                        catalog::format_size(freed_bytes)
+                    )
+                );
+            }
+            if let Some((previous, current)) = generation {
+                println!(
+                    "{}",
+                    tr!(
+                        "キャッシュの世代: {previous} -> {current}",
+                        "cache generation: {previous} -> {current}"
+                    )
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// キャッシュのメタデータとアーカイブ内のファイルの一覧を`output`の形式で出力する
+///
+/// アーカイブは受信しながら読むため、テキスト形式ではファイルを読んだ順に出力する。
+fn inspect_cache(
+    config: &Path,
+    cache: Option<String>,
+    key: &str,
+    output: OutputFormat,
+) -> Result<(), CafceError> {
+    let environment = env::Env::new()?;
+    let setting = setting::Setting::new_from_file(config)?;
+    require_s3_storage(&environment, &setting)?;
+    let ci_environment = env::CiEnv::new()?;
+    let sse = s3_client::SseConfig::from_env(&environment)?;
+    let keyring = encryption::Keyring::from_env(&environment)?;
+    let cache_name = cache.unwrap_or_else(|| setting.cache_name().to_string());
+
+    let runtime = new_runtime()?;
+    let (info, body) = runtime.block_on(async {
+        let client = s3_client::build_s3_client(&environment, &ci_environment).await?;
+        let catalog = Catalog::new(client, &setting, ci_environment.project_path(), sse)?;
+        let entry = catalog.find(&cache_name, key).await?;
+        let object = catalog.get(&entry.object_key).await?;
+        Ok::<_, CafceError>((inspect::ObjectInfo::new(entry, &object), object.body))
+    })?;
+    let codec = info.entry.codec.unwrap_or(setting.codec());
+
+    match output {
+        OutputFormat::Json => {
+            let mut entries = Vec::new();
+            let summary = inspect::stream_archive(
+                &runtime,
+                &info.entry.object_key,
+                body,
+                codec,
+                &keyring,
+                |entry| entries.push(entry.clone()),
+            )?;
+            let value = serde_json::json!({
+                "object": info,
+                "entries": entries,
+                "summary": summary,
+            });
+            println!("{}", serde_json::to_string_pretty(&value).expect("result must be serializable"));
+        }
+        OutputFormat::Text => {
+            println!("{}\n", info.to_text());
+            let summary = inspect::stream_archive(
+                &runtime,
+                &info.entry.object_key,
+                body,
+                codec,
+                &keyring,
+                |entry| println!("{}", entry.to_line()),
+            )?;
+            println!(
+                "{}",
+                tr!(
+                    "エントリ: {}件（ファイル: {}件）、展開後のサイズ: {}",
+                    "entries: {} (files: {}), uncompressed size: {}",
+                    summary.entries,
+                    summary.files,
+                    catalog::format_size(summary.uncompressed_size)
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_session_policy(config: &Path) -> Result<(), CafceError> {
+    let environment = env::Env::new()?;
+    let setting = setting::Setting::new_from_file(config)?;
+    let ci_environment = env::CiEnv::new()?;
+    let policy = session_policy::CacheSessionPolicy::new(
+        &setting,
+        ci_environment.project_path(),
+        &environment,
+    )?;
+    println!("{}", policy.to_json_pretty());
+    Ok(())
+}
@@ -0,0 +1,526 @@
+use crate::i18n::tr;
+use serde::{Deserialize, Serialize};
+
+/// キャッシュキーの計算方式のバージョン
+///
+/// キャッシュキーの計算方法（ハッシュ対象・名前空間の付け方など）を変更した場合に上げる。
+/// テンプレートの`{version}`に展開されるため、古い方式のキーと衝突しなくなる。
+pub const KEY_SCHEME_VERSION: u32 = 1;
+
+/// オブジェクトキーのテンプレートの既定値
+pub const DEFAULT_OBJECT_KEY_TEMPLATE: &str = "{project}/{cache_name}/v{version}/{key}.{ext}";
+
+/// プロジェクトのキャッシュの世代を保存するオブジェクトの名前（プロジェクトのプレフィックス直下に置く）
+pub const GENERATION_OBJECT_NAME: &str = ".cafce-generation";
+
+/// オブジェクトキーのテンプレート解釈・展開時のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum ObjectKeyError {
+    #[error("{}", tr!("オブジェクトキーのテンプレートに未知の変数があります: {{{name}}}", "unknown variable in the object key template: {{{name}}}"))]
+    UnknownVariable { name: String },
+    #[error("{}", tr!("オブジェクトキーのテンプレートの括弧が対応していません: {template}", "unbalanced braces in the object key template: {template}"))]
+    UnbalancedBrace { template: String },
+    #[error("{}", tr!("オブジェクトキーのテンプレートには{{key}}が必須です: {template}", "the object key template must contain {{key}}: {template}"))]
+    MissingKeyVariable { template: String },
+    #[error("{}", tr!("テンプレートの{{project}}を展開できません（CI_PROJECT_PATHが設定されていません）", "cannot expand {{project}} in the template (CI_PROJECT_PATH is not set)"))]
+    MissingProject,
+    #[error("{}", tr!("オブジェクトキーのテンプレートの{{cache_name}}・{{key}}より前に{{project}}がないため、プロジェクト単位の操作（キャッシュの世代・キャッシュ名を指定しない一覧）はできません", "the object key template has no {{project}} before {{cache_name}} and {{key}}, so per-project operations (the cache generation, listing all cache names) are not available"))]
+    MissingProjectPrefix,
+    #[error("{}", tr!("不正なオブジェクトキーです: {key}", "invalid object key: {key}"))]
+    InvalidObjectKey { key: String },
+}
+
+/// キャッシュアーカイブの圧縮方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    Zstd,
+    Gzip,
+    None,
+}
+
+impl Codec {
+    /// オブジェクトキーに付与する拡張子（テンプレートの`{ext}`）
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zstd => "tar.zst",
+            Self::Gzip => "tar.gz",
+            Self::None => "tar",
+        }
+    }
+
+    /// 全ての圧縮方式
+    pub const ALL: [Codec; 3] = [Self::Zstd, Self::Gzip, Self::None];
+
+    /// 設定ファイル・メタデータでの名前（"zstd" / "gzip" / "none"）
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+            Self::None => "none",
+        }
+    }
+
+    /// 名前から圧縮方式を求める（`name()`の逆）
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|codec| codec.name() == name)
+    }
+}
+
+/// テンプレートで使用できる変数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    /// GitLabのプロジェクトパス（CI_PROJECT_PATH、例: "group/app"）
+    Project,
+    /// キャッシュ名（設定ファイルの`name`）
+    CacheName,
+    /// キャッシュキー（名前空間適用後）
+    Key,
+    /// 圧縮方式に応じた拡張子
+    Ext,
+    /// キャッシュキーの計算方式のバージョン
+    Version,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Result<Self, ObjectKeyError> {
+        match name {
+            "project" => Ok(Self::Project),
+            "cache_name" => Ok(Self::CacheName),
+            "key" => Ok(Self::Key),
+            "ext" => Ok(Self::Ext),
+            "version" => Ok(Self::Version),
+            _ => Err(ObjectKeyError::UnknownVariable {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Variable(Variable),
+}
+
+/// テンプレート展開時に使用する値（キャッシュキー以外）
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectKeyContext<'a> {
+    /// GitLabのプロジェクトパス（GitLab CI外ではNone）
+    pub project: Option<&'a str>,
+    pub cache_name: &'a str,
+    pub codec: Codec,
+}
+
+/// オブジェクトキーをテンプレートと照合して取り出した値
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ParsedObjectKey {
+    /// キャッシュキー（名前空間適用後）
+    pub key: String,
+    /// キャッシュ名（テンプレートに`{cache_name}`がない場合はNone）
+    pub cache_name: Option<String>,
+    /// 拡張子から判定した圧縮方式（テンプレートに`{ext}`がない場合はNone）
+    pub codec: Option<Codec>,
+}
+
+/// オブジェクトキーのテンプレート
+///
+/// 例: `"{project}/{cache_name}/v{version}/{key}.{ext}"`
+/// -> `"group/app/node_modules/v1/0123...-protected.tar.zst"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectKeyTemplate {
+    segments: Vec<Segment>,
+}
+
+impl ObjectKeyTemplate {
+    /// テンプレート文字列を解釈する
+    ///
+    /// 未知の変数・対応しない括弧・`{key}`を含まないテンプレート
+    /// （全てのキーが同じオブジェクトを指してしまう）はエラーとする。
+    pub fn parse(template: &str) -> Result<Self, ObjectKeyError> {
+        let unbalanced = || ObjectKeyError::UnbalancedBrace {
+            template: template.to_string(),
+        };
+
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(unbalanced());
+            }
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}').ok_or_else(unbalanced)? + start;
+            let name = &rest[start + 1..end];
+            if name.contains('{') {
+                return Err(unbalanced());
+            }
+            segments.push(Segment::Variable(Variable::from_name(name)?));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        if !segments.contains(&Segment::Variable(Variable::Key)) {
+            return Err(ObjectKeyError::MissingKeyVariable {
+                template: template.to_string(),
+            });
+        }
+
+        Ok(Self { segments })
+    }
+
+    fn expand(
+        variable: Variable,
+        context: &ObjectKeyContext,
+        key: &str,
+    ) -> Result<String, ObjectKeyError> {
+        match variable {
+            Variable::Project => context
+                .project
+                .filter(|project| !project.is_empty())
+                .map(String::from)
+                .ok_or(ObjectKeyError::MissingProject),
+            Variable::CacheName => Ok(context.cache_name.to_string()),
+            Variable::Key => Ok(key.to_string()),
+            Variable::Ext => Ok(context.codec.extension().to_string()),
+            Variable::Version => Ok(KEY_SCHEME_VERSION.to_string()),
+        }
+    }
+
+    /// キャッシュキーからオブジェクトキーを生成する
+    ///
+    /// 生成結果が空・`/`始まり・空のパス要素や`.`/`..`を含む場合は
+    /// 他のキャッシュと混ざる恐れがあるためエラーとする。
+    pub fn render(&self, context: &ObjectKeyContext, key: &str) -> Result<String, ObjectKeyError> {
+        let mut object_key = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => object_key.push_str(literal),
+                Segment::Variable(variable) => {
+                    object_key.push_str(&Self::expand(*variable, context, key)?)
+                }
+            }
+        }
+
+        let is_valid = !object_key.is_empty()
+            && object_key
+                .split('/')
+                .all(|part| !part.is_empty() && part != "." && part != "..");
+        if !is_valid {
+            return Err(ObjectKeyError::InvalidObjectKey { key: object_key });
+        }
+        Ok(object_key)
+    }
+
+    /// キャッシュキーに依存しない、オブジェクトキーの固定部分（プレフィックス）を求める
+    ///
+    /// 先頭から`{key}`の直前までを展開する。一覧取得やIAMポリシーの対象範囲に使用する。
+    /// 例: `"{project}/{cache_name}/v{version}/{key}.{ext}"` -> `"group/app/node_modules/v1/"`
+    pub fn prefix(&self, context: &ObjectKeyContext) -> Result<String, ObjectKeyError> {
+        let mut prefix = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => prefix.push_str(literal),
+                Segment::Variable(Variable::Key) => break,
+                Segment::Variable(variable) => {
+                    prefix.push_str(&Self::expand(*variable, context, "")?)
+                }
+            }
+        }
+        Ok(prefix)
+    }
+
+    /// プロジェクト内の全てのキャッシュに共通するプレフィックスを求める
+    ///
+    /// 先頭から`{cache_name}`または`{key}`の直前までを展開する。キャッシュ名を問わない一覧取得に使用する。
+    /// 例: `"{project}/{cache_name}/v{version}/{key}.{ext}"` -> `"group/app/"`
+    ///
+    /// その範囲に`{project}`がない場合、プレフィックスが他のプロジェクトと共有されるためエラーとする。
+    pub fn project_prefix(&self, context: &ObjectKeyContext) -> Result<String, ObjectKeyError> {
+        if !self.has_project_prefix() {
+            return Err(ObjectKeyError::MissingProjectPrefix);
+        }
+        let mut prefix = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => prefix.push_str(literal),
+                Segment::Variable(Variable::Key | Variable::CacheName) => break,
+                Segment::Variable(variable) => {
+                    prefix.push_str(&Self::expand(*variable, context, "")?)
+                }
+            }
+        }
+        Ok(prefix)
+    }
+
+    /// `{cache_name}`・`{key}`より前に`{project}`があるか（プロジェクトごとのプレフィックスを持つか）
+    pub fn has_project_prefix(&self) -> bool {
+        self.segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Variable(variable) => Some(*variable),
+                Segment::Literal(_) => None,
+            })
+            .take_while(|variable| !matches!(variable, Variable::Key | Variable::CacheName))
+            .any(|variable| variable == Variable::Project)
+    }
+
+    /// プロジェクトのキャッシュの世代を保存するオブジェクトのキー
+    ///
+    /// 例: `"group/app/.cafce-generation"`
+    pub fn generation_object_key(&self, context: &ObjectKeyContext) -> Result<String, ObjectKeyError> {
+        Ok(format!("{}{GENERATION_OBJECT_NAME}", self.project_prefix(context)?))
+    }
+
+    /// オブジェクトキーをテンプレートと照合し、キャッシュキー・キャッシュ名・圧縮方式を取り出す
+    ///
+    /// `{project}`・`{version}`は`project`・現在の方式のバージョンと一致する必要がある。
+    /// 一致しない（cafceが作成したキャッシュではない、または古い方式の）場合はNoneを返す。
+    pub fn match_object_key(&self, project: Option<&str>, object_key: &str) -> Option<ParsedObjectKey> {
+        let mut parsed = ParsedObjectKey::default();
+        Self::match_segments(&self.segments, project, object_key, &mut parsed).then_some(parsed)
+    }
+
+    fn match_segments(
+        segments: &[Segment],
+        project: Option<&str>,
+        input: &str,
+        parsed: &mut ParsedObjectKey,
+    ) -> bool {
+        let Some((segment, rest)) = segments.split_first() else {
+            return input.is_empty();
+        };
+        let fixed = match segment {
+            Segment::Literal(literal) => Some(literal.clone()),
+            Segment::Variable(Variable::Project) => match project.filter(|project| !project.is_empty()) {
+                Some(project) => Some(project.to_string()),
+                None => return false,
+            },
+            Segment::Variable(Variable::Version) => Some(KEY_SCHEME_VERSION.to_string()),
+            Segment::Variable(_) => None,
+        };
+        if let Some(fixed) = fixed {
+            return input
+                .strip_prefix(fixed.as_str())
+                .is_some_and(|input| Self::match_segments(rest, project, input, parsed));
+        }
+
+        match segment {
+            Segment::Variable(Variable::Ext) => Codec::ALL.into_iter().any(|codec| {
+                parsed.codec = Some(codec);
+                input
+                    .strip_prefix(codec.extension())
+                    .is_some_and(|input| Self::match_segments(rest, project, input, parsed))
+            }),
+            // キャッシュキー・キャッシュ名は、残りが一致する最短の部分とする
+            Segment::Variable(variable) => input
+                .char_indices()
+                .skip(1)
+                .map(|(index, _)| index)
+                .chain([input.len()])
+                .filter(|end| *end > 0)
+                .take_while(|end| *variable == Variable::Key || !input[..*end].contains('/'))
+                .any(|end| {
+                    let value = input[..end].to_string();
+                    if *variable == Variable::Key {
+                        parsed.key = value;
+                    } else {
+                        parsed.cache_name = Some(value);
+                    }
+                    Self::match_segments(rest, project, &input[end..], parsed)
+                }),
+            Segment::Literal(_) => unreachable!(),
+        }
+    }
+}
+
+impl Default for ObjectKeyTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_OBJECT_KEY_TEMPLATE).expect("default template must be valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(project: Option<&'static str>) -> ObjectKeyContext<'static> {
+        ObjectKeyContext {
+            project,
+            cache_name: "node_modules",
+            codec: Codec::Zstd,
+        }
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let template = ObjectKeyTemplate::default();
+        let object_key = template
+            .render(&context(Some("group/app")), "abc-protected")
+            .unwrap();
+        assert_eq!(object_key, "group/app/node_modules/v1/abc-protected.tar.zst");
+    }
+
+    #[test]
+    fn test_render_custom_template() {
+        let template = ObjectKeyTemplate::parse("caches/{cache_name}/{key}.{ext}").unwrap();
+        let context = ObjectKeyContext {
+            project: None,
+            cache_name: "cargo",
+            codec: Codec::Gzip,
+        };
+        let object_key = template.render(&context, "abc").unwrap();
+        assert_eq!(object_key, "caches/cargo/abc.tar.gz");
+    }
+
+    #[test]
+    fn test_render_codec_none() {
+        let template = ObjectKeyTemplate::parse("{key}.{ext}").unwrap();
+        let context = ObjectKeyContext {
+            project: None,
+            cache_name: "cargo",
+            codec: Codec::None,
+        };
+        assert_eq!(template.render(&context, "abc").unwrap(), "abc.tar");
+    }
+
+    #[test]
+    fn test_render_missing_project() {
+        let template = ObjectKeyTemplate::default();
+        let result = template.render(&context(None), "abc");
+        assert!(matches!(result, Err(ObjectKeyError::MissingProject)));
+    }
+
+    #[test]
+    fn test_render_invalid_object_key() {
+        let template = ObjectKeyTemplate::parse("/{key}").unwrap();
+        let result = template.render(&context(None), "abc");
+        assert!(matches!(result, Err(ObjectKeyError::InvalidObjectKey { .. })));
+
+        let template = ObjectKeyTemplate::parse("caches/{key}").unwrap();
+        let result = template.render(&context(None), "../other");
+        assert!(matches!(result, Err(ObjectKeyError::InvalidObjectKey { .. })));
+    }
+
+    #[test]
+    fn test_parse_unknown_variable() {
+        let result = ObjectKeyTemplate::parse("{project}/{branch}/{key}");
+        assert!(matches!(
+            result,
+            Err(ObjectKeyError::UnknownVariable { name }) if name == "branch"
+        ));
+    }
+
+    #[test]
+    fn test_parse_unbalanced_brace() {
+        for template in ["{key", "key}", "{{key}}", "{ke{y}"] {
+            let result = ObjectKeyTemplate::parse(template);
+            assert!(
+                matches!(result, Err(ObjectKeyError::UnbalancedBrace { .. })),
+                "template={template}, result={result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_missing_key_variable() {
+        let result = ObjectKeyTemplate::parse("{project}/{cache_name}.{ext}");
+        assert!(matches!(result, Err(ObjectKeyError::MissingKeyVariable { .. })));
+    }
+
+    #[test]
+    fn test_prefix() {
+        let template = ObjectKeyTemplate::default();
+        let prefix = template.prefix(&context(Some("group/app"))).unwrap();
+        assert_eq!(prefix, "group/app/node_modules/v1/");
+    }
+
+    #[test]
+    fn test_project_prefix() {
+        let template = ObjectKeyTemplate::default();
+        let prefix = template.project_prefix(&context(Some("group/app"))).unwrap();
+        assert_eq!(prefix, "group/app/");
+
+        let template = ObjectKeyTemplate::parse("caches/{project}/v{version}/{key}-{cache_name}").unwrap();
+        assert!(template.has_project_prefix());
+        assert_eq!(template.project_prefix(&context(Some("group/app"))).unwrap(), "caches/group/app/v1/");
+
+        // {project}がない・{cache_name}より後にある場合は、他のプロジェクトとプレフィックスを共有してしまう
+        for template in ["caches/v{version}/{key}-{cache_name}", "{cache_name}/{project}/{key}.{ext}"] {
+            let template = ObjectKeyTemplate::parse(template).unwrap();
+            assert!(!template.has_project_prefix());
+            let result = template.project_prefix(&context(Some("group/app")));
+            assert!(matches!(result, Err(ObjectKeyError::MissingProjectPrefix)), "{result:?}");
+            let result = template.generation_object_key(&context(Some("group/app")));
+            assert!(matches!(result, Err(ObjectKeyError::MissingProjectPrefix)), "{result:?}");
+        }
+    }
+
+    #[test]
+    fn test_generation_object_key() {
+        let template = ObjectKeyTemplate::default();
+        let object_key = template.generation_object_key(&context(Some("group/app"))).unwrap();
+        assert_eq!(object_key, "group/app/.cafce-generation");
+        // キャッシュとして一覧されない
+        assert_eq!(template.match_object_key(Some("group/app"), &object_key), None);
+    }
+
+    #[test]
+    fn test_match_object_key() {
+        let template = ObjectKeyTemplate::default();
+        let parsed = template
+            .match_object_key(Some("group/app"), "group/app/node_modules/v1/deps-0123.v2-protected.tar.gz")
+            .unwrap();
+        assert_eq!(
+            parsed,
+            ParsedObjectKey {
+                key: "deps-0123.v2-protected".to_string(),
+                cache_name: Some("node_modules".to_string()),
+                codec: Some(Codec::Gzip),
+            }
+        );
+
+        // renderの結果は元のキーに戻せる
+        let object_key = template.render(&context(Some("group/app")), "abc.tar").unwrap();
+        let parsed = template.match_object_key(Some("group/app"), &object_key).unwrap();
+        assert_eq!(parsed.key, "abc.tar");
+        assert_eq!(parsed.codec, Some(Codec::Zstd));
+    }
+
+    #[test]
+    fn test_match_object_key_mismatch() {
+        let template = ObjectKeyTemplate::default();
+        for object_key in [
+            // 別のプロジェクト
+            "group/other/node_modules/v1/abc.tar.zst",
+            // 古い方式のバージョン
+            "group/app/node_modules/v0/abc.tar.zst",
+            // 未知の拡張子
+            "group/app/node_modules/v1/abc.zip",
+            // キャッシュ名に`/`は含まれない
+            "group/app/a/b/v1/abc.tar.zst",
+            "group/app/node_modules/v1/.tar.zst",
+        ] {
+            assert_eq!(template.match_object_key(Some("group/app"), object_key), None, "{object_key}");
+        }
+        assert_eq!(template.match_object_key(None, "group/app/node_modules/v1/abc.tar.zst"), None);
+    }
+
+    #[test]
+    fn test_codec_name() {
+        for codec in Codec::ALL {
+            assert_eq!(Codec::from_name(codec.name()), Some(codec));
+        }
+        assert_eq!(Codec::from_name("xz"), None);
+    }
+
+    #[test]
+    fn test_prefix_key_first() {
+        let template = ObjectKeyTemplate::parse("{key}/{project}").unwrap();
+        assert_eq!(template.prefix(&context(None)).unwrap(), "");
+    }
+}
@@ -0,0 +1,384 @@
+//! store / restore / keyの実行結果のレポート
+//!
+//! パイプラインのダッシュボード等で集計できるよう、1回の実行結果を1つのJSONドキュメントとして出力する。
+//! まだ実行していない処理の値（転送量・ヒット有無など）はnullとする。
+//! restoreの結果は、後続のジョブ・スクリプトが参照できるようdotenv形式でも出力できる。
+
+use crate::error::{CafceError, ErrorCategory};
+use crate::i18n::tr;
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::time::{Duration, Instant};
+
+/// 実行結果の出力形式（`--output`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// 人が読むためのテキスト
+    #[default]
+    Text,
+    /// 1つのJSONドキュメント
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(tr!(
+                "出力形式は\"text\"または\"json\"を指定してください: {value}",
+                "output format must be \"text\" or \"json\": {value}"
+            )),
+        }
+    }
+}
+
+/// restore時にどのキーでキャッシュが見つかったか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyMatch {
+    /// プライマリキーに一致した
+    Primary,
+    /// fallback_keysのいずれかに一致した
+    Fallback,
+}
+
+/// 処理（フェーズ）ごとの所要時間
+///
+/// 実行順を保ったまま、`{"フェーズ名": ミリ秒}`のオブジェクトとしてシリアライズする。
+#[derive(Debug, Clone, Default)]
+pub struct PhaseDurations(Vec<(&'static str, Duration)>);
+
+impl PhaseDurations {
+    /// `f`の所要時間を`phase`として記録する
+    pub fn measure<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.record(phase, started.elapsed());
+        result
+    }
+
+    /// 所要時間を記録する（同じフェーズは加算する）
+    pub fn record(&mut self, phase: &'static str, duration: Duration) {
+        match self.0.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.0.push((phase, duration)),
+        }
+    }
+
+    /// 記録したフェーズと所要時間を実行順に返す
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Duration)> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl Serialize for PhaseDurations {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (phase, duration) in &self.0 {
+            map.serialize_entry(phase, &(duration.as_millis() as u64))?;
+        }
+        map.end()
+    }
+}
+
+/// レポートに含めるエラー
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportError {
+    pub category: ErrorCategory,
+    pub exit_code: i32,
+    pub message: String,
+    /// `on_error = "warn"`により、警告として扱いジョブを失敗させなかったか
+    pub ignored: bool,
+}
+
+impl ReportError {
+    pub fn new(error: &CafceError, ignored: bool) -> Self {
+        Self {
+            category: error.category(),
+            exit_code: error.exit_code(),
+            message: error.message_with_causes(),
+            ignored,
+        }
+    }
+}
+
+/// 1回のstore / restore / keyの実行結果
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheReport {
+    /// 実行したサブコマンド（"store" / "restore" / "key"）
+    pub command: &'static str,
+    pub cache_name: Option<String>,
+    pub bucket: Option<String>,
+    /// 計算したプライマリキー
+    pub key: Option<String>,
+    /// プライマリキーに対応するオブジェクトキー
+    pub object_key: Option<String>,
+    pub fallback_keys: Vec<String>,
+    /// キーに反映したキャッシュの世代（keyコマンドはバケットに接続しないためNone）
+    pub generation: Option<u64>,
+    /// restore時に見つかったキャッシュのキー
+    pub matched_key: Option<String>,
+    pub matched: Option<KeyMatch>,
+    /// restore時にキャッシュが見つかったか
+    pub hit: Option<bool>,
+    /// S3との間で転送したバイト数
+    pub bytes_transferred: Option<u64>,
+    /// 圧縮後（オブジェクト）のサイズ
+    pub compressed_size: Option<u64>,
+    /// 圧縮前（アーカイブ対象のファイルの合計）のサイズ
+    pub uncompressed_size: Option<u64>,
+    /// フェーズごとの所要時間（ミリ秒）
+    pub durations_ms: PhaseDurations,
+    pub errors: Vec<ReportError>,
+}
+
+impl CacheReport {
+    pub fn new(command: &'static str) -> Self {
+        Self {
+            command,
+            cache_name: None,
+            bucket: None,
+            key: None,
+            object_key: None,
+            fallback_keys: Vec::new(),
+            generation: None,
+            matched_key: None,
+            matched: None,
+            hit: None,
+            bytes_transferred: None,
+            compressed_size: None,
+            uncompressed_size: None,
+            durations_ms: PhaseDurations::default(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// restore時に見つかったキーを記録する
+    ///
+    /// `matched_key`がNoneの場合はキャッシュミスとする。
+    pub fn set_matched_key(&mut self, matched_key: Option<&str>) {
+        self.hit = Some(matched_key.is_some());
+        self.matched = matched_key.map(|matched_key| {
+            if self.key.as_deref() == Some(matched_key) {
+                KeyMatch::Primary
+            } else {
+                KeyMatch::Fallback
+            }
+        });
+        self.matched_key = matched_key.map(String::from);
+    }
+
+    /// GitLab CIの`artifacts:reports:dotenv`、およびシェルの`source`で読み込めるdotenv形式
+    ///
+    /// - `CAFCE_CACHE_HIT`: プライマリキーに完全一致した場合のみtrue（fallbackでの復元・キャッシュミスはfalse、
+    ///   失敗により結果が分からない場合は空）
+    /// - `CAFCE_CACHE_KEY`: 計算したプライマリキー
+    /// - `CAFCE_MATCHED_KEY`: 復元したキャッシュのキー（キャッシュミスの場合は空）
+    ///
+    /// GitLabは値の引用符を取り除かないため、値は引用符で囲まずに出力し、
+    /// そのままでは表せない値はエラーとする。
+    pub fn to_dotenv(&self) -> Result<String, DotenvError> {
+        let hit = match self.hit {
+            Some(_) => (self.matched == Some(KeyMatch::Primary)).to_string(),
+            None => String::new(),
+        };
+        let variables = [
+            ("CAFCE_CACHE_HIT", hit),
+            ("CAFCE_CACHE_KEY", self.key.clone().unwrap_or_default()),
+            ("CAFCE_MATCHED_KEY", self.matched_key.clone().unwrap_or_default()),
+        ];
+        variables
+            .iter()
+            .map(|(name, value)| {
+                check_dotenv_value(name, value)?;
+                Ok(format!("{name}={value}\n"))
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report must be serializable")
+    }
+
+    /// 人が読むためのテキスト（エラーは標準エラー出力に別途出力するため含めない）
+    pub fn to_text(&self) -> String {
+        let none = "-".to_string();
+        let mut lines = vec![
+            format!("cache: {}", self.cache_name.as_ref().unwrap_or(&none)),
+            format!("bucket: {}", self.bucket.as_ref().unwrap_or(&none)),
+            format!("key: {}", self.key.as_ref().unwrap_or(&none)),
+            format!("object key: {}", self.object_key.as_ref().unwrap_or(&none)),
+        ];
+        for fallback_key in &self.fallback_keys {
+            lines.push(format!("fallback key: {fallback_key}"));
+        }
+        if let Some(hit) = self.hit {
+            lines.push(format!("hit: {hit}"));
+        }
+        if let Some(matched_key) = &self.matched_key {
+            lines.push(format!("matched key: {matched_key}"));
+        }
+        lines.join("\n")
+    }
+}
+
+/// dotenv形式で表せない値
+#[derive(Debug, thiserror::Error)]
+#[error("{}", tr!(
+    "{name}の値はdotenv形式で出力できません（英数字と-_./:+@%,=のみ使用できます）: {value:?}",
+    "the value of {name} cannot be written in dotenv format (only letters, digits and -_./:+@%,= are allowed): {value:?}"
+))]
+pub struct DotenvError {
+    pub name: &'static str,
+    pub value: String,
+}
+
+/// 値がGitLabのdotenvとシェルの`source`の両方で、引用符なしでそのまま読み込めるか検査する
+///
+/// 英数字と`-_./:+@%,=`のみの値を許可する。
+/// 改行・空白・引用符・`$`等を含む値は、GitLabとシェルで解釈が変わるためエラーとする。
+fn check_dotenv_value(name: &'static str, value: &str) -> Result<(), DotenvError> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./:+@%,=".contains(c);
+    if value.chars().all(is_safe) {
+        Ok(())
+    } else {
+        Err(DotenvError {
+            name,
+            value: value.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CacheKeyError;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!("text".parse::<OutputFormat>(), Ok(OutputFormat::Text));
+        assert_eq!("json".parse::<OutputFormat>(), Ok(OutputFormat::Json));
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_empty_report_json() {
+        let report = CacheReport::new("key");
+        let value: Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "command": "key",
+                "cache_name": null,
+                "bucket": null,
+                "key": null,
+                "object_key": null,
+                "fallback_keys": [],
+                "generation": null,
+                "matched_key": null,
+                "matched": null,
+                "hit": null,
+                "bytes_transferred": null,
+                "compressed_size": null,
+                "uncompressed_size": null,
+                "durations_ms": {},
+                "errors": [],
+            })
+        );
+    }
+
+    #[test]
+    fn test_durations_keep_order() {
+        let mut durations = PhaseDurations::default();
+        durations.record("key", Duration::from_millis(20));
+        durations.record("config", Duration::from_millis(5));
+        durations.record("key", Duration::from_millis(3));
+        assert_eq!(
+            serde_json::to_string(&durations).unwrap(),
+            r#"{"key":23,"config":5}"#
+        );
+        assert_eq!(durations.measure("connect", || 42), 42);
+        assert_eq!(durations.iter().map(|(phase, _)| phase).collect::<Vec<_>>(), ["key", "config", "connect"]);
+    }
+
+    #[test]
+    fn test_matched_key() {
+        let mut report = CacheReport::new("restore");
+        report.key = Some("primary".to_string());
+
+        report.set_matched_key(Some("primary"));
+        assert_eq!(report.hit, Some(true));
+        assert_eq!(report.matched, Some(KeyMatch::Primary));
+
+        report.set_matched_key(Some("fallback"));
+        assert_eq!(report.matched, Some(KeyMatch::Fallback));
+        assert_eq!(report.matched_key.as_deref(), Some("fallback"));
+
+        report.set_matched_key(None);
+        assert_eq!(report.hit, Some(false));
+        assert_eq!(report.matched, None);
+        assert_eq!(report.matched_key, None);
+    }
+
+    #[test]
+    fn test_dotenv() {
+        let mut report = CacheReport::new("restore");
+        report.key = Some("deps-0123abcd-protected".to_string());
+
+        report.set_matched_key(Some("deps-0123abcd-protected"));
+        assert_eq!(
+            report.to_dotenv().unwrap(),
+            "CAFCE_CACHE_HIT=true\n\
+             CAFCE_CACHE_KEY=deps-0123abcd-protected\n\
+             CAFCE_MATCHED_KEY=deps-0123abcd-protected\n"
+        );
+
+        // fallbackでの復元は完全一致ではない
+        report.set_matched_key(Some("deps-protected"));
+        let dotenv = report.to_dotenv().unwrap();
+        assert!(dotenv.starts_with("CAFCE_CACHE_HIT=false\n"));
+        assert!(dotenv.ends_with("CAFCE_MATCHED_KEY=deps-protected\n"));
+
+        report.set_matched_key(None);
+        let dotenv = report.to_dotenv().unwrap();
+        assert!(dotenv.starts_with("CAFCE_CACHE_HIT=false\n"));
+        assert!(dotenv.ends_with("CAFCE_MATCHED_KEY=\n"));
+    }
+
+    #[test]
+    fn test_dotenv_unknown_outcome() {
+        // 復元前に失敗した場合はヒットしたか分からないため、falseではなく空とする
+        let mut report = CacheReport::new("restore");
+        report.key = Some("deps-0123abcd-protected".to_string());
+        assert!(report.to_dotenv().unwrap().starts_with("CAFCE_CACHE_HIT=\n"));
+    }
+
+    #[test]
+    fn test_dotenv_rejects_unrepresentable_values() {
+        assert!(check_dotenv_value("CAFCE_CACHE_KEY", "deps-0123/v1.2").is_ok());
+        assert!(check_dotenv_value("CAFCE_CACHE_KEY", "").is_ok());
+        for value in ["my key", "it's", "\"quoted\"", "$HOME", "line\nbreak"] {
+            assert!(check_dotenv_value("CAFCE_CACHE_KEY", value).is_err(), "{value}");
+        }
+
+        let mut report = CacheReport::new("restore");
+        report.key = Some("my key".to_string());
+        let error = report.to_dotenv().unwrap_err();
+        assert_eq!(error.name, "CAFCE_CACHE_KEY");
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut report = CacheReport::new("store");
+        let error = CafceError::CacheKey(CacheKeyError::NoFilesMatched.into());
+        report.errors.push(ReportError::new(&error, false));
+        let value: Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(value["errors"][0]["category"], "key_computation");
+        assert_eq!(value["errors"][0]["exit_code"], 3);
+        assert_eq!(value["errors"][0]["ignored"], false);
+    }
+}
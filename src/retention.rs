@@ -0,0 +1,350 @@
+//! キャッシュの保持ルール（`cafce prune`）
+//!
+//! S3のライフサイクルルールでは「キャッシュ名ごとに最新N件」「最後に使われた順」といった
+//! 条件を表せないため、一覧したキャッシュエントリに対して削除対象を決める。
+//! 削除対象の決定は純粋なロジックとし、実際の削除は呼び出し元（Catalog）が行う。
+
+use crate::cache_key::{apply_generation, CacheNamespace};
+use crate::catalog::CacheEntry;
+use crate::i18n::tr;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 保持ルールの誤り
+#[derive(Debug, thiserror::Error)]
+pub enum RetentionError {
+    #[error("{}", tr!("保護するキーのパターンが不正です: {pattern}", "invalid protected key pattern: {pattern}"))]
+    InvalidPattern {
+        pattern: String,
+        #[source]
+        source: glob::PatternError,
+    },
+}
+
+/// 設定ファイルの`[retention]`
+///
+/// いずれも省略時はそのルールを適用しない。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Retention {
+    /// 最後に使用（restore・store）されてからこの日数を超えたキャッシュを削除する
+    pub max_age_days: Option<u64>,
+    /// キャッシュ名ごとに、作成日時の新しい順にこの件数だけ残す
+    pub keep_latest: Option<usize>,
+    /// キャッシュ名ごとの合計サイズの上限（MiB）
+    ///
+    /// 超えた場合は、最後に使用された日時の古い順に削除する（LRU）。
+    pub max_total_size_mib: Option<u64>,
+    /// 削除しないキャッシュキーのglobパターン（fallback_keysは常に削除しない）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protect: Vec<String>,
+}
+
+/// キャッシュを削除する理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneReason {
+    /// `max_age_days`を超えて使用されていない
+    Expired,
+    /// `keep_latest`を超える古いキャッシュ
+    Superseded,
+    /// `max_total_size_mib`を超えたため、LRUで削除する
+    OverBudget,
+}
+
+impl PruneReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Expired => "expired",
+            Self::Superseded => "superseded",
+            Self::OverBudget => "over_budget",
+        }
+    }
+}
+
+/// 削除しないキャッシュキー
+///
+/// restore時にfallbackとして使われる既定ブランチのキャッシュを消すと、
+/// 全てのブランチでキャッシュミスになるため、fallback_keysに一致するキーは常に保護する。
+#[derive(Debug, Clone, Default)]
+pub struct ProtectedKeys {
+    keys: Vec<String>,
+    patterns: Vec<glob::Pattern>,
+}
+
+impl ProtectedKeys {
+    /// # Arguments
+    /// * `fallback_keys` - 設定ファイルの`fallback_keys`（名前空間適用前）
+    /// * `patterns` - `retention.protect`のglobパターン
+    /// * `generation` - プロジェクトのキャッシュの現在の世代（古い世代のfallbackは使われないため保護しない）
+    pub fn new(fallback_keys: &[String], patterns: &[String], generation: u64) -> Result<Self, RetentionError> {
+        // どの名前空間で作成されたキャッシュも保護する
+        let keys = fallback_keys
+            .iter()
+            .flat_map(|key| {
+                CacheNamespace::ALL.map(|namespace| apply_generation(&namespace.apply(key), generation))
+            })
+            .collect();
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern).map_err(|source| RetentionError::InvalidPattern {
+                    pattern: pattern.clone(),
+                    source,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { keys, patterns })
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.iter().any(|protected| protected == key)
+            || self.patterns.iter().any(|pattern| pattern.matches(key))
+    }
+}
+
+/// 削除するキャッシュ
+#[derive(Debug, Clone, Serialize)]
+pub struct PruneCandidate {
+    #[serde(flatten)]
+    pub entry: CacheEntry,
+    pub reason: PruneReason,
+}
+
+/// 保持ルールの適用結果
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PrunePlan {
+    pub delete: Vec<PruneCandidate>,
+    /// 残すキャッシュの数
+    pub kept: usize,
+    /// 残すキャッシュの合計サイズ
+    pub kept_bytes: u64,
+    /// 保護されたため残したキャッシュの数
+    pub protected: usize,
+}
+
+impl PrunePlan {
+    /// 削除により解放されるサイズ
+    pub fn freed_bytes(&self) -> u64 {
+        self.delete.iter().map(|candidate| candidate.entry.size).sum()
+    }
+}
+
+/// キャッシュが最後に使用された日時（UNIX時間）
+///
+/// restoreでの使用日時が記録されていればそれを、なければ作成日時を使用する。
+fn last_used(entry: &CacheEntry) -> i64 {
+    let modified = entry.last_modified.map(|time| time.secs());
+    let accessed = entry.last_accessed.map(|time| time.secs());
+    modified.max(accessed).unwrap_or_default()
+}
+
+impl Retention {
+    /// いずれのルールも設定されていないか
+    pub fn is_empty(&self) -> bool {
+        self.max_age_days.is_none() && self.keep_latest.is_none() && self.max_total_size_mib.is_none()
+    }
+
+    /// 保持ルールを適用し、削除するキャッシュを決める
+    ///
+    /// 適用順は、期限切れ → キャッシュ名ごとの件数 → 合計サイズ（LRU）。
+    /// 保護されたキャッシュは削除しないが、件数・合計サイズには含める。
+    ///
+    /// # Arguments
+    /// * `entries` - 対象のキャッシュ名の全てのキャッシュ
+    /// * `now` - 現在時刻（UNIX時間）
+    pub fn plan(&self, entries: Vec<CacheEntry>, protected: &ProtectedKeys, now: i64) -> PrunePlan {
+        let mut reasons: Vec<Option<PruneReason>> = vec![None; entries.len()];
+        let is_protected: Vec<bool> = entries.iter().map(|entry| protected.contains(&entry.key)).collect();
+
+        if let Some(max_age_days) = self.max_age_days {
+            let max_age = i64::try_from(max_age_days.saturating_mul(86_400)).unwrap_or(i64::MAX);
+            let cutoff = now.saturating_sub(max_age);
+            for (index, entry) in entries.iter().enumerate() {
+                if !is_protected[index] && last_used(entry) < cutoff {
+                    reasons[index] = Some(PruneReason::Expired);
+                }
+            }
+        }
+
+        if let Some(keep_latest) = self.keep_latest {
+            let mut by_cache_name: HashMap<Option<&str>, Vec<usize>> = HashMap::new();
+            for (index, entry) in entries.iter().enumerate() {
+                if reasons[index].is_none() {
+                    by_cache_name.entry(entry.cache_name.as_deref()).or_default().push(index);
+                }
+            }
+            for mut indices in by_cache_name.into_values() {
+                indices.sort_by_key(|index| std::cmp::Reverse(entries[*index].last_modified));
+                for index in indices.into_iter().skip(keep_latest) {
+                    if !is_protected[index] {
+                        reasons[index] = Some(PruneReason::Superseded);
+                    }
+                }
+            }
+        }
+
+        if let Some(max_total_size_mib) = self.max_total_size_mib {
+            let budget = max_total_size_mib.saturating_mul(1024 * 1024);
+            let mut total: u64 = (0..entries.len())
+                .filter(|index| reasons[*index].is_none())
+                .map(|index| entries[index].size)
+                .sum();
+            let mut lru: Vec<usize> = (0..entries.len())
+                .filter(|index| reasons[*index].is_none() && !is_protected[*index])
+                .collect();
+            lru.sort_by_key(|index| last_used(&entries[*index]));
+            for index in lru {
+                if total <= budget {
+                    break;
+                }
+                reasons[index] = Some(PruneReason::OverBudget);
+                total -= entries[index].size;
+            }
+        }
+
+        let mut plan = PrunePlan::default();
+        for ((entry, reason), is_protected) in entries.into_iter().zip(reasons).zip(is_protected) {
+            match reason {
+                Some(reason) => plan.delete.push(PruneCandidate { entry, reason }),
+                None => {
+                    plan.kept += 1;
+                    plan.kept_bytes += entry.size;
+                    plan.protected += usize::from(is_protected);
+                }
+            }
+        }
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_smithy_types::DateTime;
+
+    const DAY: i64 = 86_400;
+    const NOW: i64 = 1_790_000_000;
+
+    fn entry(key: &str, cache_name: &str, size_mib: u64, modified_days_ago: i64, accessed_days_ago: Option<i64>) -> CacheEntry {
+        CacheEntry {
+            key: key.to_string(),
+            cache_name: Some(cache_name.to_string()),
+            object_key: format!("group/app/{cache_name}/v1/{key}.tar.zst"),
+            size: size_mib * 1024 * 1024,
+            codec: None,
+            last_modified: Some(DateTime::from_secs(NOW - modified_days_ago * DAY)),
+            last_accessed: accessed_days_ago.map(|days| DateTime::from_secs(NOW - days * DAY)),
+            producer_pipeline: None,
+        }
+    }
+
+    fn deleted(plan: &PrunePlan) -> Vec<(&str, PruneReason)> {
+        let mut deleted: Vec<_> = plan
+            .delete
+            .iter()
+            .map(|candidate| (candidate.entry.key.as_str(), candidate.reason))
+            .collect();
+        deleted.sort_by_key(|(key, _)| *key);
+        deleted
+    }
+
+    #[test]
+    fn test_max_age_uses_last_access() {
+        let retention = Retention {
+            max_age_days: Some(7),
+            ..Default::default()
+        };
+        let entries = vec![
+            entry("old", "deps", 1, 30, None),
+            entry("old-but-used", "deps", 1, 30, Some(1)),
+            entry("new", "deps", 1, 1, None),
+        ];
+        let plan = retention.plan(entries, &ProtectedKeys::default(), NOW);
+        assert_eq!(deleted(&plan), [("old", PruneReason::Expired)]);
+        assert_eq!(plan.kept, 2);
+    }
+
+    #[test]
+    fn test_keep_latest_per_cache_name() {
+        let retention = Retention {
+            keep_latest: Some(1),
+            ..Default::default()
+        };
+        let entries = vec![
+            entry("deps-1", "deps", 1, 3, None),
+            entry("deps-2", "deps", 1, 2, Some(0)),
+            entry("deps-3", "deps", 1, 1, None),
+            entry("target-1", "target", 1, 5, None),
+        ];
+        let plan = retention.plan(entries, &ProtectedKeys::default(), NOW);
+        assert_eq!(
+            deleted(&plan),
+            [("deps-1", PruneReason::Superseded), ("deps-2", PruneReason::Superseded)]
+        );
+    }
+
+    #[test]
+    fn test_total_size_lru() {
+        let retention = Retention {
+            max_total_size_mib: Some(5),
+            ..Default::default()
+        };
+        let entries = vec![
+            entry("a", "deps", 3, 10, Some(1)),
+            entry("b", "deps", 3, 5, None),
+            entry("c", "target", 2, 8, Some(0)),
+        ];
+        let plan = retention.plan(entries, &ProtectedKeys::default(), NOW);
+        // 最後に使用された日時が最も古いbから削除する
+        assert_eq!(deleted(&plan), [("b", PruneReason::OverBudget)]);
+        assert_eq!(plan.kept_bytes, 5 * 1024 * 1024);
+        assert_eq!(plan.freed_bytes(), 3 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_protected_keys() {
+        let protected = ProtectedKeys::new(&["deps-main".to_string()], &["release-*".to_string()], 0).unwrap();
+        assert!(protected.contains("deps-main-protected"));
+        assert!(protected.contains("deps-main-non_protected"));
+        assert!(protected.contains("deps-main"));
+        assert!(protected.contains("release-1.0-protected"));
+        assert!(!protected.contains("deps-feature-protected"));
+
+        let retention = Retention {
+            max_age_days: Some(1),
+            keep_latest: Some(0),
+            max_total_size_mib: Some(0),
+            protect: Vec::new(),
+        };
+        let entries = vec![
+            entry("deps-main-protected", "deps", 10, 100, None),
+            entry("deps-feature-protected", "deps", 1, 0, None),
+        ];
+        let plan = retention.plan(entries, &protected, NOW);
+        assert_eq!(deleted(&plan), [("deps-feature-protected", PruneReason::Superseded)]);
+        assert_eq!(plan.protected, 1);
+    }
+
+    #[test]
+    fn test_protected_keys_with_generation() {
+        let protected = ProtectedKeys::new(&["deps-main".to_string()], &[], 2).unwrap();
+        assert!(protected.contains("deps-main-protected-g2"));
+        assert!(!protected.contains("deps-main-protected"));
+        assert!(!protected.contains("deps-main-protected-g1"));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let result = ProtectedKeys::new(&[], &["[".to_string()], 0);
+        assert!(matches!(result, Err(RetentionError::InvalidPattern { .. })));
+    }
+
+    #[test]
+    fn test_empty_retention_keeps_everything() {
+        let retention = Retention::default();
+        assert!(retention.is_empty());
+        let plan = retention.plan(vec![entry("a", "deps", 1, 1000, None)], &ProtectedKeys::default(), NOW);
+        assert!(plan.delete.is_empty());
+        assert_eq!(plan.kept, 1);
+    }
+}
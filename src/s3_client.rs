@@ -1,19 +1,89 @@
 // src/s3_client.rs
-use crate::env::Env;
-use aws_sdk_s3::config::{Builder, Credentials, Region};
-use std::time::SystemTime;
+use crate::env::{CiEnv, CredentialSource, Env, RetryMode};
+use crate::http_client::{build_http_client, timeout_config, HttpClientError};
+use crate::i18n::tr;
+use crate::session_policy::{CacheSessionPolicy, SessionPolicyError};
+use crate::setting::Setting;
+use aws_credential_types::provider::{error::CredentialsError, future};
+use aws_config::retry::RetryConfig;
+use aws_config::ConfigLoader;
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::config::{
+    Builder, Credentials, ProvideCredentials, Region, SharedCredentialsProvider,
+};
+use aws_sdk_s3::operation::copy_object::builders::CopyObjectFluentBuilder;
+use aws_sdk_s3::operation::get_object::builders::GetObjectFluentBuilder;
+use aws_sdk_s3::operation::head_object::builders::HeadObjectFluentBuilder;
+use aws_sdk_s3::operation::put_object::builders::PutObjectFluentBuilder;
+use aws_sdk_s3::types::ServerSideEncryption;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::Instrument;
 
 /// S3クライアント構築時のエラー
 #[derive(Debug, thiserror::Error)]
 pub enum BuildClientError {
     #[error(transparent)]
     Endpoint(#[from] crate::env::EndpointError),
-    #[error("CAFCE_AWS_ROLE_ARNが指定されている場合、CAFCE_AWS_ACCESS_KEYとCAFCE_AWS_SECRET_KEYの指定が必須です")]
+    #[error(transparent)]
+    HttpClient(#[from] HttpClientError),
+    #[error("{}", tr!("CAFCE_AWS_MAX_ATTEMPTSは1以上を指定してください", "CAFCE_AWS_MAX_ATTEMPTS must be at least 1"))]
+    InvalidMaxAttempts,
+    #[error("{}", tr!("CAFCE_AWS_ROLE_ARNが指定されている場合、CAFCE_AWS_ACCESS_KEYとCAFCE_AWS_SECRET_KEY、またはWeb IDトークンの指定が必須です", "CAFCE_AWS_ROLE_ARN requires CAFCE_AWS_ACCESS_KEY and CAFCE_AWS_SECRET_KEY, or a web identity token"))]
     MissingAssumeRoleSourceCredentials,
-    #[error("AssumeRoleに失敗しました: {0}")]
+    #[error("{}", tr!("CAFCE_AWS_CREDENTIAL_SOURCE={credential_source}の場合、{variable}の指定が必須です", "{variable} is required when CAFCE_AWS_CREDENTIAL_SOURCE={credential_source}"))]
+    MissingCredentialSetting {
+        credential_source: CredentialSource,
+        variable: &'static str,
+    },
+    #[error("{}", tr!("AWSクレデンシャルの取得に失敗しました（取得元: {credential_source}）: {message}", "failed to obtain AWS credentials (source: {credential_source}): {message}"))]
+    Credentials {
+        credential_source: CredentialSource,
+        message: String,
+    },
+    #[error("{}", tr!("Web IDトークンが指定されている場合、CAFCE_AWS_ROLE_ARNの指定が必須です", "CAFCE_AWS_ROLE_ARN is required when a web identity token is set"))]
+    MissingWebIdentityRoleArn,
+    #[error("{}", tr!("CAFCE_AWS_WEB_IDENTITY_TOKENとCAFCE_AWS_WEB_IDENTITY_TOKEN_FILEは同時に指定できません", "CAFCE_AWS_WEB_IDENTITY_TOKEN and CAFCE_AWS_WEB_IDENTITY_TOKEN_FILE cannot be set together"))]
+    WebIdentityTokenConflict,
+    #[error("{}", tr!("Web IDトークンファイルの読み込みに失敗しました: {path}", "failed to read the web identity token file: {path}"))]
+    WebIdentityTokenFile {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}", tr!("AssumeRoleに失敗しました: {}", "AssumeRole failed: {}", .0))]
     AssumeRole(String),
-    #[error("AssumeRoleのレスポンスにクレデンシャルが含まれていません")]
+    #[error("{}", tr!("AssumeRoleのレスポンスにクレデンシャルが含まれていません", "the AssumeRole response contains no credentials"))]
     AssumeRoleMissingCredentials,
+    #[error("{}", tr!("CAFCE_AWS_ROLE_DURATION_SECONDSは900〜43200の範囲で指定してください: {}", "CAFCE_AWS_ROLE_DURATION_SECONDS must be between 900 and 43200: {}", .0))]
+    InvalidRoleDurationSeconds(i32),
+    #[error("{}", tr!("セッションタグの形式が不正です（\"キー=値\"のカンマ区切りで指定してください）: {}", "invalid session tags (expected comma-separated \"key=value\"): {}", .0))]
+    InvalidSessionTag(String),
+    #[error("{}", tr!("セッションポリシーの生成に失敗しました: {}", "failed to generate the session policy: {}", .0))]
+    SessionPolicy(#[from] SessionPolicyError),
+    #[error("{}", tr!("CAFCE_AWS_SSEの値が不正です（\"AES256\"または\"aws:kms\"を指定してください）: {}", "invalid CAFCE_AWS_SSE (expected \"AES256\" or \"aws:kms\"): {}", .0))]
+    InvalidSse(String),
+    #[error("{}", tr!("CAFCE_AWS_SSE_KMS_KEY_IDはCAFCE_AWS_SSE=aws:kmsの場合のみ指定できます", "CAFCE_AWS_SSE_KMS_KEY_ID can only be set with CAFCE_AWS_SSE=aws:kms"))]
+    SseKmsKeyIdWithoutKms,
+    #[error("{}", tr!("CAFCE_AWS_SSE_CUSTOMER_KEY（SSE-C）はCAFCE_AWS_SSEと同時に指定できません", "CAFCE_AWS_SSE_CUSTOMER_KEY (SSE-C) cannot be combined with CAFCE_AWS_SSE"))]
+    SseCustomerKeyConflict,
+    #[error("{}", tr!("CAFCE_AWS_SSE_CUSTOMER_KEYが不正です（256bitの鍵をBase64で指定してください）", "invalid CAFCE_AWS_SSE_CUSTOMER_KEY (expected a Base64-encoded 256-bit key)"))]
+    InvalidSseCustomerKey,
+    #[error("{}", tr!("SSE-CはHTTPS接続でのみ使用できます（CAFCE_AWS_INSECURE=trueとは併用できません）", "SSE-C requires HTTPS (cannot be combined with CAFCE_AWS_INSECURE=true)"))]
+    SseCustomerKeyRequiresHttps,
+}
+
+impl BuildClientError {
+    /// 設定の誤りではなく、STS・認証基盤との通信の失敗か判定する
+    ///
+    /// `on_error = "warn"`の場合、trueのエラーは警告のみとしてジョブを続行する。
+    pub fn is_runtime_error(&self) -> bool {
+        matches!(
+            self,
+            Self::Credentials { .. } | Self::AssumeRole(_) | Self::AssumeRoleMissingCredentials
+        )
+    }
 }
 
 /// S3設定ビルダーに環境変数に基づく設定（エンドポイント、Path-style）を適用する
@@ -43,128 +113,854 @@ pub fn apply_s3_config(
     Ok(builder)
 }
 
-/// STS AssumeRoleを実行して一時クレデンシャルを取得する
-///
-/// `access_key`/`secret_key`をソースクレデンシャルとしてSTSクライアントを作成し、
-/// `assume_role`を実行する。取得した一時クレデンシャルはS3クライアント用の
-/// `Credentials`に変換して返す。
+/// STSクライアントを構築する
 ///
 /// # Arguments
-/// * `access_key` / `secret_key` - AssumeRoleのソースとなる静的クレデンシャル
-/// * `role_arn` - Assumeするロールのarn
-/// * `session_name` - AssumeRoleのセッション名（未指定時は"cafce-session"）
-/// * `region` - STSクライアントに設定するリージョン
-async fn assume_role(
-    access_key: &str,
-    secret_key: &str,
-    role_arn: &str,
-    session_name: Option<&str>,
-    region: &str,
-) -> Result<Credentials, BuildClientError> {
-    // STSクライアントを静的クレデンシャルで作成
-    let sts_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .credentials_provider(Credentials::new(
-            access_key,
-            secret_key,
-            None,
-            None,
-            "cafce-source",
-        ))
-        .region(Region::new(region.to_string()))
-        .load()
-        .await;
-
-    let sts_client = aws_sdk_sts::Client::new(&sts_config);
-
-    // AssumeRole実行
-    let response = sts_client
-        .assume_role()
-        .role_arn(role_arn)
-        .role_session_name(session_name.unwrap_or("cafce-session"))
-        .send()
-        .await
-        .map_err(|e| BuildClientError::AssumeRole(e.to_string()))?;
+/// * `env` - 環境変数設定（リージョン、STSエンドポイント）
+/// * `source_credentials` - STS呼び出しに使用するクレデンシャル
+///   （AssumeRoleWithWebIdentityは署名不要のためNone）
+async fn build_sts_client(
+    env: &Env,
+    source_credentials: Option<Credentials>,
+) -> Result<aws_sdk_sts::Client, BuildClientError> {
+    let mut config_loader = aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .region(Region::new(env.get_region()));
+    config_loader = apply_connection_settings(config_loader, env)?;
+
+    config_loader = match source_credentials {
+        Some(credentials) => config_loader.credentials_provider(credentials),
+        None => config_loader.no_credentials(),
+    };
+
+    if let Some(endpoint) = env.sts_endpoint() {
+        config_loader = config_loader.endpoint_url(endpoint);
+    }
+
+    Ok(aws_sdk_sts::Client::new(&config_loader.load().await))
+}
 
-    let creds = response
-        .credentials
-        .ok_or(BuildClientError::AssumeRoleMissingCredentials)?;
+/// config loaderにHTTPクライアント（CA証明書・mTLS・プロキシ）・タイムアウト・リトライの設定を適用する
+///
+/// S3とSTSの両方の通信に同じ設定を使用する。
+fn apply_connection_settings(
+    mut config_loader: ConfigLoader,
+    env: &Env,
+) -> Result<ConfigLoader, BuildClientError> {
+    if let Some(http_client) = build_http_client(env)? {
+        config_loader = config_loader.http_client(http_client);
+    }
+    if let Some(timeout_config) = timeout_config(env) {
+        config_loader = config_loader.timeout_config(timeout_config);
+    }
+    if let Some(retry_config) = retry_config(env)? {
+        config_loader = config_loader.retry_config(retry_config);
+    }
+    Ok(config_loader)
+}
 
+/// リトライの設定を組み立てる
+///
+/// 最大試行回数・リトライ方式のいずれも未指定の場合はNone（SDKデフォルト）。
+pub fn retry_config(env: &Env) -> Result<Option<RetryConfig>, BuildClientError> {
+    if env.max_attempts().is_none() && env.retry_mode() == RetryMode::Standard {
+        return Ok(None);
+    }
+    let mut retry_config = match env.retry_mode() {
+        RetryMode::Standard => RetryConfig::standard(),
+        RetryMode::Adaptive => RetryConfig::adaptive(),
+    };
+    if let Some(max_attempts) = env.max_attempts() {
+        if max_attempts == 0 {
+            return Err(BuildClientError::InvalidMaxAttempts);
+        }
+        retry_config = retry_config.with_max_attempts(max_attempts);
+    }
+    Ok(Some(retry_config))
+}
+
+/// S3操作の失敗がタイムアウト（接続・読み取り・試行・操作全体）によるものか判定する
+pub fn is_timeout_error<E, R>(error: &SdkError<E, R>) -> bool {
+    match error {
+        SdkError::TimeoutError(_) => true,
+        SdkError::DispatchFailure(failure) => failure.is_timeout(),
+        _ => false,
+    }
+}
+
+/// S3操作の失敗がクレデンシャルの取得（AssumeRole等）の失敗によるものか判定する
+///
+/// クレデンシャルは最初のS3リクエストの際に取得するため、STS・認証基盤のエラーはS3操作のエラーとして返る。
+pub fn is_credentials_error<E, R>(error: &SdkError<E, R>) -> bool
+where
+    E: std::error::Error + 'static,
+    R: std::fmt::Debug,
+{
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        if error.is::<CredentialsError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// STSが返した一時クレデンシャルをS3クライアント用の`Credentials`に変換する
+fn into_credentials(creds: aws_sdk_sts::types::Credentials, provider_name: &'static str) -> Credentials {
     let expiration = SystemTime::try_from(creds.expiration).ok();
 
-    Ok(Credentials::new(
+    Credentials::new(
         creds.access_key_id,
         creds.secret_access_key,
         Some(creds.session_token),
         expiration,
-        "cafce-assumed-role",
-    ))
+        provider_name,
+    )
+}
+
+/// AssumeRoleで指定できる有効期間の最小値（秒）
+const MIN_ROLE_DURATION_SECONDS: i32 = 900;
+
+/// AssumeRoleで指定できる有効期間の最大値（秒）
+const MAX_ROLE_DURATION_SECONDS: i32 = 43200;
+
+/// 一時クレデンシャルの有効期限がこの時間以内に迫っていたら取り直す
+const CREDENTIALS_REFRESH_BUFFER: Duration = Duration::from_secs(5 * 60);
+
+/// GitLabのプロジェクトパスを記録するセッションタグのキー
+pub const SESSION_TAG_PROJECT: &str = "gitlab-project";
+
+/// GitLabのパイプラインIDを記録するセッションタグのキー
+pub const SESSION_TAG_PIPELINE_ID: &str = "gitlab-pipeline-id";
+
+/// AssumeRole / AssumeRoleWithWebIdentityに渡す追加パラメーター
+///
+/// AssumeRoleWithWebIdentityはExternalIdとセッションタグに対応していないため、
+/// `duration_seconds`と`session_policy`のみを使用する
+/// （セッションタグはIDトークンのクレームから付与される）。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AssumeRoleOptions {
+    pub external_id: Option<String>,
+    pub duration_seconds: Option<i32>,
+    /// インラインセッションポリシー（JSON）
+    pub session_policy: Option<String>,
+    /// セッションタグ（キー, 値）
+    pub session_tags: Vec<(String, String)>,
+}
+
+impl AssumeRoleOptions {
+    /// 環境変数からAssumeRoleの追加パラメーターを組み立てる
+    ///
+    /// - GitLab CI上では`SESSION_TAG_PROJECT` / `SESSION_TAG_PIPELINE_ID`のタグを自動で付与する
+    ///   （`env.role_session_tags_auto()`がfalseの場合は付与しない）
+    /// - `env.role_session_tags()`で同じキー（大文字・小文字は区別しない）を指定した場合はそちらを優先する
+    /// - 有効期間が900〜43200秒の範囲外、またはタグの形式が不正な場合はエラー
+    pub fn from_env(env: &Env, ci: &CiEnv) -> Result<Self, BuildClientError> {
+        let duration_seconds = env.role_duration_seconds();
+        if let Some(duration) = duration_seconds {
+            if !(MIN_ROLE_DURATION_SECONDS..=MAX_ROLE_DURATION_SECONDS).contains(&duration) {
+                return Err(BuildClientError::InvalidRoleDurationSeconds(duration));
+            }
+        }
+
+        let mut session_tags = Vec::new();
+        if env.role_session_tags_auto() {
+            if let Some(project) = ci.project_path() {
+                session_tags.push((SESSION_TAG_PROJECT.to_string(), project.to_string()));
+            }
+            if let Some(pipeline_id) = ci.pipeline_id() {
+                session_tags.push((SESSION_TAG_PIPELINE_ID.to_string(), pipeline_id.to_string()));
+            }
+        }
+        for tag in env
+            .role_session_tags()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+        {
+            let (key, value) = tag
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .filter(|(key, _)| !key.is_empty())
+                .ok_or_else(|| BuildClientError::InvalidSessionTag(tag.to_string()))?;
+            session_tags.retain(|(existing, _)| !existing.eq_ignore_ascii_case(key));
+            session_tags.push((key.to_string(), value.to_string()));
+        }
+
+        Ok(Self {
+            external_id: env.role_external_id().map(String::from),
+            duration_seconds,
+            session_policy: env.role_session_policy().map(String::from),
+            session_tags,
+        })
+    }
+}
+
+/// STSで一時クレデンシャルを取得するcredential provider
+///
+/// 取得した一時クレデンシャルは有効期限が迫るまで使い回し、期限の
+/// `CREDENTIALS_REFRESH_BUFFER`前を過ぎてから要求された場合はSTSを再度呼び出す。
+/// S3クライアントのidentity cacheが有効期限前に再取得を要求するため、
+/// 有効期間より長くかかるstoreでも途中でクレデンシャルが失効しない。
+struct AssumeRoleProvider {
+    sts_client: aws_sdk_sts::Client,
+    role_arn: String,
+    session_name: String,
+    /// Web IDトークン（Noneの場合はAssumeRole、Someの場合はAssumeRoleWithWebIdentity）
+    web_identity_token: Option<String>,
+    options: AssumeRoleOptions,
+    cached: Mutex<Option<Credentials>>,
+}
+
+impl std::fmt::Debug for AssumeRoleProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Web IDトークンを出力しないよう、Debugは手動実装している
+        f.debug_struct("AssumeRoleProvider")
+            .field("role_arn", &self.role_arn)
+            .field("session_name", &self.session_name)
+            .field("web_identity", &self.web_identity_token.is_some())
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AssumeRoleProvider {
+    /// 静的クレデンシャルをソースとしたAssumeRoleのproviderを作成する
+    ///
+    /// # Arguments
+    /// * `env` - 環境変数設定（リージョン、STSエンドポイント）
+    /// * `access_key` / `secret_key` - AssumeRoleのソースとなる静的クレデンシャル
+    /// * `role_arn` - Assumeするロールのarn
+    /// * `session_name` - AssumeRoleのセッション名（未指定時は"cafce-session"）
+    /// * `options` - ExternalId・有効期間・セッションポリシー・セッションタグ
+    async fn assume_role(
+        env: &Env,
+        access_key: &str,
+        secret_key: &str,
+        role_arn: &str,
+        session_name: Option<&str>,
+        options: AssumeRoleOptions,
+    ) -> Result<Self, BuildClientError> {
+        let source_credentials =
+            Credentials::new(access_key, secret_key, None, None, "cafce-source");
+        Ok(Self {
+            sts_client: build_sts_client(env, Some(source_credentials)).await?,
+            role_arn: role_arn.to_string(),
+            session_name: session_name.unwrap_or("cafce-session").to_string(),
+            web_identity_token: None,
+            options,
+            cached: Mutex::new(None),
+        })
+    }
+
+    /// AssumeRoleWithWebIdentityのproviderを作成する
+    ///
+    /// GitLab CIの`id_tokens`で発行したOIDCトークンをWeb IDトークンとして使用する。
+    /// AssumeRoleWithWebIdentityは署名不要のAPIのため、ソースクレデンシャルは不要。
+    async fn web_identity(
+        env: &Env,
+        token: String,
+        role_arn: &str,
+        session_name: Option<&str>,
+        options: AssumeRoleOptions,
+    ) -> Result<Self, BuildClientError> {
+        Ok(Self {
+            sts_client: build_sts_client(env, None).await?,
+            role_arn: role_arn.to_string(),
+            session_name: session_name.unwrap_or("cafce-session").to_string(),
+            web_identity_token: Some(token),
+            options,
+            cached: Mutex::new(None),
+        })
+    }
+
+    /// 一時クレデンシャルを取得する（有効期限が迫っていなければ取得済みのものを返す）
+    async fn credentials(&self) -> Result<Credentials, BuildClientError> {
+        let cached = self.cached.lock().unwrap().clone();
+        if let Some(credentials) = cached.filter(|c| !needs_refresh(c, SystemTime::now())) {
+            return Ok(credentials);
+        }
+
+        let credentials = match &self.web_identity_token {
+            Some(token) => self.send_assume_role_with_web_identity(token).await?,
+            None => self.send_assume_role().await?,
+        };
+        *self.cached.lock().unwrap() = Some(credentials.clone());
+        Ok(credentials)
+    }
+
+    async fn send_assume_role(&self) -> Result<Credentials, BuildClientError> {
+        let mut request = self
+            .sts_client
+            .assume_role()
+            .role_arn(&self.role_arn)
+            .role_session_name(&self.session_name)
+            .set_external_id(self.options.external_id.clone())
+            .set_duration_seconds(self.options.duration_seconds)
+            .set_policy(self.options.session_policy.clone());
+        for (key, value) in &self.options.session_tags {
+            let tag = aws_sdk_sts::types::Tag::builder()
+                .key(key)
+                .value(value)
+                .build()
+                .map_err(|e| BuildClientError::InvalidSessionTag(e.to_string()))?;
+            request = request.tags(tag);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| BuildClientError::AssumeRole(e.to_string()))?;
+
+        let creds = response
+            .credentials
+            .ok_or(BuildClientError::AssumeRoleMissingCredentials)?;
+
+        Ok(into_credentials(creds, "cafce-assumed-role"))
+    }
+
+    async fn send_assume_role_with_web_identity(
+        &self,
+        token: &str,
+    ) -> Result<Credentials, BuildClientError> {
+        let response = self
+            .sts_client
+            .assume_role_with_web_identity()
+            .role_arn(&self.role_arn)
+            .role_session_name(&self.session_name)
+            .web_identity_token(token)
+            .set_duration_seconds(self.options.duration_seconds)
+            .set_policy(self.options.session_policy.clone())
+            .send()
+            .await
+            .map_err(|e| BuildClientError::AssumeRole(e.to_string()))?;
+
+        let creds = response
+            .credentials
+            .ok_or(BuildClientError::AssumeRoleMissingCredentials)?;
+
+        Ok(into_credentials(creds, "cafce-web-identity"))
+    }
+}
+
+impl ProvideCredentials for AssumeRoleProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            self.credentials()
+                .await
+                .map_err(CredentialsError::provider_error)
+        })
+    }
+}
+
+/// 一時クレデンシャルを取り直すべきか判定する（有効期限のないクレデンシャルは取り直さない）
+fn needs_refresh(credentials: &Credentials, now: SystemTime) -> bool {
+    credentials
+        .expiry()
+        .is_some_and(|expiry| expiry <= now + CREDENTIALS_REFRESH_BUFFER)
+}
+
+/// Web IDトークンを環境変数またはトークンファイルから取得する
+///
+/// - `env.web_identity_token()`と`env.web_identity_token_file()`の両方が指定されている場合はエラー
+/// - トークンファイルは前後の空白・改行を除いて読み込む
+/// - どちらも未指定の場合はNone
+fn resolve_web_identity_token(env: &Env) -> Result<Option<String>, BuildClientError> {
+    match (env.web_identity_token(), env.web_identity_token_file()) {
+        (Some(_), Some(_)) => Err(BuildClientError::WebIdentityTokenConflict),
+        (Some(token), None) => Ok(Some(token.trim().to_string())),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .map(|token| Some(token.trim().to_string()))
+            .map_err(|source| BuildClientError::WebIdentityTokenFile {
+                path: path.to_string(),
+                source,
+            }),
+        (None, None) => Ok(None),
+    }
+}
+
+/// AWSクレデンシャルの取得元を決定する
+///
+/// - `env.credential_source()`が明示的に指定されている場合、その取得元に必要な
+///   環境変数が揃っているかを検証して返す
+/// - 未指定の場合は以下の優先順位で自動判定する:
+///   1. `env.role_arn()`とWeb IDトークンが指定されている場合は`web_identity`
+///   2. `env.role_arn()`が指定されている場合は`assume_role`
+///      （ソースクレデンシャルが片方でも未設定の場合はエラー）
+///   3. `env.access_key()`/`env.secret_key()`が両方とも設定されている場合は`static`
+///   4. `env.profile()`が指定されている場合は`profile`
+///   5. それ以外は`chain`（SDKデフォルトのcredential provider chain）
+/// - Web IDトークンが指定されているのに`env.role_arn()`が未指定の場合はエラー
+pub fn resolve_credential_source(env: &Env) -> Result<CredentialSource, BuildClientError> {
+    let has_static_credentials = env.access_key().is_some() && env.secret_key().is_some();
+    let has_web_identity_token =
+        env.web_identity_token().is_some() || env.web_identity_token_file().is_some();
+
+    let missing = |source: CredentialSource, variable: &'static str| {
+        BuildClientError::MissingCredentialSetting {
+            credential_source: source,
+            variable,
+        }
+    };
+
+    match env.credential_source() {
+        Some(source @ CredentialSource::Static) => {
+            if env.access_key().is_none() {
+                return Err(missing(source, "CAFCE_AWS_ACCESS_KEY"));
+            }
+            if env.secret_key().is_none() {
+                return Err(missing(source, "CAFCE_AWS_SECRET_KEY"));
+            }
+            Ok(source)
+        }
+        Some(source @ CredentialSource::Profile) => {
+            env.profile().ok_or(missing(source, "CAFCE_AWS_PROFILE"))?;
+            Ok(source)
+        }
+        Some(source @ CredentialSource::AssumeRole) => {
+            env.role_arn().ok_or(missing(source, "CAFCE_AWS_ROLE_ARN"))?;
+            if !has_static_credentials {
+                return Err(BuildClientError::MissingAssumeRoleSourceCredentials);
+            }
+            Ok(source)
+        }
+        Some(source @ CredentialSource::WebIdentity) => {
+            env.role_arn().ok_or(missing(source, "CAFCE_AWS_ROLE_ARN"))?;
+            if !has_web_identity_token {
+                return Err(missing(source, "CAFCE_AWS_WEB_IDENTITY_TOKEN"));
+            }
+            Ok(source)
+        }
+        Some(source @ (CredentialSource::Ecs | CredentialSource::Imds | CredentialSource::Chain)) => {
+            Ok(source)
+        }
+        None => {
+            if has_web_identity_token && env.role_arn().is_none() {
+                return Err(BuildClientError::MissingWebIdentityRoleArn);
+            }
+            if env.role_arn().is_some() {
+                if has_web_identity_token {
+                    return Ok(CredentialSource::WebIdentity);
+                }
+                if !has_static_credentials {
+                    return Err(BuildClientError::MissingAssumeRoleSourceCredentials);
+                }
+                return Ok(CredentialSource::AssumeRole);
+            }
+            if has_static_credentials {
+                return Ok(CredentialSource::Static);
+            }
+            if env.profile().is_some() {
+                return Ok(CredentialSource::Profile);
+            }
+            Ok(CredentialSource::Chain)
+        }
+    }
+}
+
+/// クレデンシャルの取得元ごとのプロバイダー名を取得する
+///
+/// cafceが作成するproviderは、供給する`Credentials`に記録するプロバイダー名と同じ名前を返す。
+/// `profile` / `chain`ではSDKのcredential provider chainが実際のproviderを選ぶため、
+/// chainの名前を返す（chain内で選ばれたproviderはSDKがtracingのイベントとして記録する）。
+fn credentials_provider_name(source: CredentialSource) -> &'static str {
+    match source {
+        CredentialSource::Static => "cafce-static",
+        CredentialSource::AssumeRole => "cafce-assumed-role",
+        CredentialSource::WebIdentity => "cafce-web-identity",
+        CredentialSource::Ecs => "EcsContainer",
+        CredentialSource::Imds => "IMDSv2",
+        CredentialSource::Profile | CredentialSource::Chain => "DefaultChain",
+    }
+}
+
+/// 解決済みのクレデンシャルから、実際に供給したプロバイダー名を取得する
+///
+/// `Credentials`はプロバイダー名のgetterを公開していないため、シークレットが
+/// マスクされたDebug出力から`provider_name`フィールドを取り出す。
+fn resolved_provider_name(credentials: &Credentials) -> String {
+    let debug = format!("{credentials:?}");
+    debug
+        .split_once("provider_name: \"")
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// クレデンシャルの取得元と、実際に供給したプロバイダー名のログメッセージ（シークレットは含まない）
+fn credentials_message(source: CredentialSource, credentials: &Credentials) -> String {
+    tr!(
+        "cafce: AWSクレデンシャルの取得元: {source}（プロバイダー: {}）",
+        "cafce: AWS credential source: {source} (provider: {})",
+        resolved_provider_name(credentials)
+    )
+}
+
+/// 最初にクレデンシャルを取得できた際に、取得元とプロバイダー名を標準エラー出力に記録するprovider
+///
+/// クレデンシャルは最初のS3リクエストの際に取得するため、構築時ではなく取得時に記録する。
+/// `profile` / `chain`ではchain内で実際に選ばれたproviderの名前が記録される。
+#[derive(Debug)]
+struct LoggingCredentialsProvider {
+    inner: SharedCredentialsProvider,
+    source: CredentialSource,
+    logged: AtomicBool,
+}
+
+impl LoggingCredentialsProvider {
+    fn new(inner: SharedCredentialsProvider, source: CredentialSource) -> Self {
+        Self {
+            inner,
+            source,
+            logged: AtomicBool::new(false),
+        }
+    }
+}
+
+impl ProvideCredentials for LoggingCredentialsProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            let credentials = self.inner.provide_credentials().await?;
+            if !self.logged.swap(true, Ordering::Relaxed) {
+                eprintln!("{}", credentials_message(self.source, &credentials));
+            }
+            Ok(credentials)
+        })
+    }
 }
 
 /// 環境変数設定に基づいてS3クライアントを構築する
 ///
 /// - リージョンはenv.get_region()を使用してconfig loaderに設定する
-/// - 認証方式は以下の優先順位で決定する:
-///   1. `env.role_arn()`が指定されている場合、`env.access_key()`/`env.secret_key()`を
-///      ソースクレデンシャルとしてSTS AssumeRoleを実行し、一時クレデンシャルを使用する
-///      （ソースクレデンシャルが片方でも未設定の場合はエラー）
-///   2. `env.access_key()`/`env.secret_key()`が両方とも設定されている場合、
-///      静的クレデンシャルをcredentials_providerとして設定する
-///   3. それ以外の場合、`env.profile()`が指定されていればconfig loaderに
-///      プロファイル名を設定し、SDKにクレデンシャル解決を委ねる
-///      （未指定の場合はSDKデフォルトのcredential provider chainに委ねる）
+/// - クレデンシャルの取得元はresolve_credential_source()で決定する:
+///   - `web_identity`: STS AssumeRoleWithWebIdentityで一時クレデンシャルを取得する
+///   - `assume_role`: `env.access_key()`/`env.secret_key()`をソースクレデンシャルとして
+///     STS AssumeRoleを実行し、一時クレデンシャルを取得する
+///   - どちらもAssumeRoleOptions（`ci`のプロジェクト・パイプラインIDのセッションタグを含む）を渡し、
+///     有効期限が迫ると自動で取り直す
+///   - `static`: 静的クレデンシャルを使用する
+///   - `ecs` / `imds`: コンテナクレデンシャル / EC2インスタンスメタデータ（IMDSv2）のみを使用する
+///   - `profile` / `chain`: config loaderにプロファイル名（指定時）を設定し、
+///     SDKデフォルトのcredential provider chainに委ねる
+/// - クレデンシャルは最初のS3リクエストの際に取得する（構築時には取得しない）
+/// - 取得元とプロバイダー名（シークレットは含まない）を`cafce.credentials`スパンの属性として記録し、
+///   最初の取得時に実際に供給したプロバイダー名を標準エラー出力に記録する
 /// - エンドポイント・Path-style設定はapply_s3_config()に委譲する
-pub async fn build_s3_client(env: &Env) -> Result<aws_sdk_s3::Client, BuildClientError> {
+pub async fn build_s3_client(
+    env: &Env,
+    ci: &CiEnv,
+) -> Result<aws_sdk_s3::Client, BuildClientError> {
+    build_client(env, ci, None).await
+}
+
+/// キャッシュの読み書きに使用するS3クライアントを構築する
+///
+/// build_s3_client()に加えて、AssumeRole / AssumeRoleWithWebIdentityの際に
+/// `setting`のバケット・オブジェクトキーのテンプレートから生成したCacheSessionPolicyを
+/// セッションポリシーとして渡し、ロールの権限をキャッシュのプレフィックスに限定する。
+/// `env.role_session_policy()`が指定されている場合、または`env.role_auto_session_policy()`が
+/// falseの場合は自動生成しない。
+pub async fn build_s3_client_for_cache(
+    env: &Env,
+    ci: &CiEnv,
+    setting: &Setting,
+) -> Result<aws_sdk_s3::Client, BuildClientError> {
+    build_client(env, ci, Some(setting)).await
+}
+
+/// AssumeRoleに渡す追加パラメーターを組み立てる（`setting`指定時はセッションポリシーを自動生成する）
+fn assume_role_options(
+    env: &Env,
+    ci: &CiEnv,
+    setting: Option<&Setting>,
+) -> Result<AssumeRoleOptions, BuildClientError> {
+    let mut options = AssumeRoleOptions::from_env(env, ci)?;
+    if options.session_policy.is_none() && env.role_auto_session_policy() {
+        if let Some(setting) = setting {
+            let policy = CacheSessionPolicy::new(setting, ci.project_path(), env)?;
+            options.session_policy = Some(policy.to_json());
+        }
+    }
+    Ok(options)
+}
+
+async fn build_client(
+    env: &Env,
+    ci: &CiEnv,
+    setting: Option<&Setting>,
+) -> Result<aws_sdk_s3::Client, BuildClientError> {
+    let source = resolve_credential_source(env)?;
+    let span = tracing::info_span!(
+        "cafce.credentials",
+        credential_source = %source,
+        credential_provider = credentials_provider_name(source)
+    );
+    build_client_with_source(env, ci, setting, source)
+        .instrument(span)
+        .await
+}
+
+async fn build_client_with_source(
+    env: &Env,
+    ci: &CiEnv,
+    setting: Option<&Setting>,
+    source: CredentialSource,
+) -> Result<aws_sdk_s3::Client, BuildClientError> {
     let mut config_loader = aws_config::defaults(aws_config::BehaviorVersion::latest())
         .region(Region::new(env.get_region()));
 
-    // AssumeRole・静的クレデンシャルのいずれでもない場合のみ、プロファイル名を設定する
-    // （プロファイルが指すクレデンシャル解決はSDKに任せる）
-    let uses_static_credentials = env.access_key().is_some() && env.secret_key().is_some();
-    if env.role_arn().is_none() && !uses_static_credentials {
+    // プロファイルが指すクレデンシャル解決はSDKに任せる
+    if matches!(source, CredentialSource::Profile | CredentialSource::Chain) {
         if let Some(profile) = env.profile() {
             config_loader = config_loader.profile_name(profile);
         }
     }
 
+    config_loader = apply_connection_settings(config_loader, env)?;
+
     let shared_config = config_loader.load().await;
 
-    let mut builder = Builder::from(&shared_config);
+    let credentials_provider = match source {
+        CredentialSource::WebIdentity => {
+            // AssumeRoleWithWebIdentity: GitLab CIのOIDCトークンを使用
+            let (Some(role_arn), Some(token)) = (env.role_arn(), resolve_web_identity_token(env)?)
+            else {
+                return Err(BuildClientError::MissingWebIdentityRoleArn);
+            };
+            let provider = AssumeRoleProvider::web_identity(
+                env,
+                token,
+                role_arn,
+                env.role_session_name(),
+                assume_role_options(env, ci, setting)?,
+            )
+            .await?;
+            SharedCredentialsProvider::new(provider)
+        }
+        CredentialSource::AssumeRole => {
+            // AssumeRole: access_key/secret_keyをソースクレデンシャルとして使用
+            let (Some(role_arn), Some(access_key), Some(secret_key)) =
+                (env.role_arn(), env.access_key(), env.secret_key())
+            else {
+                return Err(BuildClientError::MissingAssumeRoleSourceCredentials);
+            };
+            let provider = AssumeRoleProvider::assume_role(
+                env,
+                access_key,
+                secret_key,
+                role_arn,
+                env.role_session_name(),
+                assume_role_options(env, ci, setting)?,
+            )
+            .await?;
+            SharedCredentialsProvider::new(provider)
+        }
+        CredentialSource::Static => {
+            // 静的クレデンシャル（MinIO向け / AWS直接接続）
+            SharedCredentialsProvider::new(Credentials::new(
+                env.access_key().unwrap_or_default(),
+                env.secret_key().unwrap_or_default(),
+                env.session_token().map(String::from),
+                None,
+                "cafce-static",
+            ))
+        }
+        CredentialSource::Ecs => SharedCredentialsProvider::new(
+            aws_config::ecs::EcsCredentialsProvider::builder().build(),
+        ),
+        CredentialSource::Imds => SharedCredentialsProvider::new(
+            aws_config::imds::credentials::ImdsCredentialsProvider::builder().build(),
+        ),
+        CredentialSource::Profile | CredentialSource::Chain => shared_config
+            .credentials_provider()
+            .ok_or(BuildClientError::Credentials {
+                credential_source: source,
+                message: tr!("credential providerが設定されていません", "no credential provider is configured"),
+            })?,
+    };
+
+    let credentials_provider = LoggingCredentialsProvider::new(credentials_provider, source);
+    let mut builder = Builder::from(&shared_config).credentials_provider(credentials_provider);
 
-    if let Some(role_arn) = env.role_arn() {
-        // AssumeRole: access_key/secret_keyをソースクレデンシャルとして使用
-        let (access_key, secret_key) = match (env.access_key(), env.secret_key()) {
-            (Some(access_key), Some(secret_key)) => (access_key, secret_key),
-            _ => return Err(BuildClientError::MissingAssumeRoleSourceCredentials),
-        };
+    builder = apply_s3_config(builder, env)?;
+
+    Ok(aws_sdk_s3::Client::from_conf(builder.build()))
+}
+
+/// SSE-Cの顧客提供キー
+///
+/// Debug出力で鍵の値が漏れないよう、Debugは手動実装している。
+#[derive(Clone, PartialEq, Eq)]
+pub struct SseCustomerKey {
+    /// 256bitの鍵（Base64）
+    key: String,
+    /// 鍵のMD5ダイジェスト（Base64、S3が鍵の破損検出に使用）
+    key_md5: String,
+}
+
+impl std::fmt::Debug for SseCustomerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SseCustomerKey(..)")
+    }
+}
 
-        let assumed_credentials = assume_role(
-            access_key,
-            secret_key,
-            role_arn,
-            env.role_session_name(),
-            &env.get_region(),
+/// オブジェクトに適用するサーバーサイド暗号化の設定
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SseConfig {
+    /// 指定しない（バケットのデフォルト暗号化設定に従う）
+    #[default]
+    None,
+    /// SSE-S3（`x-amz-server-side-encryption: AES256`）
+    S3,
+    /// SSE-KMS（`key_id`未指定時はAWS管理キー`aws/s3`）
+    Kms { key_id: Option<String> },
+    /// SSE-C（アップロード・ダウンロードの両方で鍵を送信する）
+    Customer(SseCustomerKey),
+}
+
+impl SseConfig {
+    /// 環境変数設定からサーバーサイド暗号化の設定を組み立てる
+    ///
+    /// SSE-Cの鍵は平文のHTTPでは送信できないため、エンドポイントがhttpの場合はエラーとする。
+    pub fn from_env(env: &Env) -> Result<Self, BuildClientError> {
+        let uses_http = matches!(env.build_endpoint()?, Some(url) if url.scheme() == "http");
+        Self::parse(
+            env.sse(),
+            env.sse_kms_key_id(),
+            env.sse_customer_key(),
+            uses_http,
         )
-        .await?;
-
-        builder = builder.credentials_provider(assumed_credentials);
-    } else if let (Some(access_key), Some(secret_key)) = (env.access_key(), env.secret_key()) {
-        // 静的クレデンシャル（MinIO向け / AWS直接接続）
-        let credentials = Credentials::new(
-            access_key,
-            secret_key,
-            env.session_token().map(String::from),
-            None,
-            "cafce-static",
-        );
-        builder = builder.credentials_provider(credentials);
     }
-    // それ以外はSDK credential provider chainに任せる（Profile指定済み、または自動検出）
 
-    builder = apply_s3_config(builder, env)?;
+    /// 各設定値からサーバーサイド暗号化の設定を組み立てる
+    ///
+    /// この関数は純粋なロジックであり、環境変数を直接読まない。
+    ///
+    /// # Arguments
+    /// * `sse` - CAFCE_AWS_SSEの値（"AES256" / "aws:kms"）
+    /// * `kms_key_id` - CAFCE_AWS_SSE_KMS_KEY_IDの値
+    /// * `customer_key` - CAFCE_AWS_SSE_CUSTOMER_KEYの値（Base64）
+    /// * `uses_http` - エンドポイントがhttpか
+    pub fn parse(
+        sse: Option<&str>,
+        kms_key_id: Option<&str>,
+        customer_key: Option<&str>,
+        uses_http: bool,
+    ) -> Result<Self, BuildClientError> {
+        let sse = sse.filter(|s| !s.is_empty());
+        let kms_key_id = kms_key_id.filter(|s| !s.is_empty());
+
+        if let Some(customer_key) = customer_key.filter(|s| !s.is_empty()) {
+            if sse.is_some() || kms_key_id.is_some() {
+                return Err(BuildClientError::SseCustomerKeyConflict);
+            }
+            if uses_http {
+                return Err(BuildClientError::SseCustomerKeyRequiresHttps);
+            }
+            return Ok(Self::Customer(Self::parse_customer_key(customer_key)?));
+        }
 
-    Ok(aws_sdk_s3::Client::from_conf(builder.build()))
+        match sse {
+            None if kms_key_id.is_some() => Err(BuildClientError::SseKmsKeyIdWithoutKms),
+            None => Ok(Self::None),
+            Some("AES256") if kms_key_id.is_some() => Err(BuildClientError::SseKmsKeyIdWithoutKms),
+            Some("AES256") => Ok(Self::S3),
+            Some("aws:kms") => Ok(Self::Kms {
+                key_id: kms_key_id.map(String::from),
+            }),
+            Some(other) => Err(BuildClientError::InvalidSse(other.to_string())),
+        }
+    }
+
+    fn parse_customer_key(customer_key: &str) -> Result<SseCustomerKey, BuildClientError> {
+        use base64::Engine;
+        use md5::Digest;
+
+        let engine = base64::engine::general_purpose::STANDARD;
+        let raw_key = engine
+            .decode(customer_key.trim())
+            .map_err(|_| BuildClientError::InvalidSseCustomerKey)?;
+        if raw_key.len() != 32 {
+            return Err(BuildClientError::InvalidSseCustomerKey);
+        }
+        Ok(SseCustomerKey {
+            key: engine.encode(&raw_key),
+            key_md5: engine.encode(md5::Md5::digest(&raw_key)),
+        })
+    }
+
+    /// PutObjectにサーバーサイド暗号化の設定を適用する
+    pub fn apply_to_put_object(&self, builder: PutObjectFluentBuilder) -> PutObjectFluentBuilder {
+        match self {
+            Self::None => builder,
+            Self::S3 => builder.server_side_encryption(ServerSideEncryption::Aes256),
+            Self::Kms { key_id } => builder
+                .server_side_encryption(ServerSideEncryption::AwsKms)
+                .set_ssekms_key_id(key_id.clone()),
+            Self::Customer(key) => builder
+                .sse_customer_algorithm(SSE_CUSTOMER_ALGORITHM)
+                .sse_customer_key(&key.key)
+                .sse_customer_key_md5(&key.key_md5),
+        }
+    }
+
+    /// CopyObjectにサーバーサイド暗号化の設定を適用する
+    ///
+    /// コピー先にはPutObjectと同じ設定を適用する。SSE-Cの場合は、コピー元の読み出しにも鍵を付与する。
+    pub fn apply_to_copy_object(&self, builder: CopyObjectFluentBuilder) -> CopyObjectFluentBuilder {
+        match self {
+            Self::None => builder,
+            Self::S3 => builder.server_side_encryption(ServerSideEncryption::Aes256),
+            Self::Kms { key_id } => builder
+                .server_side_encryption(ServerSideEncryption::AwsKms)
+                .set_ssekms_key_id(key_id.clone()),
+            Self::Customer(key) => builder
+                .sse_customer_algorithm(SSE_CUSTOMER_ALGORITHM)
+                .sse_customer_key(&key.key)
+                .sse_customer_key_md5(&key.key_md5)
+                .copy_source_sse_customer_algorithm(SSE_CUSTOMER_ALGORITHM)
+                .copy_source_sse_customer_key(&key.key)
+                .copy_source_sse_customer_key_md5(&key.key_md5),
+        }
+    }
+
+    /// GetObjectにサーバーサイド暗号化の設定を適用する
+    ///
+    /// SSE-S3/SSE-KMSは読み出し時の指定が不要なため、SSE-Cの場合のみ鍵を付与する。
+    pub fn apply_to_get_object(&self, builder: GetObjectFluentBuilder) -> GetObjectFluentBuilder {
+        match self {
+            Self::Customer(key) => builder
+                .sse_customer_algorithm(SSE_CUSTOMER_ALGORITHM)
+                .sse_customer_key(&key.key)
+                .sse_customer_key_md5(&key.key_md5),
+            _ => builder,
+        }
+    }
+
+    /// HeadObjectにサーバーサイド暗号化の設定を適用する
+    ///
+    /// SSE-Cで暗号化されたオブジェクトは、HeadObjectでも鍵の指定が必要になる。
+    pub fn apply_to_head_object(&self, builder: HeadObjectFluentBuilder) -> HeadObjectFluentBuilder {
+        match self {
+            Self::Customer(key) => builder
+                .sse_customer_algorithm(SSE_CUSTOMER_ALGORITHM)
+                .sse_customer_key(&key.key)
+                .sse_customer_key_md5(&key.key_md5),
+            _ => builder,
+        }
+    }
 }
 
+/// SSE-Cで指定する暗号化アルゴリズム（S3はAES256のみサポート）
+const SSE_CUSTOMER_ALGORITHM: &str = "AES256";
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -248,6 +1044,1004 @@ mod tests {
         assert!(result.is_ok());
         // IPアドレスのエンドポイント設定
     }
+
+    /// ListBucketsに空の一覧を返すS3の代替サーバーを起動する
+    fn start_s3_stand_in() -> crate::test_util::MockServer {
+        crate::test_util::MockServer::start(|_| {
+            crate::test_util::MockResponse::new(200, "<ListAllMyBucketsResult><Buckets></Buckets></ListAllMyBucketsResult>")
+                .header("Content-Type", "application/xml")
+        })
+    }
+
+    mod credential_source_tests {
+        use super::*;
+
+        fn credentials_env(
+            source: Option<CredentialSource>,
+            access_key: Option<&str>,
+            secret_key: Option<&str>,
+            role_arn: Option<&str>,
+            profile: Option<&str>,
+        ) -> Env {
+            Env::new_for_test(
+                None,
+                access_key.map(String::from),
+                secret_key.map(String::from),
+                None,
+                role_arn.map(String::from),
+                None,
+                profile.map(String::from),
+                false,
+                None,
+                None,
+            )
+            .with_credential_source_for_test(source)
+        }
+
+        #[test]
+        fn test_infer_static() {
+            let env = credentials_env(None, Some("ak"), Some("sk"), None, None);
+            assert_eq!(resolve_credential_source(&env).unwrap(), CredentialSource::Static);
+        }
+
+        #[test]
+        fn test_infer_assume_role() {
+            let env = credentials_env(None, Some("ak"), Some("sk"), Some("arn:role"), None);
+            assert_eq!(
+                resolve_credential_source(&env).unwrap(),
+                CredentialSource::AssumeRole
+            );
+        }
+
+        #[test]
+        fn test_infer_assume_role_without_source_credentials() {
+            let env = credentials_env(None, Some("ak"), None, Some("arn:role"), None);
+            assert!(matches!(
+                resolve_credential_source(&env),
+                Err(BuildClientError::MissingAssumeRoleSourceCredentials)
+            ));
+        }
+
+        #[test]
+        fn test_infer_web_identity() {
+            let env = credentials_env(None, None, None, Some("arn:role"), None)
+                .with_web_identity_for_test(Some("jwt"), None, None);
+            assert_eq!(
+                resolve_credential_source(&env).unwrap(),
+                CredentialSource::WebIdentity
+            );
+        }
+
+        #[test]
+        fn test_infer_profile() {
+            let env = credentials_env(None, None, None, None, Some("my-profile"));
+            assert_eq!(resolve_credential_source(&env).unwrap(), CredentialSource::Profile);
+        }
+
+        #[test]
+        fn test_infer_chain() {
+            let env = credentials_env(None, None, None, None, None);
+            assert_eq!(resolve_credential_source(&env).unwrap(), CredentialSource::Chain);
+        }
+
+        #[test]
+        fn test_explicit_static_missing_secret_key() {
+            let env = credentials_env(Some(CredentialSource::Static), Some("ak"), None, None, None);
+            let error = resolve_credential_source(&env).unwrap_err();
+            assert!(matches!(
+                error,
+                BuildClientError::MissingCredentialSetting {
+                    credential_source: CredentialSource::Static,
+                    variable: "CAFCE_AWS_SECRET_KEY",
+                }
+            ));
+            assert!(error.to_string().contains("CAFCE_AWS_CREDENTIAL_SOURCE=static"));
+        }
+
+        #[test]
+        fn test_explicit_profile_missing_profile() {
+            let env = credentials_env(Some(CredentialSource::Profile), None, None, None, None);
+            assert!(matches!(
+                resolve_credential_source(&env),
+                Err(BuildClientError::MissingCredentialSetting {
+                    variable: "CAFCE_AWS_PROFILE",
+                    ..
+                })
+            ));
+        }
+
+        #[test]
+        fn test_explicit_assume_role_missing_role_arn() {
+            let env = credentials_env(
+                Some(CredentialSource::AssumeRole),
+                Some("ak"),
+                Some("sk"),
+                None,
+                None,
+            );
+            assert!(matches!(
+                resolve_credential_source(&env),
+                Err(BuildClientError::MissingCredentialSetting {
+                    variable: "CAFCE_AWS_ROLE_ARN",
+                    ..
+                })
+            ));
+        }
+
+        #[test]
+        fn test_explicit_web_identity_missing_token() {
+            let env = credentials_env(
+                Some(CredentialSource::WebIdentity),
+                None,
+                None,
+                Some("arn:role"),
+                None,
+            );
+            assert!(matches!(
+                resolve_credential_source(&env),
+                Err(BuildClientError::MissingCredentialSetting {
+                    variable: "CAFCE_AWS_WEB_IDENTITY_TOKEN",
+                    ..
+                })
+            ));
+        }
+
+        #[test]
+        fn test_explicit_source_overrides_inference() {
+            // 静的クレデンシャルが残っていても、明示指定した取得元が優先される
+            for source in [CredentialSource::Ecs, CredentialSource::Imds, CredentialSource::Chain] {
+                let env = credentials_env(Some(source), Some("ak"), Some("sk"), None, None);
+                assert_eq!(resolve_credential_source(&env).unwrap(), source);
+            }
+        }
+
+        #[test]
+        fn test_credentials_provider_name() {
+            assert_eq!(credentials_provider_name(CredentialSource::Static), "cafce-static");
+            assert_eq!(credentials_provider_name(CredentialSource::WebIdentity), "cafce-web-identity");
+            assert_eq!(credentials_provider_name(CredentialSource::Imds), "IMDSv2");
+            assert_eq!(credentials_provider_name(CredentialSource::Chain), "DefaultChain");
+        }
+
+        #[test]
+        fn test_credentials_message() {
+            let credentials = Credentials::new("AKIAEXAMPLE", "secret-key-value", None, None, "Environment");
+            assert_eq!(resolved_provider_name(&credentials), "Environment");
+            let message = credentials_message(CredentialSource::Chain, &credentials);
+            assert!(message.contains("chain"), "{message}");
+            assert!(message.contains("Environment"), "{message}");
+            assert!(!message.contains("secret-key-value"), "{message}");
+        }
+
+        #[tokio::test]
+        async fn test_logging_credentials_provider() {
+            let credentials = Credentials::new("ak", "sk", None, None, "cafce-static");
+            let provider =
+                LoggingCredentialsProvider::new(SharedCredentialsProvider::new(credentials), CredentialSource::Static);
+            assert!(!provider.logged.load(Ordering::Relaxed));
+            let resolved = provider.provide_credentials().await.unwrap();
+            assert_eq!(resolved.access_key_id(), "ak");
+            assert!(provider.logged.load(Ordering::Relaxed));
+            // 2回目以降も同じクレデンシャルを返す（記録は最初の1回のみ）
+            assert_eq!(provider.provide_credentials().await.unwrap().access_key_id(), "ak");
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_explicit_static() {
+            let env = credentials_env(Some(CredentialSource::Static), Some("ak"), Some("sk"), None, None);
+            assert!(build_s3_client(&env, &CiEnv::default()).await.is_ok());
+        }
+    }
+
+    mod assume_role_tests {
+        use super::*;
+        use crate::test_util::{MockResponse, MockServer};
+        use aws_sdk_sts::primitives::{DateTime, DateTimeFormat};
+
+        const ROLE_ARN: &str = "arn:aws:iam::123456789012:role/cafce-ci";
+
+        /// 指定した有効期限の一時クレデンシャルを返すSTSの代替サーバーを起動する
+        fn start_sts_stand_in(expiration: SystemTime) -> MockServer {
+            let expiration = DateTime::from(expiration)
+                .fmt(DateTimeFormat::DateTime)
+                .unwrap();
+            MockServer::start(move |request| {
+                let action = if request
+                    .body_str()
+                    .contains("Action=AssumeRoleWithWebIdentity")
+                {
+                    "AssumeRoleWithWebIdentity"
+                } else {
+                    "AssumeRole"
+                };
+                MockResponse::new(
+                    200,
+                    format!(
+                        r#"<{action}Response xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <{action}Result>
+    <Credentials>
+      <AccessKeyId>ASIAASSUMED</AccessKeyId>
+      <SecretAccessKey>assumed-secret</SecretAccessKey>
+      <SessionToken>assumed-session-token</SessionToken>
+      <Expiration>{expiration}</Expiration>
+    </Credentials>
+  </{action}Result>
+</{action}Response>"#
+                    ),
+                )
+                .header("Content-Type", "text/xml")
+            })
+        }
+
+        fn far_future() -> SystemTime {
+            SystemTime::now() + Duration::from_secs(3600)
+        }
+
+        /// S3の代替サーバーに1回リクエストを送り、クレデンシャルを取得させる
+        async fn resolve_credentials(client: &aws_sdk_s3::Client, s3: &MockServer) {
+            // レスポンスの解釈結果は問わない（署名に使われたクレデンシャルのみを確認する）
+            let _ = client.list_buckets().send().await;
+            let requests = s3.requests();
+            let request = requests.last().expect("no request reached the S3 stand-in");
+            let authorization = request.header("authorization").unwrap_or_default();
+            assert!(authorization.contains("Credential=ASIAASSUMED/"), "{authorization}");
+        }
+
+        fn assume_role_env(s3_address: Option<&str>, sts_endpoint: &str) -> Env {
+            Env::new_for_test(
+                Some(s3_address.unwrap_or("localhost:9000").to_string()),
+                Some("AKIASOURCE".to_string()),
+                Some("source-secret".to_string()),
+                None,
+                Some(ROLE_ARN.to_string()),
+                Some("cafce-test".to_string()),
+                None,
+                true,
+                None,
+                None,
+            )
+            .with_web_identity_for_test(None, None, Some(sts_endpoint))
+        }
+
+        fn options_env(duration_seconds: Option<i32>, session_tags: Option<&str>) -> Env {
+            Env::default().with_assume_role_options_for_test(
+                None,
+                duration_seconds,
+                None,
+                session_tags,
+            )
+        }
+
+        #[test]
+        fn test_options_default() {
+            let options = AssumeRoleOptions::from_env(&Env::default(), &CiEnv::default()).unwrap();
+            assert_eq!(options, AssumeRoleOptions::default());
+        }
+
+        #[test]
+        fn test_options_gitlab_session_tags() {
+            let ci = CiEnv::default().with_pipeline_for_test("group/app", "12345");
+            let options = AssumeRoleOptions::from_env(&Env::default(), &ci).unwrap();
+            assert_eq!(
+                options.session_tags,
+                vec![
+                    ("gitlab-project".to_string(), "group/app".to_string()),
+                    ("gitlab-pipeline-id".to_string(), "12345".to_string()),
+                ]
+            );
+        }
+
+        #[test]
+        fn test_options_gitlab_session_tags_disabled() {
+            let ci = CiEnv::default().with_pipeline_for_test("group/app", "12345");
+            let env = options_env(None, None).with_session_tags_auto_for_test(false);
+            let options = AssumeRoleOptions::from_env(&env, &ci).unwrap();
+            assert!(options.session_tags.is_empty(), "{:?}", options.session_tags);
+
+            // 明示的に指定したタグは付与する
+            let env = options_env(None, Some("team=platform")).with_session_tags_auto_for_test(false);
+            let options = AssumeRoleOptions::from_env(&env, &ci).unwrap();
+            assert_eq!(options.session_tags, vec![("team".to_string(), "platform".to_string())]);
+        }
+
+        #[test]
+        fn test_options_custom_session_tags_override() {
+            let ci = CiEnv::default().with_pipeline_for_test("group/app", "12345");
+            let env = options_env(None, Some("team=platform, GitLab-Project=override"));
+            let options = AssumeRoleOptions::from_env(&env, &ci).unwrap();
+            assert_eq!(
+                options.session_tags,
+                vec![
+                    ("gitlab-pipeline-id".to_string(), "12345".to_string()),
+                    ("team".to_string(), "platform".to_string()),
+                    ("GitLab-Project".to_string(), "override".to_string()),
+                ]
+            );
+        }
+
+        #[test]
+        fn test_options_invalid_session_tag() {
+            for tags in ["team", "=platform"] {
+                let result =
+                    AssumeRoleOptions::from_env(&options_env(None, Some(tags)), &CiEnv::default());
+                assert!(
+                    matches!(result, Err(BuildClientError::InvalidSessionTag(_))),
+                    "tags={tags}, result={result:?}"
+                );
+            }
+        }
+
+        #[test]
+        fn test_options_duration_seconds_range() {
+            for duration in [900, 43200] {
+                let options = AssumeRoleOptions::from_env(
+                    &options_env(Some(duration), None),
+                    &CiEnv::default(),
+                )
+                .unwrap();
+                assert_eq!(options.duration_seconds, Some(duration));
+            }
+            for duration in [899, 43201] {
+                let result = AssumeRoleOptions::from_env(
+                    &options_env(Some(duration), None),
+                    &CiEnv::default(),
+                );
+                assert!(matches!(
+                    result,
+                    Err(BuildClientError::InvalidRoleDurationSeconds(d)) if d == duration
+                ));
+            }
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_assume_role_parameters() {
+            let sts = start_sts_stand_in(far_future());
+            let s3 = start_s3_stand_in();
+            let env = assume_role_env(Some(s3.address()), &sts.url()).with_assume_role_options_for_test(
+                Some("external-id"),
+                Some(900),
+                Some(r#"{"Version":"2012-10-17"}"#),
+                Some("team=platform"),
+            );
+            let ci = CiEnv::default().with_pipeline_for_test("group/app", "12345");
+
+            let client = build_s3_client(&env, &ci).await.unwrap();
+            // クレデンシャルは構築時ではなく、最初に必要になった時点で取得する
+            assert!(sts.requests().is_empty());
+            resolve_credentials(&client, &s3).await;
+            resolve_credentials(&client, &s3).await;
+
+            // 取得結果が使い回されるため、STSの呼び出しは1回のみ
+            let requests = sts.requests();
+            assert_eq!(requests.len(), 1);
+            let body = requests[0].body_str();
+            assert!(body.contains("Action=AssumeRole&"), "{body}");
+            assert!(body.contains("ExternalId=external-id"), "{body}");
+            assert!(body.contains("DurationSeconds=900"), "{body}");
+            assert!(
+                body.contains("Policy=%7B%22Version%22%3A%222012-10-17%22%7D"),
+                "{body}"
+            );
+            assert!(body.contains("Tags.member.1.Key=gitlab-project"), "{body}");
+            assert!(body.contains("Tags.member.1.Value=group%2Fapp"), "{body}");
+            assert!(
+                body.contains("Tags.member.2.Key=gitlab-pipeline-id"),
+                "{body}"
+            );
+            assert!(body.contains("Tags.member.3.Key=team"), "{body}");
+            // ソースクレデンシャルで署名されている
+            let authorization = requests[0].header("authorization").unwrap_or_default();
+            assert!(
+                authorization.contains("Credential=AKIASOURCE/"),
+                "{authorization}"
+            );
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_web_identity_parameters() {
+            let sts = start_sts_stand_in(far_future());
+            let s3 = start_s3_stand_in();
+            let env = Env::new_for_test(
+                Some(s3.address().to_string()),
+                None,
+                None,
+                None,
+                Some(ROLE_ARN.to_string()),
+                None,
+                None,
+                true,
+                None,
+                None,
+            )
+            .with_web_identity_for_test(Some("oidc-jwt"), None, Some(&sts.url()))
+            .with_assume_role_options_for_test(
+                Some("external-id"),
+                Some(1800),
+                Some("{}"),
+                Some("team=platform"),
+            );
+            let ci = CiEnv::default().with_pipeline_for_test("group/app", "12345");
+
+            let client = build_s3_client(&env, &ci).await.unwrap();
+            resolve_credentials(&client, &s3).await;
+
+            let body = sts.requests()[0].body_str();
+            assert!(body.contains("DurationSeconds=1800"), "{body}");
+            assert!(body.contains("Policy=%7B%7D"), "{body}");
+            // AssumeRoleWithWebIdentityはExternalIdとセッションタグに対応していない
+            assert!(!body.contains("ExternalId"), "{body}");
+            assert!(!body.contains("Tags.member"), "{body}");
+        }
+
+        fn cache_setting() -> Setting {
+            toml::from_str(
+                r#"
+name = "node_modules"
+bucket = "cafce-cache"
+paths = ["node_modules"]
+key = "static"
+fallback_keys = []
+"#,
+            )
+            .unwrap()
+        }
+
+        /// STSに送信されたセッションポリシー（URLデコード済み）を取り出す
+        fn sent_policy(sts: &MockServer) -> Option<String> {
+            let body = sts.requests()[0].body_str();
+            body.split('&')
+                .find_map(|pair| pair.strip_prefix("Policy="))
+                .map(|policy| {
+                    url::form_urlencoded::parse(format!("p={policy}").as_bytes())
+                        .next()
+                        .unwrap()
+                        .1
+                        .into_owned()
+                })
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_for_cache_auto_session_policy() {
+            let sts = start_sts_stand_in(far_future());
+            let s3 = start_s3_stand_in();
+            let env = assume_role_env(Some(s3.address()), &sts.url());
+            let ci = CiEnv::default().with_pipeline_for_test("group/app", "12345");
+            let setting = cache_setting();
+
+            let client = build_s3_client_for_cache(&env, &ci, &setting).await.unwrap();
+            resolve_credentials(&client, &s3).await;
+
+            let expected = CacheSessionPolicy::new(&setting, Some("group/app"), &env)
+                .unwrap()
+                .to_json();
+            assert_eq!(sent_policy(&sts), Some(expected));
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_for_cache_explicit_session_policy() {
+            let sts = start_sts_stand_in(far_future());
+            let s3 = start_s3_stand_in();
+            let env = assume_role_env(Some(s3.address()), &sts.url()).with_assume_role_options_for_test(
+                None,
+                None,
+                Some("{}"),
+                None,
+            );
+            let ci = CiEnv::default().with_pipeline_for_test("group/app", "12345");
+
+            let client = build_s3_client_for_cache(&env, &ci, &cache_setting())
+                .await
+                .unwrap();
+            resolve_credentials(&client, &s3).await;
+
+            assert_eq!(sent_policy(&sts).as_deref(), Some("{}"));
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_for_cache_auto_session_policy_disabled() {
+            let sts = start_sts_stand_in(far_future());
+            let s3 = start_s3_stand_in();
+            let env = assume_role_env(Some(s3.address()), &sts.url()).with_auto_session_policy_for_test(false);
+            let ci = CiEnv::default().with_pipeline_for_test("group/app", "12345");
+
+            let client = build_s3_client_for_cache(&env, &ci, &cache_setting())
+                .await
+                .unwrap();
+            resolve_credentials(&client, &s3).await;
+
+            assert_eq!(sent_policy(&sts), None);
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_for_cache_session_policy_error() {
+            // CI_PROJECT_PATHがないとプレフィックスを決められないため、STSを呼ばずにエラーとする
+            let sts = start_sts_stand_in(far_future());
+            let env = assume_role_env(None, &sts.url());
+
+            let result = build_s3_client_for_cache(&env, &CiEnv::default(), &cache_setting()).await;
+
+            assert!(matches!(result, Err(BuildClientError::SessionPolicy(_))));
+            assert!(sts.requests().is_empty());
+        }
+
+        #[tokio::test]
+        async fn test_provider_reuses_credentials_until_refresh_buffer() {
+            let sts = start_sts_stand_in(far_future());
+            let env = assume_role_env(None, &sts.url());
+            let provider = AssumeRoleProvider::assume_role(
+                &env,
+                "AKIASOURCE",
+                "source-secret",
+                ROLE_ARN,
+                None,
+                AssumeRoleOptions::default(),
+            )
+            .await
+            .unwrap();
+
+            let first = provider.provide_credentials().await.unwrap();
+            let second = provider.provide_credentials().await.unwrap();
+            assert_eq!(first.access_key_id(), "ASIAASSUMED");
+            assert_eq!(second.access_key_id(), "ASIAASSUMED");
+            assert_eq!(sts.requests().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn test_provider_refreshes_expiring_credentials() {
+            // 有効期限がCREDENTIALS_REFRESH_BUFFER以内のクレデンシャルは毎回取り直す
+            let sts = start_sts_stand_in(SystemTime::now() + Duration::from_secs(60));
+            let env = assume_role_env(None, &sts.url());
+            let provider = AssumeRoleProvider::assume_role(
+                &env,
+                "AKIASOURCE",
+                "source-secret",
+                ROLE_ARN,
+                None,
+                AssumeRoleOptions::default(),
+            )
+            .await
+            .unwrap();
+
+            provider.provide_credentials().await.unwrap();
+            provider.provide_credentials().await.unwrap();
+            assert_eq!(sts.requests().len(), 2);
+        }
+
+        #[test]
+        fn test_needs_refresh() {
+            let now = SystemTime::now();
+            let credentials =
+                |expiry: Option<SystemTime>| Credentials::new("ak", "sk", None, expiry, "test");
+            assert!(!needs_refresh(&credentials(None), now));
+            assert!(!needs_refresh(
+                &credentials(Some(now + Duration::from_secs(3600))),
+                now
+            ));
+            assert!(needs_refresh(
+                &credentials(Some(now + Duration::from_secs(60))),
+                now
+            ));
+            assert!(needs_refresh(
+                &credentials(Some(now - Duration::from_secs(1))),
+                now
+            ));
+        }
+    }
+
+    mod web_identity_tests {
+        use super::*;
+        use crate::test_util::{MockResponse, MockServer};
+
+        const ROLE_ARN: &str = "arn:aws:iam::123456789012:role/cafce-ci";
+
+        /// AssumeRoleWithWebIdentityの成功レスポンスを返すSTSの代替サーバーを起動する
+        fn start_sts_stand_in() -> MockServer {
+            MockServer::start(|_| {
+                MockResponse::new(
+                    200,
+                    r#"<AssumeRoleWithWebIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleWithWebIdentityResult>
+    <Credentials>
+      <AccessKeyId>ASIAWEBIDENTITY</AccessKeyId>
+      <SecretAccessKey>web-identity-secret</SecretAccessKey>
+      <SessionToken>web-identity-session-token</SessionToken>
+      <Expiration>2099-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleWithWebIdentityResult>
+</AssumeRoleWithWebIdentityResponse>"#,
+                )
+                .header("Content-Type", "text/xml")
+            })
+        }
+
+        fn web_identity_env(
+            s3_address: Option<&str>,
+            role_arn: Option<&str>,
+            token: Option<&str>,
+            token_file: Option<&str>,
+            sts_endpoint: Option<&str>,
+        ) -> Env {
+            Env::new_for_test(
+                Some(s3_address.unwrap_or("localhost:9000").to_string()),
+                None,
+                None,
+                None,
+                role_arn.map(String::from),
+                Some("cafce-test".to_string()),
+                None,
+                true,
+                None,
+                None,
+            )
+            .with_web_identity_for_test(token, token_file, sts_endpoint)
+        }
+
+        /// S3の代替サーバーに1回リクエストを送り、その署名に使われたクレデンシャルを確認する
+        async fn assert_signed_with_web_identity(client: &aws_sdk_s3::Client, s3: &MockServer) {
+            // レスポンスの解釈結果は問わない（リクエストの署名のみを確認する）
+            let _ = client.list_buckets().send().await;
+
+            let requests = s3.requests();
+            let request = requests.last().expect("no request reached the S3 stand-in");
+            let authorization = request.header("authorization").unwrap_or_default();
+            assert!(
+                authorization.contains("Credential=ASIAWEBIDENTITY/"),
+                "{authorization}"
+            );
+            assert_eq!(
+                request.header("x-amz-security-token"),
+                Some("web-identity-session-token")
+            );
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_web_identity_token() {
+            let sts = start_sts_stand_in();
+            let s3 = start_s3_stand_in();
+            let env = web_identity_env(
+                Some(s3.address()),
+                Some(ROLE_ARN),
+                Some("oidc-jwt"),
+                None,
+                Some(&sts.url()),
+            );
+
+            let client = build_s3_client(&env, &CiEnv::default()).await.unwrap();
+            assert_signed_with_web_identity(&client, &s3).await;
+
+            let requests = sts.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].method, "POST");
+            assert_eq!(requests[0].path, "/");
+            let body = requests[0].body_str();
+            assert!(body.contains("Action=AssumeRoleWithWebIdentity"), "{body}");
+            assert!(body.contains("WebIdentityToken=oidc-jwt"), "{body}");
+            assert!(body.contains("RoleSessionName=cafce-test"), "{body}");
+            // 署名不要のAPIのため、Authorizationヘッダーは送信されない
+            assert!(requests[0].header("authorization").is_none());
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_web_identity_token_file() {
+            let sts = start_sts_stand_in();
+            let s3 = start_s3_stand_in();
+            let temp_dir = tempfile::tempdir().unwrap();
+            let token_file = temp_dir.path().join("token");
+            std::fs::write(&token_file, "oidc-jwt-from-file\n").unwrap();
+            let env = web_identity_env(
+                Some(s3.address()),
+                Some(ROLE_ARN),
+                None,
+                Some(token_file.to_str().unwrap()),
+                Some(&sts.url()),
+            );
+
+            let client = build_s3_client(&env, &CiEnv::default()).await.unwrap();
+            assert_signed_with_web_identity(&client, &s3).await;
+
+            let body = sts.requests()[0].body_str();
+            // 末尾の改行は取り除かれる
+            assert!(body.ends_with("WebIdentityToken=oidc-jwt-from-file"), "{body}");
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_web_identity_without_role_arn() {
+            let env = web_identity_env(None, None, Some("oidc-jwt"), None, None);
+            let result = build_s3_client(&env, &CiEnv::default()).await;
+            assert!(matches!(result, Err(BuildClientError::MissingWebIdentityRoleArn)));
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_web_identity_conflict() {
+            let env = web_identity_env(
+                None,
+                Some(ROLE_ARN),
+                Some("oidc-jwt"),
+                Some("/tmp/token"),
+                None,
+            );
+            let result = build_s3_client(&env, &CiEnv::default()).await;
+            assert!(matches!(result, Err(BuildClientError::WebIdentityTokenConflict)));
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_web_identity_token_file_missing() {
+            let temp_dir = tempfile::tempdir().unwrap();
+            let token_file = temp_dir.path().join("missing");
+            let env = web_identity_env(
+                None,
+                Some(ROLE_ARN),
+                None,
+                Some(token_file.to_str().unwrap()),
+                None,
+            );
+            let result = build_s3_client(&env, &CiEnv::default()).await;
+            assert!(matches!(result, Err(BuildClientError::WebIdentityTokenFile { .. })));
+        }
+
+        #[tokio::test]
+        async fn test_build_s3_client_web_identity_sts_error() {
+            let sts = MockServer::start(|_| {
+                MockResponse::new(
+                    403,
+                    r#"<ErrorResponse><Error><Type>Sender</Type><Code>InvalidIdentityToken</Code><Message>bad token</Message></Error></ErrorResponse>"#,
+                )
+                .header("Content-Type", "text/xml")
+            });
+            let env = web_identity_env(
+                None,
+                Some(ROLE_ARN),
+                Some("oidc-jwt"),
+                None,
+                Some(&sts.url()),
+            );
+            // STSのエラーは構築時ではなく、最初のS3リクエストで返る
+            let client = build_s3_client(&env, &CiEnv::default()).await.unwrap();
+            let error = client.list_buckets().send().await.unwrap_err();
+            assert!(is_credentials_error(&error), "{error:?}");
+            assert_eq!(sts.requests().len(), 1);
+        }
+    }
+
+    mod sse_config_tests {
+        use super::*;
+
+        /// `base64(0x00..0x1f)`（32バイト）
+        const CUSTOMER_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+        fn offline_client() -> aws_sdk_s3::Client {
+            let config = Builder::new()
+                .behavior_version_latest()
+                .region(Region::new("us-east-1"))
+                .build();
+            aws_sdk_s3::Client::from_conf(config)
+        }
+
+        #[test]
+        fn test_parse_none() {
+            let config = SseConfig::parse(None, None, None, false).unwrap();
+            assert_eq!(config, SseConfig::None);
+        }
+
+        #[test]
+        fn test_parse_sse_s3() {
+            let config = SseConfig::parse(Some("AES256"), None, None, false).unwrap();
+            assert_eq!(config, SseConfig::S3);
+        }
+
+        #[test]
+        fn test_parse_sse_kms_with_key_id() {
+            let config = SseConfig::parse(Some("aws:kms"), Some("alias/cafce"), None, false).unwrap();
+            assert_eq!(
+                config,
+                SseConfig::Kms {
+                    key_id: Some("alias/cafce".to_string())
+                }
+            );
+        }
+
+        #[test]
+        fn test_parse_sse_kms_without_key_id() {
+            let config = SseConfig::parse(Some("aws:kms"), None, None, false).unwrap();
+            assert_eq!(config, SseConfig::Kms { key_id: None });
+        }
+
+        #[test]
+        fn test_parse_invalid_sse() {
+            let result = SseConfig::parse(Some("kms"), None, None, false);
+            assert!(matches!(result, Err(BuildClientError::InvalidSse(v)) if v == "kms"));
+        }
+
+        #[test]
+        fn test_parse_kms_key_id_without_kms() {
+            let result = SseConfig::parse(None, Some("alias/cafce"), None, false);
+            assert!(matches!(result, Err(BuildClientError::SseKmsKeyIdWithoutKms)));
+            let result = SseConfig::parse(Some("AES256"), Some("alias/cafce"), None, false);
+            assert!(matches!(result, Err(BuildClientError::SseKmsKeyIdWithoutKms)));
+        }
+
+        #[test]
+        fn test_parse_customer_key() {
+            let config = SseConfig::parse(None, None, Some(CUSTOMER_KEY), false).unwrap();
+            let SseConfig::Customer(key) = config else {
+                panic!("expected SSE-C, got {config:?}");
+            };
+            assert_eq!(key.key, CUSTOMER_KEY);
+            // `printf '\x00\x01...\x1f' | openssl md5 -binary | base64`
+            assert_eq!(key.key_md5, "tP/LI3N87DFaSk0aoqYgzg==");
+        }
+
+        #[test]
+        fn test_parse_customer_key_conflicts_with_sse() {
+            let result = SseConfig::parse(Some("AES256"), None, Some(CUSTOMER_KEY), false);
+            assert!(matches!(result, Err(BuildClientError::SseCustomerKeyConflict)));
+            let result = SseConfig::parse(None, Some("alias/cafce"), Some(CUSTOMER_KEY), false);
+            assert!(matches!(result, Err(BuildClientError::SseCustomerKeyConflict)));
+        }
+
+        #[test]
+        fn test_parse_customer_key_invalid() {
+            let result = SseConfig::parse(None, None, Some("not-base64!"), false);
+            assert!(matches!(result, Err(BuildClientError::InvalidSseCustomerKey)));
+            // Base64としては正しいが256bitではない
+            let result = SseConfig::parse(None, None, Some("AAECAw=="), false);
+            assert!(matches!(result, Err(BuildClientError::InvalidSseCustomerKey)));
+        }
+
+        #[test]
+        fn test_parse_customer_key_requires_https() {
+            let result = SseConfig::parse(None, None, Some(CUSTOMER_KEY), true);
+            assert!(matches!(result, Err(BuildClientError::SseCustomerKeyRequiresHttps)));
+        }
+
+        #[test]
+        fn test_from_env_customer_key_over_http() {
+            let env = create_test_env(Some("localhost:9000"), true, None)
+                .with_sse_for_test(None, None, Some(CUSTOMER_KEY));
+            let result = SseConfig::from_env(&env);
+            assert!(matches!(result, Err(BuildClientError::SseCustomerKeyRequiresHttps)));
+        }
+
+        #[test]
+        fn test_debug_does_not_leak_customer_key() {
+            let config = SseConfig::parse(None, None, Some(CUSTOMER_KEY), false).unwrap();
+            assert!(!format!("{config:?}").contains(CUSTOMER_KEY));
+        }
+
+        #[test]
+        fn test_apply_to_put_object_kms() {
+            let config = SseConfig::Kms {
+                key_id: Some("alias/cafce".to_string()),
+            };
+            let builder = config.apply_to_put_object(offline_client().put_object());
+            assert_eq!(
+                builder.get_server_side_encryption(),
+                &Some(ServerSideEncryption::AwsKms)
+            );
+            assert_eq!(builder.get_ssekms_key_id().as_deref(), Some("alias/cafce"));
+        }
+
+        #[test]
+        fn test_apply_to_put_object_s3() {
+            let builder = SseConfig::S3.apply_to_put_object(offline_client().put_object());
+            assert_eq!(
+                builder.get_server_side_encryption(),
+                &Some(ServerSideEncryption::Aes256)
+            );
+            assert!(builder.get_ssekms_key_id().is_none());
+        }
+
+        #[test]
+        fn test_apply_customer_key_to_put_get_head() {
+            let config = SseConfig::parse(None, None, Some(CUSTOMER_KEY), false).unwrap();
+            let client = offline_client();
+
+            let put = config.apply_to_put_object(client.put_object());
+            assert_eq!(put.get_sse_customer_key().as_deref(), Some(CUSTOMER_KEY));
+            assert!(put.get_server_side_encryption().is_none());
+
+            let get = config.apply_to_get_object(client.get_object());
+            assert_eq!(get.get_sse_customer_key().as_deref(), Some(CUSTOMER_KEY));
+            assert_eq!(get.get_sse_customer_algorithm().as_deref(), Some("AES256"));
+
+            let head = config.apply_to_head_object(client.head_object());
+            assert_eq!(head.get_sse_customer_key().as_deref(), Some(CUSTOMER_KEY));
+
+            let copy = config.apply_to_copy_object(client.copy_object());
+            assert_eq!(copy.get_sse_customer_key().as_deref(), Some(CUSTOMER_KEY));
+            assert_eq!(copy.get_copy_source_sse_customer_key().as_deref(), Some(CUSTOMER_KEY));
+        }
+
+        #[test]
+        fn test_apply_kms_to_get_object_is_noop() {
+            let config = SseConfig::Kms { key_id: None };
+            let get = config.apply_to_get_object(offline_client().get_object());
+            assert!(get.get_sse_customer_key().is_none());
+        }
+    }
+
+    mod retry_tests {
+        use super::*;
+        use crate::test_util::{MockResponse, MockServer};
+
+        fn retry_env(
+            s3_address: &str,
+            max_attempts: Option<u32>,
+            attempt_timeout: Option<u64>,
+            restore_timeout_as_miss: bool,
+        ) -> Env {
+            Env::new_for_test(
+                Some(s3_address.to_string()),
+                Some("access".to_string()),
+                Some("secret".to_string()),
+                None,
+                None,
+                None,
+                None,
+                true,
+                None,
+                None,
+            )
+            .with_retry_for_test(max_attempts, None, attempt_timeout, None, restore_timeout_as_miss)
+        }
+
+        #[test]
+        fn test_retry_config_default() {
+            assert!(retry_config(&Env::default()).unwrap().is_none());
+        }
+
+        #[test]
+        fn test_retry_config() {
+            let env = Env::default().with_retry_for_test(Some(5), None, None, None, false);
+            let config = retry_config(&env).unwrap().unwrap();
+            assert_eq!(config.max_attempts(), 5);
+            assert_eq!(config.mode(), aws_config::retry::RetryMode::Standard);
+
+            let env = Env::default().with_retry_for_test(None, Some(RetryMode::Adaptive), None, None, false);
+            let config = retry_config(&env).unwrap().unwrap();
+            assert_eq!(config.mode(), aws_config::retry::RetryMode::Adaptive);
+        }
+
+        #[test]
+        fn test_retry_config_zero_attempts() {
+            let env = Env::default().with_retry_for_test(Some(0), None, None, None, false);
+            assert!(matches!(retry_config(&env), Err(BuildClientError::InvalidMaxAttempts)));
+        }
+
+        #[tokio::test]
+        async fn test_max_attempts() {
+            let s3 = MockServer::start(|_| MockResponse::new(500, "<Error><Code>InternalError</Code></Error>"));
+            let env = retry_env(s3.address(), Some(2), None, false);
+            let client = build_s3_client(&env, &CiEnv::default()).await.unwrap();
+
+            let result = client.list_buckets().send().await;
+            assert!(result.is_err());
+            assert_eq!(s3.requests().len(), 2);
+        }
+
+        #[tokio::test]
+        async fn test_attempt_timeout() {
+            let s3 = MockServer::start(|_| {
+                std::thread::sleep(Duration::from_secs(3));
+                MockResponse::new(200, "")
+            });
+            let env = retry_env(s3.address(), Some(1), Some(1), true);
+            let client = build_s3_client(&env, &CiEnv::default()).await.unwrap();
+
+            let error = client.list_buckets().send().await.unwrap_err();
+            assert!(is_timeout_error(&error), "{error:?}");
+        }
+
+        #[tokio::test]
+        async fn test_service_error_is_not_timeout() {
+            let s3 = MockServer::start(|_| MockResponse::new(403, "<Error><Code>AccessDenied</Code></Error>"));
+            let env = retry_env(s3.address(), Some(1), Some(1), true);
+            let client = build_s3_client(&env, &CiEnv::default()).await.unwrap();
+
+            let error = client.list_buckets().send().await.unwrap_err();
+            assert!(!is_timeout_error(&error));
+        }
+    }
 }
 
 /// RustFS（ローカルS3互換サーバー）に対する疎通確認テスト。
@@ -305,7 +2099,7 @@ mod rustfs_integration_tests {
     #[ignore]
     async fn rustfs_integration_smoke_test() {
         let env = rustfs_test_env();
-        let client = build_s3_client(&env)
+        let client = build_s3_client(&env, &CiEnv::default())
             .await
             .expect("failed to build S3 client for RustFS");
 
@@ -504,7 +2298,7 @@ mod aws_integration_tests {
             None, // force_path_style: 自動判定でvirtual-hosted styleになるはず
         );
 
-        let client = build_s3_client(&env)
+        let client = build_s3_client(&env, &CiEnv::default())
             .await
             .expect("failed to build S3 client for AWS S3 (static credentials)");
 
@@ -603,7 +2397,7 @@ mod aws_integration_tests {
             None, // force_path_style
         );
 
-        let client = build_s3_client(&env)
+        let client = build_s3_client(&env, &CiEnv::default())
             .await
             .expect("failed to build S3 client for AWS S3 (AssumeRole)");
 
@@ -720,7 +2514,7 @@ mod aws_integration_tests {
             None, // force_path_style: 自動判定でvirtual-hosted styleになるはず
         );
 
-        let client = build_s3_client(&env)
+        let client = build_s3_client(&env, &CiEnv::default())
             .await
             .expect("failed to build S3 client for AWS S3 (Profile)");
 
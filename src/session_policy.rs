@@ -0,0 +1,306 @@
+use crate::env::Env;
+use crate::i18n::tr;
+use crate::object_key::ObjectKeyError;
+use crate::setting::Setting;
+use serde_json::{json, Value};
+
+/// セッションポリシー生成時のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum SessionPolicyError {
+    #[error("{}", tr!("セッションポリシーを生成するには、設定ファイルでbucketを指定してください", "set bucket in the config file to generate a session policy"))]
+    MissingBucket,
+    #[error(transparent)]
+    ObjectKey(#[from] ObjectKeyError),
+    #[error("{}", tr!("オブジェクトキーのテンプレートが{{key}}から始まるため、セッションポリシーをキャッシュのプレフィックスに限定できません", "the object key template starts with {{key}}, so the session policy cannot be scoped to a cache prefix"))]
+    EmptyPrefix,
+    #[error("{}", tr!("キャッシュのプレフィックスがプロジェクト（{{project}}/）から始まらないため、セッションポリシーを自動生成できません: {prefix}", "the cache prefix does not start with the project ({{project}}/), so the session policy cannot be generated automatically: {prefix}"))]
+    PrefixOutsideProject { prefix: String },
+}
+
+/// キャッシュのプレフィックスのみを操作できるよう絞り込んだセッションポリシー
+///
+/// AssumeRoleのインラインセッションポリシーとして渡すと、ロール本来の権限との
+/// 積集合になるため、ジョブが侵害されても他のプロジェクトのキャッシュには触れられない。
+/// 許可するのは以下のみ:
+/// - プレフィックス配下のオブジェクトへの`s3:GetObject` / `s3:PutObject`
+/// - プレフィックスに限定した`s3:ListBucket`
+/// - プロジェクトのキャッシュの世代を保存するオブジェクトの`s3:GetObject`（読み取りのみ。
+///   `s3:ListBucket`がないため未作成の場合はAccessDeniedになり、`storage::read_generation`は世代0とみなす）
+/// - SSE-KMS使用時は、暗号化・復号に必要な`kms:GenerateDataKey` / `kms:Decrypt`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheSessionPolicy {
+    /// ARNのパーティション（"aws", "aws-cn", "aws-us-gov"）
+    partition: &'static str,
+    bucket: String,
+    /// オブジェクトキーのテンプレートの`{key}`より前の部分
+    prefix: String,
+    /// 世代を保存するオブジェクトのキー
+    generation_object_key: String,
+    /// SSE-KMSのKMSキー（ARN以外の指定やAWS管理キーの場合は"*"）
+    kms_key: Option<String>,
+}
+
+impl CacheSessionPolicy {
+    /// 設定ファイルのバケット・オブジェクトキーのテンプレートからセッションポリシーを組み立てる
+    ///
+    /// # Arguments
+    /// * `setting` - キャッシュの設定（バケット、テンプレート、キャッシュ名、圧縮方式）
+    /// * `project` - GitLabのプロジェクトパス（テンプレートの`{project}`）
+    /// * `env` - 環境変数設定（リージョン、SSE-KMSの設定）
+    pub fn new(
+        setting: &Setting,
+        project: Option<&str>,
+        env: &Env,
+    ) -> Result<Self, SessionPolicyError> {
+        let bucket = setting.bucket().ok_or(SessionPolicyError::MissingBucket)?;
+        let template = setting.object_key_template()?;
+        let context = setting.object_key_context(project);
+        let prefix = template.prefix(&context)?;
+        // プレフィックスが空の場合、バケット全体を許可することになるためエラーとする
+        if prefix.is_empty() {
+            return Err(SessionPolicyError::EmptyPrefix);
+        }
+        // `{project}/`で始まらない場合、他のプロジェクトのキャッシュや世代を操作できてしまう
+        // （例: `{cache_name}/{key}`は全プロジェクト共通、`{project}-{key}`は`group/app-other`も含む）
+        let is_project_scoped = project
+            .filter(|project| !project.is_empty())
+            .is_some_and(|project| prefix.starts_with(&format!("{project}/")));
+        if !is_project_scoped {
+            return Err(SessionPolicyError::PrefixOutsideProject { prefix });
+        }
+
+        let kms_key = (env.sse() == Some("aws:kms")).then(|| {
+            env.sse_kms_key_id()
+                .filter(|key_id| key_id.starts_with("arn:"))
+                .unwrap_or("*")
+                .to_string()
+        });
+
+        Ok(Self {
+            partition: partition(&env.get_region()),
+            bucket: bucket.to_string(),
+            prefix,
+            generation_object_key: template.generation_object_key(&context)?,
+            kms_key,
+        })
+    }
+
+    /// ポリシーを許可するキャッシュのプレフィックス
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn to_value(&self) -> Value {
+        let bucket_arn = format!("arn:{}:s3:::{}", self.partition, self.bucket);
+        let prefix = escape_wildcards(&self.prefix);
+
+        let mut statements = vec![
+            json!({
+                "Sid": "CafceCacheObjects",
+                "Effect": "Allow",
+                "Action": ["s3:GetObject", "s3:PutObject"],
+                "Resource": format!("{bucket_arn}/{prefix}*"),
+            }),
+            json!({
+                "Sid": "CafceCacheList",
+                "Effect": "Allow",
+                "Action": "s3:ListBucket",
+                "Resource": bucket_arn,
+                "Condition": {
+                    "StringLike": { "s3:prefix": format!("{prefix}*") },
+                },
+            }),
+            json!({
+                "Sid": "CafceGeneration",
+                "Effect": "Allow",
+                "Action": "s3:GetObject",
+                "Resource": format!(
+                    "{bucket_arn}/{}",
+                    escape_wildcards(&self.generation_object_key)
+                ),
+            }),
+        ];
+        if let Some(kms_key) = &self.kms_key {
+            statements.push(json!({
+                "Sid": "CafceCacheKms",
+                "Effect": "Allow",
+                "Action": ["kms:GenerateDataKey", "kms:Decrypt"],
+                "Resource": kms_key,
+            }));
+        }
+
+        json!({
+            "Version": "2012-10-17",
+            "Statement": statements,
+        })
+    }
+
+    /// STSに渡すJSON（空白なし。セッションポリシーは2048文字までのため）
+    pub fn to_json(&self) -> String {
+        self.to_value().to_string()
+    }
+
+    /// レビュー用の整形済みJSON
+    pub fn to_json_pretty(&self) -> String {
+        serde_json::to_string_pretty(&self.to_value()).expect("policy must be serializable")
+    }
+}
+
+/// リージョンからARNのパーティションを決定する
+fn partition(region: &str) -> &'static str {
+    if region.starts_with("cn-") {
+        "aws-cn"
+    } else if region.starts_with("us-gov-") {
+        "aws-us-gov"
+    } else {
+        "aws"
+    }
+}
+
+/// IAMポリシーでワイルドカードとして解釈される文字を、文字そのものとして扱うよう置き換える
+fn escape_wildcards(value: &str) -> String {
+    value.replace('*', "${*}").replace('?', "${?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setting(toml: &str) -> Setting {
+        toml::from_str(toml).unwrap()
+    }
+
+    const SETTING: &str = r#"
+name = "node_modules"
+bucket = "cafce-cache"
+paths = ["node_modules"]
+key = "static"
+fallback_keys = []
+"#;
+
+    fn statement<'a>(policy: &'a Value, sid: &str) -> &'a Value {
+        policy["Statement"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|statement| statement["Sid"] == sid)
+            .unwrap_or_else(|| panic!("statement {sid} not found in {policy}"))
+    }
+
+    #[test]
+    fn test_default_template() {
+        let policy = CacheSessionPolicy::new(&setting(SETTING), Some("group/app"), &Env::default())
+            .unwrap();
+        assert_eq!(policy.prefix(), "group/app/node_modules/v1/");
+
+        let value: Value = serde_json::from_str(&policy.to_json()).unwrap();
+        assert_eq!(value["Version"], "2012-10-17");
+        assert_eq!(value["Statement"].as_array().unwrap().len(), 3);
+
+        let objects = statement(&value, "CafceCacheObjects");
+        assert_eq!(objects["Action"], json!(["s3:GetObject", "s3:PutObject"]));
+        assert_eq!(
+            objects["Resource"],
+            "arn:aws:s3:::cafce-cache/group/app/node_modules/v1/*"
+        );
+
+        let list = statement(&value, "CafceCacheList");
+        assert_eq!(list["Action"], "s3:ListBucket");
+        assert_eq!(list["Resource"], "arn:aws:s3:::cafce-cache");
+        assert_eq!(
+            list["Condition"]["StringLike"]["s3:prefix"],
+            "group/app/node_modules/v1/*"
+        );
+
+        let generation = statement(&value, "CafceGeneration");
+        assert_eq!(generation["Action"], "s3:GetObject");
+        assert_eq!(
+            generation["Resource"],
+            "arn:aws:s3:::cafce-cache/group/app/.cafce-generation"
+        );
+    }
+
+    #[test]
+    fn test_compact_and_pretty_json_are_equivalent() {
+        let policy = CacheSessionPolicy::new(&setting(SETTING), Some("group/app"), &Env::default())
+            .unwrap();
+        assert!(!policy.to_json().contains('\n'));
+        let compact: Value = serde_json::from_str(&policy.to_json()).unwrap();
+        let pretty: Value = serde_json::from_str(&policy.to_json_pretty()).unwrap();
+        assert_eq!(compact, pretty);
+    }
+
+    #[test]
+    fn test_missing_bucket() {
+        let setting = setting(&SETTING.replace("bucket = \"cafce-cache\"", ""));
+        let result = CacheSessionPolicy::new(&setting, Some("group/app"), &Env::default());
+        assert!(matches!(result, Err(SessionPolicyError::MissingBucket)));
+    }
+
+    #[test]
+    fn test_missing_project() {
+        let result = CacheSessionPolicy::new(&setting(SETTING), None, &Env::default());
+        assert!(matches!(
+            result,
+            Err(SessionPolicyError::ObjectKey(ObjectKeyError::MissingProject))
+        ));
+    }
+
+    #[test]
+    fn test_empty_prefix() {
+        let setting = setting(&format!("object_key = \"{{key}}.{{ext}}\"\n{SETTING}"));
+        let result = CacheSessionPolicy::new(&setting, Some("group/app"), &Env::default());
+        assert!(matches!(result, Err(SessionPolicyError::EmptyPrefix)));
+    }
+
+    #[test]
+    fn test_prefix_outside_project() {
+        for template in ["{cache_name}/{key}.{ext}", "{project}-{key}.{ext}", "caches/{project}/{key}.{ext}"] {
+            let setting = setting(&format!("object_key = \"{template}\"\n{SETTING}"));
+            let result = CacheSessionPolicy::new(&setting, Some("group/app"), &Env::default());
+            assert!(
+                matches!(&result, Err(SessionPolicyError::PrefixOutsideProject { .. })),
+                "{template}: {result:?}"
+            );
+        }
+
+        let setting = setting(&format!("object_key = \"{{project}}/{{key}}.{{ext}}\"\n{SETTING}"));
+        let policy = CacheSessionPolicy::new(&setting, Some("group/app"), &Env::default()).unwrap();
+        assert_eq!(policy.prefix(), "group/app/");
+    }
+
+    #[test]
+    fn test_wildcards_in_prefix_are_escaped() {
+        let setting = setting(&SETTING.replace("node_modules\"\nbucket", "node*modules?\"\nbucket"));
+        let policy = CacheSessionPolicy::new(&setting, Some("group/app"), &Env::default()).unwrap();
+        let value: Value = serde_json::from_str(&policy.to_json()).unwrap();
+        assert_eq!(
+            statement(&value, "CafceCacheObjects")["Resource"],
+            "arn:aws:s3:::cafce-cache/group/app/node${*}modules${?}/v1/*"
+        );
+    }
+
+    #[test]
+    fn test_partition_from_region() {
+        assert_eq!(partition("ap-northeast-1"), "aws");
+        assert_eq!(partition("cn-north-1"), "aws-cn");
+        assert_eq!(partition("us-gov-west-1"), "aws-us-gov");
+    }
+
+    #[test]
+    fn test_kms_statement() {
+        let kms_key_arn = "arn:aws:kms:us-east-1:123456789012:key/abcd";
+        let env = Env::default().with_sse_for_test(Some("aws:kms"), Some(kms_key_arn), None);
+        let policy = CacheSessionPolicy::new(&setting(SETTING), Some("group/app"), &env).unwrap();
+        let value: Value = serde_json::from_str(&policy.to_json()).unwrap();
+        let kms = statement(&value, "CafceCacheKms");
+        assert_eq!(kms["Action"], json!(["kms:GenerateDataKey", "kms:Decrypt"]));
+        assert_eq!(kms["Resource"], kms_key_arn);
+
+        // エイリアス等のARN以外の指定は対象を特定できないため"*"とする
+        let env = Env::default().with_sse_for_test(Some("aws:kms"), Some("alias/cafce"), None);
+        let policy = CacheSessionPolicy::new(&setting(SETTING), Some("group/app"), &env).unwrap();
+        let value: Value = serde_json::from_str(&policy.to_json()).unwrap();
+        assert_eq!(statement(&value, "CafceCacheKms")["Resource"], "*");
+    }
+}
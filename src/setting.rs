@@ -1,9 +1,37 @@
 extern crate serde;
 use serde::{Deserialize, Serialize};
 use serde_either::StringOrStruct;
+use crate::object_key::{Codec, ObjectKeyContext, ObjectKeyError, ObjectKeyTemplate};
+use crate::i18n::tr;
+use crate::retention::Retention;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// 設定ファイルの読み書き時のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum SettingError {
+    #[error("{}", tr!("設定ファイルの読み込みに失敗しました: {}", "failed to read the config file: {}", .path.display()))]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}", tr!("設定ファイルの書き込みに失敗しました: {}", "failed to write the config file: {}", .path.display()))]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}", tr!("設定ファイルの形式が不正です: {}: {}", "invalid config file: {}: {}", .path.display(), .source))]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error(transparent)]
+    ObjectKey(#[from] ObjectKeyError),
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Key {
@@ -11,33 +39,169 @@ pub struct Key {
     pub prefix: Option<String>,
 }
 
+/// キャッシュ操作（S3・ネットワーク・アーカイブ）が失敗した場合の動作
+///
+/// 設定ファイル・環境変数の誤りは、この設定に関わらず常にエラーとする。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnError {
+    /// 警告を出力し、終了コード0で終了する（ジョブを失敗させない）
+    Warn,
+    /// エラーとして終了する
+    #[default]
+    Fail,
+}
+
+/// キャッシュ名の既定値（`name`省略時）
+pub const DEFAULT_CACHE_NAME: &str = "default";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Setting {
+    /// キャッシュ名（オブジェクトキーのテンプレートの`{cache_name}`）
+    /// 省略時: "default"
+    name: Option<String>,
+    /// キャッシュを保存するバケット名
+    bucket: Option<String>,
+    /// S3以外の保存先のURL（"file:///mnt/cache"等）
+    /// 省略時: CAFCE_STORAGEの値、それも未指定の場合は`bucket`のS3
+    storage: Option<String>,
+    /// オブジェクトキーのテンプレート
+    /// 省略時: "{project}/{cache_name}/v{version}/{key}.{ext}"
+    object_key: Option<String>,
+    /// アーカイブの圧縮方式（"zstd" / "gzip" / "none"）
+    #[serde(default)]
+    codec: Codec,
     paths: Vec<String>,
     key: StringOrStruct<Key>,
     fallback_keys: Vec<String>,
+    /// trueの場合、protectedブランチ・タグとそれ以外でキャッシュを共有する
+    /// （GitLab CIの`cache:unprotect`相当。キャッシュポイズニングの危険があるため既定はfalse）
+    #[serde(default)]
+    unprotect: bool,
+    /// キャッシュ操作が失敗した場合の動作（"warn" / "fail"）
+    /// 省略時: CAFCE_ON_ERRORの値、それも未指定の場合は"fail"
+    on_error: Option<OnError>,
+    /// キャッシュの保持ルール（`cafce prune`）
+    retention: Option<Retention>,
 }
 impl Setting {
-    pub fn new_from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut file = File::open(path)?;
+    pub fn new_from_file(path: &Path) -> Result<Self, SettingError> {
+        let read_error = |source| SettingError::Read {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut file = File::open(path).map_err(read_error)?;
         let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        let conf: Self = toml::from_str(&contents)?;
+        file.read_to_string(&mut contents).map_err(read_error)?;
+        let conf: Self = toml::from_str(&contents).map_err(|source| SettingError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        // テンプレートの誤りはstore/restoreの途中ではなく読み込み時に報告する
+        conf.object_key_template()?;
         Ok(conf)
     }
-    pub fn init_to_file(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn init_to_file(path: &Path) -> Result<(), SettingError> {
         let setting = Setting {
+            name: None,
+            bucket: None,
+            storage: None,
+            object_key: None,
+            codec: Default::default(),
             paths: vec!["foo.txt".to_string()],
             key: StringOrStruct::Struct(Key {
                 files: vec!["bar.txt".to_string()],
                 prefix: None,
             }),
             fallback_keys: Default::default(),
+            unprotect: false,
+            on_error: None,
+            retention: None,
+        };
+        let write_error = |source| SettingError::Write {
+            path: path.to_path_buf(),
+            source,
         };
-        let mut file = File::create(path)?;
+        let mut file = File::create(path).map_err(write_error)?;
         let toml = toml::to_string(&setting).unwrap();
-        write!(file, "{toml}")?;
-        file.flush()?;
+        write!(file, "{toml}").map_err(write_error)?;
+        file.flush().map_err(write_error)?;
         Ok(())
     }
+
+    pub fn cache_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_CACHE_NAME)
+    }
+    pub fn bucket(&self) -> Option<&str> {
+        self.bucket.as_deref()
+    }
+    /// キャッシュの保存先のURLを決定する（Noneの場合は`bucket`のS3）
+    ///
+    /// キャッシュごとの`storage`を優先し、未指定の場合は`global`（CAFCE_STORAGE）を使用する。
+    pub fn storage<'a>(&'a self, global: Option<&'a str>) -> Option<&'a str> {
+        self.storage.as_deref().or(global)
+    }
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+    /// オブジェクトキーのテンプレートを取得する（未指定時は既定のテンプレート）
+    pub fn object_key_template(&self) -> Result<ObjectKeyTemplate, ObjectKeyError> {
+        match &self.object_key {
+            Some(template) => ObjectKeyTemplate::parse(template),
+            None => Ok(ObjectKeyTemplate::default()),
+        }
+    }
+    /// オブジェクトキーのテンプレート展開に使用する値を組み立てる
+    ///
+    /// # Arguments
+    /// * `project` - GitLabのプロジェクトパス（CI_PROJECT_PATH）
+    pub fn object_key_context<'a>(&'a self, project: Option<&'a str>) -> ObjectKeyContext<'a> {
+        ObjectKeyContext {
+            project,
+            cache_name: self.cache_name(),
+            codec: self.codec,
+        }
+    }
+    /// キャッシュに含めるパス（globパターン可、カレントディレクトリからの相対パス）
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+    pub fn key(&self) -> &StringOrStruct<Key> {
+        &self.key
+    }
+    pub fn fallback_keys(&self) -> &[String] {
+        &self.fallback_keys
+    }
+    pub fn unprotect(&self) -> bool {
+        self.unprotect
+    }
+    /// キャッシュ操作が失敗した場合の動作を決定する
+    ///
+    /// キャッシュごとの`on_error`を優先し、未指定の場合は`global`（CAFCE_ON_ERROR）を使用する。
+    pub fn on_error(&self, global: Option<OnError>) -> OnError {
+        self.on_error.or(global).unwrap_or_default()
+    }
+    /// キャッシュの保持ルールを取得する（未指定時は全てのルールが無効）
+    pub fn retention(&self) -> Retention {
+        self.retention.clone().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_on_error_overrides_global() {
+        let setting: Setting = toml::from_str(
+            "paths = []\nkey = \"static\"\nfallback_keys = []\non_error = \"warn\"",
+        )
+        .unwrap();
+        assert_eq!(setting.on_error(Some(OnError::Fail)), OnError::Warn);
+
+        let setting: Setting =
+            toml::from_str("paths = []\nkey = \"static\"\nfallback_keys = []").unwrap();
+        assert_eq!(setting.on_error(Some(OnError::Warn)), OnError::Warn);
+        assert_eq!(setting.on_error(None), OnError::Fail);
+    }
 }
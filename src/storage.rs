@@ -0,0 +1,1214 @@
+//! キャッシュオブジェクトの保存先（ストレージバックエンド）
+//!
+//! store/restoreは`Storage`トレイトを通してオブジェクトを読み書きする。保存先は設定ファイルの
+//! `storage`（省略時はCAFCE_STORAGE）のURLで選択し、どちらも未指定の場合は`bucket`のS3とする。
+//! オブジェクトキーはどの保存先でも`ObjectKeyTemplate`で展開した同じものを使う。
+//! 一覧・削除（`cafce list` / `prune` / `delete` / `inspect`）は`Catalog`で、S3のみに対応する。
+
+use crate::catalog::METADATA_LAST_ACCESSED;
+use crate::i18n::tr;
+use crate::s3_client::{is_credentials_error, is_timeout_error, SseConfig};
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata};
+use aws_smithy_types::byte_stream::ByteStream;
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::future::Future;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use tracing::Instrument;
+
+/// 最終アクセス日時を記録する間隔（秒）
+///
+/// restoreのたびに書き込まないよう、最終アクセス日時・更新日時が
+/// この期間内のキャッシュには記録しない。
+pub const ACCESS_RECORD_INTERVAL_SECS: i64 = 24 * 60 * 60;
+
+/// ファイルの保存先で、オブジェクトのメタデータを保存するサイドカーファイルの拡張子
+pub const SIDECAR_EXTENSION: &str = ".meta.json";
+
+/// 保存先の操作の結果を返すFuture
+///
+/// 保存先を実行時に選択できる（`dyn Storage`として扱える）よう、Boxに包んで返す。
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, StorageError>> + Send + 'a>>;
+
+/// 保存先の操作時のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("{}", tr!("保存先のURLが不正です: {url}: {message}", "invalid storage URL: {url}: {message}"))]
+    InvalidUrl { url: String, message: String },
+    #[error("{}", tr!("対応していない保存先です（file://・http://・https://・gitlab://のみ指定できます）: {url}", "unsupported storage (only file://, http://, https:// and gitlab:// are supported): {url}"))]
+    UnsupportedScheme { url: String },
+    #[error("{}", tr!("この保存先では使用できないオブジェクトキーです: {object_key}", "object key cannot be used with this storage: {object_key}"))]
+    InvalidObjectKey { object_key: String },
+    #[error("{}", tr!("CAFCE_STORAGE_TOKENとCAFCE_STORAGE_USERNAMEは同時に指定できません", "CAFCE_STORAGE_TOKEN and CAFCE_STORAGE_USERNAME cannot be set together"))]
+    ConflictingAuth,
+    #[error("{}", tr!("HTTPクライアントの構築に失敗しました", "failed to build the HTTP client"))]
+    HttpClient(#[source] crate::http_client::HttpClientError),
+    #[error("{}", tr!("GitLabの保存先には{name}が必要です（GitLab CIのジョブで実行してください）", "the GitLab storage requires {name} (run it in a GitLab CI job)"))]
+    MissingCiVariable { name: &'static str },
+    #[error("{}", tr!("キャッシュ名{cache_name}はGitLabの保存先の世代に使用するため、指定できません", "the cache name {cache_name} is reserved for the generation counter of the GitLab storage"))]
+    ReservedCacheName { cache_name: String },
+    #[error(transparent)]
+    ObjectKey(#[from] crate::object_key::ObjectKeyError),
+    #[error("{}", tr!("キャッシュのメタデータの取得に失敗しました: {object_key}: {message}", "failed to get the cache metadata: {object_key}: {message}"))]
+    Head { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュのダウンロードに失敗しました: {object_key}: {message}", "failed to download the cache: {object_key}: {message}"))]
+    Get { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュの読み出しが拒否されました: {object_key}: {message}", "access to the cache was denied: {object_key}: {message}"))]
+    AccessDenied { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュのアップロードに失敗しました: {object_key}: {message}", "failed to upload the cache: {object_key}: {message}"))]
+    Put { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュの削除に失敗しました: {object_key}: {message}", "failed to delete the cache: {object_key}: {message}"))]
+    Delete { object_key: String, message: String },
+    #[error("{}", tr!("AWSクレデンシャルの取得に失敗しました: {object_key}: {message}", "failed to obtain AWS credentials: {object_key}: {message}"))]
+    Credentials { object_key: String, message: String },
+    #[error("{}", tr!("保存先の操作がタイムアウトしました: {object_key}: {message}", "the storage operation timed out: {object_key}: {message}"))]
+    Timeout { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュの最終アクセス日時の記録に失敗しました: {object_key}: {message}", "failed to record the cache access time: {object_key}: {message}"))]
+    RecordAccess { object_key: String, message: String },
+    #[error("{}", tr!("キャッシュの世代の値が不正です: {object_key}: {value}", "invalid cache generation: {object_key}: {value}"))]
+    InvalidGeneration { object_key: String, value: String },
+    #[error("{}", tr!("ファイルの読み書きに失敗しました: {}", "failed to read or write a file: {}", .path.display()))]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl StorageError {
+    /// 設定の誤りか（保存先との通信・読み書きの失敗ではないか）
+    pub fn is_config_error(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidUrl { .. }
+                | Self::UnsupportedScheme { .. }
+                | Self::InvalidObjectKey { .. }
+                | Self::ConflictingAuth
+                | Self::HttpClient(_)
+                | Self::MissingCiVariable { .. }
+                | Self::ReservedCacheName { .. }
+                | Self::ObjectKey(_)
+        )
+    }
+}
+
+/// restore時の保存先の操作の失敗をキャッシュミスとして扱うべきか判定する
+///
+/// `env.restore_timeout_as_miss()`がtrueかつタイムアウトによる失敗の場合のみtrue。
+/// 不安定なエンドポイントでジョブが失敗するより、キャッシュなしで続行する方が望ましい場合に使用する。
+pub fn is_restore_miss(env: &crate::env::Env, error: &StorageError) -> bool {
+    env.restore_timeout_as_miss() && matches!(error, StorageError::Timeout { .. })
+}
+
+/// 保存先のURL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageUrl {
+    /// 設定ファイルの`bucket`のS3
+    S3,
+    /// `file://`で指定したディレクトリ（NFS等で共有したディレクトリ）
+    File(PathBuf),
+    /// `http://`・`https://`で指定したHTTPキャッシュサーバー
+    Http(url::Url),
+    /// `gitlab://`（プロジェクトの汎用パッケージレジストリ）
+    GitLab,
+}
+
+impl StorageUrl {
+    /// 保存先のURLを解釈する（Noneの場合はS3）
+    pub fn parse(url: Option<&str>) -> Result<Self, StorageError> {
+        let Some(url) = url else {
+            return Ok(Self::S3);
+        };
+        let invalid = |message: String| StorageError::InvalidUrl {
+            url: url.to_string(),
+            message,
+        };
+        let parsed = url::Url::parse(url).map_err(|e| invalid(e.to_string()))?;
+        match parsed.scheme() {
+            "file" => parsed.to_file_path().map(Self::File).map_err(|()| {
+                invalid(tr!(
+                    "ディレクトリの絶対パスを指定してください（file:///mnt/cache等）",
+                    "expected an absolute directory path (e.g. file:///mnt/cache)"
+                ))
+            }),
+            "http" | "https" => Ok(Self::Http(parsed)),
+            "gitlab" => Ok(Self::GitLab),
+            _ => Err(StorageError::UnsupportedScheme { url: url.to_string() }),
+        }
+    }
+}
+
+/// 保存先のオブジェクトの情報
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectMetadata {
+    pub size: u64,
+    /// 内容が変わると変わる値（S3のETag、ファイルの場合は内容のMD5）
+    pub etag: Option<String>,
+    pub last_modified: Option<DateTime>,
+    /// ユーザー定義メタデータ（`cafce-cache-name`等）
+    pub metadata: HashMap<String, String>,
+}
+
+impl ObjectMetadata {
+    /// 最終アクセス日時（`cafce-last-accessed`）
+    pub fn last_accessed(&self) -> Option<DateTime> {
+        self.metadata
+            .get(METADATA_LAST_ACCESSED)
+            .and_then(|value| DateTime::from_str(value, Format::DateTime).ok())
+    }
+}
+
+/// キャッシュオブジェクトの保存先
+pub trait Storage: Send + Sync {
+    /// メタデータを取得する（存在しない場合はNone）
+    fn head<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, Option<ObjectMetadata>>;
+
+    /// 本文を受信しながら`destination`に書き込み、メタデータを返す
+    ///
+    /// 存在しない場合は何も書き込まずにNoneを返す。
+    fn get<'a>(
+        &'a self,
+        object_key: &'a str,
+        destination: &'a mut (dyn Write + Send),
+    ) -> StorageFuture<'a, Option<ObjectMetadata>>;
+
+    /// `source`のファイルをメタデータとともに保存し、保存したオブジェクトのETagを返す
+    ///
+    /// 既存のオブジェクトは置き換える。
+    fn put<'a>(
+        &'a self,
+        object_key: &'a str,
+        source: &'a Path,
+        metadata: &'a HashMap<String, String>,
+    ) -> StorageFuture<'a, Option<String>>;
+
+    /// 削除する（存在しない場合も成功とする）
+    fn delete<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, ()>;
+
+    /// restoreで使用したことをメタデータ（`cafce-last-accessed`）に記録する
+    ///
+    /// 最終アクセス日時・更新日時が`ACCESS_RECORD_INTERVAL_SECS`以内の場合、
+    /// オブジェクトが存在しない場合は何もせずfalseを返す。
+    fn record_access<'a>(&'a self, object_key: &'a str, now: i64) -> StorageFuture<'a, bool>;
+}
+
+/// プロジェクトのキャッシュの世代を取得する（一度も増やしていない場合は0）
+///
+/// S3では、`s3:ListBucket`が許可されていないキーの読み出しは、オブジェクトが存在しなくても
+/// NoSuchKeyではなくAccessDeniedになる。自動生成のセッションポリシーでは世代のオブジェクトの
+/// 読み出しのみを許可しているため、拒否された場合はオブジェクトがない（世代0）とみなす。
+pub async fn read_generation(storage: &dyn Storage, object_key: &str) -> Result<u64, StorageError> {
+    Ok(read_generation_object(storage, object_key).await?.0)
+}
+
+/// プロジェクトのキャッシュの世代と、世代を保存するオブジェクトのETag（存在しない場合はNone）を取得する
+///
+/// ETagは世代を増やす際の条件付き書き込み（`If-Match`）に使う。
+pub async fn read_generation_object(
+    storage: &dyn Storage,
+    object_key: &str,
+) -> Result<(u64, Option<String>), StorageError> {
+    let mut body = Vec::new();
+    let object = match storage.get(object_key, &mut body).await {
+        Ok(Some(object)) => object,
+        Ok(None) | Err(StorageError::AccessDenied { .. }) => return Ok((0, None)),
+        Err(error) => return Err(error),
+    };
+    let generation = parse_generation(&body).ok_or_else(|| StorageError::InvalidGeneration {
+        object_key: object_key.to_string(),
+        value: String::from_utf8_lossy(&body).into_owned(),
+    })?;
+    Ok((generation, object.etag))
+}
+
+/// 世代を保存するオブジェクトの内容（10進数）を解釈する
+pub(crate) fn parse_generation(body: &[u8]) -> Option<u64> {
+    String::from_utf8_lossy(body).trim().parse().ok()
+}
+
+/// 最終アクセス日時を記録する必要があるか
+///
+/// 最後に使用された日時（最終アクセス日時と更新日時の新しい方）から
+/// `ACCESS_RECORD_INTERVAL_SECS`以上経過している場合に記録する。
+fn needs_access_record(last_modified: Option<DateTime>, last_accessed: Option<DateTime>, now: i64) -> bool {
+    last_modified
+        .into_iter()
+        .chain(last_accessed)
+        .map(|time| time.secs())
+        .max()
+        .is_none_or(|last_used| now - last_used >= ACCESS_RECORD_INTERVAL_SECS)
+}
+
+fn format_access_time(now: i64) -> Result<String, String> {
+    DateTime::from_secs(now)
+        .fmt(Format::DateTime)
+        .map_err(|e| e.to_string())
+}
+
+/// S3の保存先
+pub struct S3Storage {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    sse: SseConfig,
+}
+
+impl S3Storage {
+    pub fn new(client: aws_sdk_s3::Client, bucket: &str, sse: SseConfig) -> Self {
+        Self {
+            client,
+            bucket: bucket.to_string(),
+            sse,
+        }
+    }
+
+    async fn put_object(
+        &self,
+        object_key: &str,
+        source: &Path,
+        metadata: &HashMap<String, String>,
+    ) -> Result<Option<String>, StorageError> {
+        let body = ByteStream::from_path(source).await.map_err(|e| StorageError::Io {
+            path: source.to_path_buf(),
+            source: std::io::Error::other(e),
+        })?;
+        let output = self
+            .sse
+            .apply_to_put_object(self.client.put_object())
+            .bucket(&self.bucket)
+            .key(object_key)
+            .set_metadata(Some(metadata.clone()))
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| {
+                if is_credentials_error(&e) {
+                    return credentials_error(object_key, &e);
+                }
+                StorageError::Put {
+                    object_key: object_key.to_string(),
+                    message: DisplayErrorContext(&e).to_string(),
+                }
+            })?;
+        Ok(output.e_tag().map(String::from))
+    }
+
+    async fn get_object(
+        &self,
+        object_key: &str,
+        destination: &mut (dyn Write + Send),
+    ) -> Result<Option<ObjectMetadata>, StorageError> {
+        let get_error = |message: String| StorageError::Get {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let result = self
+            .sse
+            .apply_to_get_object(self.client.get_object())
+            .bucket(&self.bucket)
+            .key(object_key)
+            .send()
+            .await;
+        let mut output = match result {
+            Ok(output) => output,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) if e.as_service_error().and_then(|e| e.code()) == Some("AccessDenied") => {
+                return Err(StorageError::AccessDenied {
+                    object_key: object_key.to_string(),
+                    message: DisplayErrorContext(&e).to_string(),
+                })
+            }
+            Err(e) if is_timeout_error(&e) => return Err(timeout_error(object_key, &e)),
+            Err(e) if is_credentials_error(&e) => return Err(credentials_error(object_key, &e)),
+            Err(e) => return Err(get_error(DisplayErrorContext(&e).to_string())),
+        };
+        let metadata = ObjectMetadata {
+            size: output.content_length().unwrap_or_default().max(0) as u64,
+            etag: output.e_tag().map(String::from),
+            last_modified: output.last_modified().copied(),
+            metadata: output.metadata().cloned().unwrap_or_default(),
+        };
+        while let Some(chunk) = output.body.next().await {
+            let chunk = chunk.map_err(|e| get_error(DisplayErrorContext(&e).to_string()))?;
+            destination
+                .write_all(&chunk)
+                .map_err(|e| get_error(e.to_string()))?;
+        }
+        Ok(Some(metadata))
+    }
+
+    async fn head_object(&self, object_key: &str) -> Result<Option<ObjectMetadata>, StorageError> {
+        let result = self
+            .sse
+            .apply_to_head_object(self.client.head_object())
+            .bucket(&self.bucket)
+            .key(object_key)
+            .send()
+            .await;
+        match result {
+            Ok(output) => Ok(Some(ObjectMetadata {
+                size: output.content_length().unwrap_or_default().max(0) as u64,
+                etag: output.e_tag().map(String::from),
+                last_modified: output.last_modified().copied(),
+                metadata: output.metadata().cloned().unwrap_or_default(),
+            })),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+            Err(e) if is_timeout_error(&e) => Err(timeout_error(object_key, &e)),
+            Err(e) if is_credentials_error(&e) => Err(credentials_error(object_key, &e)),
+            Err(e) => Err(StorageError::Head {
+                object_key: object_key.to_string(),
+                message: DisplayErrorContext(&e).to_string(),
+            }),
+        }
+    }
+
+    async fn delete_object(&self, object_key: &str) -> Result<(), StorageError> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(object_key)
+            .send()
+            .await
+            .map_err(|e| StorageError::Delete {
+                object_key: object_key.to_string(),
+                message: DisplayErrorContext(&e).to_string(),
+            })?;
+        Ok(())
+    }
+
+    /// S3には最終アクセス日時がないため、オブジェクトを自身に上書きコピー（CopyObject）して
+    /// メタデータを置き換える。本文はS3内でコピーされ、ダウンロード・アップロードは発生しない。
+    ///
+    /// 上書きコピーによりオブジェクトの更新日時も変わる。確認後にstoreで置き換えられた場合は、
+    /// 新しいオブジェクトを上書きしないよう`x-amz-copy-source-if-match`で記録を取りやめる。
+    async fn copy_with_access_time(&self, object_key: &str, now: i64) -> Result<bool, StorageError> {
+        let record_error = |message: String| StorageError::RecordAccess {
+            object_key: object_key.to_string(),
+            message,
+        };
+        let result = self
+            .sse
+            .apply_to_head_object(self.client.head_object())
+            .bucket(&self.bucket)
+            .key(object_key)
+            .send()
+            .await;
+        let head = match result {
+            Ok(head) => head,
+            // 復元後に削除された
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => return Ok(false),
+            Err(e) => return Err(record_error(DisplayErrorContext(&e).to_string())),
+        };
+        let mut metadata = head.metadata().cloned().unwrap_or_default();
+        let last_accessed = metadata
+            .get(METADATA_LAST_ACCESSED)
+            .and_then(|value| DateTime::from_str(value, Format::DateTime).ok());
+        if !needs_access_record(head.last_modified().copied(), last_accessed, now) {
+            return Ok(false);
+        }
+        metadata.insert(
+            METADATA_LAST_ACCESSED.to_string(),
+            format_access_time(now).map_err(record_error)?,
+        );
+
+        let result = self
+            .sse
+            .apply_to_copy_object(self.client.copy_object())
+            .bucket(&self.bucket)
+            .key(object_key)
+            .copy_source(copy_source(&self.bucket, object_key))
+            .set_copy_source_if_match(head.e_tag().map(String::from))
+            .metadata_directive(aws_sdk_s3::types::MetadataDirective::Replace)
+            .set_metadata(Some(metadata))
+            .set_content_type(head.content_type().map(String::from))
+            .send()
+            .instrument(tracing::info_span!("cafce.record_access", object_key))
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.raw_response().is_some_and(|response| response.status().as_u16() == 412) => Ok(false),
+            Err(e) => Err(record_error(DisplayErrorContext(&e).to_string())),
+        }
+    }
+}
+
+impl Storage for S3Storage {
+    fn head<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        Box::pin(self.head_object(object_key))
+    }
+
+    fn get<'a>(
+        &'a self,
+        object_key: &'a str,
+        destination: &'a mut (dyn Write + Send),
+    ) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        Box::pin(self.get_object(object_key, destination))
+    }
+
+    fn put<'a>(
+        &'a self,
+        object_key: &'a str,
+        source: &'a Path,
+        metadata: &'a HashMap<String, String>,
+    ) -> StorageFuture<'a, Option<String>> {
+        Box::pin(self.put_object(object_key, source, metadata))
+    }
+
+    fn delete<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(self.delete_object(object_key))
+    }
+
+    fn record_access<'a>(&'a self, object_key: &'a str, now: i64) -> StorageFuture<'a, bool> {
+        Box::pin(self.copy_with_access_time(object_key, now))
+    }
+}
+
+/// タイムアウトしたS3操作のエラー
+fn timeout_error<E, R>(object_key: &str, error: &aws_sdk_s3::error::SdkError<E, R>) -> StorageError
+where
+    E: std::error::Error + 'static,
+    R: std::fmt::Debug,
+{
+    StorageError::Timeout {
+        object_key: object_key.to_string(),
+        message: DisplayErrorContext(error).to_string(),
+    }
+}
+
+/// クレデンシャルの取得に失敗したS3操作のエラー
+fn credentials_error<E, R>(object_key: &str, error: &aws_sdk_s3::error::SdkError<E, R>) -> StorageError
+where
+    E: std::error::Error + 'static,
+    R: std::fmt::Debug,
+{
+    StorageError::Credentials {
+        object_key: object_key.to_string(),
+        message: DisplayErrorContext(error).to_string(),
+    }
+}
+
+/// オブジェクトキーをパスとして扱う保存先で、保存先の外を指すキーを拒否する
+///
+/// 空・`.`・`..`のセグメントや`\\`を含むキーはエラーとする。
+pub(crate) fn validate_object_key(object_key: &str) -> Result<(), StorageError> {
+    let is_invalid = |segment: &str| segment.is_empty() || segment == "." || segment == "..";
+    if object_key.contains('\\') || object_key.split('/').any(is_invalid) {
+        return Err(StorageError::InvalidObjectKey {
+            object_key: object_key.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// CopyObjectのコピー元（`バケット/オブジェクトキー`をURLエンコードし、`/`はそのまま残す）
+fn copy_source(bucket: &str, object_key: &str) -> String {
+    let mut source = String::new();
+    for byte in format!("{bucket}/{object_key}").bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~/".contains(&byte) {
+            source.push(byte as char);
+        } else {
+            let _ = write!(source, "%{byte:02X}");
+        }
+    }
+    source
+}
+
+/// ディレクトリの保存先（`file://`）
+///
+/// オブジェクトキーをディレクトリからの相対パスとしてファイルに保存し、メタデータ・ETagは
+/// 同じ名前に`SIDECAR_EXTENSION`を付けたJSONファイル（サイドカー）に保存する。
+/// 書き込みは同じディレクトリの一時ファイルに書いてからrenameするため、
+/// 他のジョブが書き込み途中のファイルを読むことはない。
+///
+/// オブジェクトとサイドカーは別々にrenameするため、置き換えの途中では新旧が食い違うことがある。
+/// サイドカーにはオブジェクトのサイズと更新日時も記録し、実際のファイルと一致しない場合は
+/// ETagを不明（None）として扱う（ローカルキャッシュが古い内容を新しいETagで保存しないため）。
+pub struct FileStorage {
+    root: PathBuf,
+}
+
+/// サイドカーファイルの内容
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Sidecar {
+    etag: Option<String>,
+    /// ETagを計算したオブジェクトのサイズ
+    size: Option<u64>,
+    /// ETagを計算したオブジェクトの更新日時（UNIXエポックからのナノ秒）
+    modified_ns: Option<u64>,
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+}
+
+impl FileStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// オブジェクトキーに対応するファイルのパス
+    ///
+    /// ディレクトリの外を指さないよう、空・`.`・`..`のセグメントを含むキーは拒否する。
+    fn object_path(&self, object_key: &str) -> Result<PathBuf, StorageError> {
+        validate_object_key(object_key)?;
+        Ok(self.root.join(object_key))
+    }
+
+    fn sidecar_path(path: &Path) -> PathBuf {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(SIDECAR_EXTENSION);
+        PathBuf::from(sidecar)
+    }
+
+    fn read_sidecar(path: &Path) -> Result<Sidecar, StorageError> {
+        let io_error = |source| StorageError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        match std::fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents).map_err(|e| io_error(std::io::Error::other(e))),
+            // サイドカーのないオブジェクト（手動で配置した等）はメタデータなしとして扱う
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Sidecar::default()),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+
+    fn write_sidecar(path: &Path, sidecar: &Sidecar) -> Result<(), StorageError> {
+        let contents = serde_json::to_vec_pretty(sidecar).expect("sidecar must be serializable");
+        write_atomically(path, |file| file.write_all(&contents))
+    }
+
+    fn head_file(&self, object_key: &str) -> Result<Option<ObjectMetadata>, StorageError> {
+        let path = self.object_path(object_key)?;
+        let file_metadata = match std::fs::metadata(&path) {
+            Ok(file_metadata) => file_metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => return Err(StorageError::Io { path, source }),
+        };
+        Self::object_metadata(&path, &file_metadata).map(Some)
+    }
+
+    /// ファイルのメタデータとサイドカーからオブジェクトのメタデータを組み立てる
+    ///
+    /// サイドカーのサイズ・更新日時が`file_metadata`と一致しない場合（置き換えの途中等）は、
+    /// サイドカーのETagは別の内容のものとしてNoneとする。
+    fn object_metadata(path: &Path, file_metadata: &std::fs::Metadata) -> Result<ObjectMetadata, StorageError> {
+        let sidecar = Self::read_sidecar(&Self::sidecar_path(path))?;
+        let matches = sidecar.size == Some(file_metadata.len())
+            && sidecar.modified_ns.is_some()
+            && sidecar.modified_ns == modified_ns(file_metadata);
+        Ok(ObjectMetadata {
+            size: file_metadata.len(),
+            etag: sidecar.etag.filter(|_| matches),
+            last_modified: file_metadata.modified().ok().map(DateTime::from),
+            metadata: sidecar.metadata.into_iter().collect(),
+        })
+    }
+
+    fn get_file(
+        &self,
+        object_key: &str,
+        destination: &mut (dyn Write + Send),
+    ) -> Result<Option<ObjectMetadata>, StorageError> {
+        let path = self.object_path(object_key)?;
+        let io_error = |source| StorageError::Io {
+            path: path.clone(),
+            source,
+        };
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => return Err(io_error(source)),
+        };
+        // パスではなく開いたファイルのメタデータと照合し、読み込む内容のETagだけを返す
+        let file_metadata = file.metadata().map_err(io_error)?;
+        let metadata = Self::object_metadata(&path, &file_metadata)?;
+        std::io::copy(&mut file, destination).map_err(io_error)?;
+        Ok(Some(metadata))
+    }
+
+    fn put_file(
+        &self,
+        object_key: &str,
+        source: &Path,
+        metadata: &HashMap<String, String>,
+    ) -> Result<Option<String>, StorageError> {
+        use md5::Digest;
+
+        let path = self.object_path(object_key)?;
+        let mut input = File::open(source).map_err(|e| StorageError::Io {
+            path: source.to_path_buf(),
+            source: e,
+        })?;
+        let mut etag = None;
+        write_atomically_then(
+            &path,
+            |file| {
+                let mut hasher = md5::Md5::new();
+                let mut buffer = vec![0u8; 64 * 1024];
+                loop {
+                    let len = input.read(&mut buffer)?;
+                    if len == 0 {
+                        return Ok(hasher.finalize());
+                    }
+                    hasher.update(&buffer[..len]);
+                    file.write_all(&buffer[..len])?;
+                }
+            },
+            // サイドカーはオブジェクトの置き換えより前に書き、書き込んだ一時ファイルの
+            // サイズ・更新日時（renameでは変わらない）を記録する
+            |file, digest| {
+                let file_metadata = file.metadata()?;
+                let sidecar = Sidecar {
+                    etag: Some(format!("\"{}\"", hex::encode(digest))),
+                    size: Some(file_metadata.len()),
+                    modified_ns: modified_ns(&file_metadata),
+                    metadata: metadata.clone().into_iter().collect(),
+                };
+                let contents = serde_json::to_vec_pretty(&sidecar).expect("sidecar must be serializable");
+                write_atomically(&Self::sidecar_path(&path), |file| file.write_all(&contents))
+                    .map_err(std::io::Error::other)?;
+                etag = sidecar.etag;
+                Ok(())
+            },
+        )?;
+        Ok(etag)
+    }
+
+    fn delete_file(&self, object_key: &str) -> Result<(), StorageError> {
+        let path = self.object_path(object_key)?;
+        for path in [Self::sidecar_path(&path), path] {
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(source) => return Err(StorageError::Io { path, source }),
+            }
+        }
+        Ok(())
+    }
+
+    /// サイドカーの最終アクセス日時のみを書き換える（オブジェクトの更新日時は変わらない）
+    fn record_file_access(&self, object_key: &str, now: i64) -> Result<bool, StorageError> {
+        let Some(object) = self.head_file(object_key)? else {
+            return Ok(false);
+        };
+        if !needs_access_record(object.last_modified, object.last_accessed(), now) {
+            return Ok(false);
+        }
+        let sidecar_path = Self::sidecar_path(&self.object_path(object_key)?);
+        let mut sidecar = Self::read_sidecar(&sidecar_path)?;
+        let accessed = format_access_time(now).map_err(|message| StorageError::RecordAccess {
+            object_key: object_key.to_string(),
+            message,
+        })?;
+        sidecar.metadata.insert(METADATA_LAST_ACCESSED.to_string(), accessed);
+        Self::write_sidecar(&sidecar_path, &sidecar)?;
+        Ok(true)
+    }
+}
+
+impl Storage for FileStorage {
+    fn head<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        Box::pin(async move { self.head_file(object_key) })
+    }
+
+    fn get<'a>(
+        &'a self,
+        object_key: &'a str,
+        destination: &'a mut (dyn Write + Send),
+    ) -> StorageFuture<'a, Option<ObjectMetadata>> {
+        Box::pin(async move { self.get_file(object_key, destination) })
+    }
+
+    fn put<'a>(
+        &'a self,
+        object_key: &'a str,
+        source: &'a Path,
+        metadata: &'a HashMap<String, String>,
+    ) -> StorageFuture<'a, Option<String>> {
+        Box::pin(async move { self.put_file(object_key, source, metadata) })
+    }
+
+    fn delete<'a>(&'a self, object_key: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(async move { self.delete_file(object_key) })
+    }
+
+    fn record_access<'a>(&'a self, object_key: &'a str, now: i64) -> StorageFuture<'a, bool> {
+        Box::pin(async move { self.record_file_access(object_key, now) })
+    }
+}
+
+/// `path`と同じディレクトリの一時ファイルに書き込み、renameで置き換える
+///
+/// 同じファイルシステム内のrenameはアトミックなため、読み込み側には置き換え前か後の
+/// どちらかの内容だけが見える。失敗した場合は一時ファイルを削除する。
+pub(crate) fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> Result<(), StorageError> {
+    write_atomically_then(path, write, |_, ()| Ok(()))
+}
+
+/// `write_atomically`と同じだが、書き込んだ一時ファイルをrenameする直前に`before_rename`を実行する
+///
+/// `before_rename`には、一時ファイルと`write`の戻り値を渡す。
+fn write_atomically_then<T>(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<T>,
+    before_rename: impl FnOnce(&File, T) -> std::io::Result<()>,
+) -> Result<(), StorageError> {
+    let io_error = |source| StorageError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let (Some(directory), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return Err(io_error(std::io::ErrorKind::InvalidInput.into()));
+    };
+    std::fs::create_dir_all(directory).map_err(io_error)?;
+    let temporary = directory.join(format!(
+        ".{}.tmp-{}",
+        file_name.to_string_lossy(),
+        temporary_suffix()
+    ));
+    let result = File::create(&temporary).and_then(|mut file| {
+        let written = write(&mut file)?;
+        // NFS等でもrename後に内容が揃っているよう、置き換える前にディスクへ書き出す
+        file.sync_all()?;
+        before_rename(&file, written)?;
+        std::fs::rename(&temporary, path)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    result.map_err(io_error)
+}
+
+/// ファイルの更新日時（UNIXエポックからのナノ秒）
+fn modified_ns(file_metadata: &std::fs::Metadata) -> Option<u64> {
+    let modified = file_metadata.modified().ok()?;
+    let nanos = modified.duration_since(std::time::UNIX_EPOCH).ok()?.as_nanos();
+    u64::try_from(nanos).ok()
+}
+
+/// 一時ファイル名の接尾辞（同じディレクトリに複数のジョブが同時に書き込んでも衝突しない値）
+pub(crate) fn temporary_suffix() -> String {
+    let mut random = [0u8; 8];
+    // 乱数が取得できない環境ではプロセスIDと時刻から生成する
+    if getrandom::getrandom(&mut random).is_err() {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        random = (nanos ^ (u64::from(std::process::id()) << 32)).to_be_bytes();
+    }
+    hex::encode(random)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{CiEnv, Env};
+    use crate::s3_client::build_s3_client;
+    use crate::test_util::{MockResponse, MockServer};
+
+    const OBJECT_KEY: &str = "group/app/node_modules/v1/deps-protected.tar.zst";
+
+    fn now() -> i64 {
+        DateTime::from_str("2026-10-18T00:00:00Z", Format::DateTime).unwrap().secs()
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_storage_url() {
+        assert_eq!(StorageUrl::parse(None).unwrap(), StorageUrl::S3);
+        assert_eq!(
+            StorageUrl::parse(Some("file:///mnt/cafce%20cache")).unwrap(),
+            StorageUrl::File(PathBuf::from("/mnt/cafce cache"))
+        );
+        assert_eq!(
+            StorageUrl::parse(Some("https://cache.example.com/cafce/")).unwrap(),
+            StorageUrl::Http(url::Url::parse("https://cache.example.com/cafce/").unwrap())
+        );
+        assert_eq!(StorageUrl::parse(Some("gitlab://")).unwrap(), StorageUrl::GitLab);
+        let result = StorageUrl::parse(Some("file://nfs-server/mnt/cache"));
+        assert!(matches!(result, Err(StorageError::InvalidUrl { .. })), "{result:?}");
+        let result = StorageUrl::parse(Some("/mnt/cache"));
+        assert!(matches!(result, Err(StorageError::InvalidUrl { .. })), "{result:?}");
+        let error = StorageUrl::parse(Some("ftp://example.com/cache")).unwrap_err();
+        assert!(matches!(error, StorageError::UnsupportedScheme { .. }), "{error:?}");
+        assert!(error.is_config_error());
+    }
+
+    #[test]
+    fn test_file_storage_put_get_delete() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(root.path().to_path_buf());
+        let source = root.path().join("archive");
+        std::fs::write(&source, b"hello").unwrap();
+        let metadata = HashMap::from([("cafce-cache-name".to_string(), "node_modules".to_string())]);
+
+        runtime().block_on(async {
+            let etag = storage.put(OBJECT_KEY, &source, &metadata).await.unwrap();
+
+            // S3と同じキーの配置で保存し、メタデータはサイドカーに保存する
+            let path = root.path().join(OBJECT_KEY);
+            assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+            let sidecar = std::fs::read_to_string(format!("{}.meta.json", path.display())).unwrap();
+            assert!(sidecar.contains("\"cafce-cache-name\": \"node_modules\""), "{sidecar}");
+            // 一時ファイルは残らない
+            let names: Vec<_> = std::fs::read_dir(path.parent().unwrap())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            assert_eq!(names.len(), 2, "{names:?}");
+
+            let mut body = Vec::new();
+            let object = storage.get(OBJECT_KEY, &mut body).await.unwrap().unwrap();
+            assert_eq!(body, b"hello");
+            assert_eq!(object.size, 5);
+            // 内容のMD5（S3のETagと同じ形式）
+            assert_eq!(object.etag.as_deref(), Some("\"5d41402abc4b2a76b9719d911017c592\""));
+            assert_eq!(object.etag, etag);
+            assert_eq!(object.metadata, metadata);
+            assert!(object.last_modified.is_some());
+            assert_eq!(storage.head(OBJECT_KEY).await.unwrap(), Some(object));
+
+            storage.delete(OBJECT_KEY).await.unwrap();
+            assert!(!path.exists());
+            assert_eq!(storage.head(OBJECT_KEY).await.unwrap(), None);
+            assert_eq!(storage.get(OBJECT_KEY, &mut Vec::new()).await.unwrap(), None);
+            // 存在しないオブジェクトの削除は成功とする
+            storage.delete(OBJECT_KEY).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_file_storage_etag_follows_object() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(root.path().to_path_buf());
+        let source = root.path().join("archive");
+        std::fs::write(&source, b"hello").unwrap();
+        let path = root.path().join(OBJECT_KEY);
+
+        runtime().block_on(async {
+            storage.put(OBJECT_KEY, &source, &HashMap::new()).await.unwrap();
+            assert!(storage.head(OBJECT_KEY).await.unwrap().unwrap().etag.is_some());
+
+            // サイドカーと食い違うオブジェクト（置き換えの途中等）のETagは不明とする
+            std::fs::write(&path, b"world").unwrap();
+            let object = storage.head(OBJECT_KEY).await.unwrap().unwrap();
+            assert_eq!(object.etag, None);
+            let mut body = Vec::new();
+            let object = storage.get(OBJECT_KEY, &mut body).await.unwrap().unwrap();
+            assert_eq!((body.as_slice(), object.etag), (&b"world"[..], None));
+
+            // 再度保存すれば、新しい内容のETagになる
+            std::fs::write(&source, b"world").unwrap();
+            let etag = storage.put(OBJECT_KEY, &source, &HashMap::new()).await.unwrap();
+            assert_eq!(storage.head(OBJECT_KEY).await.unwrap().unwrap().etag, etag);
+        });
+    }
+
+    #[test]
+    fn test_file_storage_rejects_path_traversal() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(root.path().join("cache"));
+        for object_key in ["../outside.tar.zst", "group/./x", "group//x", "/etc/passwd"] {
+            let result = runtime().block_on(storage.head(object_key));
+            assert!(
+                matches!(result, Err(StorageError::InvalidObjectKey { .. })),
+                "{object_key}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_file_storage_record_access() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(root.path().to_path_buf());
+        let source = root.path().join("archive");
+        std::fs::write(&source, b"hello").unwrap();
+
+        runtime().block_on(async {
+            storage.put(OBJECT_KEY, &source, &HashMap::new()).await.unwrap();
+            let stored = storage.head(OBJECT_KEY).await.unwrap().unwrap().last_modified.unwrap();
+
+            // 保存から1日以内は記録しない
+            assert!(!storage.record_access(OBJECT_KEY, stored.secs() + 60).await.unwrap());
+            let later = stored.secs() + 2 * ACCESS_RECORD_INTERVAL_SECS;
+            assert!(storage.record_access(OBJECT_KEY, later).await.unwrap());
+            let object = storage.head(OBJECT_KEY).await.unwrap().unwrap();
+            assert_eq!(object.last_accessed(), Some(DateTime::from_secs(later)));
+            assert!(!storage.record_access(OBJECT_KEY, later + 60).await.unwrap());
+            assert!(!storage.record_access("group/app/missing.tar.zst", later).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_read_generation() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(root.path().to_path_buf());
+        let object_key = "group/app/.cafce-generation";
+
+        runtime().block_on(async {
+            assert_eq!(read_generation(&storage, object_key).await.unwrap(), 0);
+
+            let source = root.path().join("generation");
+            std::fs::write(&source, "3\n").unwrap();
+            storage.put(object_key, &source, &HashMap::new()).await.unwrap();
+            assert_eq!(read_generation(&storage, object_key).await.unwrap(), 3);
+
+            std::fs::write(&source, "three").unwrap();
+            storage.put(object_key, &source, &HashMap::new()).await.unwrap();
+            let result = read_generation(&storage, object_key).await;
+            assert!(matches!(result, Err(StorageError::InvalidGeneration { .. })), "{result:?}");
+        });
+    }
+
+    async fn s3_storage(s3: &MockServer) -> S3Storage {
+        s3_storage_with_sse(s3, SseConfig::None).await
+    }
+
+    async fn s3_storage_with_sse(s3: &MockServer, sse: SseConfig) -> S3Storage {
+        let env = Env::new_for_test(
+            Some(s3.address().to_string()),
+            Some("access".to_string()),
+            Some("secret".to_string()),
+            None,
+            None,
+            None,
+            None,
+            true,
+            None,
+            None,
+        );
+        let client = build_s3_client(&env, &CiEnv::default()).await.unwrap();
+        S3Storage::new(client, "b", sse)
+    }
+
+    #[tokio::test]
+    async fn test_s3_storage_put_get_head() {
+        let s3 = MockServer::start(|request| match request.method.as_str() {
+            "PUT" => MockResponse::new(200, "").header("ETag", "\"etag1\""),
+            "GET" if request.path.contains("/missing.tar.zst") => MockResponse::new(
+                404,
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>NoSuchKey</Code></Error>",
+            ),
+            "GET" => MockResponse::new(200, "hello")
+                .header("ETag", "\"etag1\"")
+                .header("x-amz-meta-cafce-cache-name", "node_modules"),
+            _ => MockResponse::new(404, ""),
+        });
+        let storage = s3_storage(&s3).await;
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("archive");
+        std::fs::write(&source, b"hello").unwrap();
+        let metadata = HashMap::from([("cafce-cache-name".to_string(), "node_modules".to_string())]);
+
+        let etag = storage.put(OBJECT_KEY, &source, &metadata).await.unwrap();
+        assert_eq!(etag.as_deref(), Some("\"etag1\""));
+        let put = s3.requests().into_iter().find(|request| request.method == "PUT").unwrap();
+        assert_eq!(put.path, format!("/b/{OBJECT_KEY}?x-id=PutObject"));
+        assert_eq!(put.header("x-amz-meta-cafce-cache-name"), Some("node_modules"));
+        // ファイルからの送信はaws-chunked形式になるため、本文を含むことのみ確認する
+        assert!(put.body_str().contains("hello"), "{}", put.body_str());
+
+        let mut body = Vec::new();
+        let object = storage.get(OBJECT_KEY, &mut body).await.unwrap().unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(object.size, 5);
+        assert_eq!(object.etag.as_deref(), Some("\"etag1\""));
+        assert_eq!(object.metadata, metadata);
+
+        assert_eq!(storage.get("group/app/missing.tar.zst", &mut Vec::new()).await.unwrap(), None);
+        assert_eq!(storage.head(OBJECT_KEY).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_s3_read_generation_access_denied() {
+        // s3:ListBucketのないキーは、存在しなくてもAccessDeniedになる
+        let s3 = MockServer::start(|_| {
+            MockResponse::new(
+                403,
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>AccessDenied</Code></Error>",
+            )
+        });
+        let storage = s3_storage(&s3).await;
+        assert_eq!(read_generation(&storage, "group/app/.cafce-generation").await.unwrap(), 0);
+
+        // キャッシュの読み出しではキャッシュミスとせず、エラーとする
+        let result = storage.get(OBJECT_KEY, &mut Vec::new()).await;
+        assert!(matches!(result, Err(StorageError::AccessDenied { .. })), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn test_s3_storage_sends_sse_headers() {
+        let s3 = MockServer::start(|request| match request.method.as_str() {
+            "PUT" => MockResponse::new(200, "").header("ETag", "\"etag1\""),
+            "GET" => MockResponse::new(200, "hello"),
+            _ => MockResponse::new(200, ""),
+        });
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("archive");
+        std::fs::write(&source, b"hello").unwrap();
+
+        let kms = SseConfig::Kms {
+            key_id: Some("alias/cafce".to_string()),
+        };
+        let storage = s3_storage_with_sse(&s3, kms).await;
+        storage.put(OBJECT_KEY, &source, &HashMap::new()).await.unwrap();
+        storage.get(OBJECT_KEY, &mut Vec::new()).await.unwrap();
+        let requests = s3.requests();
+        let put = requests.iter().find(|request| request.method == "PUT").unwrap();
+        assert_eq!(put.header("x-amz-server-side-encryption"), Some("aws:kms"));
+        assert_eq!(put.header("x-amz-server-side-encryption-aws-kms-key-id"), Some("alias/cafce"));
+        // SSE-KMSは読み出し時の指定が不要
+        let get = requests.iter().find(|request| request.method == "GET").unwrap();
+        assert_eq!(get.header("x-amz-server-side-encryption"), None);
+
+        // SSE-Cは書き込み・読み出し・HEADの全てで鍵を送信する
+        let customer_key = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+        let sse = SseConfig::parse(None, None, Some(customer_key), false).unwrap();
+        let s3 = MockServer::start(|request| match request.method.as_str() {
+            "PUT" => MockResponse::new(200, "").header("ETag", "\"etag1\""),
+            "GET" => MockResponse::new(200, "hello"),
+            _ => MockResponse::new(200, ""),
+        });
+        let storage = s3_storage_with_sse(&s3, sse).await;
+        storage.put(OBJECT_KEY, &source, &HashMap::new()).await.unwrap();
+        storage.get(OBJECT_KEY, &mut Vec::new()).await.unwrap();
+        storage.head(OBJECT_KEY).await.unwrap();
+        let requests = s3.requests();
+        for method in ["PUT", "GET", "HEAD"] {
+            let request = requests.iter().find(|request| request.method == method).unwrap();
+            assert_eq!(request.header("x-amz-server-side-encryption-customer-algorithm"), Some("AES256"), "{method}");
+            assert_eq!(request.header("x-amz-server-side-encryption-customer-key"), Some(customer_key), "{method}");
+            assert_eq!(
+                request.header("x-amz-server-side-encryption-customer-key-md5"),
+                Some("tP/LI3N87DFaSk0aoqYgzg=="),
+                "{method}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_s3_timeout_is_restore_miss() {
+        let s3 = MockServer::start(|_| {
+            std::thread::sleep(std::time::Duration::from_secs(3));
+            MockResponse::new(200, "")
+        });
+        let env = Env::new_for_test(
+            Some(s3.address().to_string()),
+            Some("access".to_string()),
+            Some("secret".to_string()),
+            None,
+            None,
+            None,
+            None,
+            true,
+            None,
+            None,
+        )
+        .with_retry_for_test(Some(1), None, Some(1), None, true);
+        let client = build_s3_client(&env, &CiEnv::default()).await.unwrap();
+        let storage = S3Storage::new(client, "b", SseConfig::None);
+
+        let error = storage.get(OBJECT_KEY, &mut Vec::new()).await.unwrap_err();
+        assert!(matches!(error, StorageError::Timeout { .. }), "{error:?}");
+        assert!(is_restore_miss(&env, &error));
+        let error = storage.head(OBJECT_KEY).await.unwrap_err();
+        assert!(matches!(error, StorageError::Timeout { .. }), "{error:?}");
+
+        // 設定が無効な場合・タイムアウト以外の失敗はキャッシュミスとして扱わない
+        assert!(!is_restore_miss(&Env::default(), &error));
+        let error = StorageError::Get {
+            object_key: OBJECT_KEY.to_string(),
+            message: "AccessDenied".to_string(),
+        };
+        assert!(!is_restore_miss(&env, &error));
+    }
+
+    /// 最終アクセス日時の記録に使うS3（CopyObjectには`copy_status`を返す）
+    fn access_s3(last_accessed: &'static str, copy_status: u16) -> MockServer {
+        MockServer::start(move |request| {
+            if request.method == "HEAD" {
+                return MockResponse::new(200, "")
+                    .header("Last-Modified", "Thu, 01 Oct 2026 00:00:00 GMT")
+                    .header("ETag", "\"etag1\"")
+                    .header("Content-Type", "application/zstd")
+                    .header("x-amz-meta-cafce-pipeline-id", "42")
+                    .header("x-amz-meta-cafce-last-accessed", last_accessed);
+            }
+            if copy_status != 200 {
+                return MockResponse::new(copy_status, "<Error><Code>PreconditionFailed</Code></Error>");
+            }
+            MockResponse::new(
+                200,
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?><CopyObjectResult><ETag>\"etag2\"</ETag>\
+                 <LastModified>2026-10-18T00:00:00.000Z</LastModified></CopyObjectResult>",
+            )
+        })
+    }
+
+    #[tokio::test]
+    async fn test_s3_record_access() {
+        let s3 = access_s3("2026-10-10T00:00:00Z", 200);
+        let storage = s3_storage(&s3).await;
+        let recorded = storage
+            .record_access("group/app/node_modules/v1/deps+1.tar.zst", now())
+            .await
+            .unwrap();
+        assert!(recorded);
+
+        let copy = s3.requests().into_iter().find(|request| request.method == "PUT").unwrap();
+        assert_eq!(copy.path, "/b/group/app/node_modules/v1/deps%2B1.tar.zst?x-id=CopyObject");
+        assert_eq!(
+            copy.header("x-amz-copy-source"),
+            Some("b/group/app/node_modules/v1/deps%2B1.tar.zst")
+        );
+        assert_eq!(copy.header("x-amz-copy-source-if-match"), Some("\"etag1\""));
+        assert_eq!(copy.header("x-amz-metadata-directive"), Some("REPLACE"));
+        // 既存のメタデータ・Content-Typeを引き継ぐ
+        assert_eq!(copy.header("x-amz-meta-cafce-pipeline-id"), Some("42"));
+        assert_eq!(copy.header("content-type"), Some("application/zstd"));
+        assert_eq!(copy.header("x-amz-meta-cafce-last-accessed"), Some("2026-10-18T00:00:00Z"));
+    }
+
+    #[tokio::test]
+    async fn test_s3_record_access_at_most_once_a_day() {
+        let s3 = access_s3("2026-10-17T12:00:00Z", 200);
+        let storage = s3_storage(&s3).await;
+        let recorded = storage.record_access(OBJECT_KEY, now()).await.unwrap();
+        assert!(!recorded);
+        assert!(s3.requests().iter().all(|request| request.method == "HEAD"));
+    }
+
+    #[tokio::test]
+    async fn test_s3_record_access_skips_replaced_object() {
+        // HeadObjectの後にstoreで置き換えられた場合（ETag不一致）は記録しない
+        let s3 = access_s3("2026-10-10T00:00:00Z", 412);
+        let storage = s3_storage(&s3).await;
+        let recorded = storage.record_access(OBJECT_KEY, now()).await.unwrap();
+        assert!(!recorded);
+    }
+
+    #[test]
+    fn test_needs_access_record() {
+        let time = |secs: i64| Some(DateTime::from_secs(secs));
+        let day = ACCESS_RECORD_INTERVAL_SECS;
+        assert!(needs_access_record(None, None, now()));
+        assert!(needs_access_record(time(now() - day), None, now()));
+        assert!(!needs_access_record(time(now() - day + 1), None, now()));
+        // 更新日時が古くても、最終アクセス日時が新しければ記録しない
+        assert!(!needs_access_record(time(now() - 10 * day), time(now() - 60), now()));
+    }
+}
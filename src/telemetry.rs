@@ -0,0 +1,336 @@
+//! トレース（OTLP）・メトリクス（Prometheus Pushgateway）の送信
+//!
+//! 多数のジョブのヒット率・転送時間を集計するため、以下を任意で送信する。
+//! - `tracing`のスパン（キャッシュキーの計算・ハッシュ計算・S3の呼び出し等）を、
+//!   OTLP/HTTPでコレクターへ送信する（CAFCE_OTLP_ENDPOINT、`otlp`フィーチャーでビルドした場合のみ）
+//! - store/restoreの終了時に、実行結果のメトリクスをPushgatewayへ送信する（CAFCE_PUSHGATEWAY_URL）
+//!
+//! 送信の失敗はキャッシュ操作の結果に影響させない（呼び出し元で警告のみとする）。
+
+use crate::env::{CiEnv, Env};
+use crate::http_client::{HttpClientError, PlainHttpClient};
+use crate::i18n::tr;
+use crate::report::CacheReport;
+use base64::Engine;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// トレース・メトリクスの送信のタイムアウト
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// トレース・メトリクス送信時のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum TelemetryError {
+    #[error(transparent)]
+    HttpClient(#[from] HttpClientError),
+    #[error("{}", tr!("送信に失敗しました: {url}: {message}", "failed to send: {url}: {message}"))]
+    Send { url: String, message: String },
+    #[error("{}", tr!("送信先がエラーを返しました: {url}: HTTP {status}", "the endpoint returned an error: {url}: HTTP {status}"))]
+    Status { url: String, status: u16 },
+    #[error("{}", tr!("非同期ランタイムの起動に失敗しました", "failed to start the async runtime"))]
+    Runtime(#[source] std::io::Error),
+    #[error("{}", tr!("トレースの送信に失敗しました: {}", "failed to export traces: {}", .0))]
+    Trace(String),
+    #[error("{}", tr!("CAFCE_OTLP_ENDPOINTを使用するには、`otlp`フィーチャーを有効にしてビルドしてください", "CAFCE_OTLP_ENDPOINT requires a build with the `otlp` feature"))]
+    OtlpDisabled,
+}
+
+/// 記録中のトレース（`export_traces`でコレクターへ送信する）
+#[derive(Debug)]
+pub struct Tracing {
+    #[cfg(feature = "otlp")]
+    provider: opentelemetry_sdk::trace::SdkTracerProvider,
+}
+
+/// CAFCE_OTLP_ENDPOINTが指定されている場合、スパンの記録を開始する
+///
+/// 1プロセス（1回のコマンド実行）を1つのトレースとし、AWS SDKのスパン（S3・STSの呼び出し）も
+/// DEBUGレベルまで記録する。プロセス全体のSubscriberとして登録するため、1プロセスで1回のみ呼び出す。
+#[cfg(feature = "otlp")]
+pub fn install_tracing(env: &Env, ci: &CiEnv) -> Result<Option<Tracing>, TelemetryError> {
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let Some(endpoint) = env.otlp_endpoint() else {
+        return Ok(None);
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .with_timeout(SEND_TIMEOUT)
+        .build()
+        .map_err(|e| TelemetryError::Trace(e.to_string()))?;
+    let mut attributes = vec![KeyValue::new("service.version", env!("CARGO_PKG_VERSION"))];
+    if let Some(project) = ci.project_path() {
+        attributes.push(KeyValue::new("gitlab.project.path", project.to_string()));
+    }
+    if let Some(pipeline_id) = ci.pipeline_id() {
+        attributes.push(KeyValue::new("gitlab.pipeline.id", pipeline_id.to_string()));
+    }
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name("cafce")
+        .with_attributes(attributes)
+        .build();
+    // スパンの終了時ではなく別スレッドで送信する（非同期ランタイムの中で送信を待たない）
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("cafce")))
+        .with(tracing_subscriber::filter::LevelFilter::DEBUG);
+    tracing::subscriber::set_global_default(subscriber).map_err(|e| TelemetryError::Trace(e.to_string()))?;
+    Ok(Some(Tracing { provider }))
+}
+
+/// `otlp`フィーチャーなしでビルドした場合、CAFCE_OTLP_ENDPOINTの指定はエラーとする
+#[cfg(not(feature = "otlp"))]
+pub fn install_tracing(env: &Env, _ci: &CiEnv) -> Result<Option<Tracing>, TelemetryError> {
+    match env.otlp_endpoint() {
+        Some(_) => Err(TelemetryError::OtlpDisabled),
+        None => Ok(None),
+    }
+}
+
+/// 記録したスパンをコレクターへ送信し、記録を終了する
+#[cfg(feature = "otlp")]
+pub fn export_traces(traces: Tracing) -> Result<(), TelemetryError> {
+    let flushed = traces.provider.force_flush();
+    let shutdown = traces.provider.shutdown();
+    flushed.and(shutdown).map_err(|e| TelemetryError::Trace(e.to_string()))
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn export_traces(_traces: Tracing) -> Result<(), TelemetryError> {
+    Ok(())
+}
+
+/// store/restoreの実行結果をPrometheusのテキスト形式のメトリクスにする
+///
+/// グルーピングキー（job・command・cache・project）はPushgatewayがラベルとして付与するため、
+/// メトリクスには含めない。
+pub fn metrics_text(report: &CacheReport, now: SystemTime) -> String {
+    let mut text = String::new();
+    let mut gauge = |name: &str, help: &str, samples: &[(String, f64)]| {
+        if samples.is_empty() {
+            return;
+        }
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} gauge");
+        for (labels, value) in samples {
+            let _ = writeln!(text, "{name}{labels} {value}");
+        }
+    };
+    let sample = |value: Option<f64>| value.map(|value| vec![(String::new(), value)]).unwrap_or_default();
+
+    gauge(
+        "cafce_cache_hit",
+        "Whether restore found a cache (1) or not (0).",
+        &sample(report.hit.map(|hit| if hit { 1.0 } else { 0.0 })),
+    );
+    gauge(
+        "cafce_bytes_transferred",
+        "Bytes transferred to or from the bucket.",
+        &sample(report.bytes_transferred.map(|bytes| bytes as f64)),
+    );
+    gauge(
+        "cafce_compressed_size_bytes",
+        "Size of the cache object.",
+        &sample(report.compressed_size.map(|bytes| bytes as f64)),
+    );
+    gauge(
+        "cafce_uncompressed_size_bytes",
+        "Total size of the cached files.",
+        &sample(report.uncompressed_size.map(|bytes| bytes as f64)),
+    );
+    let durations: Vec<(String, f64)> = report
+        .durations_ms
+        .iter()
+        .map(|(phase, duration)| (format!("{{phase=\"{phase}\"}}"), duration.as_secs_f64()))
+        .collect();
+    gauge(
+        "cafce_phase_duration_seconds",
+        "Duration of each phase of the last run.",
+        &durations,
+    );
+    gauge(
+        "cafce_errors",
+        "Number of errors in the last run, including ones ignored by on_error.",
+        &[(String::new(), report.errors.len() as f64)],
+    );
+    let timestamp = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    gauge(
+        "cafce_last_run_timestamp_seconds",
+        "Unix time of the last run.",
+        &[(String::new(), timestamp as f64)],
+    );
+    text
+}
+
+/// Pushgatewayのグルーピングキーのパス（`/metrics/job/{job}/command/{command}/...`）
+///
+/// 値に`/`を含む可能性があるため、ラベルの値はURLセーフなBase64（`@base64`）で表す。
+fn grouping_path(job: &str, labels: &[(&str, &str)]) -> String {
+    let encode = |value: &str| {
+        if value.is_empty() {
+            // 空文字は"="で表す（Pushgatewayの仕様）
+            "=".to_string()
+        } else {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(value)
+        }
+    };
+    let mut path = format!("/metrics/job@base64/{}", encode(job));
+    for (name, value) in labels {
+        let _ = write!(path, "/{name}@base64/{}", encode(value));
+    }
+    path
+}
+
+/// store/restoreの実行結果のメトリクスをPushgatewayへ送信する
+///
+/// 同じグルーピングキーのメトリクスは置き換える（PUT）。
+pub fn push_metrics(env: &Env, ci: &CiEnv, report: &CacheReport) -> Result<(), TelemetryError> {
+    let Some(base_url) = env.pushgateway_url() else {
+        return Ok(());
+    };
+    let mut labels = vec![("command", report.command)];
+    if let Some(cache_name) = &report.cache_name {
+        labels.push(("cache", cache_name));
+    }
+    if let Some(project) = ci.project_path() {
+        labels.push(("project", project));
+    }
+    let url = format!(
+        "{}{}",
+        base_url.trim_end_matches('/'),
+        grouping_path(env.pushgateway_job(), &labels)
+    );
+    send(
+        env,
+        http::Method::PUT,
+        &url,
+        "text/plain; version=0.0.4",
+        metrics_text(report, SystemTime::now()),
+    )
+}
+
+fn send(
+    env: &Env,
+    method: http::Method,
+    url: &str,
+    content_type: &str,
+    body: String,
+) -> Result<(), TelemetryError> {
+    let client = PlainHttpClient::new(env, SEND_TIMEOUT)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(TelemetryError::Runtime)?;
+    let status = runtime
+        .block_on(client.send(method, url, content_type, body.into_bytes()))
+        .map_err(|e| TelemetryError::Send {
+            url: url.to_string(),
+            message: e.to_string(),
+        })?;
+    if !status.is_success() {
+        return Err(TelemetryError::Status {
+            url: url.to_string(),
+            status: status.as_u16(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer};
+
+    #[test]
+    fn test_metrics_text() {
+        let mut report = CacheReport::new("restore");
+        report.key = Some("deps".to_string());
+        report.set_matched_key(Some("deps"));
+        report.bytes_transferred = Some(2048);
+        report.durations_ms.record("key", Duration::from_millis(250));
+        let text = metrics_text(&report, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+
+        assert!(text.contains("# TYPE cafce_cache_hit gauge\ncafce_cache_hit 1\n"), "{text}");
+        assert!(text.contains("cafce_bytes_transferred 2048\n"), "{text}");
+        assert!(text.contains("cafce_phase_duration_seconds{phase=\"key\"} 0.25\n"), "{text}");
+        assert!(text.contains("cafce_errors 0\n"), "{text}");
+        assert!(text.contains("cafce_last_run_timestamp_seconds 1700000000\n"), "{text}");
+        // 値がないメトリクスは出力しない
+        assert!(!text.contains("cafce_compressed_size_bytes"), "{text}");
+    }
+
+    #[test]
+    fn test_grouping_path() {
+        assert_eq!(
+            grouping_path("cafce", &[("command", "store"), ("project", "group/app"), ("cache", "")]),
+            "/metrics/job@base64/Y2FmY2U/command@base64/c3RvcmU/project@base64/Z3JvdXAvYXBw/cache@base64/="
+        );
+    }
+
+    #[test]
+    fn test_push_metrics() {
+        let pushgateway = MockServer::start(|_| MockResponse::new(200, ""));
+        let env = Env::default().with_telemetry_for_test(None, Some(&pushgateway.url()), Some("ci"));
+        let ci = CiEnv::default().with_pipeline_for_test("group/app", "42");
+        let mut report = CacheReport::new("store");
+        report.cache_name = Some("node_modules".to_string());
+
+        push_metrics(&env, &ci, &report).unwrap();
+        let requests = pushgateway.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(
+            requests[0].path,
+            grouping_path(
+                "ci",
+                &[("command", "store"), ("cache", "node_modules"), ("project", "group/app")]
+            )
+        );
+        assert!(requests[0].body_str().contains("cafce_errors 0\n"));
+    }
+
+    #[test]
+    fn test_disabled_without_endpoints() {
+        assert!(push_metrics(&Env::default(), &CiEnv::default(), &CacheReport::new("store")).is_ok());
+        assert!(install_tracing(&Env::default(), &CiEnv::default()).unwrap().is_none());
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    #[test]
+    fn test_otlp_requires_feature() {
+        let env = Env::default().with_telemetry_for_test(Some("http://localhost:4318"), None, None);
+        let result = install_tracing(&env, &CiEnv::default());
+        assert!(matches!(result, Err(TelemetryError::OtlpDisabled)), "{result:?}");
+    }
+
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn test_export_traces() {
+        let collector = MockServer::start(|_| MockResponse::new(200, ""));
+        let env = Env::default().with_telemetry_for_test(Some(&format!("{}/", collector.url())), None, None);
+        let ci = CiEnv::default().with_pipeline_for_test("group/app", "42");
+
+        let traces = install_tracing(&env, &ci).unwrap().unwrap();
+        {
+            let _span = tracing::info_span!("cafce.run").entered();
+        }
+        export_traces(traces).unwrap();
+
+        let requests = collector.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/v1/traces");
+        assert_eq!(requests[0].header("content-type"), Some("application/x-protobuf"));
+        // protobufの文字列はそのままのバイト列で含まれる
+        let body = String::from_utf8_lossy(&requests[0].body).into_owned();
+        assert!(body.contains("cafce.run"), "{body:?}");
+        assert!(body.contains("group/app"), "{body:?}");
+    }
+}
@@ -0,0 +1,179 @@
+//! テスト用のユーティリティ
+//!
+//! 外部サービス（STS等）の代わりに、テストプロセス内で起動する簡易HTTPサーバーを提供する。
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+/// 受信したHTTPリクエスト
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    /// ヘッダーの値を取得する（名前の大文字・小文字は区別しない）
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn body_str(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// 返却するHTTPレスポンス
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&RecordedRequest) -> MockResponse + Send + Sync;
+
+/// テストプロセス内で起動する簡易HTTPサーバー
+///
+/// 受信したリクエストは全て記録され、`requests()`で取得できる。
+/// サーバースレッドはテストプロセスの終了まで動き続ける。
+pub struct MockServer {
+    address: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockServer {
+    /// リクエストごとに`handler`でレスポンスを組み立てるサーバーを起動する
+    pub fn start(handler: impl Fn(&RecordedRequest) -> MockResponse + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind mock server");
+        let address = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let recorded = Arc::clone(&recorded);
+                let handler = Arc::clone(&handler);
+                std::thread::spawn(move || {
+                    let _ = Self::serve_connection(stream, &*handler, &recorded);
+                });
+            }
+        });
+
+        Self { address, requests }
+    }
+
+    fn serve_connection(
+        stream: std::net::TcpStream,
+        handler: &Handler,
+        recorded: &Mutex<Vec<RecordedRequest>>,
+    ) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        loop {
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line)? == 0 {
+                return Ok(());
+            }
+            let mut parts = request_line.split_whitespace();
+            let method = parts.next().unwrap_or_default().to_string();
+            let path = parts.next().unwrap_or_default().to_string();
+
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    headers.push((name.trim().to_string(), value.trim().to_string()));
+                }
+            }
+
+            let mut request = RecordedRequest {
+                method,
+                path,
+                headers,
+                body: Vec::new(),
+            };
+            if request
+                .header("transfer-encoding")
+                .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+            {
+                request.body = Self::read_chunked_body(&mut reader)?;
+            } else if let Some(length) = request.header("content-length") {
+                let length: usize = length.parse().unwrap_or(0);
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body)?;
+                request.body = body;
+            }
+
+            let response = handler(&request);
+            recorded.lock().unwrap().push(request);
+
+            write!(writer, "HTTP/1.1 {} Mock\r\n", response.status)?;
+            for (name, value) in &response.headers {
+                write!(writer, "{name}: {value}\r\n")?;
+            }
+            write!(writer, "Content-Length: {}\r\n\r\n", response.body.len())?;
+            writer.write_all(&response.body)?;
+            writer.flush()?;
+        }
+    }
+
+    fn read_chunked_body(reader: &mut impl BufRead) -> std::io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        loop {
+            let mut size_line = String::new();
+            reader.read_line(&mut size_line)?;
+            let size_hex = size_line.trim().split(';').next().unwrap_or("0");
+            let size = usize::from_str_radix(size_hex, 16).unwrap_or(0);
+            let mut chunk = vec![0u8; size + 2];
+            reader.read_exact(&mut chunk)?;
+            if size == 0 {
+                return Ok(body);
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+
+    /// `host:port`形式のアドレス
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// `http://host:port`形式のURL
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// これまでに受信したリクエスト
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
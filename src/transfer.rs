@@ -0,0 +1,641 @@
+//! store / restoreでのキャッシュの転送
+//!
+//! storeは設定ファイルの`paths`に一致するファイルをtarにまとめて圧縮し、一時ファイルに書き出してから
+//! `Storage::put`で保存する。restoreはプライマリキー、fallback_keysの順に`Storage::get`で一時ファイルに
+//! ダウンロードし、最初に見つかったキャッシュをカレントディレクトリに展開する。
+//! どちらも保存先の種類（S3・ディレクトリ・HTTP・GitLab）には依存しない。
+//!
+//! CAFCE_ENCRYPTION_KEYが設定されている場合、storeは圧縮後のアーカイブを暗号化し、
+//! 鍵IDを`cafce-encryption-key-id`メタデータに記録する。restoreはこのメタデータで鍵を選んで復号する。
+
+use crate::encryption::{EncryptWriter, EncryptionKey, Keyring, KEY_ID_METADATA};
+use crate::i18n::tr;
+use crate::object_key::Codec;
+use crate::storage::{self, ObjectMetadata, Storage, StorageError};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tracing::Instrument;
+
+/// キャッシュの転送時のエラー
+#[derive(Debug, thiserror::Error)]
+pub enum TransferError {
+    #[error("{}", tr!("pathsには作業ディレクトリ内の相対パスを指定してください: {pattern}", "paths must be relative paths inside the working directory: {pattern}"))]
+    InvalidPath { pattern: String },
+    #[error("{}", tr!("pathsのパターンが不正です: {pattern}", "invalid pattern in paths: {pattern}"))]
+    InvalidPattern { pattern: String },
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error("{}", tr!("キャッシュアーカイブの作成に失敗しました: {}", "failed to create the cache archive: {}", .path.display()))]
+    Create {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}", tr!("キャッシュアーカイブの展開に失敗しました: {object_key}", "failed to extract the cache archive: {object_key}"))]
+    Extract {
+        object_key: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl TransferError {
+    /// 設定の誤りか（保存先との通信・アーカイブの読み書きの失敗ではないか）
+    pub fn is_config_error(&self) -> bool {
+        match self {
+            Self::InvalidPath { .. } | Self::InvalidPattern { .. } => true,
+            Self::Storage(error) => error.is_config_error(),
+            Self::Create { .. } | Self::Extract { .. } => false,
+        }
+    }
+
+    /// 復号の失敗（鍵の誤り・未設定、またはデータの改ざん・欠損・平文への差し替え）か
+    pub fn is_integrity_error(&self) -> bool {
+        match self {
+            Self::Extract { source, .. } => source
+                .get_ref()
+                .is_some_and(|error| error.is::<crate::encryption::EncryptionError>()),
+            _ => false,
+        }
+    }
+}
+
+/// storeの結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreOutcome {
+    /// 保存したオブジェクトのETag
+    pub etag: Option<String>,
+    /// 圧縮後（オブジェクト）のサイズ
+    pub compressed_size: u64,
+    /// アーカイブに含めた通常ファイルのサイズの合計
+    pub uncompressed_size: u64,
+}
+
+/// restoreで復元したキャッシュ
+#[derive(Debug)]
+pub struct RestoredCache {
+    /// 見つかったキャッシュキー
+    pub key: String,
+    pub object_key: String,
+    pub object: ObjectMetadata,
+    /// 展開した通常ファイルのサイズの合計
+    pub uncompressed_size: u64,
+    /// 最終アクセス日時の記録に失敗した場合のエラー
+    ///
+    /// 記録に失敗しても復元したキャッシュは使えるため、restoreは失敗させずに警告とする。
+    pub access_error: Option<StorageError>,
+}
+
+/// 処理の終了時に削除する一時ファイル
+struct TemporaryFile(PathBuf);
+
+impl TemporaryFile {
+    fn create() -> Result<(Self, File), std::io::Error> {
+        let path = std::env::temp_dir().join(format!("cafce-{}.tmp", storage::temporary_suffix()));
+        let file = File::create(&path)?;
+        Ok((Self(path), file))
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// `paths`に一致するファイルをアーカイブにまとめ、`object_key`として保存する
+///
+/// # Arguments
+/// * `base_path` - `paths`の基準とするディレクトリ（アーカイブ内のパスはここからの相対パス）
+/// * `metadata` - オブジェクトに記録するメタデータ（`cafce-cache-name`等）
+/// * `keyring` - 現行の鍵が設定されていれば、その鍵でアーカイブを暗号化する
+pub async fn store(
+    storage: &dyn Storage,
+    object_key: &str,
+    paths: &[String],
+    base_path: &Path,
+    codec: Codec,
+    metadata: &HashMap<String, String>,
+    keyring: &Keyring,
+) -> Result<StoreOutcome, TransferError> {
+    let create_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |source| TransferError::Create { path, source }
+    };
+    let (temporary, file) = TemporaryFile::create().map_err(create_error(&std::env::temp_dir()))?;
+    let uncompressed_size = {
+        let _span = tracing::info_span!("cafce.archive", object_key).entered();
+        let entries = resolve_paths(paths, base_path)?;
+        create_archive(&entries, base_path, codec, keyring.active_key(), file).map_err(create_error(&temporary.0))?
+    };
+    let mut metadata = metadata.clone();
+    if let Some((key_id, _)) = keyring.active_key() {
+        metadata.insert(KEY_ID_METADATA.to_string(), key_id.to_string());
+    }
+    let compressed_size = std::fs::metadata(&temporary.0)
+        .map_err(create_error(&temporary.0))?
+        .len();
+    let etag = storage
+        .put(object_key, &temporary.0, &metadata)
+        .instrument(tracing::info_span!("cafce.upload", object_key, bytes = compressed_size))
+        .await?;
+    Ok(StoreOutcome {
+        etag,
+        compressed_size,
+        uncompressed_size,
+    })
+}
+
+/// `candidates`（キャッシュキーとオブジェクトキーの組）を順に探し、最初に見つかったキャッシュを展開する
+///
+/// どのキーでも見つからない場合（キャッシュミス）はNoneを返す。
+/// 見つかった場合は、list・pruneのために最終アクセス日時（`now`、UNIX時間）を記録する。
+/// 暗号化されたキャッシュは、ストリームヘッダーの鍵IDに対応する`keyring`の鍵で復号する。
+/// 現行の鍵が設定されている場合、暗号化されていないキャッシュは完全性のエラーとする。
+pub async fn restore(
+    storage: &dyn Storage,
+    candidates: &[(String, String)],
+    codec: Codec,
+    base_path: &Path,
+    keyring: &Keyring,
+    now: i64,
+) -> Result<Option<RestoredCache>, TransferError> {
+    for (key, object_key) in candidates {
+        let (temporary, file) = TemporaryFile::create().map_err(|source| TransferError::Create {
+            path: std::env::temp_dir(),
+            source,
+        })?;
+        let mut writer = BufWriter::new(file);
+        let object = storage
+            .get(object_key, &mut writer)
+            .instrument(tracing::info_span!("cafce.download", object_key))
+            .await?;
+        let Some(object) = object else {
+            continue;
+        };
+        let extract_error = |source| TransferError::Extract {
+            object_key: object_key.clone(),
+            source,
+        };
+        writer.into_inner().map_err(|e| extract_error(e.into_error()))?;
+        let uncompressed_size = {
+            let _span = tracing::info_span!("cafce.extract", object_key).entered();
+            let file = File::open(&temporary.0).map_err(extract_error)?;
+            extract_archive(BufReader::new(file), codec, keyring, base_path).map_err(extract_error)?
+        };
+        let access_error = storage.record_access(object_key, now).await.err();
+        return Ok(Some(RestoredCache {
+            key: key.clone(),
+            object_key: object_key.clone(),
+            object,
+            uncompressed_size,
+            access_error,
+        }));
+    }
+    Ok(None)
+}
+
+/// `paths`のパターン（globパターン可）に一致するファイル・ディレクトリを列挙する
+///
+/// `base_path`の外を指すパターン（絶対パス・`..`を含むパス）はエラーとする。
+/// 一致したディレクトリの中のものは、ディレクトリとともにアーカイブに含めるため除外する。
+fn resolve_paths(patterns: &[String], base_path: &Path) -> Result<Vec<PathBuf>, TransferError> {
+    let escaped_base_path = glob::Pattern::escape(&base_path.to_string_lossy());
+    let mut matched = Vec::new();
+    for pattern in patterns {
+        let is_outside = Path::new(pattern)
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+        if is_outside {
+            return Err(TransferError::InvalidPath {
+                pattern: pattern.clone(),
+            });
+        }
+        let full_pattern = Path::new(&escaped_base_path).join(pattern);
+        let paths = glob::glob(&full_pattern.to_string_lossy()).map_err(|_| TransferError::InvalidPattern {
+            pattern: pattern.clone(),
+        })?;
+        matched.extend(paths.filter_map(Result::ok).filter(|path| path.starts_with(base_path)));
+    }
+    matched.sort();
+    matched.dedup();
+    let mut entries: Vec<PathBuf> = Vec::new();
+    for path in matched {
+        if !entries.iter().any(|entry| path.starts_with(entry)) {
+            entries.push(path);
+        }
+    }
+    Ok(entries)
+}
+
+/// `entries`をtarにまとめ、`codec`で圧縮して（`key`（鍵IDと鍵）があれば暗号化して）`destination`に書き出す
+///
+/// アーカイブに含めた通常ファイルのサイズの合計を返す。
+fn create_archive(
+    entries: &[PathBuf],
+    base_path: &Path,
+    codec: Codec,
+    key: Option<(&str, &EncryptionKey)>,
+    destination: File,
+) -> std::io::Result<u64> {
+    let output = BufWriter::new(destination);
+    let (uncompressed_size, output) = match key {
+        Some((key_id, key)) => {
+            let (size, writer) = compress_archive(entries, base_path, codec, EncryptWriter::new(output, key_id, key)?)?;
+            (size, writer.finish()?)
+        }
+        None => compress_archive(entries, base_path, codec, output)?,
+    };
+    output.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(uncompressed_size)
+}
+
+/// `entries`をtarにまとめて`codec`で圧縮し、通常ファイルのサイズの合計と`output`を返す
+fn compress_archive<W: Write>(
+    entries: &[PathBuf],
+    base_path: &Path,
+    codec: Codec,
+    output: W,
+) -> std::io::Result<(u64, W)> {
+    Ok(match codec {
+        Codec::Zstd => {
+            let mut builder = tar::Builder::new(zstd::stream::write::Encoder::new(output, 0)?);
+            let size = append_entries(&mut builder, entries, base_path)?;
+            (size, builder.into_inner()?.finish()?)
+        }
+        Codec::Gzip => {
+            let encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            let mut builder = tar::Builder::new(encoder);
+            let size = append_entries(&mut builder, entries, base_path)?;
+            (size, builder.into_inner()?.finish()?)
+        }
+        Codec::None => {
+            let mut builder = tar::Builder::new(output);
+            let size = append_entries(&mut builder, entries, base_path)?;
+            (size, builder.into_inner()?)
+        }
+    })
+}
+
+/// `entries`をtarに追加し、追加した通常ファイルのサイズの合計を返す
+///
+/// ディレクトリは中身ごと（名前順に）追加し、シンボリックリンクはリンクのまま追加する。
+fn append_entries<W: Write>(
+    builder: &mut tar::Builder<W>,
+    entries: &[PathBuf],
+    base_path: &Path,
+) -> std::io::Result<u64> {
+    builder.follow_symlinks(false);
+    let mut uncompressed_size = 0;
+    let mut pending: Vec<PathBuf> = entries.iter().rev().cloned().collect();
+    while let Some(path) = pending.pop() {
+        let relative = path.strip_prefix(base_path).unwrap_or(&path);
+        let file_metadata = std::fs::symlink_metadata(&path)?;
+        builder.append_path_with_name(&path, relative)?;
+        if file_metadata.is_file() {
+            uncompressed_size += file_metadata.len();
+        } else if file_metadata.is_dir() {
+            let mut children = std::fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            children.sort();
+            pending.extend(children.into_iter().rev());
+        }
+    }
+    builder.finish()?;
+    Ok(uncompressed_size)
+}
+
+/// 圧縮・暗号化されたアーカイブを読み出すReaderを組み立てる
+///
+/// # Arguments
+/// * `keyring` - 暗号化されている場合の復号鍵（ストリームヘッダーの鍵IDで選ぶ）
+pub fn decode_archive<'a, R: Read + 'a>(
+    reader: R,
+    codec: Codec,
+    keyring: &Keyring,
+) -> std::io::Result<Box<dyn Read + 'a>> {
+    let reader = crate::encryption::decrypt_stream(reader, keyring)?;
+    Ok(match codec {
+        Codec::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        Codec::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+        Codec::None => reader,
+    })
+}
+
+/// アーカイブを`destination`に展開し、展開した通常ファイルのサイズの合計を返す
+///
+/// `destination`の外を指すエントリ（`..`を含むパス等）は展開しない。
+fn extract_archive<R: Read>(
+    reader: R,
+    codec: Codec,
+    keyring: &Keyring,
+    destination: &Path,
+) -> std::io::Result<u64> {
+    let mut archive = tar::Archive::new(decode_archive(reader, codec, keyring)?);
+    archive.set_preserve_mtime(true);
+    archive.set_overwrite(true);
+    let mut uncompressed_size = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() {
+            uncompressed_size += entry.size();
+        }
+        entry.unpack_in(destination)?;
+    }
+    Ok(uncompressed_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FileStorage;
+
+    const OBJECT_KEY: &str = "group/app/node_modules/v1/deps-protected.tar.zst";
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    fn now() -> i64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+
+    fn workspace() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("node_modules/pkg")).unwrap();
+        std::fs::write(dir.path().join("node_modules/pkg/index.js"), b"hello").unwrap();
+        std::fs::write(dir.path().join("node_modules/.keep"), b"").unwrap();
+        std::fs::write(dir.path().join("build.log"), b"log").unwrap();
+        dir
+    }
+
+    fn candidates(keys: &[&str]) -> Vec<(String, String)> {
+        keys.iter()
+            .map(|key| (key.to_string(), format!("group/app/node_modules/v1/{key}.tar.zst")))
+            .collect()
+    }
+
+    #[test]
+    fn test_store_and_restore() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(remote.path().to_path_buf());
+        let source = workspace();
+        let metadata = HashMap::from([("cafce-cache-name".to_string(), "node_modules".to_string())]);
+
+        runtime().block_on(async {
+            for codec in Codec::ALL {
+                let paths = ["node_modules".to_string(), "*.log".to_string()];
+                let stored = store(&storage, OBJECT_KEY, &paths, source.path(), codec, &metadata, &Keyring::default())
+                    .await
+                    .unwrap();
+                assert_eq!(stored.uncompressed_size, 8, "{codec:?}");
+                assert_eq!(stored.compressed_size, std::fs::metadata(remote.path().join(OBJECT_KEY)).unwrap().len());
+                assert!(stored.etag.is_some());
+
+                let destination = tempfile::tempdir().unwrap();
+                let candidates = candidates(&["missing", "deps-protected"]);
+                let restored = restore(&storage, &candidates, codec, destination.path(), &Keyring::default(), now())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                // 見つからないキーは飛ばし、次のキーで復元する
+                assert_eq!(restored.key, "deps-protected");
+                assert_eq!(restored.object.metadata, metadata);
+                assert_eq!(restored.uncompressed_size, 8);
+                let restored_file = destination.path().join("node_modules/pkg/index.js");
+                assert_eq!(std::fs::read(restored_file).unwrap(), b"hello");
+                assert!(destination.path().join("node_modules/.keep").exists());
+                assert_eq!(std::fs::read(destination.path().join("build.log")).unwrap(), b"log");
+            }
+        });
+    }
+
+    #[test]
+    fn test_restore_records_access() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(remote.path().to_path_buf());
+        let source = workspace();
+        let paths = ["node_modules".to_string()];
+        let later = now() + 2 * storage::ACCESS_RECORD_INTERVAL_SECS;
+
+        runtime().block_on(async {
+            store(&storage, OBJECT_KEY, &paths, source.path(), Codec::Zstd, &HashMap::new(), &Keyring::default())
+                .await
+                .unwrap();
+            assert_eq!(storage.head(OBJECT_KEY).await.unwrap().unwrap().last_accessed(), None);
+
+            let destination = tempfile::tempdir().unwrap();
+            let restored = restore(&storage, &candidates(&["deps-protected"]), Codec::Zstd, destination.path(), &Keyring::default(), later)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(restored.access_error.is_none(), "{:?}", restored.access_error);
+            let object = storage.head(OBJECT_KEY).await.unwrap().unwrap();
+            assert_eq!(object.last_accessed().map(|time| time.secs()), Some(later));
+        });
+    }
+
+    #[test]
+    fn test_restore_miss() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(remote.path().to_path_buf());
+        let destination = tempfile::tempdir().unwrap();
+        let result = runtime().block_on(restore(
+            &storage,
+            &candidates(&["primary", "fallback"]),
+            Codec::Zstd,
+            destination.path(),
+            &Keyring::default(),
+            now(),
+        ));
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_restore_invalid_archive() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(remote.path().to_path_buf());
+        let source = remote.path().join("archive");
+        std::fs::write(&source, b"not an archive").unwrap();
+        let destination = tempfile::tempdir().unwrap();
+
+        runtime().block_on(async {
+            storage.put(OBJECT_KEY, &source, &HashMap::new()).await.unwrap();
+            let error = restore(&storage, &candidates(&["deps-protected"]), Codec::Zstd, destination.path(), &Keyring::default(), now())
+                .await
+                .unwrap_err();
+            assert!(matches!(error, TransferError::Extract { .. }), "{error:?}");
+            assert!(!error.is_config_error());
+            assert!(!error.is_integrity_error());
+        });
+    }
+
+    #[test]
+    fn test_store_and_restore_encrypted() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(remote.path().to_path_buf());
+        let source = workspace();
+        let paths = ["node_modules".to_string()];
+        let old_key = format!("k0:{}", "00".repeat(32));
+        let keyring = Keyring::parse(Some(&"11".repeat(32)), Some("k1"), Some(&old_key)).unwrap();
+        // 鍵をローテーションした後も、旧鍵で暗号化したキャッシュを復号できる
+        let rotated = Keyring::parse(Some(&"22".repeat(32)), Some("k2"), Some(&format!("k1:{}", "11".repeat(32)))).unwrap();
+
+        runtime().block_on(async {
+            store(&storage, OBJECT_KEY, &paths, source.path(), Codec::Zstd, &HashMap::new(), &keyring)
+                .await
+                .unwrap();
+            let object = storage.head(OBJECT_KEY).await.unwrap().unwrap();
+            assert_eq!(object.metadata.get(KEY_ID_METADATA).map(String::as_str), Some("k1"));
+            // 保存したオブジェクトは暗号化されており、鍵が無ければ読めない
+            let stored = std::fs::read(remote.path().join(OBJECT_KEY)).unwrap();
+            assert!(decode_archive(&stored[..], Codec::Zstd, &Keyring::default())
+                .and_then(|mut reader| std::io::copy(&mut reader, &mut std::io::sink()))
+                .is_err());
+
+            let destination = tempfile::tempdir().unwrap();
+            let candidates = candidates(&["deps-protected"]);
+            let restored = restore(&storage, &candidates, Codec::Zstd, destination.path(), &rotated, now())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(restored.uncompressed_size, 5);
+            let restored_file = destination.path().join("node_modules/pkg/index.js");
+            assert_eq!(std::fs::read(restored_file).unwrap(), b"hello");
+
+            // 鍵が設定されていなければ完全性のエラー
+            let error = restore(&storage, &candidates, Codec::Zstd, destination.path(), &Keyring::default(), now())
+                .await
+                .unwrap_err();
+            assert!(matches!(&error, TransferError::Extract { source, .. } if source.to_string().contains("k1")), "{error:?}");
+            assert!(error.is_integrity_error());
+        });
+    }
+
+    #[test]
+    fn test_restore_rejects_plaintext_when_key_configured() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(remote.path().to_path_buf());
+        let source = workspace();
+        let paths = ["node_modules".to_string()];
+        let keyring = Keyring::parse(Some(&"11".repeat(32)), Some("k1"), None).unwrap();
+
+        runtime().block_on(async {
+            // 保存先への書き込み権限を持つ第三者が、暗号化されていないキャッシュを置いた場合
+            store(&storage, OBJECT_KEY, &paths, source.path(), Codec::Zstd, &HashMap::new(), &Keyring::default())
+                .await
+                .unwrap();
+
+            let destination = tempfile::tempdir().unwrap();
+            let error = restore(&storage, &candidates(&["deps-protected"]), Codec::Zstd, destination.path(), &keyring, now())
+                .await
+                .unwrap_err();
+            assert!(matches!(error, TransferError::Extract { .. }), "{error:?}");
+            assert!(error.is_integrity_error());
+            assert!(!destination.path().join("node_modules").exists());
+        });
+    }
+
+    /// `get`を呼んだ回数を数える保存先
+    struct CountingStorage {
+        inner: FileStorage,
+        gets: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl Storage for CountingStorage {
+        fn head<'a>(&'a self, object_key: &'a str) -> storage::StorageFuture<'a, Option<ObjectMetadata>> {
+            self.inner.head(object_key)
+        }
+
+        fn get<'a>(
+            &'a self,
+            object_key: &'a str,
+            destination: &'a mut (dyn Write + Send),
+        ) -> storage::StorageFuture<'a, Option<ObjectMetadata>> {
+            self.gets.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.get(object_key, destination)
+        }
+
+        fn put<'a>(
+            &'a self,
+            object_key: &'a str,
+            source: &'a Path,
+            metadata: &'a HashMap<String, String>,
+        ) -> storage::StorageFuture<'a, Option<String>> {
+            self.inner.put(object_key, source, metadata)
+        }
+
+        fn delete<'a>(&'a self, object_key: &'a str) -> storage::StorageFuture<'a, ()> {
+            self.inner.delete(object_key)
+        }
+
+        fn record_access<'a>(&'a self, object_key: &'a str, now: i64) -> storage::StorageFuture<'a, bool> {
+            self.inner.record_access(object_key, now)
+        }
+    }
+
+    #[test]
+    fn test_restore_from_local_cache() {
+        use crate::local_cache::{LocalCache, LocalCachedStorage};
+        use std::sync::atomic::Ordering;
+
+        let dir = tempfile::tempdir().unwrap();
+        let gets = std::sync::Arc::default();
+        let local_cached = |local: &str| {
+            let remote = CountingStorage {
+                inner: FileStorage::new(dir.path().join("remote")),
+                gets: std::sync::Arc::clone(&gets),
+            };
+            LocalCachedStorage::new(Box::new(remote), LocalCache::new(dir.path().join(local), 1024 * 1024))
+        };
+        let source = workspace();
+        let candidates = candidates(&["deps-protected"]);
+        let paths = ["node_modules".to_string()];
+
+        runtime().block_on(async {
+            // storeしたランナーは、アップロードしたアーカイブをそのまま復元に使う
+            let storage = local_cached("runner1");
+            store(&storage, OBJECT_KEY, &paths, source.path(), Codec::Zstd, &HashMap::new(), &Keyring::default())
+                .await
+                .unwrap();
+            let destination = tempfile::tempdir().unwrap();
+            let restored = restore(&storage, &candidates, Codec::Zstd, destination.path(), &Keyring::default(), now()).await.unwrap();
+            assert!(restored.is_some());
+            assert_eq!(gets.load(Ordering::SeqCst), 0);
+
+            // 別のランナーは1回目のみダウンロードし、2回目はローカルキャッシュから復元する
+            let storage = local_cached("runner2");
+            for _ in 0..2 {
+                let destination = tempfile::tempdir().unwrap();
+                let restored = restore(&storage, &candidates, Codec::Zstd, destination.path(), &Keyring::default(), now()).await.unwrap();
+                assert_eq!(restored.unwrap().uncompressed_size, 5);
+                let restored_file = destination.path().join("node_modules/pkg/index.js");
+                assert_eq!(std::fs::read(restored_file).unwrap(), b"hello");
+            }
+            assert_eq!(gets.load(Ordering::SeqCst), 1);
+        });
+    }
+
+    #[test]
+    fn test_resolve_paths() {
+        let source = workspace();
+        let base = source.path();
+        let paths = ["node_modules".to_string(), "node_modules/pkg".to_string(), "*.md".to_string()];
+        // ディレクトリの中のものは重複して含めない・一致しないパターンは無視する
+        assert_eq!(resolve_paths(&paths, base).unwrap(), [base.join("node_modules")]);
+
+        for pattern in ["/etc", "../outside", "node_modules/../../outside"] {
+            let result = resolve_paths(&[pattern.to_string()], base);
+            assert!(matches!(result, Err(TransferError::InvalidPath { .. })), "{pattern}: {result:?}");
+        }
+    }
+}
@@ -81,4 +81,131 @@ mod tests {
         assert!(error_string.contains("60"));
         assert!(error_string.contains("50"));
     }
+    /// CAFCE_*・CI_*を引き継がないcafceのコマンド
+    fn cafce_command(workdir: &std::path::Path, args: &[&str]) -> std::process::Command {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_cafce"));
+        command
+            .args(args)
+            .current_dir(workdir)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("CAFCE_LANG", "en")
+            .env("CI_PROJECT_PATH", "group/app")
+            .env("CI_COMMIT_REF_PROTECTED", "true");
+        command
+    }
+
+    /// file://の保存先でcafceを実行する
+    fn cafce(workdir: &std::path::Path, storage: &std::path::Path, args: &[&str]) -> std::process::Output {
+        cafce_command(workdir, args)
+            .env("CAFCE_STORAGE", format!("file://{}", storage.display()))
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_restore_dotenv_after_store() {
+        let storage = tempfile::tempdir().unwrap();
+        let producer = tempfile::tempdir().unwrap();
+        let consumer = tempfile::tempdir().unwrap();
+        let config = "name = \"node_modules\"\npaths = [\"node_modules\"]\nkey = \"deps\"\nfallback_keys = []\n";
+        for dir in [producer.path(), consumer.path()] {
+            std::fs::write(dir.join("cafce.toml"), config).unwrap();
+        }
+        std::fs::create_dir_all(producer.path().join("node_modules/pkg")).unwrap();
+        std::fs::write(producer.path().join("node_modules/pkg/index.js"), "hello").unwrap();
+
+        // キャッシュミスでもdotenvを書き出し、終了コードは0
+        let output = cafce(consumer.path(), storage.path(), &["restore", "--config", "cafce.toml", "--dotenv", "cafce.env"]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let dotenv = std::fs::read_to_string(consumer.path().join("cafce.env")).unwrap();
+        assert!(dotenv.starts_with("CAFCE_CACHE_HIT=false\n"), "{dotenv}");
+
+        let output = cafce(producer.path(), storage.path(), &["store", "--config", "cafce.toml"]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let output = cafce(consumer.path(), storage.path(), &["restore", "--config", "cafce.toml", "--dotenv", "cafce.env"]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let dotenv = std::fs::read_to_string(consumer.path().join("cafce.env")).unwrap();
+        assert_eq!(
+            dotenv,
+            "CAFCE_CACHE_HIT=true\nCAFCE_CACHE_KEY=deps-protected\nCAFCE_MATCHED_KEY=deps-protected\n"
+        );
+        let restored = std::fs::read_to_string(consumer.path().join("node_modules/pkg/index.js")).unwrap();
+        assert_eq!(restored, "hello");
+    }
+
+    #[test]
+    fn test_dotenv_write_failure_is_a_warning() {
+        let storage = tempfile::tempdir().unwrap();
+        let workdir = tempfile::tempdir().unwrap();
+        let config = "paths = [\"node_modules\"]\nkey = \"deps\"\nfallback_keys = []\n";
+        std::fs::write(workdir.path().join("cafce.toml"), config).unwrap();
+        std::fs::create_dir(workdir.path().join("cafce.env")).unwrap();
+
+        // 書き込めないdotenvはジョブを失敗させない
+        let output = cafce(workdir.path(), storage.path(), &["restore", "--config", "cafce.toml", "--dotenv", "cafce.env"]);
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("warning: failed to write the report"), "{stderr}");
+    }
+
+    #[test]
+    fn test_restore_timeout_as_miss() {
+        // 接続は受け付けるが応答しないS3
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let workdir = tempfile::tempdir().unwrap();
+        let config = "bucket = \"cache\"\npaths = [\"node_modules\"]\nkey = \"deps\"\nfallback_keys = []\n";
+        std::fs::write(workdir.path().join("cafce.toml"), config).unwrap();
+
+        let run = |timeout_as_miss: &str| {
+            cafce_command(workdir.path(), &["restore", "--config", "cafce.toml", "--dotenv", "cafce.env"])
+                .env("CAFCE_AWS_SERVER_ADDRESS", &address)
+                .env("CAFCE_AWS_INSECURE", "true")
+                .env("CAFCE_AWS_ACCESS_KEY", "access")
+                .env("CAFCE_AWS_SECRET_KEY", "secret")
+                .env("CAFCE_AWS_MAX_ATTEMPTS", "1")
+                .env("CAFCE_AWS_OPERATION_ATTEMPT_TIMEOUT", "1")
+                .env("CAFCE_RESTORE_TIMEOUT_AS_MISS", timeout_as_miss)
+                .output()
+                .unwrap()
+        };
+
+        let output = run("true");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{stderr}");
+        assert!(stderr.contains("treating the restore as a cache miss"), "{stderr}");
+        let dotenv = std::fs::read_to_string(workdir.path().join("cafce.env")).unwrap();
+        assert!(dotenv.starts_with("CAFCE_CACHE_HIT=false\n"), "{dotenv}");
+
+        // 無効な場合はネットワークエラー（終了コード5）
+        let output = run("false");
+        assert_eq!(output.status.code(), Some(5), "{}", String::from_utf8_lossy(&output.stderr));
+        drop(listener);
+    }
+
+    #[test]
+    fn test_key_does_not_read_generation() {
+        let storage = tempfile::tempdir().unwrap();
+        let workdir = tempfile::tempdir().unwrap();
+        let config = "paths = [\"node_modules\"]\nkey = \"deps\"\nfallback_keys = []\n";
+        std::fs::write(workdir.path().join("cafce.toml"), config).unwrap();
+        std::fs::create_dir_all(storage.path().join("group/app")).unwrap();
+        std::fs::write(storage.path().join("group/app/.cafce-generation"), "3\n").unwrap();
+        let report = |command: &str| {
+            let output = cafce(workdir.path(), storage.path(), &[command, "--config", "cafce.toml", "--output", "json"]);
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+        };
+
+        // keyは保存先に接続しないため、世代を含まないキーを出力する
+        let key = report("key");
+        assert_eq!(key["key"], "deps-protected");
+        assert_eq!(key["generation"], serde_json::Value::Null);
+
+        let restore = report("restore");
+        assert_eq!(restore["key"], "deps-protected-g3");
+        assert_eq!(restore["generation"], 3);
+    }
 }